@@ -0,0 +1,558 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use propane_ir::mono::{self, Binding, Env, FunctionDecl, Instance, Instances, Scope};
+use propane_parser::expression::{Expression, ExpressionKind, Ident, Literal, Operator, Statement, StatementKind};
+use propane_typeck::{Inference, Type};
+
+use crate::RUNTIME;
+
+/// A C variable, by its name.
+type Variable = Rc<str>;
+
+/// The parts of the translation unit being generated.
+#[derive(Default)]
+struct Module<'a> {
+    typedefs: String,
+    globals: String,
+    prototypes: String,
+    definitions: String,
+    /// The typedef naming each function pointer type.
+    function_types: HashMap<Type, String>,
+    /// The C name of each function instance.
+    instances: Instances<'a, Variable, String>,
+    /// The C and source names of every function, for printing function
+    /// values.
+    function_names: Vec<(String, String)>,
+    next_id: usize,
+}
+
+/// Generates a C99 translation unit from programs that have been type
+/// checked one after another, like `propanec run` runs them. The resulting
+/// executable runs each program in turn and prints the value of the last.
+///
+/// Functions are generated once per type they are used at, as C has no
+/// generics. Functions that capture the variables of an enclosing function,
+/// and generic function values, are not supported.
+#[derive(Default)]
+pub struct Generator<'a> {
+    module: Module<'a>,
+    /// The top-level bindings of the programs so far.
+    env: Env<'a, Variable>,
+    programs: usize,
+}
+
+impl<'a> Generator<'a> {
+    pub fn new() -> Generator<'a> {
+        Generator::default()
+    }
+
+    /// Adds a program as returned by [propane_parser::parse], along with the
+    /// types [propane_typeck::TypeChecker] inferred for it.
+    pub fn add(&mut self, program: &'a Expression, inference: &'a Inference) -> Result<(), Diagnostic<FileId>> {
+        let name = format!("pp_program_{}", self.programs);
+        self.programs += 1;
+
+        let id = self.module.fresh_id();
+        let scope = Scope::program(id, &inference.types, self.env.clone());
+        let mut function = FunctionGenerator::new(&mut self.module, scope);
+        function.program = true;
+
+        let (value, ty) = match &program.kind {
+            ExpressionKind::StmtExpr(statements) => {
+                let value = function.statements(statements, true)?;
+
+                match (value, statements.last()) {
+                    (Some(value), Some(Statement { kind: StatementKind::Expression(expression), .. })) => {
+                        (value, function.scope.type_of(expression.span))
+                    }
+                    _ => (function.zero(&Type::Unit), Type::Unit),
+                }
+            }
+            _ => (function.expression(program)?, function.scope.type_of(program.span)),
+        };
+        function.print(&value, &ty);
+
+        self.env = function.scope.env.clone();
+        let body = function.body;
+
+        writeln!(self.module.prototypes, "static void {name}(bool print);").unwrap();
+        write!(self.module.definitions, "static void {name}(bool print) {{\n{body}}}\n\n").unwrap();
+
+        self.module.generate_pending()
+    }
+
+    /// The C source of the programs added so far.
+    pub fn finish(self) -> String {
+        let module = self.module;
+        let mut output = String::new();
+
+        writeln!(output, "/* Generated by propanec. */\n").unwrap();
+        writeln!(output, "{RUNTIME}").unwrap();
+
+        for section in [&module.typedefs, &module.globals, &module.prototypes] {
+            if !section.is_empty() {
+                writeln!(output, "{section}").unwrap();
+            }
+        }
+
+        writeln!(output, "static const struct {{\n    void (*function)(void);\n    const char *name;\n}} pp_functions[] = {{").unwrap();
+        for (c_name, name) in &module.function_names {
+            writeln!(output, "    {{ (void (*)(void)){c_name}, {} }},", c_string(name)).unwrap();
+        }
+        writeln!(output, "    {{ NULL, NULL }},\n}};\n").unwrap();
+        writeln!(output, "\
+static void pp_print_function(void (*function)(void)) {{
+    size_t i;
+
+    for (i = 0; pp_functions[i].name != NULL; i++) {{
+        if (pp_functions[i].function == function) {{
+            printf(\"<fun %s>\", pp_functions[i].name);
+        }}
+    }}
+}}
+").unwrap();
+
+        output.push_str(&module.definitions);
+
+        writeln!(output, "int main(void) {{").unwrap();
+        for program in 0..self.programs {
+            let print = program + 1 == self.programs;
+
+            writeln!(output, "    pp_program_{program}({print});").unwrap();
+        }
+        writeln!(output, "    return 0;\n}}").unwrap();
+
+        output
+    }
+}
+
+impl<'a> Module<'a> {
+    fn fresh_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// A unique C identifier for something called `name` in the source.
+    fn fresh_name(&mut self, prefix: &str, name: &str) -> String {
+        let mut c_name = format!("{prefix}_");
+
+        for ch in name.chars() {
+            if ch.is_ascii_alphanumeric() || ch == '_' {
+                c_name.push(ch);
+            } else {
+                write!(c_name, "_u{:x}_", ch as u32).unwrap();
+            }
+        }
+
+        format!("{c_name}_{}", self.fresh_id())
+    }
+
+    fn c_type(&mut self, ty: &Type) -> String {
+        match ty {
+            Type::Int => "int64_t".to_string(),
+            Type::Float => "double".to_string(),
+            Type::Bool => "bool".to_string(),
+            Type::Char => "uint32_t".to_string(),
+            Type::Str => "pp_str".to_string(),
+            // Types that are still unknown are those of values that are
+            // never produced, such as a block ending in `return`.
+            Type::Unit | Type::Var(_) => "pp_unit".to_string(),
+            Type::Function { parameters, ret } => {
+                if let Some(name) = self.function_types.get(ty) {
+                    return name.clone();
+                }
+
+                let ret = self.c_type(ret);
+                let parameters = match parameters.is_empty() {
+                    true => "void".to_string(),
+                    false => parameters.iter().map(|parameter| self.c_type(parameter)).collect::<Vec<_>>().join(", "),
+                };
+
+                let name = format!("pp_fn_{}", self.function_types.len());
+                writeln!(self.typedefs, "typedef {ret} (*{name})({parameters});").unwrap();
+                self.function_types.insert(ty.clone(), name.clone());
+
+                name
+            }
+        }
+    }
+
+    /// The C name of `decl` instantiated at `ty`, queueing the instance to be
+    /// generated if it is new.
+    fn instance(&mut self, decl: &Rc<FunctionDecl<'a, Variable>>, ty: Type) -> String {
+        if let Some(c_name) = self.instances.get(decl, &ty) {
+            return c_name;
+        }
+
+        let c_name = self.fresh_name("f", &decl.name.name);
+
+        self.function_names.push((c_name.clone(), decl.name.name.clone()));
+        self.instances.insert(decl, ty, c_name.clone());
+
+        c_name
+    }
+
+    fn generate_pending(&mut self) -> Result<(), Diagnostic<FileId>> {
+        while let Some(instance) = self.instances.pop() {
+            self.generate_instance(instance)?;
+        }
+
+        Ok(())
+    }
+
+    fn generate_instance(&mut self, instance: Instance<'a, Variable, String>) -> Result<(), Diagnostic<FileId>> {
+        let decl = instance.decl.clone();
+        let (parameters, ret) = instance.signature();
+
+        let id = self.fresh_id();
+        let mut function = FunctionGenerator::new(self, instance.scope(id));
+
+        let mut c_parameters = vec![];
+        for (parameter, ty) in decl.parameters.iter().zip(parameters) {
+            let c_name = function.module.fresh_name("v", &parameter.name.name);
+
+            c_parameters.push(format!("{} {c_name}", function.module.c_type(ty)));
+            function.scope.define_local(&parameter.name.name, c_name.into());
+        }
+
+        let value = function.expression(decl.body)?;
+        function.line(format!("return {value};"));
+        let body = function.body;
+
+        let ret = self.c_type(ret);
+        let parameters = match c_parameters.is_empty() {
+            true => "void".to_string(),
+            false => c_parameters.join(", "),
+        };
+        let signature = format!("static {ret} {}({parameters})", instance.id);
+
+        writeln!(self.prototypes, "{signature};").unwrap();
+        write!(self.definitions, "{signature} {{\n{body}}}\n\n").unwrap();
+
+        Ok(())
+    }
+}
+
+/// Generates the body of one C function, either a program or an instance of
+/// a Propane function.
+///
+/// Every subexpression that may have an effect, such as a call or checked
+/// arithmetic, is stored in a temporary, so that the C code evaluates them
+/// in the same order as the interpreter.
+struct FunctionGenerator<'m, 'a> {
+    module: &'m mut Module<'a>,
+    scope: Scope<'a, Variable>,
+    /// Whether this is a program, whose `return`s print the value returned.
+    program: bool,
+    body: String,
+    indent: usize,
+}
+
+impl<'m, 'a> FunctionGenerator<'m, 'a> {
+    fn new(module: &'m mut Module<'a>, scope: Scope<'a, Variable>) -> FunctionGenerator<'m, 'a> {
+        FunctionGenerator {
+            module,
+            scope,
+            program: false,
+            body: String::new(),
+            indent: 1,
+        }
+    }
+
+    /// Generates `statements`, returning the value of the last one if it is an
+    /// expression statement. `globals` makes `let`s define global variables,
+    /// for the top level of a program.
+    fn statements(&mut self, statements: &'a [Statement], globals: bool) -> Result<Option<String>, Diagnostic<FileId>> {
+        let mut decls = self.scope.declare_functions(statements);
+
+        let mut value = None;
+        for statement in statements {
+            value = None;
+
+            match &statement.kind {
+                StatementKind::Let { name, value } => {
+                    let value = self.expression(value)?;
+                    let ty = self.scope.type_of(name.span);
+
+                    if matches!(ty, Type::Function { .. }) && mono::has_vars(&ty) {
+                        return Err(generic_value_error(name, &ty));
+                    }
+
+                    let c_type = self.module.c_type(&ty);
+                    let c_name = self.module.fresh_name("v", &name.name);
+
+                    if globals {
+                        writeln!(self.module.globals, "static {c_type} {c_name};").unwrap();
+                        self.line(format!("{c_name} = {value};"));
+
+                        self.scope.define_global(&name.name, c_name.into());
+                    } else {
+                        self.line(format!("{c_type} {c_name} = {value};"));
+
+                        self.scope.define_local(&name.name, c_name.into());
+                    }
+                }
+                StatementKind::Return { value } => {
+                    let ty = self.scope.type_of(value.span);
+                    let value = self.expression(value)?;
+
+                    if self.program {
+                        self.print(&value, &ty);
+                        self.line("return;");
+                    } else {
+                        self.line(format!("return {value};"));
+                    }
+                }
+                StatementKind::While { condition, body } => {
+                    self.line("for (;;) {");
+                    self.indent += 1;
+
+                    let condition = self.expression(condition)?;
+                    self.line(format!("if (!{condition}) break;"));
+                    self.expression(body)?;
+
+                    self.indent -= 1;
+                    self.line("}");
+                }
+                StatementKind::For { variable, start, end, body } => {
+                    let start = self.expression(start)?;
+                    let end = self.expression(end)?;
+                    let end = self.temp(&Type::Int, end);
+                    let c_name = self.module.fresh_name("v", &variable.name);
+
+                    self.line(format!("for (int64_t {c_name} = {start}; {c_name} < {end}; {c_name}++) {{"));
+                    self.indent += 1;
+
+                    let env = self.scope.env.clone();
+                    self.scope.define_local(&variable.name, c_name.into());
+                    self.expression(body)?;
+                    self.scope.env = env;
+
+                    self.indent -= 1;
+                    self.line("}");
+                }
+                StatementKind::Function { .. } => {
+                    decls.next().expect("functions were declared on entering the block").reach(&self.scope);
+                }
+                StatementKind::Expression(expression) => value = Some(self.expression(expression)?),
+            }
+        }
+
+        Ok(value)
+    }
+
+    /// Generates the code evaluating `expression`, returning a C expression
+    /// for its value that has no effects.
+    fn expression(&mut self, expression: &'a Expression) -> Result<String, Diagnostic<FileId>> {
+        let value = match &expression.kind {
+            ExpressionKind::Binary { left, operator, right } => {
+                let ty = self.scope.type_of(left.span);
+                let left = self.expression(left)?;
+                let right = self.expression(right)?;
+
+                self.binary(*operator, &ty, left, right)
+            }
+            ExpressionKind::Grouping(inner) => self.expression(inner)?,
+            ExpressionKind::Literal(literal) => literal_value(literal),
+            ExpressionKind::Unary(operator, operand) => {
+                let ty = self.scope.type_of(operand.span);
+                let operand = self.expression(operand)?;
+
+                match (operator, ty) {
+                    (Operator::Not, _) => format!("(!{operand})"),
+                    (_, Type::Int) => self.temp(&Type::Int, format!("pp_negate({operand})")),
+                    _ => format!("(-{operand})"),
+                }
+            }
+            ExpressionKind::Variable(ident) => self.variable(ident, expression.span)?,
+            ExpressionKind::Call { callee, args } => {
+                let callee = self.expression(callee)?;
+                let args = args.iter().map(|arg| self.expression(arg)).collect::<Result<Vec<_>, _>>()?;
+                let ty = self.scope.type_of(expression.span);
+
+                self.temp(&ty, format!("{callee}({})", args.join(", ")))
+            }
+            ExpressionKind::Block { statements, value } => {
+                let env = self.scope.env.clone();
+                self.statements(statements, false)?;
+
+                let value = match value {
+                    Some(value) => self.expression(value)?,
+                    None => self.zero(&self.scope.type_of(expression.span)),
+                };
+                self.scope.env = env;
+
+                value
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                let condition = self.expression(condition)?;
+
+                let Some(else_branch) = else_branch else {
+                    self.line(format!("if ({condition}) {{"));
+                    self.branch(then_branch, None)?;
+                    self.line("}");
+
+                    return Ok(self.zero(&Type::Unit));
+                };
+
+                let result = format!("t{}", self.module.fresh_id());
+                let c_type = self.module.c_type(&self.scope.type_of(expression.span));
+
+                self.line(format!("{c_type} {result};"));
+                self.line(format!("if ({condition}) {{"));
+                self.branch(then_branch, Some(&result))?;
+                self.line("} else {");
+                self.branch(else_branch, Some(&result))?;
+                self.line("}");
+
+                result
+            }
+            ExpressionKind::StmtExpr(statements) => match self.statements(statements, false)? {
+                Some(value) => value,
+                None => self.zero(&Type::Unit),
+            },
+            ExpressionKind::Error => return Err(mono::parse_error(expression)),
+        };
+
+        Ok(value)
+    }
+
+    /// Generates a branch of an `if`, storing its value in `result`.
+    fn branch(&mut self, branch: &'a Expression, result: Option<&str>) -> Result<(), Diagnostic<FileId>> {
+        self.indent += 1;
+
+        let value = self.expression(branch)?;
+        if let Some(result) = result {
+            self.line(format!("{result} = {value};"));
+        }
+
+        self.indent -= 1;
+
+        Ok(())
+    }
+
+    fn binary(&mut self, operator: Operator, ty: &Type, left: String, right: String) -> String {
+        use Operator::*;
+
+        match (operator, ty) {
+            (Plus, Type::Str) => self.temp(&Type::Str, format!("pp_str_concat({left}, {right})")),
+            (Plus | Minus | Star | Slash, Type::Int) => {
+                let function = match operator {
+                    Plus => "pp_add",
+                    Minus => "pp_subtract",
+                    Star => "pp_multiply",
+                    _ => "pp_divide",
+                };
+
+                self.temp(&Type::Int, format!("{function}({left}, {right})"))
+            }
+            (_, Type::Str) => format!("(pp_str_compare({left}, {right}) {} 0)", operator.as_str()),
+            _ => format!("({left} {} {right})", operator.as_str()),
+        }
+    }
+
+    fn variable(&mut self, ident: &Ident, span: Span) -> Result<String, Diagnostic<FileId>> {
+        match self.scope.lookup(ident, Some("C"))? {
+            Binding::Variable { value: c_name, .. } => Ok(c_name.to_string()),
+            Binding::Function(decl) => {
+                let ty = self.scope.type_of(span);
+
+                Ok(self.module.instance(&decl, ty))
+            }
+        }
+    }
+
+    /// Emits the code printing `value` when this program is the last one.
+    fn print(&mut self, value: &str, ty: &Type) {
+        let call = match ty {
+            Type::Int => format!("pp_print_int({value})"),
+            Type::Float => format!("pp_print_float({value})"),
+            Type::Bool => format!("pp_print_bool({value})"),
+            Type::Char => format!("pp_print_char({value})"),
+            Type::Str => format!("pp_print_str({value})"),
+            Type::Unit | Type::Var(_) => format!("pp_print_unit({value})"),
+            Type::Function { .. } => format!("pp_print_function((void (*)(void)){value})"),
+        };
+
+        self.line("if (print) {");
+        self.indent += 1;
+        self.line(format!("{call};"));
+        self.line("putchar('\\n');");
+        self.indent -= 1;
+        self.line("}");
+    }
+
+    /// Stores `value` in a new temporary.
+    fn temp(&mut self, ty: &Type, value: String) -> String {
+        let name = format!("t{}", self.module.fresh_id());
+        let c_type = self.module.c_type(ty);
+
+        self.line(format!("{c_type} {name} = {value};"));
+
+        name
+    }
+
+    /// A value of type `ty`, for expressions that have no value of their own
+    /// such as blocks without a trailing expression.
+    fn zero(&mut self, ty: &Type) -> String {
+        format!("(({}){{0}})", self.module.c_type(ty))
+    }
+
+    fn line(&mut self, line: impl AsRef<str>) {
+        for _ in 0..self.indent {
+            self.body.push_str("    ");
+        }
+        self.body.push_str(line.as_ref());
+        self.body.push('\n');
+    }
+}
+
+fn literal_value(literal: &Literal) -> String {
+    match literal {
+        // `-9223372036854775808` is the negation of a literal too big for
+        // `int64_t`.
+        Literal::Int(i64::MIN) => "INT64_MIN".to_string(),
+        Literal::Int(value) => format!("INT64_C({value})"),
+        Literal::Float(value) if value.is_infinite() && value.is_sign_negative() => "-HUGE_VAL".to_string(),
+        Literal::Float(value) if value.is_infinite() => "HUGE_VAL".to_string(),
+        Literal::Float(value) => format!("{value:?}"),
+        Literal::Bool(value) => value.to_string(),
+        Literal::Char(value) => format!("UINT32_C({})", *value as u32),
+        Literal::Str(value) => format!("pp_str_literal({}, {})", c_string(value), value.len()),
+    }
+}
+
+/// A C string literal with the bytes of `value`. Everything but printable
+/// ASCII is escaped, as is `?` to avoid trigraphs.
+fn c_string(value: &str) -> String {
+    let mut literal = String::from("\"");
+
+    for byte in value.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => write!(literal, "\\{}", byte as char).unwrap(),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => write!(literal, "\\{byte:03o}").unwrap(),
+        }
+    }
+
+    literal.push('"');
+    literal
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
+
+fn generic_value_error(name: &Ident, ty: &Type) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("cannot compile a generic function value to C")
+        .with_labels(vec![
+            Label::primary(name.file_id, range(name.span)).with_message(format!("`{}` has type `{ty}`", name.name)),
+        ])
+        .with_notes(vec!["annotate the function's parameters so that its type is known".to_string()])
+}
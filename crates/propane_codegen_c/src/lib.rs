@@ -0,0 +1,134 @@
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_parser::expression::Expression;
+use propane_typeck::Inference;
+
+pub use crate::generator::Generator;
+
+mod generator;
+
+/// Definitions every generated program starts with: the representation of
+/// strings and `()`, checked arithmetic and printing values. Every function
+/// is `static`.
+pub const RUNTIME: &str = include_str!("runtime.c");
+
+/// Generates C source for a single type checked program. The resulting
+/// executable prints the program's value, or exits with status 101 after
+/// printing a runtime error to stderr.
+pub fn generate(program: &Expression, inference: &Inference) -> Result<String, Diagnostic<FileId>> {
+    let mut generator = Generator::new();
+    generator.add(program, inference)?;
+
+    Ok(generator.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    /// Compiles the C source with the system compiler and runs it, returning
+    /// what it printed to stdout, or to stderr if it failed.
+    fn compile_and_run(source: &str) -> Result<String, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let c_file = dir.path().join("program.c");
+        let executable = dir.path().join("program");
+        std::fs::write(&c_file, source).unwrap();
+
+        let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let output = Command::new(&compiler)
+            .args(["-std=c99", "-pedantic-errors", "-o"])
+            .arg(&executable)
+            .arg(&c_file)
+            .arg("-lm")
+            .output()
+            .unwrap_or_else(|error| panic!("failed to run `{compiler}`: {error}"));
+        assert!(output.status.success(), "{}\n{source}", String::from_utf8_lossy(&output.stderr));
+
+        let output = Command::new(&executable).output().unwrap();
+        match output.status.code() {
+            Some(0) => Ok(String::from_utf8(output.stdout).unwrap()),
+            Some(101) => Err(String::from_utf8(output.stderr).unwrap()),
+            status => panic!("program exited with {status:?}"),
+        }
+    }
+
+    fn run(src: &str) -> Result<String, String> {
+        let program = propane_parser::parse_str(src);
+        let inference = propane_test_support::check(&program);
+
+        compile_and_run(&generate(&program, &inference).unwrap())
+    }
+
+    /// Runs `src` compiled to C, with its output or error message as the
+    /// interpreter gives them.
+    fn differential_run(src: &str) -> Result<String, Diagnostic<FileId>> {
+        let trim = |output: &str| output.strip_suffix('\n').unwrap_or(output).to_string();
+
+        match run(src) {
+            Ok(output) => Ok(trim(&output)),
+            Err(output) => Err(Diagnostic::error().with_message(trim(output.strip_prefix("error: ").unwrap_or(&output)))),
+        }
+    }
+
+    #[test]
+    fn prints_the_program_value() {
+        assert_eq!(run("return 1 + 2 * 3;"), Ok("7\n".to_string()));
+        assert_eq!(run(r#"let greeting = "hello, " + "world"; greeting;"#), Ok("hello, world\n".to_string()));
+        assert_eq!(run("let a = 1;"), Ok("()\n".to_string()));
+        assert_eq!(run("1 / 0;"), Err("error: attempt to divide by zero\n".to_string()));
+    }
+
+    #[test]
+    fn generic_functions_are_instantiated_per_type() {
+        let program = propane_parser::parse_str(r#"fun id(x) { x } id(1); id("a");"#);
+        let inference = propane_test_support::check(&program);
+        let source = generate(&program, &inference).unwrap();
+
+        assert_eq!(source.matches("static int64_t f_id_").count(), 2);
+        assert_eq!(source.matches("static pp_str f_id_").count(), 2);
+        assert_eq!(compile_and_run(&source), Ok("a\n".to_string()));
+    }
+
+    #[test]
+    fn capturing_closures_are_rejected() {
+        let program = propane_parser::parse_str("fun make(n) { fun get() { n } get } make(1)();");
+        let inference = propane_test_support::check(&program);
+        let error = generate(&program, &inference).unwrap_err();
+
+        assert_eq!(error.message, "cannot capture `n` in a function compiled to C");
+        assert_eq!(error.labels[0].range, 26..27);
+
+        let program = propane_parser::parse_str("fun id(x) { x } let f = id;");
+        let inference = propane_test_support::check(&program);
+        let error = generate(&program, &inference).unwrap_err();
+
+        assert_eq!(error.message, "cannot compile a generic function value to C");
+    }
+
+    #[test]
+    fn programs_share_top_level_bindings() {
+        let programs = propane_test_support::shared_bindings();
+
+        let mut generator = Generator::new();
+        for (program, inference) in &programs {
+            generator.add(program, inference).unwrap();
+        }
+
+        assert_eq!(compile_and_run(&generator.finish()), Ok("42\n".to_string()));
+    }
+
+    #[test]
+    fn agrees_with_the_interpreter() {
+        let programs = [
+            "fun apply(f, x) { f(x) } fun inc(x: int): int { x + 1 } return apply(inc, 41);",
+            "fun f(x: int): int { x } return f == f;",
+            "fun f(x: int): int { x } return f;",
+        ];
+
+        for programs in [propane_test_support::PROGRAMS, propane_test_support::FLOATS, propane_test_support::OVERFLOWS, propane_test_support::STRINGS, &programs] {
+            propane_test_support::agrees_with_the_interpreter(programs, differential_run);
+        }
+    }
+}
@@ -0,0 +1,199 @@
+#include <inttypes.h>
+#include <math.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef unsigned char pp_unit;
+
+typedef struct {
+    const char *data;
+    size_t length;
+} pp_str;
+
+static void pp_panic(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n", message);
+    exit(101);
+}
+
+static int64_t pp_add(int64_t a, int64_t b) {
+    if ((b > 0 && a > INT64_MAX - b) || (b < 0 && a < INT64_MIN - b)) {
+        pp_panic("arithmetic overflow");
+    }
+    return a + b;
+}
+
+static int64_t pp_subtract(int64_t a, int64_t b) {
+    if ((b < 0 && a > INT64_MAX + b) || (b > 0 && a < INT64_MIN + b)) {
+        pp_panic("arithmetic overflow");
+    }
+    return a - b;
+}
+
+static int64_t pp_multiply(int64_t a, int64_t b) {
+    if (a != 0 && b != 0) {
+        bool overflows = a > 0
+            ? (b > 0 ? a > INT64_MAX / b : b < INT64_MIN / a)
+            : (b > 0 ? a < INT64_MIN / b : b < INT64_MAX / a);
+        if (overflows) {
+            pp_panic("arithmetic overflow");
+        }
+    }
+    return a * b;
+}
+
+static int64_t pp_divide(int64_t a, int64_t b) {
+    if (b == 0) {
+        pp_panic("attempt to divide by zero");
+    }
+    if (a == INT64_MIN && b == -1) {
+        pp_panic("arithmetic overflow");
+    }
+    return a / b;
+}
+
+static int64_t pp_negate(int64_t a) {
+    if (a == INT64_MIN) {
+        pp_panic("arithmetic overflow");
+    }
+    return -a;
+}
+
+static pp_str pp_str_concat(pp_str a, pp_str b) {
+    char *data = malloc(a.length + b.length + 1);
+    pp_str result;
+
+    if (data == NULL) {
+        pp_panic("out of memory");
+    }
+    memcpy(data, a.data, a.length);
+    memcpy(data + a.length, b.data, b.length);
+    data[a.length + b.length] = '\0';
+
+    result.data = data;
+    result.length = a.length + b.length;
+    return result;
+}
+
+/* Compares strings bytewise, returning a negative, zero or positive number. */
+static int pp_str_compare(pp_str a, pp_str b) {
+    size_t length = a.length < b.length ? a.length : b.length;
+    int order = memcmp(a.data, b.data, length);
+
+    if (order != 0) {
+        return order;
+    }
+    return (a.length > b.length) - (a.length < b.length);
+}
+
+static pp_str pp_str_literal(const char *data, size_t length) {
+    pp_str result;
+
+    result.data = data;
+    result.length = length;
+    return result;
+}
+
+static void pp_print_int(int64_t value) {
+    printf("%" PRId64, value);
+}
+
+/* Prints the shortest representation that reads back as the same value, in
+ * the format the interpreter uses: `1.0`, `0.25`, `1e16` or `1.5e-7`. */
+static void pp_print_float(double value) {
+    char buffer[32];
+    char digits[20];
+    int precision, exponent, count = 0, i;
+    const char *p;
+    double magnitude = fabs(value);
+
+    if (value != value) {
+        fputs("NaN", stdout);
+        return;
+    }
+    if (magnitude == HUGE_VAL) {
+        fputs(value < 0 ? "-inf" : "inf", stdout);
+        return;
+    }
+
+    for (precision = 1; precision < 17; precision++) {
+        sprintf(buffer, "%.*e", precision - 1, value);
+        if (strtod(buffer, NULL) == value) {
+            break;
+        }
+    }
+    sprintf(buffer, "%.*e", precision - 1, value);
+
+    p = buffer;
+    if (*p == '-') {
+        putchar('-');
+        p++;
+    }
+    for (; *p != 'e'; p++) {
+        if (*p != '.') {
+            digits[count++] = *p;
+        }
+    }
+    exponent = atoi(p + 1);
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+
+    if (magnitude != 0 && (magnitude < 1e-4 || magnitude >= 1e16)) {
+        putchar(digits[0]);
+        if (count > 1) {
+            printf(".%.*s", count - 1, digits + 1);
+        }
+        printf("e%d", exponent);
+    } else if (exponent < 0) {
+        fputs("0.", stdout);
+        for (i = 0; i < -exponent - 1; i++) {
+            putchar('0');
+        }
+        printf("%.*s", count, digits);
+    } else {
+        for (i = 0; i <= exponent; i++) {
+            putchar(i < count ? digits[i] : '0');
+        }
+        putchar('.');
+        if (count > exponent + 1) {
+            printf("%.*s", count - exponent - 1, digits + exponent + 1);
+        } else {
+            putchar('0');
+        }
+    }
+}
+
+static void pp_print_bool(bool value) {
+    fputs(value ? "true" : "false", stdout);
+}
+
+static void pp_print_char(uint32_t value) {
+    if (value < 0x80) {
+        putchar((int)value);
+    } else if (value < 0x800) {
+        putchar((int)(0xC0 | (value >> 6)));
+        putchar((int)(0x80 | (value & 0x3F)));
+    } else if (value < 0x10000) {
+        putchar((int)(0xE0 | (value >> 12)));
+        putchar((int)(0x80 | ((value >> 6) & 0x3F)));
+        putchar((int)(0x80 | (value & 0x3F)));
+    } else {
+        putchar((int)(0xF0 | (value >> 18)));
+        putchar((int)(0x80 | ((value >> 12) & 0x3F)));
+        putchar((int)(0x80 | ((value >> 6) & 0x3F)));
+        putchar((int)(0x80 | (value & 0x3F)));
+    }
+}
+
+static void pp_print_str(pp_str value) {
+    fwrite(value.data, 1, value.length, stdout);
+}
+
+static void pp_print_unit(pp_unit value) {
+    (void)value;
+    fputs("()", stdout);
+}
@@ -0,0 +1,511 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use cranelift_codegen::ir::condcodes::{FloatCC, IntCC};
+use cranelift_codegen::ir::{self as clif, types, AbiParam, FuncRef, GlobalValue, InstBuilder, MemFlags, Signature, Value};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_module::{DataDescription, DataId, FuncId, Linkage, Module};
+use propane_ir::{self as ir, BinaryOperator, Constant, FunctionId, InstructionKind, Source, Terminator, UnaryOperator};
+
+use crate::Error;
+
+/// What [define] declared in a Cranelift module for an IR module.
+pub struct Definitions {
+    /// The Cranelift function of each IR function.
+    pub functions: Vec<FuncId>,
+    /// The data of each global, or `None` for globals of type `()`.
+    pub globals: Vec<Option<DataId>>,
+    /// An `i64` that is zero until a runtime error is raised, and then one
+    /// more than the error's index in `errors`. Functions return as soon as
+    /// it is set, with an arbitrary result.
+    pub error: DataId,
+    /// The runtime errors the code can raise, each at a particular place in
+    /// the source.
+    pub errors: Vec<Diagnostic<FileId>>,
+}
+
+/// Reports the first value of `function` that has no machine type: strings
+/// and function values aren't supported.
+pub fn check(function: &ir::Function) -> Result<(), Diagnostic<FileId>> {
+    for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+        let what = match &instruction.kind {
+            InstructionKind::Const(Constant::Str(_)) => "strings",
+            InstructionKind::Function(_) => "function values",
+            _ => continue,
+        };
+        let source = instruction.source.expect("constants and functions record their source");
+        let result = instruction.result.expect("constants and functions produce a value");
+
+        return Err(unsupported_error(what, source, function.type_of(result)));
+    }
+
+    Ok(())
+}
+
+/// Declares and defines the functions and globals of `ir` in `module`, which
+/// [check] accepted.
+pub fn define<M: Module>(module: &mut M, ir: &ir::Module) -> Result<Definitions, Error> {
+    let error = define_zeroed(module, "propane_error".to_string(), 8)?;
+
+    let globals = ir
+        .globals
+        .iter()
+        .enumerate()
+        .map(|(i, global)| match clif_type(&global.ty) {
+            Some(clif_type) => define_zeroed(module, format!("global_{i}"), clif_type.bytes() as usize).map(Some),
+            None => Ok(None),
+        })
+        .collect::<Result<_, Error>>()?;
+
+    let functions = ir
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, function)| {
+            let signature = signature(module, function);
+            let name = format!("{}_{i}", symbol(&function.name));
+
+            Ok(module.declare_function(&name, Linkage::Local, &signature)?)
+        })
+        .collect::<Result<_, Error>>()?;
+
+    let mut definitions = Definitions { functions, globals, error, errors: vec![] };
+    let mut errors = vec![];
+
+    let mut context = module.make_context();
+    let mut builder_context = FunctionBuilderContext::new();
+
+    for (id, function) in ir.functions.iter().enumerate() {
+        context.func.signature = signature(module, function);
+
+        let builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+        let translator = FunctionTranslator {
+            builder,
+            module,
+            ir,
+            definitions: &definitions,
+            errors: &mut errors,
+            function,
+            blocks: vec![],
+            values: vec![None; function.values.len()],
+            func_refs: HashMap::new(),
+            data_refs: HashMap::new(),
+        };
+        translator.translate();
+
+        module.define_function(definitions.functions[id], &mut context)?;
+        module.clear_context(&mut context);
+    }
+
+    definitions.errors = errors;
+
+    Ok(definitions)
+}
+
+/// The machine type of values of type `ty`, or `None` for `()`.
+pub fn clif_type(ty: &ir::Type) -> Option<types::Type> {
+    match ty {
+        ir::Type::Int => Some(types::I64),
+        ir::Type::Float => Some(types::F64),
+        ir::Type::Bool => Some(types::I8),
+        ir::Type::Char => Some(types::I32),
+        ir::Type::Unit => None,
+        ir::Type::Str | ir::Type::Function { .. } => unreachable!("`check` rejects strings and function values"),
+    }
+}
+
+/// The parameter or result for a value of type `ty`. `bool`s are extended,
+/// as C compilers expect of the runtime's arguments.
+pub fn abi_param(ty: &ir::Type) -> Option<AbiParam> {
+    let param = AbiParam::new(clif_type(ty)?);
+
+    Some(if *ty == ir::Type::Bool { param.uext() } else { param })
+}
+
+fn signature<M: Module>(module: &M, function: &ir::Function) -> Signature {
+    let mut signature = module.make_signature();
+
+    signature.params.extend(function.parameters.iter().filter_map(abi_param));
+    signature.returns.extend(abi_param(&function.result));
+
+    signature
+}
+
+fn define_zeroed<M: Module>(module: &mut M, name: String, size: usize) -> Result<DataId, Error> {
+    let id = module.declare_data(&name, Linkage::Local, true, false)?;
+
+    let mut data = DataDescription::new();
+    data.define_zeroinit(size);
+    data.set_align(8);
+    module.define_data(id, &data)?;
+
+    Ok(id)
+}
+
+/// A symbol name made of the ASCII alphanumeric characters of `name`.
+fn symbol(name: &str) -> String {
+    name.chars().map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' }).collect()
+}
+
+/// The blocks of `function` in reverse postorder, so that each block comes
+/// after the blocks that dominate it, whose values it can use.
+fn reverse_postorder(function: &ir::Function) -> Vec<ir::Block> {
+    let mut visited = vec![false; function.blocks.len()];
+    let mut postorder = vec![];
+    // Each block being visited, and the index of its next target.
+    let mut stack = vec![(function.entry(), 0)];
+    visited[function.entry().0] = true;
+
+    while let Some((block, i)) = stack.pop() {
+        match function.block(block).terminator.targets().get(i) {
+            Some(target) => {
+                stack.push((block, i + 1));
+
+                if !visited[target.block.0] {
+                    visited[target.block.0] = true;
+                    stack.push((target.block, 0));
+                }
+            }
+            None => postorder.push(block),
+        }
+    }
+
+    postorder.reverse();
+    postorder
+}
+
+/// Translates the body of one IR function.
+struct FunctionTranslator<'b, 'm, M: Module> {
+    builder: FunctionBuilder<'b>,
+    module: &'m mut M,
+    ir: &'m ir::Module,
+    definitions: &'m Definitions,
+    errors: &'m mut Vec<Diagnostic<FileId>>,
+    function: &'m ir::Function,
+    /// The Cranelift block of each block.
+    blocks: Vec<clif::Block>,
+    /// The Cranelift value of each value, or `None` for values of type `()`.
+    values: Vec<Option<Value>>,
+    func_refs: HashMap<FunctionId, FuncRef>,
+    data_refs: HashMap<DataId, GlobalValue>,
+}
+
+impl<M: Module> FunctionTranslator<'_, '_, M> {
+    fn translate(mut self) {
+        let function = self.function;
+
+        // The parameters of the entry block are those of the function, which
+        // leave out values of type `()` like the signature.
+        for block in &function.blocks {
+            let clif_block = self.builder.create_block();
+
+            for &parameter in &block.parameters {
+                if let Some(ty) = clif_type(function.type_of(parameter)) {
+                    self.values[parameter.0] = Some(self.builder.append_block_param(clif_block, ty));
+                }
+            }
+
+            self.blocks.push(clif_block);
+        }
+
+        for block in reverse_postorder(function) {
+            self.builder.switch_to_block(self.blocks[block.0]);
+
+            let block = function.block(block);
+            for instruction in &block.instructions {
+                let value = self.instruction(instruction);
+
+                if let Some(result) = instruction.result {
+                    self.values[result.0] = value;
+                }
+            }
+            self.terminator(&block.terminator);
+        }
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    /// Translates `instruction`, returning its value unless it has type `()`.
+    fn instruction(&mut self, instruction: &ir::Instruction) -> Option<Value> {
+        match &instruction.kind {
+            InstructionKind::Const(constant) => match constant {
+                Constant::Int(value) => Some(self.builder.ins().iconst(types::I64, *value)),
+                Constant::Float(value) => Some(self.builder.ins().f64const(*value)),
+                Constant::Bool(value) => Some(self.builder.ins().iconst(types::I8, *value as i64)),
+                Constant::Char(value) => Some(self.builder.ins().iconst(types::I32, *value as i64)),
+                Constant::Unit => None,
+                Constant::Str(_) => unreachable!("`check` rejects strings"),
+            },
+            InstructionKind::Binary(operator, left, right) => {
+                let operand_ty = self.function.type_of(*left);
+
+                let (Some(left), Some(right)) = (self.values[left.0], self.values[right.0]) else {
+                    // Values of type `()` are all equal.
+                    let equal = matches!(operator, BinaryOperator::Eq | BinaryOperator::Le | BinaryOperator::Ge);
+
+                    return Some(self.builder.ins().iconst(types::I8, equal as i64));
+                };
+
+                Some(match operand_ty {
+                    ir::Type::Int if operator.is_comparison() => self.builder.ins().icmp(int_condition(*operator, true), left, right),
+                    ir::Type::Int => match instruction.source {
+                        Some(source) => self.checked_arithmetic(*operator, left, right, source),
+                        None => self.unchecked_arithmetic(*operator, left, right),
+                    },
+                    ir::Type::Float => self.float_binary(*operator, left, right),
+                    // `char`s and `bool`s are never negative.
+                    _ => self.builder.ins().icmp(int_condition(*operator, false), left, right),
+                })
+            }
+            InstructionKind::Unary(UnaryOperator::Neg, operand) => {
+                let is_int = *self.function.type_of(*operand) == ir::Type::Int;
+                let operand = self.values[operand.0]?;
+
+                if !is_int {
+                    return Some(self.builder.ins().fneg(operand));
+                }
+
+                let overflow = self.error(source(instruction).overflow_error());
+                let overflows = self.builder.ins().icmp_imm(IntCC::Equal, operand, i64::MIN);
+                self.fail_if(overflows, overflow);
+
+                Some(self.builder.ins().ineg(operand))
+            }
+            InstructionKind::Unary(UnaryOperator::Not, operand) => {
+                let operand = self.values[operand.0]?;
+
+                Some(self.builder.ins().bxor_imm(operand, 1))
+            }
+            InstructionKind::Call(function, arguments) => {
+                let arguments = arguments.iter().filter_map(|argument| self.values[argument.0]).collect::<Vec<_>>();
+
+                let func_ref = self.func_ref(*function);
+                let call = self.builder.ins().call(func_ref, &arguments);
+                let result = self.builder.inst_results(call).first().copied();
+
+                self.check_error();
+
+                result
+            }
+            InstructionKind::GlobalGet(global) => {
+                let data = self.definitions.globals[global.0]?;
+                let ty = clif_type(&self.ir.global(*global).ty)?;
+                let address = self.data_address(data);
+
+                Some(self.builder.ins().load(ty, MemFlags::trusted(), address, 0))
+            }
+            InstructionKind::GlobalSet(global, value) => {
+                if let (Some(value), Some(data)) = (self.values[value.0], self.definitions.globals[global.0]) {
+                    let address = self.data_address(data);
+                    self.builder.ins().store(MemFlags::trusted(), value, address, 0);
+                }
+
+                None
+            }
+            InstructionKind::Function(_) | InstructionKind::CallIndirect(..) => {
+                unreachable!("`check` rejects function values")
+            }
+        }
+    }
+
+    fn terminator(&mut self, terminator: &Terminator) {
+        match terminator {
+            Terminator::Jump(target) => {
+                let arguments = self.arguments(target);
+
+                self.builder.ins().jump(self.blocks[target.block.0], &arguments);
+            }
+            Terminator::Branch { condition, then_target, else_target } => {
+                let condition = self.values[condition.0].expect("conditions are `bool`s");
+                let then_arguments = self.arguments(then_target);
+                let else_arguments = self.arguments(else_target);
+
+                self.builder.ins().brif(
+                    condition,
+                    self.blocks[then_target.block.0],
+                    &then_arguments,
+                    self.blocks[else_target.block.0],
+                    &else_arguments,
+                );
+            }
+            Terminator::Return(value) => {
+                let value = self.values[value.0];
+
+                self.builder.ins().return_(value.as_slice());
+            }
+        }
+    }
+
+    /// The values passed to a block, leaving out those of type `()` like its
+    /// parameters.
+    fn arguments(&self, target: &ir::BlockCall) -> Vec<Value> {
+        target.arguments.iter().filter_map(|argument| self.values[argument.0]).collect()
+    }
+
+    /// `int` arithmetic that raises an error at `source` when it fails.
+    fn checked_arithmetic(&mut self, operator: BinaryOperator, left: Value, right: Value, source: Source) -> Value {
+        let overflow = self.error(source.overflow_error());
+
+        let (result, overflows) = match operator {
+            BinaryOperator::Add => self.builder.ins().sadd_overflow(left, right),
+            BinaryOperator::Sub => self.builder.ins().ssub_overflow(left, right),
+            BinaryOperator::Mul => self.builder.ins().smul_overflow(left, right),
+            BinaryOperator::Div => {
+                let division_by_zero = self.error(source.division_by_zero_error());
+                let is_zero = self.builder.ins().icmp_imm(IntCC::Equal, right, 0);
+                self.fail_if(is_zero, division_by_zero);
+
+                let is_min = self.builder.ins().icmp_imm(IntCC::Equal, left, i64::MIN);
+                let is_minus_one = self.builder.ins().icmp_imm(IntCC::Equal, right, -1);
+                let overflows = self.builder.ins().band(is_min, is_minus_one);
+                self.fail_if(overflows, overflow);
+
+                return self.builder.ins().sdiv(left, right);
+            }
+            _ => unreachable!("comparisons can't fail"),
+        };
+
+        self.fail_if(overflows, overflow);
+
+        result
+    }
+
+    /// `int` arithmetic that the lowerer knows can't fail.
+    fn unchecked_arithmetic(&mut self, operator: BinaryOperator, left: Value, right: Value) -> Value {
+        match operator {
+            BinaryOperator::Add => self.builder.ins().iadd(left, right),
+            BinaryOperator::Sub => self.builder.ins().isub(left, right),
+            BinaryOperator::Mul => self.builder.ins().imul(left, right),
+            BinaryOperator::Div => self.builder.ins().sdiv(left, right),
+            _ => unreachable!("comparisons are not arithmetic"),
+        }
+    }
+
+    fn float_binary(&mut self, operator: BinaryOperator, left: Value, right: Value) -> Value {
+        let condition = match operator {
+            BinaryOperator::Add => return self.builder.ins().fadd(left, right),
+            BinaryOperator::Sub => return self.builder.ins().fsub(left, right),
+            BinaryOperator::Mul => return self.builder.ins().fmul(left, right),
+            BinaryOperator::Div => return self.builder.ins().fdiv(left, right),
+            BinaryOperator::Eq => FloatCC::Equal,
+            BinaryOperator::Ne => FloatCC::NotEqual,
+            BinaryOperator::Lt => FloatCC::LessThan,
+            BinaryOperator::Le => FloatCC::LessThanOrEqual,
+            BinaryOperator::Gt => FloatCC::GreaterThan,
+            BinaryOperator::Ge => FloatCC::GreaterThanOrEqual,
+        };
+
+        self.builder.ins().fcmp(condition, left, right)
+    }
+
+    /// Records a runtime error the function can raise, returning its index.
+    fn error(&mut self, diagnostic: Diagnostic<FileId>) -> usize {
+        self.errors.push(diagnostic);
+        self.errors.len() - 1
+    }
+
+    /// Raises `error` if `condition` is set.
+    fn fail_if(&mut self, condition: Value, error: usize) {
+        let fail = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.set_cold_block(fail);
+        self.builder.ins().brif(condition, fail, &[], next, &[]);
+
+        self.builder.switch_to_block(fail);
+        let address = self.data_address(self.definitions.error);
+        let code = self.builder.ins().iconst(types::I64, error as i64 + 1);
+        self.builder.ins().store(MemFlags::trusted(), code, address, 0);
+        self.return_anything();
+
+        self.builder.switch_to_block(next);
+    }
+
+    /// Returns from the function if a callee raised an error.
+    fn check_error(&mut self) {
+        let address = self.data_address(self.definitions.error);
+        let error = self.builder.ins().load(types::I64, MemFlags::trusted(), address, 0);
+
+        let fail = self.builder.create_block();
+        let next = self.builder.create_block();
+        self.builder.set_cold_block(fail);
+        self.builder.ins().brif(error, fail, &[], next, &[]);
+
+        self.builder.switch_to_block(fail);
+        self.return_anything();
+
+        self.builder.switch_to_block(next);
+    }
+
+    /// Returns an arbitrary value of the function's result type, after a
+    /// runtime error.
+    fn return_anything(&mut self) {
+        let value = clif_type(&self.function.result).map(|clif_type| match clif_type {
+            types::F64 => self.builder.ins().f64const(0.0),
+            _ => self.builder.ins().iconst(clif_type, 0),
+        });
+
+        self.builder.ins().return_(value.as_slice());
+    }
+
+    fn func_ref(&mut self, function: FunctionId) -> FuncRef {
+        if let Some(&func_ref) = self.func_refs.get(&function) {
+            return func_ref;
+        }
+
+        let func_ref = self.module.declare_func_in_func(self.definitions.functions[function.0], self.builder.func);
+        self.func_refs.insert(function, func_ref);
+
+        func_ref
+    }
+
+    fn data_address(&mut self, data: DataId) -> Value {
+        let global_value = match self.data_refs.get(&data) {
+            Some(&global_value) => global_value,
+            None => {
+                let global_value = self.module.declare_data_in_func(data, self.builder.func);
+                self.data_refs.insert(data, global_value);
+
+                global_value
+            }
+        };
+        let pointer_type = self.module.target_config().pointer_type();
+
+        self.builder.ins().global_value(pointer_type, global_value)
+    }
+}
+
+/// The source of an instruction that can raise a runtime error.
+fn source(instruction: &ir::Instruction) -> Source {
+    instruction.source.expect("arithmetic records its source")
+}
+
+fn int_condition(operator: BinaryOperator, signed: bool) -> IntCC {
+    match (operator, signed) {
+        (BinaryOperator::Eq, _) => IntCC::Equal,
+        (BinaryOperator::Ne, _) => IntCC::NotEqual,
+        (BinaryOperator::Lt, true) => IntCC::SignedLessThan,
+        (BinaryOperator::Le, true) => IntCC::SignedLessThanOrEqual,
+        (BinaryOperator::Gt, true) => IntCC::SignedGreaterThan,
+        (BinaryOperator::Ge, true) => IntCC::SignedGreaterThanOrEqual,
+        (BinaryOperator::Lt, false) => IntCC::UnsignedLessThan,
+        (BinaryOperator::Le, false) => IntCC::UnsignedLessThanOrEqual,
+        (BinaryOperator::Gt, false) => IntCC::UnsignedGreaterThan,
+        (BinaryOperator::Ge, false) => IntCC::UnsignedGreaterThanOrEqual,
+        _ => unreachable!("arithmetic is not a comparison"),
+    }
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
+
+fn unsupported_error(what: &str, source: Source, ty: &ir::Type) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message(format!("{what} are not supported by the native backend"))
+        .with_labels(vec![
+            Label::primary(source.file_id, range(source.span)).with_message(format!("this has type `{ty}`")),
+        ])
+}
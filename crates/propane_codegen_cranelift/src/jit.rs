@@ -0,0 +1,97 @@
+use std::mem::ManuallyDrop;
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_jit::{JITBuilder, JITModule};
+
+use propane_ir as ir;
+
+use crate::{codegen, Error, Value};
+
+/// Programs compiled to machine code in memory, ready to run.
+pub struct Jit {
+    module: ManuallyDrop<JITModule>,
+    /// The code of each program's function, and the type it returns.
+    programs: Vec<(*const u8, ir::Type)>,
+    /// See [codegen::Definitions::error].
+    error: *mut i64,
+    errors: Vec<Diagnostic<FileId>>,
+}
+
+impl Jit {
+    pub(crate) fn new(ir: &ir::Module) -> Result<Jit, Error> {
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").expect("`opt_level` is a known setting");
+
+        let isa = cranelift_native::builder()
+            .map_err(|error| Error::Host(error.to_string()))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|error| Error::Host(error.to_string()))?;
+
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, cranelift_module::default_libcall_names()));
+        let definitions = codegen::define(&mut module, ir)?;
+        module.finalize_definitions()?;
+
+        let programs = ir
+            .programs
+            .iter()
+            .map(|&id| (module.get_finalized_function(definitions.functions[id.0]), ir.function(id).result.clone()))
+            .collect();
+        let (error, _) = module.get_finalized_data(definitions.error);
+
+        Ok(Jit { module: ManuallyDrop::new(module), programs, error: error as *mut i64, errors: definitions.errors })
+    }
+
+    /// Runs the programs in turn, returning the value of the last, or the
+    /// first runtime error.
+    pub fn run(&mut self) -> Result<Value, Diagnostic<FileId>> {
+        let mut value = Value::Unit;
+
+        for (code, ty) in &self.programs {
+            // SAFETY: the error is an `i64` that only the generated code,
+            // which isn't running, accesses. The function takes no arguments
+            // and returns a value of type `ty`.
+            unsafe {
+                self.error.write(0);
+                value = call(*code, ty);
+
+                let error = self.error.read();
+                if error != 0 {
+                    return Err(self.errors[error as usize - 1].clone());
+                }
+            }
+        }
+
+        Ok(value)
+    }
+}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        // SAFETY: the module isn't used again, and no generated code is
+        // running, as `run` borrows the `Jit`.
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+/// Calls the code of a program's function, which returns a value of type
+/// `ty`.
+unsafe fn call(code: *const u8, ty: &ir::Type) -> Value {
+    match ty {
+        ir::Type::Int => Value::Int(std::mem::transmute::<*const u8, extern "C" fn() -> i64>(code)()),
+        ir::Type::Float => Value::Float(std::mem::transmute::<*const u8, extern "C" fn() -> f64>(code)()),
+        ir::Type::Bool => Value::Bool(std::mem::transmute::<*const u8, extern "C" fn() -> u8>(code)() != 0),
+        ir::Type::Char => {
+            let value = std::mem::transmute::<*const u8, extern "C" fn() -> u32>(code)();
+
+            Value::Char(char::from_u32(value).expect("`char`s are Unicode scalar values"))
+        }
+        ir::Type::Unit => {
+            std::mem::transmute::<*const u8, extern "C" fn()>(code)();
+
+            Value::Unit
+        }
+        ir::Type::Str | ir::Type::Function { .. } => unreachable!("`codegen::check` rejects strings and function values"),
+    }
+}
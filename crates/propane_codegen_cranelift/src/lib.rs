@@ -0,0 +1,256 @@
+use std::fmt;
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_parser::expression::Expression;
+use propane_typeck::Inference;
+
+pub use crate::jit::Jit;
+
+mod codegen;
+mod jit;
+mod object;
+
+/// The C source of the functions that object files from [Generator::object]
+/// call to print values and report runtime errors. Compile it and link it
+/// with them to get an executable.
+pub fn runtime() -> String {
+    format!("{}{}", propane_codegen_c::RUNTIME, include_str!("runtime.c"))
+}
+
+/// Compiles a single type checked program in memory and runs it.
+pub fn run(program: &Expression, inference: &Inference) -> Result<Result<Value, Diagnostic<FileId>>, Error> {
+    let mut generator = Generator::new();
+
+    match generator.add(program, inference) {
+        Ok(()) => Ok(generator.jit()?.run()),
+        Err(error) => Ok(Err(error)),
+    }
+}
+
+/// Compiles programs that have been type checked one after another, like
+/// `propanec run` runs them, to machine code for the host, either in memory
+/// or as an object file.
+///
+/// Programs are first lowered to [propane_ir], in which generic functions are
+/// instantiated at each type they are used at, and then to Cranelift IR.
+/// Integer arithmetic is checked as in the interpreter. Strings, function
+/// values and functions that capture the variables of an enclosing function
+/// are not supported.
+pub struct Generator<'a> {
+    lowerer: propane_ir::Lowerer<'a>,
+    /// The number of lowered functions checked to be supported.
+    checked: usize,
+}
+
+impl Default for Generator<'_> {
+    fn default() -> Self {
+        Generator::new()
+    }
+}
+
+impl<'a> Generator<'a> {
+    pub fn new() -> Generator<'a> {
+        Generator { lowerer: propane_ir::Lowerer::for_target("native code"), checked: 0 }
+    }
+
+    /// Adds a program as returned by [propane_parser::parse], along with the
+    /// types [propane_typeck::TypeChecker] inferred for it.
+    pub fn add(&mut self, program: &'a Expression, inference: &'a Inference) -> Result<(), Diagnostic<FileId>> {
+        self.lowerer.add(program, inference)?;
+
+        let functions = &self.lowerer.module().functions;
+        for function in &functions[self.checked..] {
+            codegen::check(function)?;
+        }
+        self.checked = functions.len();
+
+        Ok(())
+    }
+
+    /// Compiles the programs added so far to machine code in memory.
+    pub fn jit(self) -> Result<Jit, Error> {
+        Jit::new(&self.lowerer.finish())
+    }
+
+    /// Compiles the programs added so far to an object file defining a
+    /// `main` function that prints the value of the last, to be linked with
+    /// the [runtime].
+    pub fn object(self) -> Result<Vec<u8>, Error> {
+        object::emit(&self.lowerer.finish())
+    }
+}
+
+/// The value of a program run with [Jit::run].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Unit,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value:?}"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Char(value) => write!(f, "{value}"),
+            Value::Unit => write!(f, "()"),
+        }
+    }
+}
+
+/// Why Cranelift failed to generate code. Programs that [Generator::add]
+/// accepts are always valid, so this is a problem with the host or a bug.
+#[derive(Debug)]
+pub enum Error {
+    /// The host isn't supported by Cranelift.
+    Host(String),
+    Module(Box<cranelift_module::ModuleError>),
+    /// The object file couldn't be written.
+    Emit(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Host(error) => write!(f, "cannot generate code for this machine: {error}"),
+            Error::Module(error) => write!(f, "failed to generate code: {error}"),
+            Error::Emit(error) => write!(f, "failed to write object file: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<cranelift_module::ModuleError> for Error {
+    fn from(error: cranelift_module::ModuleError) -> Error {
+        Error::Module(Box::new(error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::*;
+
+    fn jit(src: &str) -> Result<String, Diagnostic<FileId>> {
+        let program = propane_parser::parse_str(src);
+        let inference = propane_test_support::check(&program);
+
+        run(&program, &inference).unwrap().map(|value| value.to_string())
+    }
+
+    /// Links the object file with the runtime using the system compiler and
+    /// runs it, returning what it printed to stdout, or to stderr if it failed.
+    fn link_and_run(object: &[u8]) -> Result<String, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let object_file = dir.path().join("program.o");
+        let runtime_file = dir.path().join("runtime.c");
+        let executable = dir.path().join("program");
+        std::fs::write(&object_file, object).unwrap();
+        std::fs::write(&runtime_file, runtime()).unwrap();
+
+        let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let output = Command::new(&compiler)
+            .args(["-std=c99", "-pedantic-errors", "-o"])
+            .arg(&executable)
+            .arg(&object_file)
+            .arg(&runtime_file)
+            .arg("-lm")
+            .output()
+            .unwrap_or_else(|error| panic!("failed to run `{compiler}`: {error}"));
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+        let output = Command::new(&executable).output().unwrap();
+        match output.status.code() {
+            Some(0) => Ok(String::from_utf8(output.stdout).unwrap()),
+            Some(101) => Err(String::from_utf8(output.stderr).unwrap()),
+            status => panic!("program exited with {status:?}"),
+        }
+    }
+
+    fn build(src: &str) -> Result<String, String> {
+        let program = propane_parser::parse_str(src);
+        let inference = propane_test_support::check(&program);
+
+        let mut generator = Generator::new();
+        generator.add(&program, &inference).unwrap();
+
+        link_and_run(&generator.object().unwrap())
+    }
+
+    #[test]
+    fn runs_in_memory() {
+        assert_eq!(jit("return 1 + 2 * 3;"), Ok("7".to_string()));
+        assert_eq!(jit("let a = 1;"), Ok("()".to_string()));
+        assert_eq!(jit("return 0.5 > 0.25;"), Ok("true".to_string()));
+    }
+
+    #[test]
+    fn reports_runtime_errors_like_the_interpreter() {
+        for src in [
+            "let zero = 0; return 1 / zero;",
+            "fun f(n: int): int { n * 2 } f(4611686018427387904);",
+            "let min = -9223372036854775807 - 1; return -min;",
+        ] {
+            let error = jit(src).unwrap_err();
+
+            assert_eq!(Err(error), propane_test_support::interpret(src), "{src}");
+        }
+    }
+
+    #[test]
+    fn emits_object_files() {
+        assert_eq!(build("fun square(x: int): int { x * x } return square(7);"), Ok("49\n".to_string()));
+        assert_eq!(build("return 0.1 + 0.2;"), Ok("0.30000000000000004\n".to_string()));
+        assert_eq!(build("return 'λ';"), Ok("λ\n".to_string()));
+        assert_eq!(build("return 1 < 2;"), Ok("true\n".to_string()));
+        assert_eq!(build("let a = 1;"), Ok("()\n".to_string()));
+        assert_eq!(build("1 / 0;"), Err("error: attempt to divide by zero\n".to_string()));
+        assert_eq!(build("return 9223372036854775807 + 1;"), Err("error: arithmetic overflow\n".to_string()));
+    }
+
+    #[test]
+    fn unsupported_features() {
+        let error = |src| {
+            let program = propane_parser::parse_str(src);
+            let inference = propane_test_support::check(&program);
+
+            Generator::new().add(&program, &inference).unwrap_err()
+        };
+
+        let diagnostic = error(r#"let s = "a";"#);
+        assert_eq!(diagnostic.message, "strings are not supported by the native backend");
+        assert_eq!(diagnostic.labels[0].range, 8..11);
+
+        let diagnostic = error("fun f(x: int): int { x } let g = f;");
+        assert_eq!(diagnostic.message, "function values are not supported by the native backend");
+
+        let diagnostic = error("fun make(n: int) { fun get() { n } get() } make(1);");
+        assert_eq!(diagnostic.message, "cannot capture `n` in a function compiled to native code");
+    }
+
+    #[test]
+    fn programs_share_top_level_bindings() {
+        let programs = propane_test_support::shared_bindings();
+
+        let mut generator = Generator::new();
+        for (program, inference) in &programs {
+            generator.add(program, inference).unwrap();
+        }
+
+        assert_eq!(generator.jit().unwrap().run(), Ok(Value::Int(42)));
+    }
+
+    #[test]
+    fn agrees_with_the_interpreter() {
+        for programs in [propane_test_support::PROGRAMS, propane_test_support::FLOATS, propane_test_support::OVERFLOWS] {
+            propane_test_support::agrees_with_the_interpreter(programs, jit);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlags};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_module::{DataDescription, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+
+use propane_ir as ir;
+
+use crate::codegen::{self, Definitions};
+use crate::Error;
+
+/// Compiles `ir` to an object file for the host, with a `main` function that
+/// runs the programs and prints the value of the last.
+pub fn emit(ir: &ir::Module) -> Result<Vec<u8>, Error> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").expect("`opt_level` is a known setting");
+    // Executables are position independent by default.
+    flags.set("is_pic", "true").expect("`is_pic` is a known setting");
+
+    let isa = cranelift_native::builder()
+        .map_err(|error| Error::Host(error.to_string()))?
+        .finish(settings::Flags::new(flags))
+        .map_err(|error| Error::Host(error.to_string()))?;
+
+    let mut module = ObjectModule::new(ObjectBuilder::new(isa, "propane", cranelift_module::default_libcall_names())?);
+    let definitions = codegen::define(&mut module, ir)?;
+    define_main(&mut module, ir, &definitions)?;
+
+    module.finish().emit().map_err(|error| Error::Emit(error.to_string()))
+}
+
+/// Defines `main`, which calls the runtime to print the result of the last
+/// program, or the message of the runtime error one raised.
+fn define_main(module: &mut ObjectModule, ir: &ir::Module, definitions: &Definitions) -> Result<(), Error> {
+    let pointer_type = module.target_config().pointer_type();
+
+    let mut signature = module.make_signature();
+    signature.returns.push(AbiParam::new(types::I32));
+    let main = module.declare_function("main", Linkage::Export, &signature)?;
+
+    let result = ir.programs.last().map_or(ir::Type::Unit, |&id| ir.function(id).result.clone());
+    let mut signature = module.make_signature();
+    signature.params.extend(codegen::abi_param(&result));
+    let print_name = match result {
+        ir::Type::Int => "propane_print_int",
+        ir::Type::Float => "propane_print_float",
+        ir::Type::Bool => "propane_print_bool",
+        ir::Type::Char => "propane_print_char",
+        ir::Type::Unit => "propane_print_unit",
+        ir::Type::Str | ir::Type::Function { .. } => unreachable!("`codegen::check` rejects strings and function values"),
+    };
+    let print = module.declare_function(print_name, Linkage::Import, &signature)?;
+
+    let mut signature = module.make_signature();
+    signature.params.push(AbiParam::new(pointer_type));
+    let panic = module.declare_function("propane_panic", Linkage::Import, &signature)?;
+
+    // A table of pointers to each error's message.
+    let messages = module.declare_data("error_messages", Linkage::Local, false, false)?;
+    let mut table = DataDescription::new();
+    // Not `define_zeroinit`, as zeroed data can't be relocated.
+    table.define(vec![0; definitions.errors.len().max(1) * pointer_type.bytes() as usize].into_boxed_slice());
+    let mut message_data = HashMap::new();
+    for (i, error) in definitions.errors.iter().enumerate() {
+        let data = match message_data.get(&error.message) {
+            Some(&data) => data,
+            None => {
+                let data = module.declare_data(&format!("error_message_{i}"), Linkage::Local, false, false)?;
+                let mut message = DataDescription::new();
+                message.define(format!("{}\0", error.message).into_bytes().into_boxed_slice());
+                module.define_data(data, &message)?;
+                message_data.insert(error.message.clone(), data);
+
+                data
+            }
+        };
+
+        let global_value = module.declare_data_in_data(data, &mut table);
+        table.write_data_addr(i as u32 * pointer_type.bytes(), global_value, 0);
+    }
+    module.define_data(messages, &table)?;
+
+    let mut context = Context::new();
+    context.func.signature = module.declarations().get_function_decl(main).signature.clone();
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+
+    let entry = builder.create_block();
+    let fail = builder.create_block();
+    builder.set_cold_block(fail);
+    builder.switch_to_block(entry);
+
+    let error_address = module.declare_data_in_func(definitions.error, builder.func);
+    let error_address = builder.ins().global_value(pointer_type, error_address);
+
+    let mut value = None;
+    for &id in &ir.programs {
+        let function = module.declare_func_in_func(definitions.functions[id.0], builder.func);
+        let call = builder.ins().call(function, &[]);
+        value = builder.inst_results(call).first().copied();
+
+        let error = builder.ins().load(types::I64, MemFlags::trusted(), error_address, 0);
+        let next = builder.create_block();
+        builder.ins().brif(error, fail, &[], next, &[]);
+        builder.switch_to_block(next);
+    }
+
+    let print = module.declare_func_in_func(print, builder.func);
+    builder.ins().call(print, value.as_slice());
+    let success = builder.ins().iconst(types::I32, 0);
+    builder.ins().return_(&[success]);
+
+    builder.switch_to_block(fail);
+    let error = builder.ins().load(types::I64, MemFlags::trusted(), error_address, 0);
+    let index = builder.ins().iadd_imm(error, -1);
+    let offset = builder.ins().imul_imm(index, pointer_type.bytes() as i64);
+    let table = module.declare_data_in_func(messages, builder.func);
+    let table = builder.ins().global_value(pointer_type, table);
+    let address = builder.ins().iadd(table, offset);
+    let message = builder.ins().load(pointer_type, MemFlags::trusted(), address, 0);
+    let panic = module.declare_func_in_func(panic, builder.func);
+    builder.ins().call(panic, &[message]);
+    // `propane_panic` exits, but in case it doesn't:
+    let failure = builder.ins().iconst(types::I32, 101);
+    builder.ins().return_(&[failure]);
+
+    builder.seal_all_blocks();
+    builder.finalize();
+
+    module.define_function(main, &mut context)?;
+
+    Ok(())
+}
@@ -0,0 +1,32 @@
+
+/* The functions that object files from the Cranelift backend call, added to
+ * the C backend's runtime. */
+
+void propane_print_int(int64_t value) {
+    pp_print_int(value);
+    putchar('\n');
+}
+
+void propane_print_float(double value) {
+    pp_print_float(value);
+    putchar('\n');
+}
+
+void propane_print_bool(bool value) {
+    pp_print_bool(value);
+    putchar('\n');
+}
+
+void propane_print_char(uint32_t value) {
+    pp_print_char(value);
+    putchar('\n');
+}
+
+void propane_print_unit(void) {
+    pp_print_unit(0);
+    putchar('\n');
+}
+
+void propane_panic(const char *message) {
+    pp_panic(message);
+}
@@ -0,0 +1,605 @@
+use std::fmt::Write;
+use std::ops::Range;
+use std::rc::Rc;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use propane_ir::mono::{self, Binding, Env, FunctionDecl, Instance, Instances, Scope};
+use propane_parser::expression::{Expression, ExpressionKind, Literal, Operator, Statement, StatementKind};
+use propane_typeck::{Inference, Type};
+
+/// A WebAssembly value type. Values of type `()` have no representation.
+type ValType = &'static str;
+
+/// Where the value of a variable is kept.
+#[derive(Clone)]
+enum Variable {
+    Local(Rc<str>),
+    Global(Rc<str>),
+    /// A variable of type `()`, which needs no storage.
+    Unit,
+}
+
+/// The parts of the module being generated.
+#[derive(Default)]
+struct Module<'a> {
+    globals: String,
+    functions: String,
+    /// Exported names and the functions they refer to.
+    exports: Vec<(String, String)>,
+    /// The name of each function instance.
+    instances: Instances<'a, Variable, String>,
+    next_id: usize,
+}
+
+/// Generates a WebAssembly module, in the text format, from programs that
+/// have been type checked one after another, like `propanec run` runs them.
+///
+/// The module exports each top-level function whose type is known, except
+/// for one called `main`, and a `main` function that runs the programs in
+/// turn and returns the value of the last.
+///
+/// `int`, `bool` and `char` are represented as `i32`, and `float` as `f32`.
+/// Integer arithmetic wraps on overflow, and division by zero traps.
+/// Strings, function values and functions that capture the variables of an
+/// enclosing function are not supported.
+#[derive(Default)]
+pub struct Generator<'a> {
+    module: Module<'a>,
+    /// The top-level bindings of the programs so far.
+    env: Env<'a, Variable>,
+    /// The name and result type of each program's function.
+    programs: Vec<(String, Option<ValType>)>,
+}
+
+impl<'a> Generator<'a> {
+    pub fn new() -> Generator<'a> {
+        Generator::default()
+    }
+
+    /// Adds a program as returned by [propane_parser::parse], along with the
+    /// types [propane_typeck::TypeChecker] inferred for it.
+    pub fn add(&mut self, program: &'a Expression, inference: &'a Inference) -> Result<(), Diagnostic<FileId>> {
+        let name = format!("$program_{}", self.programs.len());
+
+        let id = self.module.fresh_id();
+        let scope = Scope::program(id, &inference.types, self.env.clone());
+        let mut function = FunctionGenerator::new(&mut self.module, scope);
+
+        let result = match &program.kind {
+            ExpressionKind::StmtExpr(statements) => {
+                let result = match statements.last().map(|statement| &statement.kind) {
+                    Some(StatementKind::Expression(value) | StatementKind::Return { value }) => {
+                        let ty = function.scope.type_of(value.span);
+
+                        function.val_type(&ty, value.file_id, value.span)?
+                    }
+                    _ => None,
+                };
+
+                function.program_result = Some(result);
+                function.statements(statements, true, true)?;
+
+                result
+            }
+            _ => {
+                let ty = function.scope.type_of(program.span);
+                let result = function.val_type(&ty, program.file_id, program.span)?;
+
+                function.program_result = Some(result);
+                function.expression(program)?;
+
+                result
+            }
+        };
+
+        self.env = function.scope.env.clone();
+        let function = function.finish(&name, "", result);
+        self.module.functions.push_str(&function);
+        self.programs.push((name, result));
+
+        // Top-level functions are exported if they have a single type.
+        if let ExpressionKind::StmtExpr(statements) = &program.kind {
+            for statement in statements {
+                let StatementKind::Function { name, .. } = &statement.kind else {
+                    continue;
+                };
+                let Some(Binding::Function(decl)) = self.env.lookup(&name.name).cloned() else {
+                    continue;
+                };
+                let ty = &inference.types[&name.span];
+
+                if mono::has_vars(ty) || name.name == "main" {
+                    continue;
+                }
+
+                let instance = self.module.instance(&decl, ty.clone());
+                self.module.exports.retain(|(export, _)| *export != name.name);
+                self.module.exports.push((name.name.clone(), instance));
+            }
+        }
+
+        self.module.generate_pending()
+    }
+
+    /// The text of the module generated from the programs added so far.
+    pub fn finish(self) -> String {
+        let module = self.module;
+        let mut output = String::from("(module\n");
+
+        output.push_str(&module.globals);
+        output.push_str(&module.functions);
+
+        let result = self.programs.last().and_then(|&(_, result)| result);
+        match result {
+            Some(result) => writeln!(output, "  (func $main (result {result})").unwrap(),
+            None => writeln!(output, "  (func $main").unwrap(),
+        }
+        for (i, (name, result)) in self.programs.iter().enumerate() {
+            writeln!(output, "    call {name}").unwrap();
+
+            if result.is_some() && i + 1 < self.programs.len() {
+                writeln!(output, "    drop").unwrap();
+            }
+        }
+        writeln!(output, "  )").unwrap();
+
+        for (export, name) in &module.exports {
+            writeln!(output, "  (export \"{export}\" (func {name}))").unwrap();
+        }
+        writeln!(output, "  (export \"main\" (func $main))").unwrap();
+        writeln!(output, ")").unwrap();
+
+        output
+    }
+}
+
+impl<'a> Module<'a> {
+    fn fresh_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// A unique identifier for something called `name` in the source.
+    fn fresh_name(&mut self, name: &str) -> String {
+        let mut identifier = String::from("$");
+
+        for ch in name.chars() {
+            if ch.is_ascii_alphanumeric() || ch == '_' {
+                identifier.push(ch);
+            } else {
+                write!(identifier, "_u{:x}_", ch as u32).unwrap();
+            }
+        }
+
+        format!("{identifier}_{}", self.fresh_id())
+    }
+
+    /// The name of `decl` instantiated at `ty`, queueing the instance to be
+    /// generated if it is new.
+    fn instance(&mut self, decl: &Rc<FunctionDecl<'a, Variable>>, ty: Type) -> String {
+        if let Some(name) = self.instances.get(decl, &ty) {
+            return name;
+        }
+
+        let name = self.fresh_name(&decl.name.name);
+        self.instances.insert(decl, ty, name.clone());
+
+        name
+    }
+
+    fn generate_pending(&mut self) -> Result<(), Diagnostic<FileId>> {
+        while let Some(instance) = self.instances.pop() {
+            self.generate_instance(instance)?;
+        }
+
+        Ok(())
+    }
+
+    fn generate_instance(&mut self, instance: Instance<'a, Variable, String>) -> Result<(), Diagnostic<FileId>> {
+        let decl = instance.decl.clone();
+        let (parameters, ret) = instance.signature();
+
+        let id = self.fresh_id();
+        let mut function = FunctionGenerator::new(self, instance.scope(id));
+
+        let mut signature = String::new();
+        for (parameter, ty) in decl.parameters.iter().zip(parameters) {
+            let name = &parameter.name;
+            let variable = match function.val_type(ty, name.file_id, name.span)? {
+                Some(val_type) => {
+                    let local = function.module.fresh_name(&name.name);
+                    write!(signature, " (param {local} {val_type})").unwrap();
+
+                    Variable::Local(local.into())
+                }
+                None => Variable::Unit,
+            };
+
+            function.scope.define_local(&parameter.name.name, variable);
+        }
+
+        let result = function.val_type(ret, decl.name.file_id, decl.name.span)?;
+        function.expression(decl.body)?;
+
+        let function = function.finish(&instance.id, &signature, result);
+        self.functions.push_str(&function);
+
+        Ok(())
+    }
+}
+
+/// Generates the body of one function, either a program or an instance of a
+/// Propane function.
+struct FunctionGenerator<'m, 'a> {
+    module: &'m mut Module<'a>,
+    scope: Scope<'a, Variable>,
+    /// For a program, the type its `return`s and final value must have.
+    program_result: Option<Option<ValType>>,
+    locals: Vec<(String, ValType)>,
+    body: String,
+    indent: usize,
+}
+
+impl<'m, 'a> FunctionGenerator<'m, 'a> {
+    fn new(module: &'m mut Module<'a>, scope: Scope<'a, Variable>) -> FunctionGenerator<'m, 'a> {
+        FunctionGenerator {
+            module,
+            scope,
+            program_result: None,
+            locals: vec![],
+            body: String::new(),
+            indent: 2,
+        }
+    }
+
+    /// Generates `statements`, dropping the values of expression statements
+    /// other than the last if `keep_last` is set. `globals` makes `let`s
+    /// define global variables, for the top level of a program.
+    fn statements(&mut self, statements: &'a [Statement], globals: bool, keep_last: bool) -> Result<(), Diagnostic<FileId>> {
+        let mut decls = self.scope.declare_functions(statements);
+
+        for (i, statement) in statements.iter().enumerate() {
+            match &statement.kind {
+                StatementKind::Let { name, value } => {
+                    self.expression(value)?;
+
+                    let ty = self.scope.type_of(name.span);
+                    let variable = match self.val_type(&ty, name.file_id, name.span)? {
+                        Some(val_type) if globals => {
+                            let global = self.module.fresh_name(&name.name);
+                            writeln!(self.module.globals, "  (global {global} (mut {val_type}) ({val_type}.const 0))").unwrap();
+                            self.line(format!("global.set {global}"));
+
+                            Variable::Global(global.into())
+                        }
+                        Some(val_type) => {
+                            let local = self.local(&name.name, val_type);
+                            self.line(format!("local.set {local}"));
+
+                            Variable::Local(local.into())
+                        }
+                        None => Variable::Unit,
+                    };
+
+                    if globals {
+                        self.scope.define_global(&name.name, variable);
+                    } else {
+                        self.scope.define_local(&name.name, variable);
+                    }
+                }
+                StatementKind::Return { value } => {
+                    self.expression(value)?;
+
+                    if let Some(result) = self.program_result {
+                        let ty = self.scope.type_of(value.span);
+
+                        if self.val_type(&ty, value.file_id, value.span)? != result {
+                            return Err(program_result_error(value, &ty));
+                        }
+                    }
+
+                    self.line("return");
+                }
+                StatementKind::While { condition, body } => {
+                    self.line("block");
+                    self.indent += 1;
+                    self.line("loop");
+                    self.indent += 1;
+
+                    self.expression(condition)?;
+                    self.line("i32.eqz");
+                    self.line("br_if 1");
+                    self.expression(body)?;
+                    self.drop_value(body)?;
+                    self.line("br 0");
+
+                    self.indent -= 1;
+                    self.line("end");
+                    self.indent -= 1;
+                    self.line("end");
+                }
+                StatementKind::For { variable, start, end, body } => {
+                    let counter = self.local(&variable.name, "i32");
+                    let end_local = self.local("end", "i32");
+
+                    self.expression(start)?;
+                    self.line(format!("local.set {counter}"));
+                    self.expression(end)?;
+                    self.line(format!("local.set {end_local}"));
+
+                    self.line("block");
+                    self.indent += 1;
+                    self.line("loop");
+                    self.indent += 1;
+
+                    self.line(format!("local.get {counter}"));
+                    self.line(format!("local.get {end_local}"));
+                    self.line("i32.ge_s");
+                    self.line("br_if 1");
+
+                    let env = self.scope.env.clone();
+                    self.scope.define_local(&variable.name, Variable::Local(counter.as_str().into()));
+                    self.expression(body)?;
+                    self.drop_value(body)?;
+                    self.scope.env = env;
+
+                    self.line(format!("local.get {counter}"));
+                    self.line("i32.const 1");
+                    self.line("i32.add");
+                    self.line(format!("local.set {counter}"));
+                    self.line("br 0");
+
+                    self.indent -= 1;
+                    self.line("end");
+                    self.indent -= 1;
+                    self.line("end");
+                }
+                StatementKind::Function { .. } => {
+                    decls.next().expect("functions were declared on entering the block").reach(&self.scope);
+                }
+                StatementKind::Expression(expression) => {
+                    self.expression(expression)?;
+
+                    if !(keep_last && i + 1 == statements.len()) {
+                        self.drop_value(expression)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Generates the code pushing the value of `expression`, if it has one.
+    fn expression(&mut self, expression: &'a Expression) -> Result<(), Diagnostic<FileId>> {
+        match &expression.kind {
+            ExpressionKind::Binary { left, operator, right } => {
+                let ty = self.scope.type_of(left.span);
+                let val_type = self.val_type(&ty, left.file_id, left.span)?;
+
+                self.expression(left)?;
+                self.expression(right)?;
+
+                let Some(val_type) = val_type else {
+                    // Values of type `()` are all equal.
+                    let equal = *operator == Operator::EqEq;
+                    self.line(format!("i32.const {}", equal as i32));
+
+                    return Ok(());
+                };
+
+                let instruction = match (operator, ty) {
+                    (Operator::Plus, _) => "add",
+                    (Operator::Minus, _) => "sub",
+                    (Operator::Star, _) => "mul",
+                    (Operator::Slash, Type::Float) => "div",
+                    (Operator::Slash, _) => "div_s",
+                    (Operator::EqEq, _) => "eq",
+                    (Operator::NotEq, _) => "ne",
+                    (Operator::Gt, Type::Float) => "gt",
+                    (Operator::GtEq, Type::Float) => "ge",
+                    (Operator::Lt, Type::Float) => "lt",
+                    (Operator::LtEq, Type::Float) => "le",
+                    (Operator::Gt, _) => "gt_s",
+                    (Operator::GtEq, _) => "ge_s",
+                    (Operator::Lt, _) => "lt_s",
+                    (Operator::LtEq, _) => "le_s",
+                    (Operator::Not, _) => unreachable!("`!` is not a binary operator"),
+                };
+
+                self.line(format!("{val_type}.{instruction}"));
+            }
+            ExpressionKind::Grouping(inner) => self.expression(inner)?,
+            ExpressionKind::Literal(literal) => {
+                let instruction = literal_instruction(literal, expression)?;
+
+                self.line(instruction);
+            }
+            ExpressionKind::Unary(Operator::Not, operand) => {
+                self.expression(operand)?;
+                self.line("i32.eqz");
+            }
+            ExpressionKind::Unary(_, operand) => {
+                if self.scope.type_of(operand.span) == Type::Float {
+                    self.expression(operand)?;
+                    self.line("f32.neg");
+                } else {
+                    self.line("i32.const 0");
+                    self.expression(operand)?;
+                    self.line("i32.sub");
+                }
+            }
+            ExpressionKind::Variable(ident) => match self.scope.lookup(ident, Some("WebAssembly"))? {
+                Binding::Variable { value: Variable::Local(name), .. } => self.line(format!("local.get {name}")),
+                Binding::Variable { value: Variable::Global(name), .. } => self.line(format!("global.get {name}")),
+                Binding::Variable { value: Variable::Unit, .. } => {}
+                Binding::Function(_) => {
+                    let ty = self.scope.type_of(expression.span);
+
+                    return Err(unsupported_error("function values", expression.file_id, expression.span, &ty));
+                }
+            },
+            ExpressionKind::Call { callee, args } => {
+                let Some(decl) = self.scope.callee(callee) else {
+                    let ty = self.scope.type_of(callee.span);
+
+                    return Err(unsupported_error("function values", callee.file_id, callee.span, &ty));
+                };
+
+                for arg in args {
+                    self.expression(arg)?;
+                }
+
+                let ty = self.scope.type_of(callee.span);
+                let function = self.module.instance(&decl, ty);
+                self.line(format!("call {function}"));
+            }
+            ExpressionKind::Block { statements, value } => {
+                let env = self.scope.env.clone();
+
+                self.statements(statements, false, false)?;
+                if let Some(value) = value {
+                    self.expression(value)?;
+                }
+
+                self.scope.env = env;
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                self.expression(condition)?;
+
+                let Some(else_branch) = else_branch else {
+                    self.line("if");
+                    self.indent += 1;
+                    self.expression(then_branch)?;
+                    self.drop_value(then_branch)?;
+                    self.indent -= 1;
+                    self.line("end");
+
+                    return Ok(());
+                };
+
+                let ty = self.scope.type_of(expression.span);
+                match self.val_type(&ty, expression.file_id, expression.span)? {
+                    Some(val_type) => self.line(format!("if (result {val_type})")),
+                    None => self.line("if"),
+                }
+
+                self.indent += 1;
+                self.expression(then_branch)?;
+                self.indent -= 1;
+                self.line("else");
+                self.indent += 1;
+                self.expression(else_branch)?;
+                self.indent -= 1;
+                self.line("end");
+            }
+            ExpressionKind::StmtExpr(statements) => self.statements(statements, false, true)?,
+            ExpressionKind::Error => return Err(mono::parse_error(expression)),
+        }
+
+        Ok(())
+    }
+
+    /// Drops the value `expression` left on the stack, if it has one.
+    fn drop_value(&mut self, expression: &Expression) -> Result<(), Diagnostic<FileId>> {
+        let ty = self.scope.type_of(expression.span);
+
+        if self.val_type(&ty, expression.file_id, expression.span)?.is_some() {
+            self.line("drop");
+        }
+
+        Ok(())
+    }
+
+    /// The representation of values of type `ty`, reporting an error at
+    /// `span` for types that can't be represented.
+    fn val_type(&self, ty: &Type, file_id: FileId, span: Span) -> Result<Option<ValType>, Diagnostic<FileId>> {
+        match ty {
+            Type::Int | Type::Bool | Type::Char => Ok(Some("i32")),
+            Type::Float => Ok(Some("f32")),
+            // Types that are still unknown are those of values that are
+            // never produced, such as a block ending in `return`.
+            Type::Unit | Type::Var(_) => Ok(None),
+            Type::Str => Err(unsupported_error("strings", file_id, span, ty)),
+            Type::Function { .. } => Err(unsupported_error("function values", file_id, span, ty)),
+        }
+    }
+
+    /// Declares a new local for something called `name` in the source.
+    fn local(&mut self, name: &str, val_type: ValType) -> String {
+        let local = self.module.fresh_name(name);
+        self.locals.push((local.clone(), val_type));
+
+        local
+    }
+
+    fn line(&mut self, line: impl AsRef<str>) {
+        for _ in 0..self.indent {
+            self.body.push_str("  ");
+        }
+        self.body.push_str(line.as_ref());
+        self.body.push('\n');
+    }
+
+    /// The text of the function, with the given name and parameters.
+    fn finish(self, name: &str, parameters: &str, result: Option<ValType>) -> String {
+        let mut function = format!("  (func {name}{parameters}");
+        if let Some(result) = result {
+            write!(function, " (result {result})").unwrap();
+        }
+        function.push('\n');
+
+        for (local, val_type) in &self.locals {
+            writeln!(function, "    (local {local} {val_type})").unwrap();
+        }
+        function.push_str(&self.body);
+        function.push_str("  )\n");
+
+        function
+    }
+}
+
+fn literal_instruction(literal: &Literal, expression: &Expression) -> Result<String, Diagnostic<FileId>> {
+    let instruction = match literal {
+        Literal::Int(value) => match i32::try_from(*value) {
+            Ok(value) => format!("i32.const {value}"),
+            Err(_) => return Err(int_range_error(expression)),
+        },
+        Literal::Float(value) => format!("f32.const {:?}", *value as f32),
+        Literal::Bool(value) => format!("i32.const {}", *value as i32),
+        Literal::Char(value) => format!("i32.const {}", *value as u32),
+        Literal::Str(_) => return Err(unsupported_error("strings", expression.file_id, expression.span, &Type::Str)),
+    };
+
+    Ok(instruction)
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
+
+fn unsupported_error(what: &str, file_id: FileId, span: Span, ty: &Type) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message(format!("{what} are not supported by the WebAssembly backend"))
+        .with_labels(vec![
+            Label::primary(file_id, range(span)).with_message(format!("this has type `{ty}`")),
+        ])
+}
+
+fn int_range_error(expression: &Expression) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("integer literal out of range for WebAssembly")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span)).with_message("does not fit in an `i32`"),
+        ])
+}
+
+fn program_result_error(value: &Expression, ty: &Type) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("a program compiled to WebAssembly must always produce the same type")
+        .with_labels(vec![
+            Label::primary(value.file_id, range(value.span))
+                .with_message(format!("this returns `{ty}`, unlike the program's last statement")),
+        ])
+}
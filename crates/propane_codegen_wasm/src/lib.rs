@@ -0,0 +1,190 @@
+use std::fmt;
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_parser::expression::Expression;
+use propane_typeck::Inference;
+
+pub use crate::generator::Generator;
+
+mod generator;
+
+/// Generates the text of a WebAssembly module for a single type checked
+/// program.
+pub fn generate(program: &Expression, inference: &Inference) -> Result<String, Diagnostic<FileId>> {
+    let mut generator = Generator::new();
+    generator.add(program, inference)?;
+
+    Ok(generator.finish())
+}
+
+/// Why a module's text could not be turned into a valid binary. Modules from
+/// [Generator] always can, so this indicates a bug in it.
+#[derive(Debug)]
+pub enum EncodeError {
+    Parse(wat::Error),
+    Invalid(wasmparser::BinaryReaderError),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Parse(error) => write!(f, "invalid module text: {error}"),
+            EncodeError::Invalid(error) => write!(f, "invalid module: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for EncodeError {}
+
+/// Converts the text of a module to the binary format, and validates it.
+pub fn encode(wat: &str) -> Result<Vec<u8>, EncodeError> {
+    let binary = wat::parse_str(wat).map_err(EncodeError::Parse)?;
+    wasmparser::validate(&binary).map_err(EncodeError::Invalid)?;
+
+    Ok(binary)
+}
+
+#[cfg(test)]
+mod tests {
+    use propane_parser::expression::{ExpressionKind, StatementKind};
+    use propane_typeck::Type;
+
+    use super::*;
+
+    /// Compiles `src` to a module and calls its `main` function, returning the
+    /// result as the interpreter would print it, or the error a trap stands
+    /// for.
+    fn run(src: &str) -> Result<String, Diagnostic<FileId>> {
+        let program = propane_parser::parse_str(src);
+        let inference = propane_test_support::check(&program);
+        let wat = generate(&program, &inference).unwrap();
+        let binary = encode(&wat).unwrap_or_else(|error| panic!("{error}\n{wat}"));
+
+        let ExpressionKind::StmtExpr(statements) = &program.kind else {
+            unreachable!("programs are statement lists");
+        };
+        let ty = match statements.last().map(|statement| &statement.kind) {
+            Some(StatementKind::Expression(value) | StatementKind::Return { value }) => inference.types[&value.span].clone(),
+            _ => Type::Unit,
+        };
+
+        let engine = wasmi::Engine::default();
+        let module = wasmi::Module::new(&engine, &binary).unwrap();
+        let mut store = wasmi::Store::new(&engine, ());
+        let instance = wasmi::Linker::<()>::new(&engine)
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let main = instance.get_func(&store, "main").unwrap();
+
+        let mut results = vec![wasmi::Val::I32(0); main.ty(&store).results().len()];
+        if let Err(error) = main.call(&mut store, &[], &mut results) {
+            return match error.as_trap_code() {
+                Some(wasmi::core::TrapCode::IntegerDivisionByZero) => Err(Diagnostic::error().with_message("attempt to divide by zero")),
+                _ => panic!("{src}: {error}"),
+            };
+        }
+
+        let value = match (ty, results.first()) {
+            (Type::Int, Some(wasmi::Val::I32(value))) => value.to_string(),
+            (Type::Bool, Some(wasmi::Val::I32(value))) => (*value != 0).to_string(),
+            (Type::Char, Some(wasmi::Val::I32(value))) => char::from_u32(*value as u32).unwrap().to_string(),
+            (Type::Float, Some(wasmi::Val::F32(value))) => format!("{:?}", f32::from(*value) as f64),
+            (_, None) => "()".to_string(),
+            (ty, value) => panic!("`main` returned {value:?} for a program of type `{ty}`"),
+        };
+
+        Ok(value)
+    }
+
+    #[test]
+    fn module_text() {
+        let program = propane_parser::parse_str("fun add(a: int, b: int): int { a + b } let one = 1; return add(one, 2) > 2;");
+        let inference = propane_test_support::check(&program);
+
+        assert_eq!(generate(&program, &inference).unwrap(), "\
+(module
+  (global $one_2 (mut i32) (i32.const 0))
+  (func $program_0 (result i32)
+    i32.const 1
+    global.set $one_2
+    global.get $one_2
+    i32.const 2
+    call $add_3
+    i32.const 2
+    i32.gt_s
+    return
+  )
+  (func $add_3 (param $a_5 i32) (param $b_6 i32) (result i32)
+    local.get $a_5
+    local.get $b_6
+    i32.add
+  )
+  (func $main (result i32)
+    call $program_0
+  )
+  (export \"add\" (func $add_3))
+  (export \"main\" (func $main))
+)
+");
+    }
+
+    #[test]
+    fn exports_top_level_functions() {
+        let program = propane_parser::parse_str("fun square(x: float): float { x * x } fun id(x) { x } fun helper() { }");
+        let inference = propane_test_support::check(&program);
+        let binary = encode(&generate(&program, &inference).unwrap()).unwrap();
+
+        let engine = wasmi::Engine::default();
+        let module = wasmi::Module::new(&engine, &binary).unwrap();
+        let mut exports: Vec<&str> = module.exports().map(|export| export.name()).collect();
+        exports.sort_unstable();
+
+        // `id` is generic, so it has no single type to export.
+        assert_eq!(exports, ["helper", "main", "square"]);
+    }
+
+    #[test]
+    fn unsupported_features() {
+        let error = |src| {
+            let program = propane_parser::parse_str(src);
+            let inference = propane_test_support::check(&program);
+
+            generate(&program, &inference).unwrap_err()
+        };
+
+        let diagnostic = error(r#"let s = "a";"#);
+        assert_eq!(diagnostic.message, "strings are not supported by the WebAssembly backend");
+        assert_eq!(diagnostic.labels[0].range, 8..11);
+
+        let diagnostic = error("fun f(x: int): int { x } let g = f;");
+        assert_eq!(diagnostic.message, "function values are not supported by the WebAssembly backend");
+
+        let diagnostic = error("fun make(n: int) { fun get() { n } get() } make(1);");
+        assert_eq!(diagnostic.message, "cannot capture `n` in a function compiled to WebAssembly");
+
+        let diagnostic = error("return 3000000000;");
+        assert_eq!(diagnostic.message, "integer literal out of range for WebAssembly");
+    }
+
+    #[test]
+    fn programs_share_top_level_bindings() {
+        let programs = propane_test_support::shared_bindings();
+
+        let mut generator = Generator::new();
+        for (program, inference) in &programs {
+            generator.add(program, inference).unwrap();
+        }
+
+        let wat = generator.finish();
+        assert!(wat.contains("  (func $main (result i32)\n    call $program_0\n    call $program_1\n  )\n"));
+        encode(&wat).unwrap();
+    }
+
+    #[test]
+    fn agrees_with_the_interpreter() {
+        propane_test_support::agrees_with_the_interpreter(propane_test_support::PROGRAMS, run);
+    }
+}
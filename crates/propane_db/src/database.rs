@@ -0,0 +1,314 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use codespan::{FileId, Files};
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_lexer::LexerToken;
+use propane_parser::Parse;
+use propane_resolve::{Resolution, Resolver};
+use propane_typeck::{Inference, TypeChecker};
+
+use crate::query::{Memo, Query, Revision, Table};
+
+/// The resolution of a file, and the resolver it left behind for the file
+/// after it to continue with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolved {
+    pub resolution: Resolution,
+    /// The resolver after resolving the file, which knows the top-level
+    /// bindings of it and the files before it.
+    pub resolver: Resolver,
+}
+
+/// The types inferred for a file, and the type checker it left behind for
+/// the file after it to continue with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checked {
+    pub inference: Inference,
+    /// The type checker after checking the file, which knows the top-level
+    /// bindings of it and the files before it.
+    pub checker: TypeChecker,
+}
+
+/// The inputs of a file, and the revisions they last changed in.
+#[derive(Debug)]
+struct Input {
+    previous: Option<FileId>,
+    text_changed_at: Revision,
+    previous_changed_at: Revision,
+}
+
+/// The source files of a program, and what the compiler worked out about
+/// them, computed on demand.
+///
+/// The source text of each file, and the file before it whose top-level
+/// bindings it sees, are inputs. Everything else is a query: tokenizing,
+/// parsing, resolving and type checking a file. A query's value is kept,
+/// along with the queries it read, until one of its inputs changes. It is
+/// then computed again the next time it is asked for, unless none of the
+/// queries it read turn out to have changed. A value that is computed again
+/// but comes out the same doesn't count as a change, so e.g. adding a
+/// comment to the end of a file doesn't check the files after it again.
+///
+/// Resolving and type checking a file continue from the resolver and type
+/// checker left behind by the file before it, so that files are checked in
+/// order as one program, like the CLI does with the files it is given and
+/// the REPL with its inputs. Changing a file invalidates those queries for
+/// the files after it, but not their tokens or parses.
+#[derive(Debug)]
+pub struct Database {
+    files: Files<String>,
+    revision: Revision,
+    inputs: HashMap<FileId, Input>,
+    tokens: Table<Vec<LexerToken>>,
+    parses: Table<Parse>,
+    resolutions: Table<Resolved>,
+    inferences: Table<Checked>,
+    /// The queries read so far by each query being computed, innermost
+    /// last.
+    active: RefCell<Vec<Vec<Query>>>,
+}
+
+impl Default for Database {
+    fn default() -> Database {
+        Database::new()
+    }
+}
+
+impl Database {
+    pub fn new() -> Database {
+        Database {
+            files: Files::new(),
+            revision: Revision::default(),
+            inputs: HashMap::new(),
+            tokens: Table::default(),
+            parses: Table::default(),
+            resolutions: Table::default(),
+            inferences: Table::default(),
+            active: RefCell::new(vec![]),
+        }
+    }
+
+    /// The files, for reporting diagnostics about them.
+    pub fn files(&self) -> &Files<String> {
+        &self.files
+    }
+
+    /// Adds a file, which doesn't see the bindings of any other file until
+    /// [Database::set_previous_file] is called.
+    pub fn add_file(&mut self, name: impl Into<String>, text: impl Into<String>) -> FileId {
+        let file_id = self.files.add(name.into(), text.into());
+        let input = Input { previous: None, text_changed_at: self.revision, previous_changed_at: self.revision };
+        self.inputs.insert(file_id, input);
+
+        file_id
+    }
+
+    /// Removes a file and what is known about it. Files that came after it
+    /// now come after the file before it.
+    ///
+    /// The file's name stays in [Database::files], but its text is dropped.
+    pub fn remove_file(&mut self, file_id: FileId) {
+        let previous = self.input(file_id).previous;
+        let after: Vec<FileId> = self.inputs.iter().filter(|(_, input)| input.previous == Some(file_id)).map(|(&file, _)| file).collect();
+        for file in after {
+            self.set_previous_file(file, previous);
+        }
+
+        self.inputs.remove(&file_id);
+        self.files.update(file_id, String::new());
+        self.tokens.borrow_mut().remove(&file_id);
+        self.parses.borrow_mut().remove(&file_id);
+        self.resolutions.borrow_mut().remove(&file_id);
+        self.inferences.borrow_mut().remove(&file_id);
+    }
+
+    pub fn set_source_text(&mut self, file_id: FileId, text: impl Into<String>) {
+        let text = text.into();
+        if *self.files.source(file_id) == text {
+            return;
+        }
+
+        self.revision = self.revision.next();
+        self.files.update(file_id, text);
+        self.input_mut(file_id).text_changed_at = self.revision;
+    }
+
+    /// Makes `file_id` see the top-level bindings of `previous` and the
+    /// files before it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `previous` is `file_id` or a file after it, as a file can't
+    /// come after itself.
+    pub fn set_previous_file(&mut self, file_id: FileId, previous: Option<FileId>) {
+        if self.input(file_id).previous == previous {
+            return;
+        }
+
+        let mut before = previous;
+        while let Some(file) = before {
+            assert_ne!(file, file_id, "a file can't come after itself");
+            before = self.input(file).previous;
+        }
+
+        let revision = self.revision.next();
+        self.revision = revision;
+
+        let input = self.input_mut(file_id);
+        input.previous = previous;
+        input.previous_changed_at = revision;
+    }
+
+    pub fn source_text(&self, file_id: FileId) -> &str {
+        self.read(Query::SourceText(file_id));
+
+        self.files.source(file_id)
+    }
+
+    pub fn previous_file(&self, file_id: FileId) -> Option<FileId> {
+        self.read(Query::PreviousFile(file_id));
+
+        self.input(file_id).previous
+    }
+
+    pub fn tokens(&self, file_id: FileId) -> Arc<Vec<LexerToken>> {
+        self.fetch(Query::Tokens(file_id), &self.tokens)
+    }
+
+    pub fn parse(&self, file_id: FileId) -> Arc<Parse> {
+        self.fetch(Query::Parse(file_id), &self.parses)
+    }
+
+    pub fn resolve(&self, file_id: FileId) -> Arc<Resolved> {
+        self.fetch(Query::Resolve(file_id), &self.resolutions)
+    }
+
+    pub fn check(&self, file_id: FileId) -> Arc<Checked> {
+        self.fetch(Query::Check(file_id), &self.inferences)
+    }
+
+    /// The errors and warnings from parsing, resolving and type checking a
+    /// file, in that order.
+    pub fn diagnostics(&self, file_id: FileId) -> Vec<Diagnostic<FileId>> {
+        let mut diagnostics = self.parse(file_id).errors.clone();
+        diagnostics.extend_from_slice(&self.resolve(file_id).resolution.diagnostics);
+        diagnostics.extend_from_slice(&self.check(file_id).inference.diagnostics);
+
+        diagnostics
+    }
+
+    /// The warnings about top-level bindings of `file_id` and the files
+    /// before it that none of them use, for the last file of a program.
+    pub fn unused_globals(&self, file_id: FileId) -> Vec<Diagnostic<FileId>> {
+        self.resolve(file_id).resolver.clone().finish()
+    }
+
+    fn execute(&self, query: Query) {
+        match query {
+            Query::SourceText(_) | Query::PreviousFile(_) => unreachable!("inputs aren't computed"),
+            Query::Tokens(file_id) => self.memoize(query, &self.tokens, || propane_lexer::tokenize(self.source_text(file_id))),
+            Query::Parse(file_id) => self.memoize(query, &self.parses, || propane_parser::parse(file_id, self.source_text(file_id), &self.tokens(file_id))),
+            Query::Resolve(file_id) => self.memoize(query, &self.resolutions, || {
+                let parse = self.parse(file_id);
+                let mut resolver = match self.previous_file(file_id) {
+                    Some(previous) => self.resolve(previous).resolver.clone(),
+                    None => Resolver::new(),
+                };
+                let resolution = resolver.resolve(&parse.program);
+
+                Resolved { resolution, resolver }
+            }),
+            Query::Check(file_id) => self.memoize(query, &self.inferences, || {
+                let parse = self.parse(file_id);
+                let mut checker = match self.previous_file(file_id) {
+                    Some(previous) => self.check(previous).checker.clone(),
+                    None => TypeChecker::new(),
+                };
+                let inference = checker.check(&parse.program);
+
+                Checked { inference, checker }
+            }),
+        }
+    }
+
+    /// The up to date value of a derived query, recording that the query
+    /// being computed read it.
+    fn fetch<T>(&self, query: Query, table: &Table<T>) -> Arc<T> {
+        self.read(query);
+        self.changed_at(query);
+
+        table.borrow()[&query.file_id()].value.clone()
+    }
+
+    /// Brings a query up to date, returning the revision its value last
+    /// changed in.
+    fn changed_at(&self, query: Query) -> Revision {
+        match query {
+            Query::SourceText(file_id) => return self.input(file_id).text_changed_at,
+            Query::PreviousFile(file_id) => return self.input(file_id).previous_changed_at,
+            Query::Tokens(_) => self.verify(query, &self.tokens),
+            Query::Parse(_) => self.verify(query, &self.parses),
+            Query::Resolve(_) => self.verify(query, &self.resolutions),
+            Query::Check(_) => self.verify(query, &self.inferences),
+        }
+        .unwrap_or_else(|| {
+            self.execute(query);
+            self.revision
+        })
+    }
+
+    /// Checks whether a derived query's value is still up to date, which it
+    /// is if none of the queries it read have changed since it was last
+    /// verified, returning the revision it last changed in if so.
+    fn verify<T>(&self, query: Query, table: &Table<T>) -> Option<Revision> {
+        let (verified_at, dependencies) = table
+            .borrow()
+            .get(&query.file_id())
+            .map(|memo| (memo.verified_at, memo.dependencies.clone()))?;
+
+        if verified_at != self.revision && dependencies.into_iter().any(|dependency| self.changed_at(dependency) > verified_at) {
+            return None;
+        }
+
+        let mut table = table.borrow_mut();
+        let memo = table.get_mut(&query.file_id()).expect("memo removed while verifying it");
+        memo.verified_at = self.revision;
+
+        Some(memo.changed_at)
+    }
+
+    /// Computes a derived query with `compute`, recording the queries it
+    /// reads, and keeps its value. If the value is the same as before, it
+    /// keeps the revision it last changed in, so the queries that read it
+    /// don't need to be computed again.
+    fn memoize<T: PartialEq>(&self, query: Query, table: &Table<T>, compute: impl FnOnce() -> T) {
+        self.active.borrow_mut().push(vec![]);
+        let value = compute();
+        let dependencies = self.active.borrow_mut().pop().expect("query stack is empty");
+
+        let mut table = table.borrow_mut();
+        let changed_at = match table.get(&query.file_id()) {
+            Some(memo) if *memo.value == value => memo.changed_at,
+            _ => self.revision,
+        };
+        let memo = Memo { value: Arc::new(value), verified_at: self.revision, changed_at, dependencies };
+        table.insert(query.file_id(), memo);
+    }
+
+    /// Records that the query being computed, if any, read `query`.
+    fn read(&self, query: Query) {
+        if let Some(dependencies) = self.active.borrow_mut().last_mut() {
+            dependencies.push(query);
+        }
+    }
+
+    fn input(&self, file_id: FileId) -> &Input {
+        self.inputs.get(&file_id).expect("file is not in the database")
+    }
+
+    fn input_mut(&mut self, file_id: FileId) -> &mut Input {
+        self.inputs.get_mut(&file_id).expect("file is not in the database")
+    }
+}
@@ -0,0 +1,147 @@
+pub use crate::database::{Checked, Database, Resolved};
+
+mod database;
+mod query;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use codespan::FileId;
+
+    use super::*;
+
+    /// A database with the files `sources` in order, as one program.
+    fn program(sources: &[&str]) -> (Database, Vec<FileId>) {
+        let mut db = Database::new();
+        let mut file_ids: Vec<FileId> = vec![];
+
+        for (i, source) in sources.iter().enumerate() {
+            let file_id = db.add_file(format!("file{i}"), *source);
+            db.set_previous_file(file_id, file_ids.last().copied());
+            file_ids.push(file_id);
+        }
+
+        (db, file_ids)
+    }
+
+    fn messages(db: &Database, file_id: FileId) -> Vec<String> {
+        db.diagnostics(file_id).into_iter().map(|diagnostic| diagnostic.message).collect()
+    }
+
+    #[test]
+    fn queries_are_memoized() {
+        let (db, file_ids) = program(&["let a = 1;"]);
+
+        assert!(Arc::ptr_eq(&db.tokens(file_ids[0]), &db.tokens(file_ids[0])));
+        assert!(Arc::ptr_eq(&db.parse(file_ids[0]), &db.parse(file_ids[0])));
+        assert!(Arc::ptr_eq(&db.resolve(file_ids[0]), &db.resolve(file_ids[0])));
+        assert!(Arc::ptr_eq(&db.check(file_ids[0]), &db.check(file_ids[0])));
+    }
+
+    #[test]
+    fn files_see_the_files_before_them() {
+        let (db, file_ids) = program(&["fun double(x) { x * 2 }", "let a = double(2);", "a + 1;"]);
+
+        for &file_id in &file_ids {
+            assert_eq!(messages(&db, file_id), Vec::<String>::new());
+        }
+        assert_eq!(db.check(file_ids[2]).checker.global("a").map(|scheme| scheme.to_string()), Some("int".to_string()));
+
+        let mut db = Database::new();
+        let file_id = db.add_file("alone", "a + 1;");
+        assert_eq!(messages(&db, file_id), vec!["cannot find value `a` in this scope".to_string()]);
+    }
+
+    #[test]
+    fn changing_a_file_invalidates_what_depends_on_it() {
+        let (mut db, file_ids) = program(&["let a = 1;", "let b = a + 1;"]);
+        let [first, second] = [file_ids[0], file_ids[1]];
+
+        let parses = [db.parse(first), db.parse(second)];
+        let checked = [db.check(first), db.check(second)];
+        db.set_source_text(second, "let b = a + 2;");
+
+        // The first file doesn't depend on the second.
+        assert!(Arc::ptr_eq(&parses[0], &db.parse(first)));
+        assert!(Arc::ptr_eq(&checked[0], &db.check(first)));
+        assert!(!Arc::ptr_eq(&parses[1], &db.parse(second)));
+        assert!(!Arc::ptr_eq(&checked[1], &db.check(second)));
+
+        let parses = [db.parse(first), db.parse(second)];
+        let resolved = db.resolve(second);
+        db.set_source_text(first, "let a = \"one\";");
+
+        // The second file is checked again with the first's new types, but
+        // isn't parsed again.
+        assert!(!Arc::ptr_eq(&parses[0], &db.parse(first)));
+        assert!(Arc::ptr_eq(&parses[1], &db.parse(second)));
+        assert!(!Arc::ptr_eq(&resolved, &db.resolve(second)));
+        assert_eq!(messages(&db, second), vec!["mismatched types".to_string()]);
+    }
+
+    #[test]
+    fn unchanged_values_dont_invalidate_what_depends_on_them() {
+        let (mut db, file_ids) = program(&["let a = 1;", "let b = a + 1;"]);
+        let [first, second] = [file_ids[0], file_ids[1]];
+
+        let parse = db.parse(first);
+        let checked = [db.check(first), db.check(second)];
+        db.set_source_text(first, "let a = 1; // one");
+
+        // The first file is checked again, with the same outcome, so the
+        // second isn't.
+        assert!(!Arc::ptr_eq(&parse, &db.parse(first)));
+        assert!(!Arc::ptr_eq(&checked[0], &db.check(first)));
+        assert_eq!(checked[0], db.check(first));
+        assert!(Arc::ptr_eq(&checked[1], &db.check(second)));
+    }
+
+    #[test]
+    fn setting_an_input_to_its_value_changes_nothing() {
+        let (mut db, file_ids) = program(&["let a = 1;", "a;"]);
+
+        let checked = db.check(file_ids[1]);
+        db.set_source_text(file_ids[0], "let a = 1;");
+        db.set_previous_file(file_ids[1], Some(file_ids[0]));
+
+        assert!(Arc::ptr_eq(&checked, &db.check(file_ids[1])));
+    }
+
+    #[test]
+    fn changing_the_previous_file() {
+        let (mut db, file_ids) = program(&["let a = 1;", "a;"]);
+
+        assert_eq!(messages(&db, file_ids[1]), Vec::<String>::new());
+
+        db.set_previous_file(file_ids[1], None);
+        assert_eq!(messages(&db, file_ids[1]), vec!["cannot find value `a` in this scope".to_string()]);
+    }
+
+    #[test]
+    fn removing_a_file() {
+        let (mut db, file_ids) = program(&["let a = 1;", "let b = 2;", "a + b;"]);
+
+        assert_eq!(messages(&db, file_ids[2]), Vec::<String>::new());
+        db.remove_file(file_ids[1]);
+
+        assert_eq!(db.previous_file(file_ids[2]), Some(file_ids[0]));
+        assert_eq!(messages(&db, file_ids[2]), vec!["cannot find value `b` in this scope".to_string()]);
+    }
+
+    #[test]
+    fn unused_globals_of_a_program() {
+        let (db, file_ids) = program(&["let a = 1; let b = 2;", "b;"]);
+
+        let unused: Vec<_> = db.unused_globals(file_ids[1]).into_iter().map(|diagnostic| diagnostic.message).collect();
+        assert_eq!(unused, vec!["unused variable `a`".to_string()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "a file can't come after itself")]
+    fn files_cant_come_after_themselves() {
+        let (mut db, file_ids) = program(&["1;", "2;"]);
+
+        db.set_previous_file(file_ids[0], Some(file_ids[1]));
+    }
+}
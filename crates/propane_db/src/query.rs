@@ -0,0 +1,55 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use codespan::FileId;
+
+/// A version of the inputs. Each change to an input starts a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Revision(u64);
+
+impl Revision {
+    pub fn next(self) -> Revision {
+        Revision(self.0 + 1)
+    }
+}
+
+/// A query with its key, for recording which queries another one read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Query {
+    SourceText(FileId),
+    PreviousFile(FileId),
+    Tokens(FileId),
+    Parse(FileId),
+    Resolve(FileId),
+    Check(FileId),
+}
+
+impl Query {
+    pub fn file_id(self) -> FileId {
+        match self {
+            Query::SourceText(file_id)
+            | Query::PreviousFile(file_id)
+            | Query::Tokens(file_id)
+            | Query::Parse(file_id)
+            | Query::Resolve(file_id)
+            | Query::Check(file_id) => file_id,
+        }
+    }
+}
+
+/// The value of a derived query, and what it was computed from.
+#[derive(Debug)]
+pub struct Memo<T> {
+    pub value: Arc<T>,
+    /// The last revision the value was known to be up to date in.
+    pub verified_at: Revision,
+    /// The revision the value last changed in.
+    pub changed_at: Revision,
+    /// The queries read while computing the value, in the order they were
+    /// read.
+    pub dependencies: Vec<Query>,
+}
+
+/// The memos of one derived query, by the file they are for.
+pub type Table<T> = RefCell<HashMap<FileId, Memo<T>>>;
@@ -0,0 +1,118 @@
+/// Code to lay out: text, and the places it can be broken into lines.
+#[derive(Debug, Clone)]
+pub enum Doc {
+    Text(String),
+    /// A space, or a line break if the enclosing group is broken.
+    Line,
+    /// Nothing, or a line break if the enclosing group is broken.
+    SoftLine,
+    /// Always a line break, which breaks the enclosing groups.
+    HardLine,
+    /// Breaks the enclosing groups without printing anything, e.g. after a
+    /// line comment that the next line break has to end.
+    BreakParent,
+    /// Text printed only if the enclosing group is broken.
+    IfBreak(&'static str),
+    /// Indents the lines started in it.
+    Indent(Vec<Doc>),
+    /// Laid out on one line if it fits, and with each of its lines broken
+    /// otherwise. Groups inside it are laid out separately.
+    Group(Vec<Doc>),
+    Concat(Vec<Doc>),
+}
+
+pub const INDENT: usize = 4;
+
+pub fn text(text: impl Into<String>) -> Doc {
+    Doc::Text(text.into())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Flat,
+    Break,
+}
+
+/// Lays `doc` out, breaking groups that don't fit in `width` columns.
+pub fn print(doc: &Doc, width: usize) -> String {
+    let mut output = String::new();
+    let mut column = 0;
+    let mut stack = vec![(0, Mode::Break, doc)];
+
+    while let Some((indent, mode, doc)) = stack.pop() {
+        match doc {
+            Doc::Text(text) => {
+                output.push_str(text);
+                column = match text.rfind('\n') {
+                    Some(newline) => text[newline + 1..].chars().count(),
+                    None => column + text.chars().count(),
+                };
+            }
+            Doc::Line if mode == Mode::Flat => {
+                output.push(' ');
+                column += 1;
+            }
+            Doc::SoftLine if mode == Mode::Flat => {}
+            Doc::Line | Doc::SoftLine | Doc::HardLine => {
+                // Leave no trailing whitespace, such as on blank lines.
+                output.truncate(output.trim_end_matches(' ').len());
+                output.push('\n');
+                output.extend(std::iter::repeat_n(' ', indent));
+                column = indent;
+            }
+            Doc::BreakParent => {}
+            Doc::IfBreak(text) => {
+                if mode == Mode::Break {
+                    output.push_str(text);
+                    column += text.chars().count();
+                }
+            }
+            Doc::Indent(docs) => stack.extend(docs.iter().rev().map(|doc| (indent + INDENT, mode, doc))),
+            Doc::Concat(docs) => stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc))),
+            Doc::Group(docs) => {
+                let remaining = width as isize - column as isize;
+                let mode = if mode == Mode::Flat || fits(remaining, docs, &stack) { Mode::Flat } else { Mode::Break };
+
+                stack.extend(docs.iter().rev().map(|doc| (indent, mode, doc)));
+            }
+        }
+    }
+
+    output
+}
+
+/// Whether a group fits on the rest of the line when laid out flat, along
+/// with whatever follows it up to the next line break.
+fn fits(mut remaining: isize, group: &[Doc], rest: &[(usize, Mode, &Doc)]) -> bool {
+    let mut stack: Vec<(Mode, &Doc)> = group.iter().rev().map(|doc| (Mode::Flat, doc)).collect();
+    let mut rest = rest.iter().rev();
+
+    loop {
+        let (mode, doc) = match stack.pop() {
+            Some(next) => next,
+            None => match rest.next() {
+                Some(&(_, mode, doc)) => (mode, doc),
+                None => return true,
+            },
+        };
+
+        match doc {
+            // Text spanning lines, like a block comment, can't be flat.
+            Doc::Text(text) if text.contains('\n') => return mode == Mode::Break,
+            Doc::Text(text) => remaining -= text.chars().count() as isize,
+            Doc::Line | Doc::SoftLine if mode == Mode::Break => return true,
+            Doc::Line => remaining -= 1,
+            Doc::SoftLine => {}
+            Doc::HardLine => return mode == Mode::Break,
+            Doc::BreakParent if mode == Mode::Flat => return false,
+            Doc::BreakParent => {}
+            Doc::IfBreak(text) if mode == Mode::Break => remaining -= text.chars().count() as isize,
+            Doc::IfBreak(_) => {}
+            Doc::Indent(docs) | Doc::Concat(docs) | Doc::Group(docs) => stack.extend(docs.iter().rev().map(|doc| (mode, doc))),
+        }
+
+        if remaining < 0 {
+            return false;
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use codespan::Span;
+use propane_lexer::{LexerToken, TokenKind};
+use propane_parser::expression::{Expression, ExpressionKind, Ident, Operator, Parameter, Statement, StatementKind};
+
+use crate::doc::{text, Doc};
+
+/// Turns a program into a [Doc], putting the comments the parser skipped
+/// back before the nodes that follow them.
+pub struct Formatter<'a> {
+    src: &'a str,
+    comments: Vec<Span>,
+    /// The index in `comments` of the first one not printed yet.
+    next: usize,
+}
+
+impl<'a> Formatter<'a> {
+    pub fn new(src: &'a str, tokens: &[LexerToken]) -> Formatter<'a> {
+        let comments = tokens
+            .iter()
+            .filter(|token| matches!(token.kind, TokenKind::LineComment | TokenKind::BlockComment { .. }))
+            .map(|token| token.span)
+            .collect();
+
+        Formatter { src, comments, next: 0 }
+    }
+
+    /// The program's statements, one per line, ending in a newline unless
+    /// the file is empty.
+    pub fn program(&mut self, program: &Expression) -> Doc {
+        let ExpressionKind::StmtExpr(statements) = &program.kind else {
+            return self.expression(program);
+        };
+
+        let doc = self.statements(statements, None, self.src.len(), false);
+        if self.src.trim().is_empty() {
+            return doc;
+        }
+
+        Doc::Concat(vec![doc, Doc::HardLine])
+    }
+
+    /// Lays out statements and the value of the block they are in one per
+    /// line, along with the comments up to `end`. Blank lines between them
+    /// are kept, but runs of them are collapsed into one.
+    fn statements(&mut self, statements: &[Statement], value: Option<&Expression>, end: usize, in_block: bool) -> Doc {
+        let mut docs = vec![];
+        let mut previous_end = None;
+
+        for (i, statement) in statements.iter().enumerate() {
+            let start = statement.span.start().to_usize();
+            self.comments_between(&mut docs, &mut previous_end, start);
+            self.separate(&mut docs, previous_end, start);
+
+            // A block-like statement at the end of a block needs its `;`,
+            // or it would become the block's value.
+            let last = in_block && value.is_none() && i + 1 == statements.len();
+            docs.push(self.statement(statement, last));
+            previous_end = Some(statement.span.end().to_usize());
+        }
+
+        if let Some(value) = value {
+            let start = value.span.start().to_usize();
+            self.comments_between(&mut docs, &mut previous_end, start);
+            self.separate(&mut docs, previous_end, start);
+
+            docs.push(self.expression(value));
+            previous_end = Some(value.span.end().to_usize());
+        }
+
+        self.comments_between(&mut docs, &mut previous_end, end);
+
+        Doc::Concat(docs)
+    }
+
+    /// Adds the comments before `before` in a list of statements, keeping
+    /// ones on the same line as the end of the previous item there.
+    fn comments_between(&mut self, docs: &mut Vec<Doc>, previous_end: &mut Option<usize>, before: usize) {
+        while let Some(comment) = self.next_comment_before(before) {
+            let start = comment.start().to_usize();
+
+            match *previous_end {
+                Some(end) if end <= start && !self.src[end..start].contains('\n') => docs.push(text(" ")),
+                _ => self.separate(docs, *previous_end, start),
+            }
+
+            docs.push(self.comment(comment));
+            *previous_end = Some(comment.end().to_usize());
+        }
+    }
+
+    /// Starts a new line for an item at `start`, after a blank line if there
+    /// was one before it.
+    fn separate(&self, docs: &mut Vec<Doc>, previous_end: Option<usize>, start: usize) {
+        let Some(end) = previous_end else {
+            return;
+        };
+
+        docs.push(Doc::HardLine);
+        if self.src.get(end..start).unwrap_or_default().matches('\n').count() > 1 {
+            docs.push(Doc::HardLine);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement, last_in_block: bool) -> Doc {
+        let end = statement.span.end().to_usize();
+
+        match &statement.kind {
+            StatementKind::Let { name, value } => {
+                Doc::Concat(vec![text("let "), self.ident(name), text(" = "), self.expression(value), self.semicolon(end)])
+            }
+            StatementKind::Return { value } => Doc::Concat(vec![text("return "), self.expression(value), self.semicolon(end)]),
+            StatementKind::While { condition, body } => {
+                Doc::Concat(vec![text("while "), self.expression(condition), text(" "), self.expression(body)])
+            }
+            StatementKind::For { variable, start, end, body } => Doc::Concat(vec![
+                text("for "),
+                self.ident(variable),
+                text(" in "),
+                self.expression(start),
+                text(".."),
+                self.expression(end),
+                text(" "),
+                self.expression(body),
+            ]),
+            StatementKind::Function { name, parameters, return_type, body } => {
+                let mut docs = vec![text("fun "), self.ident(name), self.parameters(parameters, body.span.start().to_usize())];
+                if let Some(return_type) = return_type {
+                    docs.extend([text(": "), self.ident(return_type)]);
+                }
+                docs.extend([text(" "), self.expression(body)]);
+
+                Doc::Concat(docs)
+            }
+            StatementKind::Expression(value) if is_block_like(value) && !last_in_block => self.expression(value),
+            StatementKind::Expression(value) => Doc::Concat(vec![self.expression(value), self.semicolon(end)]),
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Doc {
+        let leading = self.leading(expression.span.start().to_usize());
+        let end = expression.span.end().to_usize();
+
+        let doc = match &expression.kind {
+            ExpressionKind::Literal(_) | ExpressionKind::Error => text(&self.src[span_range(expression.span)]),
+            ExpressionKind::Variable(ident) => text(&ident.name),
+            ExpressionKind::Grouping(inner) => Doc::Concat(vec![
+                text("("),
+                Doc::Group(vec![Doc::Indent(vec![Doc::SoftLine, self.expression(inner)]), self.closing(end - 1), Doc::SoftLine]),
+                text(")"),
+            ]),
+            ExpressionKind::Unary(operator, operand) => Doc::Concat(vec![text(operator.as_str()), self.expression(operand)]),
+            ExpressionKind::Binary { .. } => self.binary(expression),
+            ExpressionKind::Call { callee, args } => {
+                let callee = self.expression(callee);
+                let args = args.iter().map(|arg| self.expression(arg)).collect();
+
+                Doc::Concat(vec![callee, self.list(args, end - 1)])
+            }
+            ExpressionKind::Block { statements, value } => self.block(statements, value.as_deref(), end - 1),
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                let mut docs = vec![text("if "), self.expression(condition), text(" "), self.expression(then_branch)];
+                if let Some(else_branch) = else_branch {
+                    docs.extend([text(" else "), self.expression(else_branch)]);
+                }
+
+                Doc::Concat(docs)
+            }
+            ExpressionKind::StmtExpr(statements) => self.statements(statements, None, end, false),
+        };
+
+        Doc::Concat(vec![leading, doc])
+    }
+
+    /// A chain of binary operators of the same precedence, e.g. `a + b - c`,
+    /// broken before every operator if it doesn't fit on one line.
+    fn binary(&mut self, expression: &Expression) -> Doc {
+        let mut operands: Vec<(Operator, &Expression)> = vec![];
+        let mut first = expression;
+        while let ExpressionKind::Binary { left, operator, right } = &first.kind {
+            if !operands.is_empty() && !same_precedence(*operator, operands[0].0) {
+                break;
+            }
+
+            operands.push((*operator, right));
+            first = left;
+        }
+
+        let first = self.expression(first);
+        let rest = operands
+            .into_iter()
+            .rev()
+            .map(|(operator, right)| Doc::Concat(vec![Doc::Line, text(operator.as_str()), text(" "), self.expression(right)]))
+            .collect();
+
+        Doc::Group(vec![first, Doc::Indent(rest)])
+    }
+
+    /// A block, on one line if it is short and only has a value, e.g.
+    /// `{ a + 1 }`, and with every statement on its own line otherwise.
+    fn block(&mut self, statements: &[Statement], value: Option<&Expression>, end: usize) -> Doc {
+        if statements.is_empty() && value.is_none() && self.comments.get(self.next).is_none_or(|comment| comment.start().to_usize() >= end) {
+            return text("{}");
+        }
+
+        let line = if statements.is_empty() { Doc::Line } else { Doc::HardLine };
+        let body = self.statements(statements, value, end, true);
+
+        Doc::Group(vec![text("{"), Doc::Indent(vec![line.clone(), body]), line, text("}")])
+    }
+
+    /// A parenthesized, comma separated list, with each item on its own line
+    /// and a trailing comma if it doesn't fit on one.
+    fn list(&mut self, items: Vec<Doc>, end: usize) -> Doc {
+        let closing = self.closing(end);
+        if items.is_empty() {
+            return Doc::Group(vec![text("("), closing, Doc::SoftLine, text(")")]);
+        }
+
+        let mut body = vec![Doc::SoftLine];
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                body.extend([text(","), Doc::Line]);
+            }
+            body.push(item);
+        }
+
+        Doc::Group(vec![text("("), Doc::Indent(body), Doc::IfBreak(","), closing, Doc::SoftLine, text(")")])
+    }
+
+    /// The parameters of a function, whose body starts at `end`.
+    fn parameters(&mut self, parameters: &[Parameter], end: usize) -> Doc {
+        let items = parameters
+            .iter()
+            .map(|parameter| match &parameter.ty {
+                Some(ty) => Doc::Concat(vec![self.ident(&parameter.name), text(": "), self.ident(ty)]),
+                None => self.ident(&parameter.name),
+            })
+            .collect();
+
+        // Comments after the last parameter go before the `)`, the last
+        // token before the body that could be preceded by one.
+        let close = self.src[..end].rfind(')').unwrap_or(end);
+
+        self.list(items, close)
+    }
+
+    fn ident(&mut self, ident: &Ident) -> Doc {
+        Doc::Concat(vec![self.leading(ident.span.start().to_usize()), text(&ident.name)])
+    }
+
+    /// A statement's `;` at `end`, after any comments before it.
+    fn semicolon(&mut self, end: usize) -> Doc {
+        let mut docs = vec![];
+        while let Some(comment) = self.next_comment_before(end) {
+            docs.extend([text(" "), self.comment(comment)]);
+            if self.is_line_comment(comment) {
+                docs.push(Doc::HardLine);
+            }
+        }
+        docs.push(text(";"));
+
+        Doc::Concat(docs)
+    }
+
+    /// The comments before a node starting at `start`, each followed by a
+    /// line break if one followed it in the source, and a space otherwise.
+    fn leading(&mut self, start: usize) -> Doc {
+        let mut docs = vec![];
+        while let Some(comment) = self.next_comment_before(start) {
+            docs.push(self.comment(comment));
+
+            let after = &self.src[comment.end().to_usize()..start];
+            if self.is_line_comment(comment) || after.contains('\n') {
+                docs.push(Doc::HardLine);
+            } else {
+                docs.push(text(" "));
+            }
+        }
+
+        Doc::Concat(docs)
+    }
+
+    /// The comments before a closing `)` or `}` at `end`, which has to be
+    /// preceded by a line that the group containing it breaks after a line
+    /// comment.
+    fn closing(&mut self, end: usize) -> Doc {
+        let mut docs = vec![];
+        while let Some(comment) = self.next_comment_before(end) {
+            docs.extend([text(" "), self.comment(comment)]);
+        }
+
+        Doc::Concat(docs)
+    }
+
+    fn comment(&self, comment: Span) -> Doc {
+        let comment_text = text(&self.src[span_range(comment)]);
+
+        if self.is_line_comment(comment) {
+            Doc::Concat(vec![comment_text, Doc::BreakParent])
+        } else {
+            comment_text
+        }
+    }
+
+    fn is_line_comment(&self, comment: Span) -> bool {
+        self.src[span_range(comment)].starts_with("//")
+    }
+
+    /// Takes the next comment if it starts before `offset`.
+    fn next_comment_before(&mut self, offset: usize) -> Option<Span> {
+        let comment = *self.comments.get(self.next)?;
+        if comment.start().to_usize() >= offset {
+            return None;
+        }
+
+        self.next += 1;
+        Some(comment)
+    }
+}
+
+/// Whether an expression statement is an `if` or block, which don't need a
+/// `;` after them.
+fn is_block_like(expression: &Expression) -> bool {
+    matches!(expression.kind, ExpressionKind::If { .. } | ExpressionKind::Block { .. })
+}
+
+fn same_precedence(a: Operator, b: Operator) -> bool {
+    a.binary_precedence() == b.binary_precedence()
+}
+
+fn span_range(span: Span) -> std::ops::Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
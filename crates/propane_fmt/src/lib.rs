@@ -0,0 +1,242 @@
+//! A formatter for Propane source, keeping its comments.
+//!
+//! Code is laid out from its syntax tree, with one statement per line,
+//! blocks indented and a space on either side of binary operators. Groups
+//! that don't fit in the configured width, like long chains of operators or
+//! argument lists, are broken over several lines. Comments are taken from
+//! the tokens and put back before the code that followed them, or at the end
+//! of the line they ended.
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+
+mod doc;
+mod formatter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// The number of columns to fit lines in where possible.
+    pub width: usize,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config { width: 100 }
+    }
+}
+
+/// Formats a file, or returns its parse errors if it has any, as there is no
+/// syntax tree to format code that doesn't parse.
+///
+/// Formatting is idempotent: formatting the result again leaves it as is.
+pub fn format(file_id: FileId, src: &str, config: &Config) -> Result<String, Vec<Diagnostic<FileId>>> {
+    let tokens = propane_lexer::tokenize(src);
+    let program = propane_parser::parse(file_id, src, &tokens).into_result()?;
+
+    let doc = formatter::Formatter::new(src, &tokens).program(&program);
+
+    Ok(doc::print(&doc, config.width))
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Files;
+    use propane_lexer::TokenKind;
+
+    use super::*;
+
+    fn format_with(src: &str, width: usize) -> String {
+        let mut files = Files::new();
+        let file_id = files.add("test.pp", src.to_string());
+
+        let formatted = format(file_id, src, &Config { width }).unwrap_or_else(|errors| panic!("{src:?} failed to parse: {errors:?}"));
+
+        let again = format(file_id, &formatted, &Config { width }).unwrap_or_else(|errors| panic!("{formatted:?} failed to parse: {errors:?}"));
+        assert_eq!(again, formatted, "formatting is not idempotent");
+        assert_eq!(significant_tokens(&formatted), significant_tokens(src), "formatting changed the program");
+        assert_eq!(comments(&formatted), comments(src), "formatting changed the comments");
+
+        formatted
+    }
+
+    fn fmt(src: &str) -> String {
+        format_with(src, Config::default().width)
+    }
+
+    /// The tokens of the program, without the optional `;`s after blocks and
+    /// trailing commas that the formatter may add or remove.
+    fn significant_tokens(src: &str) -> Vec<(TokenKind, &str)> {
+        let tokens: Vec<_> = propane_lexer::tokenize(src)
+            .into_iter()
+            .filter(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::LineComment | TokenKind::BlockComment { .. }))
+            .map(|token| (token.kind, &src[token.span.start().to_usize()..token.span.end().to_usize()]))
+            .collect();
+
+        (0..tokens.len())
+            .filter(|&i| {
+                let previous = i.checked_sub(1).map(|i| tokens[i].0);
+                let next = tokens.get(i + 1).map(|token| token.0);
+
+                !(tokens[i].0 == TokenKind::Semi && previous == Some(TokenKind::CloseBrace) && next != Some(TokenKind::CloseBrace)
+                    || tokens[i].0 == TokenKind::Comma && next == Some(TokenKind::CloseParen))
+            })
+            .map(|i| tokens[i])
+            .collect()
+    }
+
+    fn comments(src: &str) -> Vec<&str> {
+        propane_lexer::tokenize(src)
+            .into_iter()
+            .filter(|token| matches!(token.kind, TokenKind::LineComment | TokenKind::BlockComment { .. }))
+            .map(|token| &src[token.span.start().to_usize()..token.span.end().to_usize()])
+            .collect()
+    }
+
+    #[test]
+    fn normalizes_operator_spacing() {
+        assert_eq!(fmt("let  a=1+2*  3;"), "let a = 1 + 2 * 3;\n");
+        assert_eq!(fmt("let b = ! a==false!=(-1 <=-  2);"), "let b = !a == false != (-1 <= -2);\n");
+        assert_eq!(fmt("let c=a>=b;let d = a< b;print( a/b ,c- d);"), "let c = a >= b;\nlet d = a < b;\nprint(a / b, c - d);\n");
+        assert_eq!(fmt("return --1 ;"), "return --1;\n");
+    }
+
+    #[test]
+    fn keeps_literals_as_written() {
+        assert_eq!(fmt("let a = 0x1F+1_000 ; let s =\"a\\n\";let c='\\'';"), "let a = 0x1F + 1_000;\nlet s = \"a\\n\";\nlet c = '\\'';\n");
+    }
+
+    #[test]
+    fn indents_blocks() {
+        let src = "fun fib(n:int):int{if n<2{return n;}fib(n-1)+fib(n-2)}\nfor i in 0..10{while i>0{print(i);}}\nlet a={let b=1;b};";
+        let expected = "\
+fun fib(n: int): int {
+    if n < 2 {
+        return n;
+    }
+    fib(n - 1) + fib(n - 2)
+}
+for i in 0..10 {
+    while i > 0 {
+        print(i);
+    }
+}
+let a = {
+    let b = 1;
+    b
+};
+";
+        assert_eq!(fmt(src), expected);
+        assert_eq!(fmt("fun f(){}\nif a {} else if b {1;} else {2}"), "fun f() {}\nif a {} else if b {\n    1;\n} else { 2 }\n");
+    }
+
+    #[test]
+    fn keeps_semicolons_that_matter() {
+        // Without the `;`, the `if` would be the value of the block.
+        assert_eq!(fmt("{ if a { 1 } else { 2 }; }"), "{\n    if a { 1 } else { 2 };\n}\n");
+        assert_eq!(fmt("if a { 1 } else { 2 };\n{ 3 };"), "if a { 1 } else { 2 }\n{ 3 }\n");
+    }
+
+    #[test]
+    fn collapses_blank_lines() {
+        assert_eq!(fmt("\n\nlet a = 1;\n\n\n\nlet b = 2;\nlet c = 3;\n\n"), "let a = 1;\n\nlet b = 2;\nlet c = 3;\n");
+        assert_eq!(fmt(""), "");
+        assert_eq!(fmt("  \n"), "");
+    }
+
+    #[test]
+    fn preserves_comments() {
+        let src = "\
+// The answer.
+let a = 42; // trailing
+/* block */ let b = a;
+
+fun f(x /* the x */) {
+    // first
+    let y = x; /* after y */
+
+    // last
+}
+let c = f(1 + // one
+    2);
+let d = {
+    // nothing but a comment
+};
+// at the end
+";
+        let expected = "\
+// The answer.
+let a = 42; // trailing
+/* block */
+let b = a;
+
+fun f(x /* the x */) {
+    // first
+    let y = x; /* after y */
+
+    // last
+}
+let c = f(
+    1
+        + // one
+        2,
+);
+let d = {
+    // nothing but a comment
+};
+// at the end
+";
+        assert_eq!(fmt(src), expected);
+        assert_eq!(fmt("/* only */"), "/* only */\n");
+        assert_eq!(fmt("let a = /* before */ 1 /* after */;"), "let a = /* before */ 1 /* after */;\n");
+        assert_eq!(fmt("let a = (1 // one\n);"), "let a = (\n    1 // one\n);\n");
+        assert_eq!(fmt("f(/* none */);\nf(// none\n);"), "f( /* none */);\nf( // none\n);\n");
+        assert_eq!(fmt("/* a\n   multiline comment */ let a = 1;"), "/* a\n   multiline comment */\nlet a = 1;\n");
+    }
+
+    #[test]
+    fn wraps_long_expressions() {
+        assert_eq!(
+            format_with("let total = first_value + second_value * factor - third_value;", 40),
+            "let total = first_value\n    + second_value * factor\n    - third_value;\n"
+        );
+        assert_eq!(
+            format_with("let result = compute(first_argument, second_argument, 3);", 40),
+            "let result = compute(\n    first_argument,\n    second_argument,\n    3,\n);\n"
+        );
+        assert_eq!(
+            format_with("fun f(first: int, second: int, third: int): int { first }", 40),
+            "fun f(\n    first: int,\n    second: int,\n    third: int,\n): int { first }\n"
+        );
+        assert_eq!(
+            format_with("let a = (aaaaaaaaaaaa + bbbbbbbbbbbbbbb) * (cccccccccccc + ddddddddd);", 40),
+            "let a = (aaaaaaaaaaaa + bbbbbbbbbbbbbbb)\n    * (cccccccccccc + ddddddddd);\n"
+        );
+        assert_eq!(format_with("let a = f(1,2,3,);", 40), "let a = f(1, 2, 3);\n");
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let sources = [
+            "fun   apply(f,x){f(x)}let  r=apply(fun_value, 1);",
+            "if a{b}else if c{d}else{if e{f}else{g}}",
+            "let a = {{{1}}};\nwhile a {print(a);}",
+            "fun f(a /* a */, // b\n b) /* c */ : int { // d\n a }",
+            "let a = f(g(h(aaaaaaaaaaaaaaaa, bbbbbbbbbbbbbbbbbbbbbbbb), cccccccccccccccccccc), ddddddddddddddddddddd);",
+            "let a = b // end\n;",
+        ];
+
+        for src in sources {
+            format_with(src, 40);
+            format_with(src, 100);
+        }
+    }
+
+    #[test]
+    fn refuses_code_with_errors() {
+        let mut files = Files::new();
+        let file_id = files.add("test.pp", String::new());
+
+        assert!(format(file_id, "let a = ;", &Config::default()).is_err());
+        assert!(format(file_id, "/* unterminated", &Config::default()).is_err());
+    }
+}
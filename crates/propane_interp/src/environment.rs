@@ -0,0 +1,58 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::value::Value;
+
+/// A lexical scope. Cloning an environment shares it, so that closures see
+/// the functions of their block, which are all defined in one scope on
+/// entering it, e.g. for recursive functions. Other bindings each get a
+/// scope of their own.
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    scope: Rc<RefCell<Scope>>,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    bindings: HashMap<String, Value>,
+    parent: Option<Environment>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment::default()
+    }
+
+    /// Creates a scope nested in this one.
+    pub fn child(&self) -> Environment {
+        Environment {
+            scope: Rc::new(RefCell::new(Scope {
+                bindings: HashMap::new(),
+                parent: Some(self.clone()),
+            })),
+        }
+    }
+
+    /// Binds `name` in this scope, shadowing any existing binding.
+    pub fn define(&self, name: &str, value: Value) {
+        self.scope.borrow_mut().bindings.insert(name.to_string(), value);
+    }
+
+    /// Looks `name` up in this scope and then its parents.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let mut scope = self.scope.clone();
+
+        loop {
+            let parent = {
+                let scope = scope.borrow();
+                if let Some(value) = scope.bindings.get(name) {
+                    return Some(value.clone());
+                }
+
+                scope.parent.as_ref()?.scope.clone()
+            };
+            scope = parent;
+        }
+    }
+}
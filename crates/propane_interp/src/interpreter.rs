@@ -0,0 +1,426 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use propane_parser::expression::{Expression, ExpressionKind, Ident, Operator, Statement, StatementKind};
+
+use crate::environment::Environment;
+use crate::value::{Function, Value};
+
+/// Calls nested deeper than this are reported as a stack overflow instead of
+/// overflowing the interpreter's own stack. Evaluation recurses on the host
+/// stack, so reaching this limit needs a few more megabytes than the default
+/// for spawned threads; see [STACK_SIZE].
+const MAX_CALL_DEPTH: usize = 1000;
+
+/// A stack size that is enough to run programs up to the call depth limit,
+/// for use with [std::thread::Builder::stack_size].
+pub const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Why evaluation stopped before reaching the end of an expression.
+enum Unwind {
+    Return(Value),
+    Error(Box<Diagnostic<FileId>>),
+}
+
+impl From<Diagnostic<FileId>> for Unwind {
+    fn from(error: Diagnostic<FileId>) -> Unwind {
+        Unwind::Error(Box::new(error))
+    }
+}
+
+type Eval<T = Value> = Result<T, Unwind>;
+
+/// Evaluates programs one after another in a shared global scope, so later
+/// programs can use bindings from earlier ones.
+#[derive(Debug, Default)]
+pub struct Interpreter {
+    globals: Environment,
+    call_depth: usize,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter::default()
+    }
+
+    /// Runs a program as returned by [propane_parser::parse], evaluating to
+    /// the value of a top-level `return`, or else of the last statement.
+    ///
+    /// The program's top-level bindings are only kept for later programs if
+    /// it runs to completion, as a program that fails partway would leave
+    /// some of the bindings that later programs are checked against out.
+    pub fn run(&mut self, program: &Expression) -> Result<Value, Diagnostic<FileId>> {
+        let mut globals = self.globals.clone();
+        self.call_depth = 0;
+
+        let result = match &program.kind {
+            ExpressionKind::StmtExpr(statements) => self.execute_statements(statements, &mut globals),
+            _ => self.evaluate(program, &globals),
+        };
+
+        match result {
+            Ok(value) | Err(Unwind::Return(value)) => {
+                self.globals = globals;
+
+                Ok(value)
+            }
+            Err(Unwind::Error(error)) => Err(*error),
+        }
+    }
+
+    /// Executes `statements` in order in a new scope nested in `env`,
+    /// evaluating to the value of the last one if it is an expression
+    /// statement. `env` is left as the scope of the bindings declared by the
+    /// statements that ran.
+    fn execute_statements(&mut self, statements: &[Statement], env: &mut Environment) -> Eval {
+        let functions = env.child();
+        declare_functions(statements, &functions);
+        *env = functions.clone();
+
+        let mut value = Value::Unit;
+
+        for statement in statements {
+            if let StatementKind::Function { .. } = &statement.kind {
+                // Declared again now that the bindings before it are in
+                // scope, so that it sees them but none declared after it.
+                declare_function(statement, &functions, env);
+            }
+
+            value = self.execute(statement, env)?;
+        }
+
+        Ok(value)
+    }
+
+    fn execute(&mut self, statement: &Statement, env: &mut Environment) -> Eval {
+        match &statement.kind {
+            // Each binding gets a scope of its own, so that a later binding
+            // of the same name shadows it without changing what closures
+            // that captured it see.
+            StatementKind::Let { name, value } => {
+                let value = self.evaluate(value, env)?;
+                *env = env.child();
+                env.define(&name.name, value);
+            }
+            StatementKind::Return { value } => {
+                let value = self.evaluate(value, env)?;
+
+                return Err(Unwind::Return(value));
+            }
+            StatementKind::While { condition, body } => {
+                while self.evaluate_condition(condition, env)? {
+                    self.evaluate(body, env)?;
+                }
+            }
+            StatementKind::For { variable, start, end, body } => {
+                let start = self.evaluate_int(start, env)?;
+                let end = self.evaluate_int(end, env)?;
+
+                for i in start..end {
+                    let env = env.child();
+                    env.define(&variable.name, Value::Int(i));
+
+                    self.evaluate(body, &env)?;
+                }
+            }
+            // Declared by [Interpreter::execute_statements].
+            StatementKind::Function { .. } => {}
+            StatementKind::Expression(expression) => return self.evaluate(expression, env),
+        }
+
+        Ok(Value::Unit)
+    }
+
+    fn evaluate(&mut self, expression: &Expression, env: &Environment) -> Eval {
+        match &expression.kind {
+            ExpressionKind::Binary { left, operator, right } => {
+                let left_value = self.evaluate(left, env)?;
+                let right_value = self.evaluate(right, env)?;
+
+                Ok(binary(expression, left, *operator, right, left_value, right_value)?)
+            }
+            ExpressionKind::Grouping(inner) => self.evaluate(inner, env),
+            ExpressionKind::Literal(literal) => Ok(Value::from(literal)),
+            ExpressionKind::Unary(operator, operand) => {
+                let value = self.evaluate(operand, env)?;
+
+                Ok(unary(expression, *operator, value)?)
+            }
+            ExpressionKind::Variable(ident) => match env.get(&ident.name) {
+                Some(value) => Ok(value),
+                None => Err(undefined_variable_error(ident).into()),
+            },
+            ExpressionKind::Call { callee, args } => {
+                let function = match self.evaluate(callee, env)? {
+                    Value::Function(function) => function,
+                    other => return Err(not_callable_error(callee, &other).into()),
+                };
+
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.evaluate(arg, env)?);
+                }
+
+                self.call(expression, &function, values)
+            }
+            ExpressionKind::Block { statements, value } => {
+                let mut env = env.clone();
+                self.execute_statements(statements, &mut env)?;
+
+                match value {
+                    Some(value) => self.evaluate(value, &env),
+                    None => Ok(Value::Unit),
+                }
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                let condition = self.evaluate_condition(condition, env)?;
+
+                match else_branch {
+                    Some(else_branch) if !condition => self.evaluate(else_branch, env),
+                    Some(_) => self.evaluate(then_branch, env),
+                    // Without an `else`, the `if` evaluates to `()` either
+                    // way.
+                    None => {
+                        if condition {
+                            self.evaluate(then_branch, env)?;
+                        }
+
+                        Ok(Value::Unit)
+                    }
+                }
+            }
+            ExpressionKind::StmtExpr(statements) => self.execute_statements(statements, &mut env.clone()),
+            ExpressionKind::Error => Err(error_node_error(expression).into()),
+        }
+    }
+
+    fn call(&mut self, call: &Expression, function: &Function, args: Vec<Value>) -> Eval {
+        if args.len() != function.parameters.len() {
+            return Err(arity_error(call, function, args.len()).into());
+        }
+
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(stack_overflow_error(call).into());
+        }
+
+        let env = function.closure.child();
+        for (parameter, arg) in function.parameters.iter().zip(args) {
+            env.define(&parameter.name, arg);
+        }
+
+        self.call_depth += 1;
+        let result = self.evaluate(&function.body, &env);
+        self.call_depth -= 1;
+
+        match result {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn evaluate_condition(&mut self, condition: &Expression, env: &Environment) -> Eval<bool> {
+        match self.evaluate(condition, env)? {
+            Value::Bool(value) => Ok(value),
+            other => Err(expected_type_error(condition, "bool", &other).into()),
+        }
+    }
+
+    fn evaluate_int(&mut self, expression: &Expression, env: &Environment) -> Eval<i64> {
+        match self.evaluate(expression, env)? {
+            Value::Int(value) => Ok(value),
+            other => Err(expected_type_error(expression, "int", &other).into()),
+        }
+    }
+}
+
+/// Defines the functions declared in `statements` up front in `env`, so that
+/// they can be called before their declaration and be mutually recursive.
+fn declare_functions(statements: &[Statement], env: &Environment) {
+    for statement in statements {
+        declare_function(statement, env, env);
+    }
+}
+
+/// Defines the function declared by `statement`, if any, in `env`, with the
+/// bindings in `closure` in scope in its body.
+fn declare_function(statement: &Statement, env: &Environment, closure: &Environment) {
+    if let StatementKind::Function { name, parameters, body, .. } = &statement.kind {
+        let function = Function {
+            name: name.clone(),
+            parameters: parameters.iter().map(|parameter| parameter.name.clone()).collect(),
+            body: body.clone(),
+            closure: closure.clone(),
+        };
+
+        env.define(&name.name, Value::Function(Rc::new(function)));
+    }
+}
+
+fn unary(expression: &Expression, operator: Operator, value: Value) -> Result<Value, Diagnostic<FileId>> {
+    match (operator, value) {
+        (Operator::Minus, Value::Int(value)) => value.checked_neg()
+            .map(Value::Int)
+            .ok_or_else(|| overflow_error(expression)),
+        (Operator::Minus, Value::Float(value)) => Ok(Value::Float(-value)),
+        (Operator::Not, Value::Bool(value)) => Ok(Value::Bool(!value)),
+        (operator, value) => Err(
+            Diagnostic::error()
+                .with_message("mismatched types")
+                .with_labels(vec![
+                    Label::primary(expression.file_id, range(expression.span))
+                        .with_message(format!("cannot apply unary operator `{}` to type `{}`", operator.as_str(), value.type_name())),
+                ])
+        ),
+    }
+}
+
+fn binary(
+    expression: &Expression,
+    left: &Expression,
+    operator: Operator,
+    right: &Expression,
+    left_value: Value,
+    right_value: Value,
+) -> Result<Value, Diagnostic<FileId>> {
+    use Operator::*;
+
+    let value = match (operator, &left_value, &right_value) {
+        (EqEq, _, _) if same_type(&left_value, &right_value) => Value::Bool(left_value == right_value),
+        (NotEq, _, _) if same_type(&left_value, &right_value) => Value::Bool(left_value != right_value),
+
+        (Plus, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_add(*b).ok_or_else(|| overflow_error(expression))?),
+        (Minus, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_sub(*b).ok_or_else(|| overflow_error(expression))?),
+        (Star, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_mul(*b).ok_or_else(|| overflow_error(expression))?),
+        (Slash, Value::Int(_), Value::Int(0)) => return Err(division_by_zero_error(expression, right)),
+        (Slash, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_div(*b).ok_or_else(|| overflow_error(expression))?),
+
+        (Plus, Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+        (Minus, Value::Float(a), Value::Float(b)) => Value::Float(a - b),
+        (Star, Value::Float(a), Value::Float(b)) => Value::Float(a * b),
+        (Slash, Value::Float(a), Value::Float(b)) => Value::Float(a / b),
+
+        (Plus, Value::Str(a), Value::Str(b)) => Value::Str(format!("{a}{b}").into()),
+
+        (Gt | GtEq | Lt | LtEq, Value::Int(a), Value::Int(b)) => Value::Bool(compare(operator, a, b)),
+        (Gt | GtEq | Lt | LtEq, Value::Float(a), Value::Float(b)) => Value::Bool(compare(operator, a, b)),
+        (Gt | GtEq | Lt | LtEq, Value::Char(a), Value::Char(b)) => Value::Bool(compare(operator, a, b)),
+        (Gt | GtEq | Lt | LtEq, Value::Str(a), Value::Str(b)) => Value::Bool(compare(operator, a, b)),
+
+        _ => return Err(binary_type_error(expression, left, operator, right, &left_value, &right_value)),
+    };
+
+    Ok(value)
+}
+
+fn compare<T: PartialOrd>(operator: Operator, a: T, b: T) -> bool {
+    match operator {
+        Operator::Gt => a > b,
+        Operator::GtEq => a >= b,
+        Operator::Lt => a < b,
+        Operator::LtEq => a <= b,
+        _ => unreachable!("`{}` is not a comparison", operator.as_str()),
+    }
+}
+
+fn same_type(left: &Value, right: &Value) -> bool {
+    std::mem::discriminant(left) == std::mem::discriminant(right)
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
+
+fn binary_type_error(
+    expression: &Expression,
+    left: &Expression,
+    operator: Operator,
+    right: &Expression,
+    left_value: &Value,
+    right_value: &Value,
+) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("mismatched types")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span)).with_message(format!(
+                "cannot apply `{}` to `{}` and `{}`",
+                operator.as_str(),
+                left_value.type_name(),
+                right_value.type_name(),
+            )),
+            Label::secondary(left.file_id, range(left.span)).with_message(format!("this is of type `{}`", left_value.type_name())),
+            Label::secondary(right.file_id, range(right.span)).with_message(format!("this is of type `{}`", right_value.type_name())),
+        ])
+}
+
+fn expected_type_error(expression: &Expression, expected: &str, found: &Value) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("mismatched types")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span))
+                .with_message(format!("expected `{}`, found `{}`", expected, found.type_name())),
+        ])
+}
+
+fn division_by_zero_error(expression: &Expression, divisor: &Expression) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("attempt to divide by zero")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span)),
+            Label::secondary(divisor.file_id, range(divisor.span)).with_message("this evaluates to zero"),
+        ])
+}
+
+fn overflow_error(expression: &Expression) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("arithmetic overflow")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span)).with_message("result does not fit in an `int`"),
+        ])
+}
+
+fn undefined_variable_error(ident: &Ident) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message(format!("cannot find value `{}` in this scope", ident.name))
+        .with_labels(vec![
+            Label::primary(ident.file_id, range(ident.span)).with_message("not found in this scope"),
+        ])
+}
+
+fn not_callable_error(callee: &Expression, value: &Value) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message(format!("expected function, found `{}`", value.type_name()))
+        .with_labels(vec![
+            Label::primary(callee.file_id, range(callee.span)).with_message("call expression requires function"),
+        ])
+}
+
+fn arity_error(call: &Expression, function: &Function, found: usize) -> Diagnostic<FileId> {
+    let expected = function.parameters.len();
+    let plural = if expected == 1 { "" } else { "s" };
+    let supplied = if found == 1 { "argument was" } else { "arguments were" };
+
+    Diagnostic::error()
+        .with_message(format!("this function takes {expected} argument{plural} but {found} {supplied} supplied"))
+        .with_labels(vec![
+            Label::primary(call.file_id, range(call.span)),
+            Label::secondary(function.name.file_id, range(function.name.span)).with_message("function defined here"),
+        ])
+}
+
+fn stack_overflow_error(call: &Expression) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("stack overflow")
+        .with_labels(vec![
+            Label::primary(call.file_id, range(call.span)).with_message(format!("calls nested more than {MAX_CALL_DEPTH} deep")),
+        ])
+}
+
+fn error_node_error(expression: &Expression) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("cannot run code that failed to parse")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span)),
+        ])
+}
@@ -0,0 +1,184 @@
+pub use crate::environment::Environment;
+pub use crate::interpreter::{Interpreter, STACK_SIZE};
+pub use crate::value::{Function, Value};
+
+mod environment;
+mod interpreter;
+mod value;
+
+#[cfg(test)]
+mod tests {
+    use codespan::FileId;
+    use codespan_reporting::diagnostic::Diagnostic;
+
+    use super::*;
+
+    fn run(src: &str) -> Result<Value, Diagnostic<FileId>> {
+        Interpreter::new().run_source(src)
+    }
+
+    trait RunSource {
+        fn run_source(&mut self, src: &str) -> Result<Value, Diagnostic<FileId>>;
+    }
+
+    impl RunSource for Interpreter {
+        fn run_source(&mut self, src: &str) -> Result<Value, Diagnostic<FileId>> {
+            self.run(&propane_parser::parse_str(src))
+        }
+    }
+
+    fn error_message(src: &str) -> String {
+        run(src).expect_err("expected a runtime error").message
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(run("return 1 + 2 * 3;"), Ok(Value::Int(7)));
+        assert_eq!(run("return (1 + 2) * 3;"), Ok(Value::Int(9)));
+        assert_eq!(run("return 7 / 2 - -1;"), Ok(Value::Int(4)));
+        assert_eq!(run("return 1.5 * 2.0;"), Ok(Value::Float(3.0)));
+        assert_eq!(run(r#"return "foo" + "bar";"#), Ok(Value::Str("foobar".into())));
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(run("return 1 < 2;"), Ok(Value::Bool(true)));
+        assert_eq!(run("return 2 <= 1;"), Ok(Value::Bool(false)));
+        assert_eq!(run("return 1.0 != 1.0;"), Ok(Value::Bool(false)));
+        assert_eq!(run("return 'a' < 'b';"), Ok(Value::Bool(true)));
+        assert_eq!(run(r#"return "a" == "a";"#), Ok(Value::Bool(true)));
+        assert_eq!(run("return !(1 == 2);"), Ok(Value::Bool(true)));
+    }
+
+    #[test]
+    fn let_bindings() {
+        assert_eq!(run("let a = 1; let b = a + 1; return a + b;"), Ok(Value::Int(3)));
+        assert_eq!(run("let a = 1; let a = a + 1; return a;"), Ok(Value::Int(2)));
+    }
+
+    #[test]
+    fn block_scopes() {
+        assert_eq!(run("let a = 1; let b = { let a = 10; a + 1 }; return a + b;"), Ok(Value::Int(12)));
+    }
+
+    #[test]
+    fn result_of_last_statement() {
+        assert_eq!(run("let a = 2; a * 3;"), Ok(Value::Int(6)));
+        assert_eq!(run("let a = 2;"), Ok(Value::Unit));
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(run("return if 1 < 2 { 1 } else { 2 };"), Ok(Value::Int(1)));
+        assert_eq!(run("return if false { 1 };"), Ok(Value::Unit));
+        assert_eq!(run("for i in 0..5 { if i == 3 { return i * 10; } } return 0;"), Ok(Value::Int(30)));
+        assert_eq!(run("let i = 1; for i in 0..5 { } return i;"), Ok(Value::Int(1)));
+        assert_eq!(run("while 1 > 2 { } return 1;"), Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn functions() {
+        let src = "
+            fun add(a: int, b: int): int { a + b }
+            return add(3, 3);
+        ";
+        assert_eq!(run(src), Ok(Value::Int(6)));
+
+        let src = "
+            fun fib(n) {
+                if n < 2 { return n; }
+                fib(n - 1) + fib(n - 2)
+            }
+            return fib(15);
+        ";
+        assert_eq!(run(src), Ok(Value::Int(610)));
+    }
+
+    #[test]
+    fn functions_are_hoisted() {
+        let src = "
+            fun even(n) { if n == 0 { true } else { odd(n - 1) } }
+            fun odd(n) { if n == 0 { false } else { even(n - 1) } }
+            return even(7);
+        ";
+        assert_eq!(run(src), Ok(Value::Bool(false)));
+
+        assert_eq!(run("return { let a = f(); fun f() { 2 } a };"), Ok(Value::Int(2)));
+    }
+
+    #[test]
+    fn closures_capture_their_scope() {
+        let src = "
+            let f = {
+                let x = 41;
+                fun inner() { x + 1 }
+                inner
+            };
+            return f();
+        ";
+        assert_eq!(run(src), Ok(Value::Int(42)));
+    }
+
+    #[test]
+    fn closures_dont_see_later_bindings() {
+        assert_eq!(run("let a = 1; fun f() { a } let a = 2; f();"), Ok(Value::Int(1)));
+        assert_eq!(run("fun f() { let a = 1; fun g() { a } let a = 2; g() } f();"), Ok(Value::Int(1)));
+        assert_eq!(run("fun f() { g() } let a = 1; fun g() { a } f();"), Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn bindings_persist_between_runs() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(interpreter.run_source("let a = 20; fun double(x) { x * 2 }"), Ok(Value::Unit));
+        assert_eq!(interpreter.run_source("double(a) + 2;"), Ok(Value::Int(42)));
+
+        // Nothing is kept from a run that fails.
+        assert!(interpreter.run_source("let a = 1; let b = 1 / 0;").is_err());
+        assert_eq!(interpreter.run_source("a;"), Ok(Value::Int(20)));
+        assert_eq!(interpreter.run_source("b;").unwrap_err().message, "cannot find value `b` in this scope");
+    }
+
+    #[test]
+    fn division_by_zero() {
+        let error = run("let zero = 0; return 1 / zero;").unwrap_err();
+
+        assert_eq!(error.message, "attempt to divide by zero");
+        assert_eq!(error.labels[0].range, 21..29);
+        assert_eq!(error.labels[1].range, 25..29);
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let error = run(r#"return 1 + "a";"#).unwrap_err();
+
+        assert_eq!(error.message, "mismatched types");
+        assert_eq!(error.labels[0].message, "cannot apply `+` to `int` and `string`");
+        assert_eq!(error.labels[1].range, 7..8);
+        assert_eq!(error.labels[2].range, 11..14);
+
+        assert_eq!(error_message("return -true;"), "mismatched types");
+        assert_eq!(error_message("if 1 { 2 }"), "mismatched types");
+        assert_eq!(error_message("return 1 == 1.0;"), "mismatched types");
+    }
+
+    #[test]
+    fn runtime_errors() {
+        assert_eq!(error_message("return a;"), "cannot find value `a` in this scope");
+        assert_eq!(error_message("return 9223372036854775807 + 1;"), "arithmetic overflow");
+        assert_eq!(error_message("let a = 1; a();"), "expected function, found `int`");
+        assert_eq!(error_message("fun f(a) { a } f();"), "this function takes 1 argument but 0 arguments were supplied");
+    }
+
+    #[test]
+    fn stack_overflow() {
+        let message = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn(|| error_message("fun f() { { if true { 1 + f() } else { 0 } } } f();"))
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert_eq!(message, "stack overflow");
+    }
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+use std::rc::Rc;
+
+use propane_parser::expression::{Expression, Ident, Literal};
+
+use crate::environment::Environment;
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(Rc<str>),
+    Function(Rc<Function>),
+    /// The value of statements, blocks without a trailing expression and
+    /// `if`s without an `else`.
+    Unit,
+}
+
+/// A function declaration together with the environment it was declared in.
+#[derive(Debug)]
+pub struct Function {
+    pub name: Ident,
+    pub parameters: Vec<Ident>,
+    pub body: Expression,
+    pub closure: Environment,
+}
+
+impl Value {
+    /// Name of the value's type, as used in diagnostics.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "int",
+            Value::Float(_) => "float",
+            Value::Bool(_) => "bool",
+            Value::Char(_) => "char",
+            Value::Str(_) => "string",
+            Value::Function(_) => "function",
+            Value::Unit => "()",
+        }
+    }
+}
+
+impl From<&Literal> for Value {
+    fn from(literal: &Literal) -> Value {
+        match literal {
+            Literal::Int(value) => Value::Int(*value),
+            Literal::Float(value) => Value::Float(*value),
+            Literal::Bool(value) => Value::Bool(*value),
+            Literal::Char(value) => Value::Char(*value),
+            Literal::Str(value) => Value::Str(value.as_str().into()),
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Int(left), Value::Int(right)) => left == right,
+            (Value::Float(left), Value::Float(right)) => left == right,
+            (Value::Bool(left), Value::Bool(right)) => left == right,
+            (Value::Char(left), Value::Char(right)) => left == right,
+            (Value::Str(left), Value::Str(right)) => left == right,
+            (Value::Function(left), Value::Function(right)) => Rc::ptr_eq(left, right),
+            (Value::Unit, Value::Unit) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(value) => write!(f, "{value}"),
+            Value::Float(value) => write!(f, "{value:?}"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Char(value) => write!(f, "{value}"),
+            Value::Str(value) => write!(f, "{value}"),
+            Value::Function(function) => write!(f, "<fun {}>", function.name.name),
+            Value::Unit => write!(f, "()"),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! The textual form of the IR, which [crate::parse] reads back.
+//!
+//! ```text
+//! global $limit: int
+//!
+//! program @main() -> bool {
+//! block0:
+//!     v0: int = const 10
+//!     global.set $limit, v0
+//!     v1: int = call @double(v0)
+//!     v2: int = global.get $limit
+//!     v3: bool = gt v1, v2
+//!     return v3
+//! }
+//!
+//! fun @double(int) -> int {
+//! block0(v0: int):
+//!     v1: int = const 2
+//!     v2: int = mul v0, v1
+//!     return v2
+//! }
+//! ```
+
+use std::fmt;
+
+use crate::ir::{Block, BlockCall, Constant, Function, InstructionKind, Module, Terminator, Type, Value};
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Char => write!(f, "char"),
+            Type::Str => write!(f, "string"),
+            Type::Unit => write!(f, "()"),
+            Type::Function { parameters, ret } => {
+                write!(f, "fun(")?;
+                write_list(f, parameters)?;
+                write!(f, ") -> {ret}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}", self.0)
+    }
+}
+
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "block{}", self.0)
+    }
+}
+
+impl fmt::Display for BlockCall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.block)?;
+
+        if !self.arguments.is_empty() {
+            write!(f, "(")?;
+            write_list(f, &self.arguments)?;
+            write!(f, ")")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(value) => write!(f, "{value}"),
+            Constant::Float(value) => write!(f, "{value:?}"),
+            Constant::Bool(value) => write!(f, "{value}"),
+            Constant::Char(value) => write!(f, "{value:?}"),
+            Constant::Str(value) => write!(f, "{value:?}"),
+            Constant::Unit => write!(f, "()"),
+        }
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for global in &self.globals {
+            writeln!(f, "global ${}: {}", global.name, global.ty)?;
+        }
+
+        for (i, function) in self.functions.iter().enumerate() {
+            if i > 0 || !self.globals.is_empty() {
+                writeln!(f)?;
+            }
+
+            let is_program = self.programs.iter().any(|program| program.0 == i);
+            self.fmt_function(f, function, is_program)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Module {
+    fn fmt_function(&self, f: &mut fmt::Formatter<'_>, function: &Function, is_program: bool) -> fmt::Result {
+        let keyword = if is_program { "program" } else { "fun" };
+        write!(f, "{keyword} @{}(", function.name)?;
+        write_list(f, &function.parameters)?;
+        writeln!(f, ") -> {} {{", function.result)?;
+
+        for (i, block) in function.blocks.iter().enumerate() {
+            write!(f, "{}", Block(i))?;
+            if !block.parameters.is_empty() {
+                write!(f, "(")?;
+                for (i, &parameter) in block.parameters.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{parameter}: {}", function.type_of(parameter))?;
+                }
+                write!(f, ")")?;
+            }
+            writeln!(f, ":")?;
+
+            for instruction in &block.instructions {
+                write!(f, "    ")?;
+                if let Some(result) = instruction.result {
+                    write!(f, "{result}: {} = ", function.type_of(result))?;
+                }
+
+                match &instruction.kind {
+                    InstructionKind::Const(constant) => write!(f, "const {constant}")?,
+                    InstructionKind::Binary(operator, left, right) => write!(f, "{} {left}, {right}", operator.as_str())?,
+                    InstructionKind::Unary(operator, operand) => write!(f, "{} {operand}", operator.as_str())?,
+                    InstructionKind::Call(callee, arguments) => {
+                        write!(f, "call @{}(", self.function(*callee).name)?;
+                        write_list(f, arguments)?;
+                        write!(f, ")")?;
+                    }
+                    InstructionKind::CallIndirect(callee, arguments) => {
+                        write!(f, "call_indirect {callee}(")?;
+                        write_list(f, arguments)?;
+                        write!(f, ")")?;
+                    }
+                    InstructionKind::Function(function) => write!(f, "func @{}", self.function(*function).name)?,
+                    InstructionKind::GlobalGet(global) => write!(f, "global.get ${}", self.global(*global).name)?,
+                    InstructionKind::GlobalSet(global, value) => write!(f, "global.set ${}, {value}", self.global(*global).name)?,
+                }
+                writeln!(f)?;
+            }
+
+            match &block.terminator {
+                Terminator::Jump(target) => writeln!(f, "    jump {target}")?,
+                Terminator::Branch { condition, then_target, else_target } => {
+                    writeln!(f, "    branch {condition}, {then_target}, {else_target}")?
+                }
+                Terminator::Return(value) => writeln!(f, "    return {value}")?,
+            }
+        }
+
+        writeln!(f, "}}")
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, items: &[impl fmt::Display]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{item}")?;
+    }
+
+    Ok(())
+}
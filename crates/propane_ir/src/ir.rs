@@ -0,0 +1,238 @@
+use std::ops::Range;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+
+/// The type of a value. Unlike [propane_typeck::Type], there are no type
+/// variables: generic functions are instantiated at each type they are used
+/// at.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Char,
+    Str,
+    Unit,
+    Function { parameters: Vec<Type>, ret: Box<Type> },
+}
+
+/// A value defined by an instruction or a block parameter, unique within its
+/// function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Value(pub usize);
+
+/// A basic block of a function. The first block is the entry block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Block(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FunctionId(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GlobalId(pub usize);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Module {
+    pub globals: Vec<Global>,
+    pub functions: Vec<Function>,
+    /// The functions that run the programs the module was lowered from, in
+    /// the order they run.
+    pub programs: Vec<FunctionId>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    /// The name of the global, unique within its module.
+    pub name: String,
+    pub ty: Type,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    /// The name of the function, unique within its module.
+    pub name: String,
+    pub parameters: Vec<Type>,
+    pub result: Type,
+    /// The type of each value.
+    pub values: Vec<Type>,
+    pub blocks: Vec<BlockData>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockData {
+    /// The values passed by the jumps to the block, which take the place of
+    /// phi nodes. The parameters of the entry block are those of the
+    /// function.
+    pub parameters: Vec<Value>,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub result: Option<Value>,
+    pub kind: InstructionKind,
+    /// The code the instruction was lowered from, for reporting the runtime
+    /// errors it raises, or that a backend can't compile it.
+    pub source: Option<Source>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Source {
+    pub file_id: FileId,
+    pub span: Span,
+    /// For a division, the divisor, which the error for dividing by zero
+    /// points at.
+    pub divisor: Option<Span>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstructionKind {
+    Const(Constant),
+    /// Arithmetic or a comparison of two values of the same type. `int`
+    /// arithmetic raises an error on overflow and division by zero, unless it
+    /// has no [Source] because it can't fail, and `add` concatenates strings.
+    Binary(BinaryOperator, Value, Value),
+    Unary(UnaryOperator, Value),
+    Call(FunctionId, Vec<Value>),
+    CallIndirect(Value, Vec<Value>),
+    /// A function as a value.
+    Function(FunctionId),
+    GlobalGet(GlobalId),
+    GlobalSet(GlobalId, Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Char(char),
+    Str(String),
+    Unit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOperator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOperator {
+    Neg,
+    Not,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Jump(BlockCall),
+    Branch { condition: Value, then_target: BlockCall, else_target: BlockCall },
+    Return(Value),
+}
+
+/// A jump target, with the arguments for the block's parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockCall {
+    pub block: Block,
+    pub arguments: Vec<Value>,
+}
+
+impl Function {
+    pub fn entry(&self) -> Block {
+        Block(0)
+    }
+
+    pub fn block(&self, block: Block) -> &BlockData {
+        &self.blocks[block.0]
+    }
+
+    pub fn type_of(&self, value: Value) -> &Type {
+        &self.values[value.0]
+    }
+}
+
+impl Module {
+    pub fn function(&self, id: FunctionId) -> &Function {
+        &self.functions[id.0]
+    }
+
+    pub fn global(&self, id: GlobalId) -> &Global {
+        &self.globals[id.0]
+    }
+}
+
+impl Source {
+    /// The error `int` arithmetic raises when the result doesn't fit.
+    pub fn overflow_error(&self) -> Diagnostic<FileId> {
+        Diagnostic::error()
+            .with_message("arithmetic overflow")
+            .with_labels(vec![
+                Label::primary(self.file_id, range(self.span)).with_message("result does not fit in an `int`"),
+            ])
+    }
+
+    /// The error `int` division raises when the divisor is zero.
+    pub fn division_by_zero_error(&self) -> Diagnostic<FileId> {
+        let mut labels = vec![Label::primary(self.file_id, range(self.span))];
+        if let Some(divisor) = self.divisor {
+            labels.push(Label::secondary(self.file_id, range(divisor)).with_message("this evaluates to zero"));
+        }
+
+        Diagnostic::error().with_message("attempt to divide by zero").with_labels(labels)
+    }
+}
+
+impl BinaryOperator {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            BinaryOperator::Add => "add",
+            BinaryOperator::Sub => "sub",
+            BinaryOperator::Mul => "mul",
+            BinaryOperator::Div => "div",
+            BinaryOperator::Eq => "eq",
+            BinaryOperator::Ne => "ne",
+            BinaryOperator::Lt => "lt",
+            BinaryOperator::Le => "le",
+            BinaryOperator::Gt => "gt",
+            BinaryOperator::Ge => "ge",
+        }
+    }
+
+    pub fn is_comparison(self) -> bool {
+        !matches!(self, BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div)
+    }
+}
+
+impl UnaryOperator {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UnaryOperator::Neg => "neg",
+            UnaryOperator::Not => "not",
+        }
+    }
+}
+
+impl Terminator {
+    /// The blocks the terminator can jump to.
+    pub fn targets(&self) -> Vec<&BlockCall> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch { then_target, else_target, .. } => vec![then_target, else_target],
+            Terminator::Return(_) => vec![],
+        }
+    }
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
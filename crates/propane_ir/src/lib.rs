@@ -0,0 +1,414 @@
+//! A typed SSA intermediate representation for backends and optimizations.
+//!
+//! A [Module] holds functions made of basic blocks, each ending in a
+//! [Terminator]. Block parameters take the place of phi nodes. Programs are
+//! lowered to it with [Lowerer], and modules can be written as text with
+//! `Display` and read back with [parse], for golden tests.
+//!
+//! The name resolution and monomorphization in [mono] are shared by the
+//! [Lowerer] and the code generators that compile the AST directly.
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_parser::expression::Expression;
+use propane_typeck::Inference;
+
+pub use crate::ir::*;
+pub use crate::lower::Lowerer;
+pub use crate::parse::{parse, ParseError};
+pub use crate::verify::{verify, VerifyError};
+
+mod display;
+mod ir;
+mod lower;
+pub mod mono;
+mod parse;
+mod verify;
+
+/// Lowers a single type checked program to a module.
+pub fn lower(program: &Expression, inference: &Inference) -> Result<Module, Diagnostic<FileId>> {
+    let mut lowerer = Lowerer::new();
+    lowerer.add(program, inference)?;
+
+    Ok(lowerer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lower_src(src: &str) -> Module {
+        let program = propane_parser::parse_str(src);
+        let inference = propane_test_support::check(&program);
+
+        lower(&program, &inference).unwrap_or_else(|error| panic!("failed to lower {src:?}: {error:?}"))
+    }
+
+    fn lower_error(src: &str) -> Diagnostic<FileId> {
+        let program = propane_parser::parse_str(src);
+        let inference = propane_test_support::check(&program);
+
+        lower(&program, &inference).unwrap_err()
+    }
+
+    /// Parses and verifies a module written as text.
+    fn verify_text(text: &str) -> Result<(), Vec<String>> {
+        let module = parse(text).unwrap_or_else(|error| panic!("failed to parse: {error}"));
+
+        verify(&module).map_err(|errors| errors.into_iter().map(|error| error.to_string()).collect())
+    }
+
+    #[test]
+    fn lowers_programs() {
+        let module = lower_src("fun double(x: int): int { x * 2 } let limit = 10; return double(limit) > limit;");
+
+        assert_eq!(
+            module.to_string(),
+            "\
+global $limit: int
+
+program @main() -> bool {
+block0:
+    v0: int = const 10
+    global.set $limit, v0
+    v1: int = global.get $limit
+    v2: int = call @double(v1)
+    v3: int = global.get $limit
+    v4: bool = gt v2, v3
+    return v4
+}
+
+fun @double(int) -> int {
+block0(v0: int):
+    v1: int = const 2
+    v2: int = mul v0, v1
+    return v2
+}
+"
+        );
+    }
+
+    #[test]
+    fn lowers_control_flow() {
+        let module = lower_src("fun abs(n: int): int { if n < 0 { -n } else { n } } let a = 0; for i in 0..3 { abs(i); }");
+
+        assert_eq!(
+            module.to_string(),
+            "\
+global $a: int
+
+program @main() -> () {
+block0:
+    v0: int = const 0
+    global.set $a, v0
+    v1: int = const 0
+    v2: int = const 3
+    jump block1(v1)
+block1(v3: int):
+    v4: bool = lt v3, v2
+    branch v4, block2, block3
+block2:
+    v5: int = call @abs(v3)
+    v6: () = const ()
+    v7: int = const 1
+    v8: int = add v3, v7
+    jump block1(v8)
+block3:
+    v9: () = const ()
+    return v9
+}
+
+fun @abs(int) -> int {
+block0(v0: int):
+    v1: int = const 0
+    v2: bool = lt v0, v1
+    branch v2, block1, block2
+block1:
+    v3: int = neg v0
+    jump block3(v3)
+block2:
+    jump block3(v0)
+block3(v4: int):
+    return v4
+}
+"
+        );
+    }
+
+    #[test]
+    fn leaves_out_unreachable_code() {
+        let module = lower_src("fun f(n: int): int { if n > 0 { return n; } else { return 0 - n; } } f(1);");
+
+        assert_eq!(
+            module.to_string(),
+            "\
+program @main() -> int {
+block0:
+    v0: int = const 1
+    v1: int = call @f(v0)
+    return v1
+}
+
+fun @f(int) -> int {
+block0(v0: int):
+    v1: int = const 0
+    v2: bool = gt v0, v1
+    branch v2, block1, block2
+block1:
+    return v0
+block2:
+    v3: int = const 0
+    v4: int = sub v3, v0
+    return v4
+}
+"
+        );
+    }
+
+    #[test]
+    fn instantiates_generic_functions() {
+        let module = lower_src("fun id(x) { x } if id(true) { id(1.5) } else { 0.0 }");
+
+        let mut signatures = module
+            .functions
+            .iter()
+            .map(|function| {
+                let parameters = function.parameters.iter().map(Type::to_string).collect::<Vec<_>>();
+
+                format!("{}({}) -> {}", function.name, parameters.join(", "), function.result)
+            })
+            .collect::<Vec<_>>();
+        signatures.sort();
+
+        assert_eq!(signatures, ["id(bool) -> bool", "id.1(float) -> float", "main() -> float"]);
+    }
+
+    #[test]
+    fn records_the_source_of_arithmetic() {
+        let module = lower_src("let zero = 0; return 1 / zero;");
+
+        let division = module.functions[0].blocks[0]
+            .instructions
+            .iter()
+            .find(|instruction| matches!(instruction.kind, InstructionKind::Binary(BinaryOperator::Div, ..)))
+            .unwrap();
+        let source = division.source.unwrap();
+        let diagnostic = source.division_by_zero_error();
+
+        assert_eq!(diagnostic.message, "attempt to divide by zero");
+        assert_eq!(diagnostic.labels[0].range, 21..29);
+        assert_eq!(diagnostic.labels[1].range, 25..29);
+        assert_eq!(source.overflow_error().message, "arithmetic overflow");
+    }
+
+    #[test]
+    fn programs_share_top_level_bindings() {
+        let programs = propane_test_support::shared_bindings();
+
+        let mut lowerer = Lowerer::new();
+        for (program, inference) in &programs {
+            lowerer.add(program, inference).unwrap();
+        }
+        let module = lowerer.finish();
+
+        assert_eq!(module.programs, [FunctionId(0), FunctionId(1)]);
+        assert_eq!(module.functions[1].name, "main.1");
+        assert_eq!(module.globals, [Global { name: "a".to_string(), ty: Type::Int }]);
+        verify(&module).unwrap();
+    }
+
+    #[test]
+    fn lowering_errors() {
+        let diagnostic = lower_error("fun make(n: int) { fun get() { n } get() } make(1);");
+        assert_eq!(diagnostic.message, "cannot capture `n` in a compiled function");
+
+        let diagnostic = lower_error("fun id(x) { x } let f = id; f(1); f(true);");
+        assert_eq!(diagnostic.message, "cannot use `f` at more than one type in compiled code");
+        assert_eq!(diagnostic.labels[0].range, 28..29);
+
+        let diagnostic = lower_error(r#"if true { return 1; } "a";"#);
+        assert_eq!(diagnostic.message, "a compiled program must always produce the same type");
+    }
+
+    #[test]
+    fn lowered_code_verifies_and_round_trips() {
+        let programs = [
+            "return 7 / 2 - -1;",
+            "return 1.0 != 1.0;",
+            "return 'a' < 'b';",
+            r#"let s = "a\n" + "\u{1F600}'"; return s < "b";"#,
+            "return !(1 >= 2);",
+            "let a = 1; let a = a + 1; return a;",
+            "let i = 1; for i in 0..5 { } return i;",
+            "let total = { let sum = 0; for i in 0..10 { let sum = sum + i; } sum }; return total;",
+            "while 1 > 2 { } return 1;",
+            "fun fib(n) { if n < 2 { return n; } fib(n - 1) + fib(n - 2) } return fib(20);",
+            "for i in 0..5 { if i == 3 { return i * 10; } } return 0;",
+            "fun sign(n) { if n < 0 { -1 } else if n == 0 { 0 } else { 1 } } return sign(-5) + sign(0) * 10;",
+            "fun even(n) { if n == 0 { true } else { odd(n - 1) } } fun odd(n) { if n == 0 { false } else { even(n - 1) } } return even(10);",
+            "fun outer(n) { fun twice(x) { x * 2 } twice(n) + 1 } return outer(20);",
+            "fun nothing() { } return nothing() == nothing();",
+            "let nothing = if false { 1; }; nothing;",
+            "let a = 1; fun f() { a } let a = 2; return f();",
+            "fun inc(x: int): int { x + 1 } let f = inc; return f(1) + inc(2);",
+            "fun apply(f, x: int): int { f(x) } fun inc(x: int): int { x + 1 } return apply(inc, 1);",
+            "fun first(n: int): int { if n > 0 { return n; } 0 } return first(3);",
+            "return { return 1; 2 };",
+            "return 1e16 * -1.0 / 0.0;",
+        ];
+
+        for src in programs {
+            let module = lower_src(src);
+            verify(&module).unwrap_or_else(|errors| panic!("{src}: {errors:?}\n{module}"));
+
+            let text = module.to_string();
+            let parsed = parse(&text).unwrap_or_else(|error| panic!("{src}: {error}\n{text}"));
+            assert_eq!(parsed.to_string(), text, "{src}");
+        }
+    }
+
+    #[test]
+    fn parses_text() {
+        let module = parse(
+            r#"
+            ; Functions can be used before they are defined.
+            program @main() -> float {
+            block0:
+                v0: fun(float) -> float = func @half
+                v1: float = const inf
+                v2: float = call_indirect v0(v1)
+                v3: char = const '\''
+                v4: string = const "\"\u{1f600}\""
+                return v2
+            }
+
+            fun @half(float) -> float {
+            block0(v0: float):
+                v1: float = const 2.0
+                v2: float = div v0, v1
+                return v2
+            }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(module.programs, [FunctionId(0)]);
+        let main = &module.functions[0];
+        assert_eq!(main.blocks[0].instructions[1].kind, InstructionKind::Const(Constant::Float(f64::INFINITY)));
+        assert_eq!(main.blocks[0].instructions[3].kind, InstructionKind::Const(Constant::Char('\'')));
+        assert_eq!(main.blocks[0].instructions[4].kind, InstructionKind::Const(Constant::Str("\"😀\"".to_string())));
+        assert_eq!(main.blocks[0].instructions[2].kind, InstructionKind::CallIndirect(Value(0), vec![Value(1)]));
+        verify(&module).unwrap();
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text| parse(text).unwrap_err().to_string();
+
+        assert_eq!(error("fun @f() -> int {\nblock0:\n    v0: int = const true\n    return v0\n}"), "line 3: expected a constant of type `int`");
+        assert_eq!(error("fun @f() -> int {\nblock0:\n    return v1\n}"), "line 1: `v0` is never defined in `@f`");
+        assert_eq!(error("fun @f() -> int {\nblock1:\n}"), "line 2: expected `block0`");
+        assert_eq!(error("fun @f() -> () {\nblock0:\n    v0: () = call @g()\n    return v0\n}"), "line 3: there is no function `@g`");
+        assert_eq!(error("fun @f() -> () {\nblock0:\n    v0: () = frob\n}"), "line 3: unknown instruction `frob`");
+        assert_eq!(error("fun @f() -> () {\nblock0:\n    jump block1\n}"), "line 1: `@f` jumps to `block1`, which is not defined");
+        assert_eq!(error("global $g: int\nglobal $g: int"), "line 2: the global `g` is defined more than once");
+        assert_eq!(error("program @main() -> int"), "line 1: expected `{`, found the end of the input");
+    }
+
+    #[test]
+    fn verifier_errors() {
+        // Operands of the wrong type.
+        assert_eq!(
+            verify_text(
+                "fun @f(int, float) -> int {
+                block0(v0: int, v1: float):
+                    v2: int = add v0, v1
+                    v3: bool = not v0
+                    v4: string = const \"a\"
+                    v5: string = mul v4, v4
+                    return v1
+                }"
+            ),
+            Err(vec![
+                "in `@f`: `add` is applied to v0 of type `int` and v1 of type `float`".to_string(),
+                "in `@f`: `not` cannot be applied to `int`".to_string(),
+                "in `@f`: `mul` cannot be applied to `string`".to_string(),
+                "in `@f`: the result must have type `int`, but v1 has type `float`".to_string(),
+            ])
+        );
+
+        // Uses that aren't dominated by their definitions.
+        assert_eq!(
+            verify_text(
+                "fun @f(bool) -> int {
+                block0(v0: bool):
+                    branch v0, block1, block2
+                block1:
+                    v1: int = const 1
+                    jump block2
+                block2:
+                    return v1
+                }"
+            ),
+            Err(vec!["in `@f`: v1 is used in block2 where it may not have been defined".to_string()])
+        );
+        assert_eq!(
+            verify_text(
+                "fun @f() -> int {
+                block0:
+                    v0: int = add v1, v1
+                    v1: int = const 1
+                    return v0
+                }"
+            ),
+            Err(vec![
+                "in `@f`: v1 is used in block0 where it may not have been defined".to_string(),
+                "in `@f`: v1 is used in block0 where it may not have been defined".to_string(),
+            ])
+        );
+
+        // Malformed control flow.
+        assert_eq!(
+            verify_text(
+                "fun @f(int) -> int {
+                block0(v0: int):
+                    v1: int = const 1
+                    branch v1, block1(v0), block0(v0)
+                block1:
+                    return v0
+                block2:
+                    return v0
+                }"
+            ),
+            Err(vec![
+                "in `@f`: block0 is the entry block, but is jumped to".to_string(),
+                "in `@f`: block2 is unreachable".to_string(),
+                "in `@f`: the branch condition must have type `bool`, but v1 has type `int`".to_string(),
+                "in `@f`: block1 takes 0 arguments, but is passed 1".to_string(),
+            ])
+        );
+
+        // Calls and globals.
+        assert_eq!(
+            verify_text(
+                "global $g: bool
+
+                program @main(int) -> () {
+                block0(v0: int):
+                    v1: () = call @main(v0, v0)
+                    global.set $g, v0
+                    v2: int = global.get $g
+                    v3: () = call_indirect v0()
+                    return v1
+                }"
+            ),
+            Err(vec![
+                "in `@main`: programs cannot have parameters".to_string(),
+                "in `@main`: `@main` takes 1 arguments, but is passed 2".to_string(),
+                "in `@main`: `$g` must have type `bool`, but v0 has type `int`".to_string(),
+                "in `@main`: v2 has type `int`, but its instruction produces `bool`".to_string(),
+                "in `@main`: v0 is called, but has type `int`".to_string(),
+            ])
+        );
+    }
+}
@@ -0,0 +1,673 @@
+use std::collections::HashSet;
+use std::ops::Range;
+use std::rc::Rc;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use propane_parser::expression::{Expression, ExpressionKind, Ident, Literal, Operator, Statement, StatementKind};
+use propane_typeck::{Inference, Type};
+
+use crate::ir::{
+    self, BinaryOperator, Block, BlockCall, BlockData, Constant, FunctionId, GlobalId, Instruction, InstructionKind,
+    Source, Terminator, UnaryOperator, Value,
+};
+use crate::mono::{self, Binding, Env, FunctionDecl, Instance, Instances, Scope};
+
+/// What a variable is lowered to.
+#[derive(Clone)]
+enum Variable {
+    /// A value of the function it is local to.
+    Value(Value),
+    Global(GlobalId),
+}
+
+/// The module being lowered, and the function instances in it.
+#[derive(Default)]
+struct Context<'a> {
+    module: ir::Module,
+    instances: Instances<'a, Variable, FunctionId>,
+    /// The names given to functions and globals so far.
+    names: HashSet<String>,
+    next_id: usize,
+    /// What the module is compiled to, for errors.
+    target: Option<&'static str>,
+}
+
+/// Lowers programs that have been type checked one after another, like
+/// `propanec run` runs them, to a single [ir::Module].
+#[derive(Default)]
+pub struct Lowerer<'a> {
+    context: Context<'a>,
+    /// The top-level bindings of the programs so far.
+    env: Env<'a, Variable>,
+}
+
+impl<'a> Lowerer<'a> {
+    pub fn new() -> Lowerer<'a> {
+        Lowerer::default()
+    }
+
+    /// A lowerer for a backend compiling to `target`, such as `"native
+    /// code"`, which errors about what it can't compile mention.
+    pub fn for_target(target: &'static str) -> Lowerer<'a> {
+        let mut lowerer = Lowerer::new();
+        lowerer.context.target = Some(target);
+
+        lowerer
+    }
+
+    /// Adds a program as returned by [propane_parser::parse], along with the
+    /// types [propane_typeck::TypeChecker] inferred for it.
+    pub fn add(&mut self, program: &'a Expression, inference: &'a Inference) -> Result<(), Diagnostic<FileId>> {
+        let name = self.context.fresh_name("main");
+        let id = self.context.reserve(name);
+        let scope = Scope::program(self.context.fresh_id(), &inference.types, self.env.clone());
+        let mut function = FunctionLowerer::new(&mut self.context, scope);
+
+        let (result, value) = match &program.kind {
+            ExpressionKind::StmtExpr(statements) => {
+                let result = match statements.last().map(|statement| &statement.kind) {
+                    Some(StatementKind::Expression(value) | StatementKind::Return { value }) => {
+                        function.ir_type(&function.scope.type_of(value.span))
+                    }
+                    _ => ir::Type::Unit,
+                };
+
+                function.program_result = Some(result.clone());
+                let value = match function.statements(statements, true)? {
+                    Some(value) => value,
+                    None => function.unit(),
+                };
+
+                (result, value)
+            }
+            _ => {
+                let result = function.ir_type(&function.scope.type_of(program.span));
+
+                function.program_result = Some(result.clone());
+                (result, function.expression(program)?)
+            }
+        };
+
+        self.env = function.scope.env.clone();
+        function.finish(id, vec![], result, value);
+        self.context.module.programs.push(id);
+
+        self.context.lower_pending()
+    }
+
+    /// The module lowered from the programs added so far.
+    pub fn module(&self) -> &ir::Module {
+        &self.context.module
+    }
+
+    pub fn finish(self) -> ir::Module {
+        self.context.module
+    }
+}
+
+impl<'a> Context<'a> {
+    fn fresh_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// A name for a function or global called `name` in the source, made
+    /// unique with a numeric suffix.
+    fn fresh_name(&mut self, name: &str) -> String {
+        let mut unique = name.to_string();
+        let mut suffix = 0;
+
+        while !self.names.insert(unique.clone()) {
+            suffix += 1;
+            unique = format!("{name}.{suffix}");
+        }
+
+        unique
+    }
+
+    /// Reserves an id for a function called `name` that is yet to be lowered.
+    fn reserve(&mut self, name: String) -> FunctionId {
+        self.module.functions.push(ir::Function {
+            name,
+            parameters: vec![],
+            result: ir::Type::Unit,
+            values: vec![],
+            blocks: vec![],
+        });
+
+        FunctionId(self.module.functions.len() - 1)
+    }
+
+    /// The id of `decl` instantiated at `ty`, queueing the instance to be
+    /// lowered if it is new.
+    fn instance(&mut self, decl: &Rc<FunctionDecl<'a, Variable>>, ty: Type) -> FunctionId {
+        if let Some(id) = self.instances.get(decl, &ty) {
+            return id;
+        }
+
+        let name = self.fresh_name(&decl.name.name);
+        let id = self.reserve(name);
+        self.instances.insert(decl, ty, id);
+
+        id
+    }
+
+    fn lower_pending(&mut self) -> Result<(), Diagnostic<FileId>> {
+        while let Some(instance) = self.instances.pop() {
+            self.lower_instance(instance)?;
+        }
+
+        Ok(())
+    }
+
+    fn lower_instance(&mut self, instance: Instance<'a, Variable, FunctionId>) -> Result<(), Diagnostic<FileId>> {
+        let decl = instance.decl.clone();
+        let (parameters, ret) = instance.signature();
+
+        let id = self.fresh_id();
+        let mut function = FunctionLowerer::new(self, instance.scope(id));
+
+        let mut parameter_types = vec![];
+        for (parameter, ty) in decl.parameters.iter().zip(parameters) {
+            let ty = function.ir_type(ty);
+            let value = function.value(ty.clone());
+            function.blocks[0].parameters.push(value);
+
+            function.scope.define_local(&parameter.name.name, Variable::Value(value));
+            parameter_types.push(ty);
+        }
+
+        let result = function.ir_type(ret);
+        let value = function.expression(decl.body)?;
+        function.finish(instance.id, parameter_types, result, value);
+
+        Ok(())
+    }
+}
+
+/// A block whose terminator may not have been lowered yet.
+#[derive(Default)]
+struct PartialBlock {
+    parameters: Vec<Value>,
+    instructions: Vec<Instruction>,
+    terminator: Option<Terminator>,
+}
+
+/// Lowers the body of one function, either a program or an instance of a
+/// Propane function.
+struct FunctionLowerer<'c, 'a> {
+    context: &'c mut Context<'a>,
+    scope: Scope<'a, Variable>,
+    /// For a program, the type its `return`s and final value must have.
+    program_result: Option<ir::Type>,
+    values: Vec<ir::Type>,
+    blocks: Vec<PartialBlock>,
+    /// The block instructions are added to.
+    current: Block,
+}
+
+impl<'c, 'a> FunctionLowerer<'c, 'a> {
+    fn new(context: &'c mut Context<'a>, scope: Scope<'a, Variable>) -> FunctionLowerer<'c, 'a> {
+        FunctionLowerer {
+            context,
+            scope,
+            program_result: None,
+            values: vec![],
+            blocks: vec![PartialBlock::default()],
+            current: Block(0),
+        }
+    }
+
+    /// Lowers `statements`, returning the value of the last one if it is an
+    /// expression statement. `globals` makes `let`s define global variables,
+    /// for the top level of a program.
+    fn statements(&mut self, statements: &'a [Statement], globals: bool) -> Result<Option<Value>, Diagnostic<FileId>> {
+        let mut decls = self.scope.declare_functions(statements);
+
+        let mut last = None;
+
+        for statement in statements {
+            last = None;
+
+            match &statement.kind {
+                StatementKind::Let { name, value } => {
+                    let value = self.expression(value)?;
+
+                    if globals {
+                        let ty = self.ir_type(&self.scope.type_of(name.span));
+                        let global = GlobalId(self.context.module.globals.len());
+                        let global_name = self.context.fresh_name(&name.name);
+                        self.context.module.globals.push(ir::Global { name: global_name, ty });
+                        self.push(Instruction { result: None, kind: InstructionKind::GlobalSet(global, value), source: None });
+
+                        self.scope.define_global(&name.name, Variable::Global(global));
+                    } else {
+                        self.scope.define_local(&name.name, Variable::Value(value));
+                    }
+                }
+                StatementKind::Return { value } => {
+                    let lowered = self.expression(value)?;
+
+                    if let Some(result) = &self.program_result {
+                        let ty = self.scope.type_of(value.span);
+
+                        if self.ir_type(&ty) != *result {
+                            return Err(program_result_error(value, &ty, self.context.target));
+                        }
+                    }
+
+                    self.terminate(Terminator::Return(lowered));
+                    // Anything after the `return` is unreachable.
+                    self.current = self.block();
+                }
+                StatementKind::While { condition, body } => {
+                    let header = self.block();
+                    let body_block = self.block();
+                    let exit = self.block();
+
+                    self.terminate(jump(header, vec![]));
+                    self.current = header;
+                    let condition = self.expression(condition)?;
+                    self.terminate(Terminator::Branch {
+                        condition,
+                        then_target: BlockCall { block: body_block, arguments: vec![] },
+                        else_target: BlockCall { block: exit, arguments: vec![] },
+                    });
+
+                    self.current = body_block;
+                    self.expression(body)?;
+                    self.terminate(jump(header, vec![]));
+
+                    self.current = exit;
+                }
+                StatementKind::For { variable, start, end, body } => {
+                    let start = self.expression(start)?;
+                    let end = self.expression(end)?;
+
+                    let header = self.block();
+                    let body_block = self.block();
+                    let exit = self.block();
+
+                    let counter = self.value(ir::Type::Int);
+                    self.blocks[header.0].parameters.push(counter);
+                    self.terminate(jump(header, vec![start]));
+
+                    self.current = header;
+                    let condition = self.instruction(ir::Type::Bool, InstructionKind::Binary(BinaryOperator::Lt, counter, end), None);
+                    self.terminate(Terminator::Branch {
+                        condition,
+                        then_target: BlockCall { block: body_block, arguments: vec![] },
+                        else_target: BlockCall { block: exit, arguments: vec![] },
+                    });
+
+                    self.current = body_block;
+                    let env = self.scope.env.clone();
+                    self.scope.define_local(&variable.name, Variable::Value(counter));
+                    self.expression(body)?;
+                    self.scope.env = env;
+
+                    // The counter is less than `end`, so this can't overflow.
+                    let one = self.constant(Constant::Int(1));
+                    let next = self.instruction(ir::Type::Int, InstructionKind::Binary(BinaryOperator::Add, counter, one), None);
+                    self.terminate(jump(header, vec![next]));
+
+                    self.current = exit;
+                }
+                StatementKind::Function { .. } => {
+                    let decl = decls.next().expect("functions were declared on entering the block");
+
+                    decl.reach(&self.scope);
+                }
+                StatementKind::Expression(expression) => {
+                    last = Some(self.expression(expression)?);
+                }
+            }
+        }
+
+        Ok(last)
+    }
+
+    fn expression(&mut self, expression: &'a Expression) -> Result<Value, Diagnostic<FileId>> {
+        let ty = self.ir_type(&self.scope.type_of(expression.span));
+        let source = Source { file_id: expression.file_id, span: expression.span, divisor: None };
+
+        let value = match &expression.kind {
+            ExpressionKind::Binary { left, operator, right } => {
+                let operator = match operator {
+                    Operator::Plus => BinaryOperator::Add,
+                    Operator::Minus => BinaryOperator::Sub,
+                    Operator::Star => BinaryOperator::Mul,
+                    Operator::Slash => BinaryOperator::Div,
+                    Operator::EqEq => BinaryOperator::Eq,
+                    Operator::NotEq => BinaryOperator::Ne,
+                    Operator::Lt => BinaryOperator::Lt,
+                    Operator::LtEq => BinaryOperator::Le,
+                    Operator::Gt => BinaryOperator::Gt,
+                    Operator::GtEq => BinaryOperator::Ge,
+                    Operator::Not => unreachable!("`!` is not a binary operator"),
+                };
+
+                let left_value = self.expression(left)?;
+                let right_value = self.expression(right)?;
+                let divisor = (operator == BinaryOperator::Div).then_some(right.span);
+
+                let source = Source { divisor, ..source };
+
+                self.instruction(ty, InstructionKind::Binary(operator, left_value, right_value), Some(source))
+            }
+            ExpressionKind::Grouping(inner) => self.expression(inner)?,
+            ExpressionKind::Literal(literal) => {
+                let constant = match literal {
+                    Literal::Int(value) => Constant::Int(*value),
+                    Literal::Float(value) => Constant::Float(*value),
+                    Literal::Bool(value) => Constant::Bool(*value),
+                    Literal::Char(value) => Constant::Char(*value),
+                    Literal::Str(value) => Constant::Str(value.clone()),
+                };
+
+                self.instruction(ty, InstructionKind::Const(constant), Some(source))
+            }
+            ExpressionKind::Unary(operator, operand) => {
+                let operator = match operator {
+                    Operator::Not => UnaryOperator::Not,
+                    _ => UnaryOperator::Neg,
+                };
+                let operand = self.expression(operand)?;
+
+                self.instruction(ty, InstructionKind::Unary(operator, operand), Some(source))
+            }
+            ExpressionKind::Variable(ident) => match self.scope.lookup(ident, self.context.target)? {
+                Binding::Variable { value: Variable::Value(value), .. } => {
+                    if self.values[value.0] != ty {
+                        return Err(generic_value_error(ident, &self.scope.type_of(expression.span)));
+                    }
+
+                    value
+                }
+                Binding::Variable { value: Variable::Global(global), .. } => {
+                    if self.context.module.global(global).ty != ty {
+                        return Err(generic_value_error(ident, &self.scope.type_of(expression.span)));
+                    }
+
+                    self.instruction(ty, InstructionKind::GlobalGet(global), Some(source))
+                }
+                Binding::Function(decl) => {
+                    let function = self.context.instance(&decl, self.scope.type_of(expression.span));
+
+                    self.instruction(ty, InstructionKind::Function(function), Some(source))
+                }
+            },
+            ExpressionKind::Call { callee, args } => {
+                match self.scope.callee(callee) {
+                    Some(decl) => {
+                        let arguments = args.iter().map(|arg| self.expression(arg)).collect::<Result<_, _>>()?;
+                        let function = self.context.instance(&decl, self.scope.type_of(callee.span));
+
+                        self.instruction(ty, InstructionKind::Call(function, arguments), Some(source))
+                    }
+                    None => {
+                        let callee = self.expression(callee)?;
+                        let arguments = args.iter().map(|arg| self.expression(arg)).collect::<Result<_, _>>()?;
+
+                        self.instruction(ty, InstructionKind::CallIndirect(callee, arguments), Some(source))
+                    }
+                }
+            }
+            ExpressionKind::Block { statements, value } => {
+                let env = self.scope.env.clone();
+
+                self.statements(statements, false)?;
+                let value = match value {
+                    Some(value) => self.expression(value)?,
+                    None => self.unit(),
+                };
+
+                self.scope.env = env;
+
+                value
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                let condition = self.expression(condition)?;
+
+                let then_block = self.block();
+                let else_block = self.block();
+                let merge = self.block();
+                self.terminate(Terminator::Branch {
+                    condition,
+                    then_target: BlockCall { block: then_block, arguments: vec![] },
+                    else_target: BlockCall { block: else_block, arguments: vec![] },
+                });
+
+                // Values of type `()` aren't passed to the merge block.
+                let has_value = ty != ir::Type::Unit;
+
+                self.current = then_block;
+                let value = self.expression(then_branch)?;
+                self.terminate(jump(merge, if has_value { vec![value] } else { vec![] }));
+
+                self.current = else_block;
+                let value = match else_branch {
+                    Some(else_branch) => self.expression(else_branch)?,
+                    None => self.unit(),
+                };
+                self.terminate(jump(merge, if has_value { vec![value] } else { vec![] }));
+
+                self.current = merge;
+                if has_value {
+                    let value = self.value(ty);
+                    self.blocks[merge.0].parameters.push(value);
+
+                    value
+                } else {
+                    self.unit()
+                }
+            }
+            ExpressionKind::StmtExpr(statements) => {
+                let env = self.scope.env.clone();
+
+                let value = match self.statements(statements, false)? {
+                    Some(value) => value,
+                    None => self.unit(),
+                };
+
+                self.scope.env = env;
+
+                value
+            }
+            ExpressionKind::Error => return Err(mono::parse_error(expression)),
+        };
+
+        Ok(value)
+    }
+
+    /// The IR type of values of type `ty`.
+    fn ir_type(&self, ty: &Type) -> ir::Type {
+        match ty {
+            Type::Int => ir::Type::Int,
+            Type::Float => ir::Type::Float,
+            Type::Bool => ir::Type::Bool,
+            Type::Char => ir::Type::Char,
+            Type::Str => ir::Type::Str,
+            // Types that are still unknown are those of values that are
+            // never produced, such as a block ending in `return`.
+            Type::Unit | Type::Var(_) => ir::Type::Unit,
+            Type::Function { parameters, ret } => ir::Type::Function {
+                parameters: parameters.iter().map(|parameter| self.ir_type(parameter)).collect(),
+                ret: Box::new(self.ir_type(ret)),
+            },
+        }
+    }
+
+    fn value(&mut self, ty: ir::Type) -> Value {
+        self.values.push(ty);
+        Value(self.values.len() - 1)
+    }
+
+    fn block(&mut self) -> Block {
+        self.blocks.push(PartialBlock::default());
+        Block(self.blocks.len() - 1)
+    }
+
+    /// Adds an instruction producing a value of type `ty` to the current
+    /// block.
+    fn instruction(&mut self, ty: ir::Type, kind: InstructionKind, source: Option<Source>) -> Value {
+        let result = self.value(ty);
+        self.push(Instruction { result: Some(result), kind, source });
+
+        result
+    }
+
+    fn push(&mut self, instruction: Instruction) {
+        self.blocks[self.current.0].instructions.push(instruction);
+    }
+
+    fn constant(&mut self, constant: Constant) -> Value {
+        let ty = match &constant {
+            Constant::Int(_) => ir::Type::Int,
+            Constant::Float(_) => ir::Type::Float,
+            Constant::Bool(_) => ir::Type::Bool,
+            Constant::Char(_) => ir::Type::Char,
+            Constant::Str(_) => ir::Type::Str,
+            Constant::Unit => ir::Type::Unit,
+        };
+
+        self.instruction(ty, InstructionKind::Const(constant), None)
+    }
+
+    fn unit(&mut self) -> Value {
+        self.constant(Constant::Unit)
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        let block = &mut self.blocks[self.current.0];
+        debug_assert!(block.terminator.is_none(), "{} is already terminated", self.current);
+
+        block.terminator = Some(terminator);
+    }
+
+    /// Returns `value` from the current block and defines the function with
+    /// the given id, leaving out the blocks that can't be reached and the
+    /// values they define.
+    fn finish(mut self, id: FunctionId, parameters: Vec<ir::Type>, result: ir::Type, value: Value) {
+        self.terminate(Terminator::Return(value));
+
+        let mut block_ids = vec![None; self.blocks.len()];
+        let mut reachable = vec![];
+        let mut stack = vec![Block(0)];
+        while let Some(block) = stack.pop() {
+            if block_ids[block.0].is_some() {
+                continue;
+            }
+            block_ids[block.0] = Some(Block(0));
+            reachable.push(block);
+
+            let terminator = self.blocks[block.0].terminator.as_ref().expect("every block is terminated");
+            stack.extend(terminator.targets().iter().rev().map(|target| target.block));
+        }
+        // Keep the blocks in the order they were created.
+        reachable.sort();
+        for (i, block) in reachable.iter().enumerate() {
+            block_ids[block.0] = Some(Block(i));
+        }
+
+        let mut value_ids = vec![None; self.values.len()];
+        let mut defined = vec![];
+        for block in &reachable {
+            let block = &self.blocks[block.0];
+            defined.extend(&block.parameters);
+            defined.extend(block.instructions.iter().filter_map(|instruction| instruction.result));
+        }
+        defined.sort();
+        let values = defined
+            .iter()
+            .enumerate()
+            .map(|(i, value)| {
+                value_ids[value.0] = Some(Value(i));
+                self.values[value.0].clone()
+            })
+            .collect();
+
+        let value = |value: Value| value_ids[value.0].expect("values are used where they are defined");
+        let block_call = |target: BlockCall| BlockCall {
+            block: block_ids[target.block.0].expect("targets are reachable"),
+            arguments: target.arguments.into_iter().map(value).collect(),
+        };
+
+        let blocks = reachable
+            .into_iter()
+            .map(|block| {
+                let block = std::mem::take(&mut self.blocks[block.0]);
+
+                let instructions = block
+                    .instructions
+                    .into_iter()
+                    .map(|instruction| Instruction {
+                        result: instruction.result.map(value),
+                        kind: match instruction.kind {
+                            InstructionKind::Binary(operator, left, right) => {
+                                InstructionKind::Binary(operator, value(left), value(right))
+                            }
+                            InstructionKind::Unary(operator, operand) => InstructionKind::Unary(operator, value(operand)),
+                            InstructionKind::Call(function, arguments) => {
+                                InstructionKind::Call(function, arguments.into_iter().map(value).collect())
+                            }
+                            InstructionKind::CallIndirect(callee, arguments) => {
+                                InstructionKind::CallIndirect(value(callee), arguments.into_iter().map(value).collect())
+                            }
+                            InstructionKind::GlobalSet(global, operand) => InstructionKind::GlobalSet(global, value(operand)),
+                            kind @ (InstructionKind::Const(_) | InstructionKind::Function(_) | InstructionKind::GlobalGet(_)) => kind,
+                        },
+                        source: instruction.source,
+                    })
+                    .collect();
+
+                let terminator = match block.terminator.expect("every block is terminated") {
+                    Terminator::Jump(target) => Terminator::Jump(block_call(target)),
+                    Terminator::Branch { condition, then_target, else_target } => Terminator::Branch {
+                        condition: value(condition),
+                        then_target: block_call(then_target),
+                        else_target: block_call(else_target),
+                    },
+                    Terminator::Return(returned) => Terminator::Return(value(returned)),
+                };
+
+                BlockData { parameters: block.parameters.into_iter().map(value).collect(), instructions, terminator }
+            })
+            .collect();
+
+        let function = &mut self.context.module.functions[id.0];
+        *function = ir::Function { name: std::mem::take(&mut function.name), parameters, result, values, blocks };
+    }
+}
+
+fn jump(block: Block, arguments: Vec<Value>) -> Terminator {
+    Terminator::Jump(BlockCall { block, arguments })
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
+
+fn program_result_error(value: &Expression, ty: &Type, target: Option<&str>) -> Diagnostic<FileId> {
+    let program = match target {
+        Some(target) => format!("a program compiled to {target}"),
+        None => "a compiled program".to_string(),
+    };
+
+    Diagnostic::error()
+        .with_message(format!("{program} must always produce the same type"))
+        .with_labels(vec![
+            Label::primary(value.file_id, range(value.span))
+                .with_message(format!("this returns `{ty}`, unlike the program's last statement")),
+        ])
+}
+
+fn generic_value_error(ident: &Ident, ty: &Type) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message(format!("cannot use `{}` at more than one type in compiled code", ident.name))
+        .with_labels(vec![
+            Label::primary(ident.file_id, range(ident.span)).with_message(format!("this has type `{ty}`")),
+        ])
+        .with_notes(vec!["only functions declared with `fun` can be used at more than one type".to_string()])
+}
@@ -0,0 +1,317 @@
+//! Name resolution and monomorphization for compiling type checked programs,
+//! shared by the [Lowerer](crate::Lowerer) and the code generators.
+//!
+//! Names are resolved to the [Binding]s of an [Env], in which variables hold
+//! whatever a backend represents them with. A function declaration is
+//! compiled once for each type it is used at, as an [Instance] whose body is
+//! compiled in a [Scope] that knows its concrete types.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use propane_parser::expression::{Expression, ExpressionKind, Ident, Parameter, Statement, StatementKind};
+use propane_typeck::{Type, TypeVar};
+
+type Substitution = HashMap<TypeVar, Type>;
+
+/// A function declaration. It is compiled once for each type it is used at.
+pub struct FunctionDecl<'a, V> {
+    pub name: &'a Ident,
+    pub parameters: &'a [Parameter],
+    pub body: &'a Expression,
+    /// The types inferred for the program the function is declared in.
+    types: &'a HashMap<Span, Type>,
+    /// The types of the instance of the enclosing function that the
+    /// declaration belongs to.
+    substitution: Rc<Substitution>,
+    /// The bindings in scope at the declaration, set once it is reached.
+    env: RefCell<Option<Env<'a, V>>>,
+}
+
+#[derive(Clone)]
+pub enum Binding<'a, V> {
+    /// A variable, represented by the backend as `value`, local to the
+    /// function with the given id or, if there is none, global.
+    Variable { value: V, function: Option<usize> },
+    Function(Rc<FunctionDecl<'a, V>>),
+}
+
+/// The bindings in scope, innermost first. Scopes share their tails, so a
+/// function can keep the scope it was declared in.
+pub struct Env<'a, V>(Option<Rc<EnvEntry<'a, V>>>);
+
+struct EnvEntry<'a, V> {
+    name: &'a str,
+    binding: Binding<'a, V>,
+    next: Env<'a, V>,
+}
+
+impl<'a, V> Env<'a, V> {
+    pub fn define(&self, name: &'a str, binding: Binding<'a, V>) -> Env<'a, V> {
+        Env(Some(Rc::new(EnvEntry { name, binding, next: self.clone() })))
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&Binding<'a, V>> {
+        let mut env = self;
+
+        while let Some(entry) = &env.0 {
+            if entry.name == name {
+                return Some(&entry.binding);
+            }
+            env = &entry.next;
+        }
+
+        None
+    }
+}
+
+impl<V> Clone for Env<'_, V> {
+    fn clone(&self) -> Self {
+        Env(self.0.clone())
+    }
+}
+
+impl<V> Default for Env<'_, V> {
+    fn default() -> Self {
+        Env(None)
+    }
+}
+
+/// A function declaration at a particular type, waiting to be compiled.
+pub struct Instance<'a, V, I> {
+    pub decl: Rc<FunctionDecl<'a, V>>,
+    pub ty: Type,
+    /// What the backend calls the instance.
+    pub id: I,
+}
+
+/// The function instances used so far, each identified by the backend with
+/// an `I`, and those still waiting to be compiled.
+pub struct Instances<'a, V, I> {
+    /// The id of each instance, keyed by its declaration and type.
+    ids: HashMap<(*const FunctionDecl<'a, V>, Type), I>,
+    /// The declarations in `ids`, kept alive so that their addresses stay
+    /// unique.
+    decls: Vec<Rc<FunctionDecl<'a, V>>>,
+    pending: Vec<Instance<'a, V, I>>,
+}
+
+impl<V, I> Default for Instances<'_, V, I> {
+    fn default() -> Self {
+        Instances { ids: HashMap::new(), decls: vec![], pending: vec![] }
+    }
+}
+
+impl<'a, V, I: Clone> Instances<'a, V, I> {
+    /// The id of `decl` instantiated at `ty`, if it has been used before.
+    pub fn get(&self, decl: &Rc<FunctionDecl<'a, V>>, ty: &Type) -> Option<I> {
+        self.ids.get(&(Rc::as_ptr(decl), ty.clone())).cloned()
+    }
+
+    /// Records the id of a new instance, queueing it to be compiled.
+    pub fn insert(&mut self, decl: &Rc<FunctionDecl<'a, V>>, ty: Type, id: I) {
+        self.pending.push(Instance { decl: decl.clone(), ty: ty.clone(), id: id.clone() });
+        self.ids.insert((Rc::as_ptr(decl), ty), id);
+        self.decls.push(decl.clone());
+    }
+
+    /// The next instance waiting to be compiled.
+    pub fn pop(&mut self) -> Option<Instance<'a, V, I>> {
+        self.pending.pop()
+    }
+}
+
+impl<'a, V, I> Instance<'a, V, I> {
+    /// The scope to compile the instance's body in, as the function with the
+    /// given id. Its parameters are yet to be defined.
+    pub fn scope(&self, function: usize) -> Scope<'a, V> {
+        let decl = &self.decl;
+
+        let generic = substitute(&decl.types[&decl.name.span], &decl.substitution);
+        let mut substitution = (*decl.substitution).clone();
+        bind(&generic, &self.ty, &mut substitution);
+
+        let env = decl.env.borrow().clone().expect("functions are declared before they are compiled");
+
+        Scope { function, types: decl.types, substitution: Rc::new(substitution), env }
+    }
+
+    /// The types of the instance's parameters and result.
+    pub fn signature(&self) -> (&[Type], &Type) {
+        let Type::Function { parameters, ret } = &self.ty else {
+            unreachable!("functions have function types");
+        };
+
+        (parameters, ret)
+    }
+}
+
+impl<'a, V> FunctionDecl<'a, V> {
+    /// Records the bindings in scope where the declaration is reached, which
+    /// its body can use.
+    pub fn reach(&self, scope: &Scope<'a, V>) {
+        *self.env.borrow_mut() = Some(scope.env.clone());
+    }
+}
+
+/// The function being compiled: the types inferred for its program, made
+/// concrete for the instance being compiled, and the bindings in scope.
+pub struct Scope<'a, V> {
+    /// Distinguishes the variables of this function from those of others.
+    pub function: usize,
+    types: &'a HashMap<Span, Type>,
+    substitution: Rc<Substitution>,
+    pub env: Env<'a, V>,
+}
+
+impl<'a, V: Clone> Scope<'a, V> {
+    /// The scope of a program, which sees `env`, the top-level bindings of
+    /// the programs before it.
+    pub fn program(function: usize, types: &'a HashMap<Span, Type>, env: Env<'a, V>) -> Scope<'a, V> {
+        Scope { function, types, substitution: Rc::default(), env }
+    }
+
+    pub fn type_of(&self, span: Span) -> Type {
+        let ty = self.types.get(&span).expect("the type checker records the type of every expression");
+
+        substitute(ty, &self.substitution)
+    }
+
+    /// Declares the functions in `statements`, which are visible throughout
+    /// the block they are declared in, returning them in order so that each
+    /// can be [reached](FunctionDecl::reach) at its declaration.
+    pub fn declare_functions(&mut self, statements: &'a [Statement]) -> std::vec::IntoIter<Rc<FunctionDecl<'a, V>>> {
+        let mut decls = vec![];
+
+        for statement in statements {
+            if let StatementKind::Function { name, parameters, body, .. } = &statement.kind {
+                let decl = Rc::new(FunctionDecl {
+                    name,
+                    parameters,
+                    body,
+                    types: self.types,
+                    substitution: self.substitution.clone(),
+                    env: RefCell::new(None),
+                });
+
+                self.env = self.env.define(&name.name, Binding::Function(decl.clone()));
+                decls.push(decl);
+            }
+        }
+
+        decls.into_iter()
+    }
+
+    /// Defines a variable of this function.
+    pub fn define_local(&mut self, name: &'a str, value: V) {
+        self.env = self.env.define(name, Binding::Variable { value, function: Some(self.function) });
+    }
+
+    pub fn define_global(&mut self, name: &'a str, value: V) {
+        self.env = self.env.define(name, Binding::Variable { value, function: None });
+    }
+
+    /// What `ident` refers to. Variables of an enclosing function can't be
+    /// captured, which is reported as an error in code compiled to `target`.
+    pub fn lookup(&self, ident: &Ident, target: Option<&str>) -> Result<Binding<'a, V>, Diagnostic<FileId>> {
+        match self.env.lookup(&ident.name) {
+            Some(Binding::Variable { function: Some(function), .. }) if *function != self.function => {
+                Err(capture_error(ident, target))
+            }
+            Some(binding) => Ok(binding.clone()),
+            None => Err(undefined_error(ident)),
+        }
+    }
+
+    /// The function declaration `callee` names, if any, for calling it
+    /// directly.
+    pub fn callee(&self, callee: &Expression) -> Option<Rc<FunctionDecl<'a, V>>> {
+        let ExpressionKind::Variable(ident) = &callee.kind else {
+            return None;
+        };
+
+        match self.env.lookup(&ident.name) {
+            Some(Binding::Function(decl)) => Some(decl.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn substitute(ty: &Type, substitution: &Substitution) -> Type {
+    match ty {
+        Type::Function { parameters, ret } => Type::Function {
+            parameters: parameters.iter().map(|parameter| substitute(parameter, substitution)).collect(),
+            ret: Box::new(substitute(ret, substitution)),
+        },
+        Type::Var(var) => substitution.get(var).cloned().unwrap_or(Type::Var(*var)),
+        ty => ty.clone(),
+    }
+}
+
+/// Extends `substitution` to map the variables in `generic` to the parts of
+/// `concrete` in their place.
+fn bind(generic: &Type, concrete: &Type, substitution: &mut Substitution) {
+    match (generic, concrete) {
+        (Type::Var(var), ty) => {
+            substitution.entry(*var).or_insert_with(|| ty.clone());
+        }
+        (
+            Type::Function { parameters: generic_parameters, ret: generic_ret },
+            Type::Function { parameters: concrete_parameters, ret: concrete_ret },
+        ) => {
+            for (generic, concrete) in generic_parameters.iter().zip(concrete_parameters) {
+                bind(generic, concrete, substitution);
+            }
+            bind(generic_ret, concrete_ret, substitution);
+        }
+        _ => {}
+    }
+}
+
+pub fn has_vars(ty: &Type) -> bool {
+    match ty {
+        Type::Function { parameters, ret } => parameters.iter().any(has_vars) || has_vars(ret),
+        Type::Var(_) => true,
+        _ => false,
+    }
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
+
+/// The error for using a variable of an enclosing function in code compiled
+/// to `target`, or compiled in general if there is none.
+pub fn capture_error(ident: &Ident, target: Option<&str>) -> Diagnostic<FileId> {
+    let (function, functions) = match target {
+        Some(target) => (format!("a function compiled to {target}"), format!("functions compiled to {target}")),
+        None => ("a compiled function".to_string(), "compiled functions".to_string()),
+    };
+
+    Diagnostic::error()
+        .with_message(format!("cannot capture `{}` in {function}", ident.name))
+        .with_labels(vec![
+            Label::primary(ident.file_id, range(ident.span)).with_message("captured from an enclosing function"),
+        ])
+        .with_notes(vec![format!("{functions} can only use their own variables and top-level bindings")])
+}
+
+pub fn undefined_error(ident: &Ident) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message(format!("cannot find value `{}` in this scope", ident.name))
+        .with_labels(vec![
+            Label::primary(ident.file_id, range(ident.span)).with_message("not found in this scope"),
+        ])
+}
+
+pub fn parse_error(expression: &Expression) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("cannot compile code that failed to parse")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span)),
+        ])
+}
@@ -0,0 +1,611 @@
+//! Reads the textual form of the IR that [Module]'s `Display` writes.
+//!
+//! Values are named `v0`, `v1`, ... and blocks `block0`, `block1`, ..., with
+//! the numbers being their indices, so blocks must be written in order and
+//! every value up to the highest must be defined. Comments run from `;` to
+//! the end of the line.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::ir::{
+    BinaryOperator, Block, BlockCall, BlockData, Constant, Function, FunctionId, Global, GlobalId, Instruction,
+    InstructionKind, Module, Terminator, Type, UnaryOperator, Value,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses a module from its textual form.
+pub fn parse(src: &str) -> Result<Module, ParseError> {
+    let tokens = tokenize(src)?;
+    let mut parser = Parser { tokens, position: 0, functions: HashMap::new(), globals: HashMap::new(), used_values: 0 };
+
+    parser.declare_names()?;
+    parser.module()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Keywords, opcodes, value and block names and numbers.
+    Word(String),
+    /// `@name`.
+    Function(String),
+    /// `$name`.
+    Global(String),
+    Str(String),
+    Char(char),
+    Punct(char),
+    Arrow,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{word}`"),
+            Token::Function(name) => write!(f, "`@{name}`"),
+            Token::Global(name) => write!(f, "`${name}`"),
+            Token::Str(value) => write!(f, "`{value:?}`"),
+            Token::Char(value) => write!(f, "`{value:?}`"),
+            Token::Punct(ch) => write!(f, "`{ch}`"),
+            Token::Arrow => write!(f, "`->`"),
+        }
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Token, usize)>, ParseError> {
+    let mut tokens = vec![];
+    let mut chars = src.chars().peekable();
+    let mut line = 1;
+
+    while let Some(ch) = chars.next() {
+        let token = match ch {
+            '\n' => {
+                line += 1;
+                continue;
+            }
+            ch if ch.is_whitespace() => continue,
+            ';' => {
+                while chars.next_if(|&ch| ch != '\n').is_some() {}
+                continue;
+            }
+            '-' if chars.next_if_eq(&'>').is_some() => Token::Arrow,
+            '(' | ')' | '{' | '}' | ',' | ':' | '=' => Token::Punct(ch),
+            '@' | '$' => {
+                let mut name = String::new();
+                while let Some(ch) = chars.next_if(|&ch| !ch.is_whitespace() && !"(),:{}=;".contains(ch)) {
+                    name.push(ch);
+                }
+                if name.is_empty() {
+                    return Err(ParseError { line, message: format!("expected a name after `{ch}`") });
+                }
+
+                if ch == '@' {
+                    Token::Function(name)
+                } else {
+                    Token::Global(name)
+                }
+            }
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => value.push(escape(&mut chars, line)?),
+                        Some(ch) => value.push(ch),
+                        None => return Err(ParseError { line, message: "unterminated string".to_string() }),
+                    }
+                }
+
+                Token::Str(value)
+            }
+            '\'' => {
+                let value = match chars.next() {
+                    Some('\\') => escape(&mut chars, line)?,
+                    Some(ch) => ch,
+                    None => return Err(ParseError { line, message: "unterminated character".to_string() }),
+                };
+                if chars.next() != Some('\'') {
+                    return Err(ParseError { line, message: "unterminated character".to_string() });
+                }
+
+                Token::Char(value)
+            }
+            ch if ch.is_alphanumeric() || "_.-+".contains(ch) => {
+                let mut word = String::from(ch);
+                while let Some(ch) = chars.next_if(|&ch| ch.is_alphanumeric() || "_.-+".contains(ch)) {
+                    word.push(ch);
+                }
+
+                Token::Word(word)
+            }
+            ch => return Err(ParseError { line, message: format!("unexpected character `{ch}`") }),
+        };
+
+        tokens.push((token, line));
+    }
+
+    Ok(tokens)
+}
+
+/// Reads the rest of an escape sequence as written by `{:?}`.
+fn escape(chars: &mut std::iter::Peekable<std::str::Chars<'_>>, line: usize) -> Result<char, ParseError> {
+    let error = || ParseError { line, message: "invalid escape sequence".to_string() };
+
+    match chars.next().ok_or_else(error)? {
+        'n' => Ok('\n'),
+        'r' => Ok('\r'),
+        't' => Ok('\t'),
+        '0' => Ok('\0'),
+        ch @ ('\\' | '\'' | '"') => Ok(ch),
+        'u' => {
+            if chars.next() != Some('{') {
+                return Err(error());
+            }
+            let mut digits = String::new();
+            while let Some(ch) = chars.next_if(|&ch| ch != '}') {
+                digits.push(ch);
+            }
+            chars.next();
+
+            u32::from_str_radix(&digits, 16).ok().and_then(char::from_u32).ok_or_else(error)
+        }
+        _ => Err(error()),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    functions: HashMap<String, FunctionId>,
+    globals: HashMap<String, GlobalId>,
+    /// One more than the highest value used in the current function.
+    used_values: usize,
+}
+
+impl Parser {
+    /// Gives each function and global its id, so they can be referred to
+    /// before they are defined.
+    fn declare_names(&mut self) -> Result<(), ParseError> {
+        for window in self.tokens.windows(2) {
+            let duplicate = match window {
+                [(Token::Word(keyword), _), (Token::Function(name), line)] if keyword == "fun" || keyword == "program" => {
+                    let id = FunctionId(self.functions.len());
+
+                    self.functions.insert(name.clone(), id).is_some().then_some(("function", name, *line))
+                }
+                [(Token::Word(keyword), _), (Token::Global(name), line)] if keyword == "global" => {
+                    let id = GlobalId(self.globals.len());
+
+                    self.globals.insert(name.clone(), id).is_some().then_some(("global", name, *line))
+                }
+                _ => None,
+            };
+
+            if let Some((kind, name, line)) = duplicate {
+                return Err(ParseError { line, message: format!("the {kind} `{name}` is defined more than once") });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn module(&mut self) -> Result<Module, ParseError> {
+        let mut module = Module::default();
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Word(word) if word == "global" => {
+                    self.advance();
+                    let name = self.global_name()?;
+                    self.expect(Token::Punct(':'))?;
+                    let ty = self.ty()?;
+
+                    module.globals.push(Global { name, ty });
+                }
+                Token::Word(word) if word == "fun" || word == "program" => {
+                    if word == "program" {
+                        module.programs.push(FunctionId(module.functions.len()));
+                    }
+                    self.advance();
+
+                    module.functions.push(self.function()?);
+                }
+                _ => return Err(self.unexpected("`global`, `fun` or `program`")),
+            }
+        }
+
+        Ok(module)
+    }
+
+    fn function(&mut self) -> Result<Function, ParseError> {
+        let line = self.line();
+        let name = match self.advance() {
+            Some(Token::Function(name)) => name,
+            _ => return Err(self.unexpected_previous("a function name")),
+        };
+
+        self.expect(Token::Punct('('))?;
+        let mut parameters = vec![];
+        if !self.eat(&Token::Punct(')')) {
+            loop {
+                parameters.push(self.ty()?);
+                if self.eat(&Token::Punct(')')) {
+                    break;
+                }
+                self.expect(Token::Punct(','))?;
+            }
+        }
+        self.expect(Token::Arrow)?;
+        let result = self.ty()?;
+        self.expect(Token::Punct('{'))?;
+
+        let mut values: Vec<Option<Type>> = vec![];
+        let mut blocks = vec![];
+        self.used_values = 0;
+        let mut max_block = 0;
+
+        while !self.eat(&Token::Punct('}')) {
+            let block = self.block_name()?;
+            if block.0 != blocks.len() {
+                return Err(self.error_previous(format!("expected `block{}`", blocks.len())));
+            }
+
+            let mut block_parameters = vec![];
+            if self.eat(&Token::Punct('(')) {
+                loop {
+                    let value = self.define(&mut values)?;
+                    block_parameters.push(value);
+                    if self.eat(&Token::Punct(')')) {
+                        break;
+                    }
+                    self.expect(Token::Punct(','))?;
+                }
+            }
+            self.expect(Token::Punct(':'))?;
+
+            let mut instructions = vec![];
+            let terminator = loop {
+                if let Some(terminator) = self.terminator(&mut max_block)? {
+                    break terminator;
+                }
+                instructions.push(self.instruction(&mut values)?);
+            };
+
+            blocks.push(BlockData { parameters: block_parameters, instructions, terminator });
+        }
+
+        if blocks.is_empty() {
+            return Err(ParseError { line, message: format!("`@{name}` has no blocks") });
+        }
+        if max_block >= blocks.len() {
+            return Err(ParseError { line, message: format!("`@{name}` jumps to `block{max_block}`, which is not defined") });
+        }
+        values.resize(values.len().max(self.used_values), None);
+        let values = values
+            .into_iter()
+            .enumerate()
+            .map(|(i, ty)| ty.ok_or_else(|| ParseError { line, message: format!("`v{i}` is never defined in `@{name}`") }))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Function { name, parameters, result, values, blocks })
+    }
+
+    /// Parses a terminator if the next token starts one.
+    fn terminator(&mut self, max_block: &mut usize) -> Result<Option<Terminator>, ParseError> {
+        let Some(Token::Word(word)) = self.peek() else {
+            return Ok(None);
+        };
+
+        let terminator = match word.as_str() {
+            "jump" => {
+                self.advance();
+
+                Terminator::Jump(self.block_call(max_block)?)
+            }
+            "branch" => {
+                self.advance();
+                let condition = self.value()?;
+                self.expect(Token::Punct(','))?;
+                let then_target = self.block_call(max_block)?;
+                self.expect(Token::Punct(','))?;
+                let else_target = self.block_call(max_block)?;
+
+                Terminator::Branch { condition, then_target, else_target }
+            }
+            "return" => {
+                self.advance();
+
+                Terminator::Return(self.value()?)
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(terminator))
+    }
+
+    fn instruction(&mut self, values: &mut Vec<Option<Type>>) -> Result<Instruction, ParseError> {
+        let result = match self.peek() {
+            Some(Token::Word(word)) if word.starts_with('v') && word[1..].parse::<usize>().is_ok() => {
+                let value = self.define(values)?;
+                self.expect(Token::Punct('='))?;
+
+                Some(value)
+            }
+            _ => None,
+        };
+
+        let opcode = match self.advance() {
+            Some(Token::Word(word)) => word,
+            _ => return Err(self.unexpected_previous("an instruction")),
+        };
+
+        let kind = match opcode.as_str() {
+            "const" => {
+                let Some(result) = result else {
+                    return Err(self.error_previous("`const` must define a value".to_string()));
+                };
+                let ty = values[result.0].clone().expect("the result was just defined");
+
+                InstructionKind::Const(self.constant(&ty)?)
+            }
+            "neg" => InstructionKind::Unary(UnaryOperator::Neg, self.value()?),
+            "not" => InstructionKind::Unary(UnaryOperator::Not, self.value()?),
+            "call" => {
+                let function = self.function_name()?;
+
+                InstructionKind::Call(function, self.arguments()?)
+            }
+            "call_indirect" => {
+                let callee = self.value()?;
+
+                InstructionKind::CallIndirect(callee, self.arguments()?)
+            }
+            "func" => InstructionKind::Function(self.function_name()?),
+            "global.get" => InstructionKind::GlobalGet(self.global()?),
+            "global.set" => {
+                let global = self.global()?;
+                self.expect(Token::Punct(','))?;
+
+                InstructionKind::GlobalSet(global, self.value()?)
+            }
+            opcode => {
+                let operator = match opcode {
+                    "add" => BinaryOperator::Add,
+                    "sub" => BinaryOperator::Sub,
+                    "mul" => BinaryOperator::Mul,
+                    "div" => BinaryOperator::Div,
+                    "eq" => BinaryOperator::Eq,
+                    "ne" => BinaryOperator::Ne,
+                    "lt" => BinaryOperator::Lt,
+                    "le" => BinaryOperator::Le,
+                    "gt" => BinaryOperator::Gt,
+                    "ge" => BinaryOperator::Ge,
+                    _ => return Err(self.error_previous(format!("unknown instruction `{opcode}`"))),
+                };
+                let left = self.value()?;
+                self.expect(Token::Punct(','))?;
+
+                InstructionKind::Binary(operator, left, self.value()?)
+            }
+        };
+
+        Ok(Instruction { result, kind, source: None })
+    }
+
+    fn constant(&mut self, ty: &Type) -> Result<Constant, ParseError> {
+        let constant = match (ty, self.advance()) {
+            (Type::Int, Some(Token::Word(word))) => word.parse().ok().map(Constant::Int),
+            (Type::Float, Some(Token::Word(word))) => word.parse().ok().map(Constant::Float),
+            (Type::Bool, Some(Token::Word(word))) => word.parse().ok().map(Constant::Bool),
+            (Type::Char, Some(Token::Char(value))) => Some(Constant::Char(value)),
+            (Type::Str, Some(Token::Str(value))) => Some(Constant::Str(value)),
+            (Type::Unit, Some(Token::Punct('('))) if self.eat(&Token::Punct(')')) => Some(Constant::Unit),
+            _ => None,
+        };
+
+        constant.ok_or_else(|| self.error_previous(format!("expected a constant of type `{ty}`")))
+    }
+
+    fn arguments(&mut self) -> Result<Vec<Value>, ParseError> {
+        let mut arguments = vec![];
+
+        self.expect(Token::Punct('('))?;
+        if self.eat(&Token::Punct(')')) {
+            return Ok(arguments);
+        }
+        loop {
+            arguments.push(self.value()?);
+            if self.eat(&Token::Punct(')')) {
+                return Ok(arguments);
+            }
+            self.expect(Token::Punct(','))?;
+        }
+    }
+
+    fn block_call(&mut self, max_block: &mut usize) -> Result<BlockCall, ParseError> {
+        let block = self.block_name()?;
+        *max_block = (*max_block).max(block.0);
+
+        let arguments = match self.peek() {
+            Some(Token::Punct('(')) => self.arguments()?,
+            _ => vec![],
+        };
+
+        Ok(BlockCall { block, arguments })
+    }
+
+    fn ty(&mut self) -> Result<Type, ParseError> {
+        let ty = match self.advance() {
+            Some(Token::Word(word)) => match word.as_str() {
+                "int" => Type::Int,
+                "float" => Type::Float,
+                "bool" => Type::Bool,
+                "char" => Type::Char,
+                "string" => Type::Str,
+                "fun" => {
+                    self.expect(Token::Punct('('))?;
+                    let mut parameters = vec![];
+                    if !self.eat(&Token::Punct(')')) {
+                        loop {
+                            parameters.push(self.ty()?);
+                            if self.eat(&Token::Punct(')')) {
+                                break;
+                            }
+                            self.expect(Token::Punct(','))?;
+                        }
+                    }
+                    self.expect(Token::Arrow)?;
+
+                    Type::Function { parameters, ret: Box::new(self.ty()?) }
+                }
+                _ => return Err(self.unexpected_previous("a type")),
+            },
+            Some(Token::Punct('(')) => {
+                self.expect(Token::Punct(')'))?;
+
+                Type::Unit
+            }
+            _ => return Err(self.unexpected_previous("a type")),
+        };
+
+        Ok(ty)
+    }
+
+    /// Parses `vN: type`, recording the type of the value.
+    fn define(&mut self, values: &mut Vec<Option<Type>>) -> Result<Value, ParseError> {
+        let value = self.value()?;
+        self.expect(Token::Punct(':'))?;
+        let ty = self.ty()?;
+
+        if values.len() <= value.0 {
+            values.resize(value.0 + 1, None);
+        }
+        if values[value.0].replace(ty).is_some() {
+            return Err(self.error_previous(format!("`{value}` is defined more than once")));
+        }
+
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.advance() {
+            Some(Token::Word(word)) if word.starts_with('v') => match word[1..].parse() {
+                Ok(index) => {
+                    self.used_values = self.used_values.max(index + 1);
+
+                    Ok(Value(index))
+                }
+                Err(_) => Err(self.unexpected_previous("a value")),
+            },
+            _ => Err(self.unexpected_previous("a value")),
+        }
+    }
+
+    fn block_name(&mut self) -> Result<Block, ParseError> {
+        match self.advance() {
+            Some(Token::Word(word)) if word.starts_with("block") => match word["block".len()..].parse() {
+                Ok(index) => Ok(Block(index)),
+                Err(_) => Err(self.unexpected_previous("a block")),
+            },
+            _ => Err(self.unexpected_previous("a block")),
+        }
+    }
+
+    fn function_name(&mut self) -> Result<FunctionId, ParseError> {
+        match self.advance() {
+            Some(Token::Function(name)) => match self.functions.get(&name) {
+                Some(&id) => Ok(id),
+                None => Err(self.error_previous(format!("there is no function `@{name}`"))),
+            },
+            _ => Err(self.unexpected_previous("a function name")),
+        }
+    }
+
+    fn global(&mut self) -> Result<GlobalId, ParseError> {
+        match self.advance() {
+            Some(Token::Global(name)) => match self.globals.get(&name) {
+                Some(&id) => Ok(id),
+                None => Err(self.error_previous(format!("there is no global `${name}`"))),
+            },
+            _ => Err(self.unexpected_previous("a global name")),
+        }
+    }
+
+    fn global_name(&mut self) -> Result<String, ParseError> {
+        match self.advance() {
+            Some(Token::Global(name)) => Ok(name),
+            _ => Err(self.unexpected_previous("a global name")),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).map(|(token, _)| token.clone());
+        self.position += 1;
+
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        match self.eat(&token) {
+            true => Ok(()),
+            false => Err(self.unexpected(&token.to_string())),
+        }
+    }
+
+    fn line(&self) -> usize {
+        match self.tokens.get(self.position).or(self.tokens.last()) {
+            Some(&(_, line)) => line,
+            None => 1,
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> ParseError {
+        let message = match self.peek() {
+            Some(token) => format!("expected {expected}, found {token}"),
+            None => format!("expected {expected}, found the end of the input"),
+        };
+
+        ParseError { line: self.line(), message }
+    }
+
+    /// An error about the token just consumed.
+    fn unexpected_previous(&mut self, expected: &str) -> ParseError {
+        self.position -= 1;
+
+        self.unexpected(expected)
+    }
+
+    fn error_previous(&self, message: String) -> ParseError {
+        let line = match self.tokens.get(self.position.saturating_sub(1)) {
+            Some(&(_, line)) => line,
+            None => self.line(),
+        };
+
+        ParseError { line, message }
+    }
+}
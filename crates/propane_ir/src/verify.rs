@@ -0,0 +1,436 @@
+//! Checks that a module is well formed: every value is defined once before it
+//! is used, and every instruction and terminator is applied to values of the
+//! right types.
+
+use std::fmt;
+
+use crate::ir::{
+    BinaryOperator, Block, BlockCall, Constant, Function, InstructionKind, Module, Terminator, Type, UnaryOperator,
+    Value,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    /// The name of the function the error is in.
+    pub function: String,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "in `@{}`: {}", self.function, self.message)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+/// Checks `module`, returning every problem found.
+pub fn verify(module: &Module) -> Result<(), Vec<VerifyError>> {
+    let mut errors = vec![];
+
+    for &program in &module.programs {
+        match module.functions.get(program.0) {
+            Some(function) if !function.parameters.is_empty() => errors.push(VerifyError {
+                function: function.name.clone(),
+                message: "programs cannot have parameters".to_string(),
+            }),
+            Some(_) => {}
+            None => errors.push(VerifyError { function: String::new(), message: format!("there is no function {}", program.0) }),
+        }
+    }
+
+    for function in &module.functions {
+        let mut verifier = Verifier { module, function, errors: vec![] };
+        verifier.function();
+
+        errors.extend(verifier.errors.into_iter().map(|message| VerifyError { function: function.name.clone(), message }));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Where a value is defined: its block, and the index of the instruction
+/// defining it, or `None` for a block parameter.
+type Definition = (Block, Option<usize>);
+
+struct Verifier<'m> {
+    module: &'m Module,
+    function: &'m Function,
+    errors: Vec<String>,
+}
+
+impl<'m> Verifier<'m> {
+    fn function(&mut self) {
+        let function = self.function;
+
+        if function.blocks.is_empty() {
+            self.errors.push("the function has no blocks".to_string());
+            return;
+        }
+
+        let entry = function.block(function.entry());
+        let entry_types = entry.parameters.iter().map(|&parameter| self.type_of(parameter)).collect::<Vec<_>>();
+        if entry_types.into_iter().ne(function.parameters.iter().map(Some)) {
+            self.errors.push(format!("the parameters of {} don't match those of the function", function.entry()));
+        }
+
+        let Some(definitions) = self.definitions() else {
+            return;
+        };
+
+        let predecessors = self.predecessors();
+        if !predecessors[function.entry().0].is_empty() {
+            self.errors.push(format!("{} is the entry block, but is jumped to", function.entry()));
+        }
+        let dominators = dominators(&predecessors);
+        for (i, dominators) in dominators.iter().enumerate() {
+            if dominators.is_none() {
+                self.errors.push(format!("{} is unreachable", Block(i)));
+            }
+        }
+
+        for (i, block) in function.blocks.iter().enumerate() {
+            // Each value used in the block, and the position it is used at.
+            let mut used = vec![];
+
+            for (position, instruction) in block.instructions.iter().enumerate() {
+                self.instruction(&instruction.kind, instruction.result);
+
+                used.extend(operands(&instruction.kind).into_iter().map(|value| (value, Some(position))));
+            }
+
+            self.terminator(&block.terminator);
+            let terminator_values = match &block.terminator {
+                Terminator::Jump(target) => target.arguments.clone(),
+                Terminator::Branch { condition, then_target, else_target } => {
+                    let mut values = vec![*condition];
+                    values.extend(&then_target.arguments);
+                    values.extend(&else_target.arguments);
+                    values
+                }
+                Terminator::Return(value) => vec![*value],
+            };
+            used.extend(terminator_values.into_iter().map(|value| (value, Some(block.instructions.len()))));
+
+            let block = Block(i);
+            for (value, position) in used {
+                let Some(&Some((defined_in, defined_at))) = definitions.get(value.0) else {
+                    self.errors.push(format!("{value} is not defined"));
+                    continue;
+                };
+
+                let dominated = if defined_in == block {
+                    defined_at < position
+                } else {
+                    match &dominators[block.0] {
+                        Some(dominators) => dominators[defined_in.0],
+                        // Uses in unreachable blocks were already reported.
+                        None => true,
+                    }
+                };
+                if !dominated {
+                    self.errors.push(format!("{value} is used in {block} where it may not have been defined"));
+                }
+            }
+        }
+    }
+
+    /// Where each value is defined, reporting values defined more than once
+    /// or not at all.
+    fn definitions(&mut self) -> Option<Vec<Option<Definition>>> {
+        let function = self.function;
+        let mut definitions = vec![None; function.values.len()];
+        let errors = self.errors.len();
+
+        let mut define = |errors: &mut Vec<String>, value: Value, definition: Definition| match definitions.get_mut(value.0) {
+            Some(Some(_)) => errors.push(format!("{value} is defined more than once")),
+            Some(slot) => *slot = Some(definition),
+            None => errors.push(format!("{value} has no type")),
+        };
+
+        for (i, block) in function.blocks.iter().enumerate() {
+            for &parameter in &block.parameters {
+                define(&mut self.errors, parameter, (Block(i), None));
+            }
+            for (position, instruction) in block.instructions.iter().enumerate() {
+                if let Some(result) = instruction.result {
+                    define(&mut self.errors, result, (Block(i), Some(position)));
+                }
+            }
+        }
+
+        for (i, definition) in definitions.iter().enumerate() {
+            if definition.is_none() {
+                self.errors.push(format!("{} is never defined", Value(i)));
+            }
+        }
+
+        (self.errors.len() == errors).then_some(definitions)
+    }
+
+    fn predecessors(&mut self) -> Vec<Vec<Block>> {
+        let mut predecessors = vec![vec![]; self.function.blocks.len()];
+
+        for (i, block) in self.function.blocks.iter().enumerate() {
+            for target in block.terminator.targets() {
+                match predecessors.get_mut(target.block.0) {
+                    Some(predecessors) => predecessors.push(Block(i)),
+                    None => self.errors.push(format!("{} jumps to {}, which does not exist", Block(i), target.block)),
+                }
+            }
+        }
+
+        predecessors
+    }
+
+    fn instruction(&mut self, kind: &InstructionKind, result: Option<Value>) {
+        let result_type = match (kind, result) {
+            (InstructionKind::GlobalSet(..), None) => None,
+            (InstructionKind::GlobalSet(..), Some(result)) => {
+                self.errors.push(format!("{result} is defined by `global.set`, which has no result"));
+                return;
+            }
+            (_, None) => {
+                self.errors.push("an instruction's result is missing".to_string());
+                return;
+            }
+            (_, Some(result)) => match self.type_of(result) {
+                Some(ty) => Some(ty),
+                None => return,
+            },
+        };
+
+        let expected = match kind {
+            InstructionKind::Const(constant) => Some(match constant {
+                Constant::Int(_) => Type::Int,
+                Constant::Float(_) => Type::Float,
+                Constant::Bool(_) => Type::Bool,
+                Constant::Char(_) => Type::Char,
+                Constant::Str(_) => Type::Str,
+                Constant::Unit => Type::Unit,
+            }),
+            &InstructionKind::Binary(operator, left, right) => {
+                let (Some(left_type), Some(right_type)) = (self.type_of(left), self.type_of(right)) else {
+                    return;
+                };
+                if left_type != right_type {
+                    self.errors.push(format!(
+                        "`{}` is applied to {left} of type `{left_type}` and {right} of type `{right_type}`",
+                        operator.as_str()
+                    ));
+                    return;
+                }
+
+                let supported = match operator {
+                    BinaryOperator::Add => matches!(left_type, Type::Int | Type::Float | Type::Str),
+                    BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div => {
+                        matches!(left_type, Type::Int | Type::Float)
+                    }
+                    BinaryOperator::Eq | BinaryOperator::Ne => true,
+                    BinaryOperator::Lt | BinaryOperator::Le | BinaryOperator::Gt | BinaryOperator::Ge => {
+                        matches!(left_type, Type::Int | Type::Float | Type::Char | Type::Str)
+                    }
+                };
+                if !supported {
+                    self.errors.push(format!("`{}` cannot be applied to `{left_type}`", operator.as_str()));
+                    return;
+                }
+
+                Some(if operator.is_comparison() { Type::Bool } else { left_type.clone() })
+            }
+            &InstructionKind::Unary(operator, operand) => {
+                let Some(operand_type) = self.type_of(operand) else {
+                    return;
+                };
+
+                let supported = match operator {
+                    UnaryOperator::Neg => matches!(operand_type, Type::Int | Type::Float),
+                    UnaryOperator::Not => *operand_type == Type::Bool,
+                };
+                if !supported {
+                    self.errors.push(format!("`{}` cannot be applied to `{operand_type}`", operator.as_str()));
+                    return;
+                }
+
+                Some(operand_type.clone())
+            }
+            InstructionKind::Call(callee, arguments) => {
+                let Some(callee) = self.module.functions.get(callee.0) else {
+                    self.errors.push(format!("there is no function {}", callee.0));
+                    return;
+                };
+
+                let name = format!("`@{}`", callee.name);
+                self.arguments(&name, &callee.parameters, arguments);
+
+                Some(callee.result.clone())
+            }
+            InstructionKind::CallIndirect(callee, arguments) => {
+                let Some(callee_type) = self.type_of(*callee) else {
+                    return;
+                };
+                let Type::Function { parameters, ret } = callee_type else {
+                    self.errors.push(format!("{callee} is called, but has type `{callee_type}`"));
+                    return;
+                };
+
+                self.arguments(&callee.to_string(), parameters, arguments);
+
+                Some((**ret).clone())
+            }
+            InstructionKind::Function(function) => match self.module.functions.get(function.0) {
+                Some(function) => Some(Type::Function {
+                    parameters: function.parameters.clone(),
+                    ret: Box::new(function.result.clone()),
+                }),
+                None => {
+                    self.errors.push(format!("there is no function {}", function.0));
+                    return;
+                }
+            },
+            InstructionKind::GlobalGet(global) => match self.module.globals.get(global.0) {
+                Some(global) => Some(global.ty.clone()),
+                None => {
+                    self.errors.push(format!("there is no global {}", global.0));
+                    return;
+                }
+            },
+            InstructionKind::GlobalSet(global, value) => {
+                let Some(global) = self.module.globals.get(global.0) else {
+                    self.errors.push(format!("there is no global {}", global.0));
+                    return;
+                };
+
+                self.expect(*value, &global.ty, &format!("`${}`", global.name));
+
+                None
+            }
+        };
+
+        if let (Some(result), Some(result_type), Some(expected)) = (result, result_type, expected) {
+            if *result_type != expected {
+                self.errors.push(format!("{result} has type `{result_type}`, but its instruction produces `{expected}`"));
+            }
+        }
+    }
+
+    fn terminator(&mut self, terminator: &Terminator) {
+        match terminator {
+            Terminator::Jump(target) => self.block_call(target),
+            Terminator::Branch { condition, then_target, else_target } => {
+                self.expect(*condition, &Type::Bool, "the branch condition");
+                self.block_call(then_target);
+                self.block_call(else_target);
+            }
+            Terminator::Return(value) => {
+                let function: &'m Function = self.function;
+
+                self.expect(*value, &function.result, "the result");
+            }
+        }
+    }
+
+    fn block_call(&mut self, target: &BlockCall) {
+        let Some(block) = self.function.blocks.get(target.block.0) else {
+            // Reported with the predecessors.
+            return;
+        };
+
+        let parameters = block.parameters.iter().map(|&parameter| self.function.values.get(parameter.0).cloned());
+        let Some(parameters) = parameters.collect::<Option<Vec<_>>>() else {
+            return;
+        };
+
+        self.arguments(&target.block.to_string(), &parameters, &target.arguments);
+    }
+
+    /// Checks the arguments passed to a function or block.
+    fn arguments(&mut self, callee: &str, parameters: &[Type], arguments: &[Value]) {
+        if parameters.len() != arguments.len() {
+            self.errors.push(format!("{callee} takes {} arguments, but is passed {}", parameters.len(), arguments.len()));
+            return;
+        }
+
+        for (i, (parameter, &argument)) in parameters.iter().zip(arguments).enumerate() {
+            self.expect(argument, parameter, &format!("argument {} of {callee}", i + 1));
+        }
+    }
+
+    /// Checks that `value`, used as `what`, has type `expected`.
+    fn expect(&mut self, value: Value, expected: &Type, what: &str) {
+        if let Some(ty) = self.type_of(value) {
+            if ty != expected {
+                self.errors.push(format!("{what} must have type `{expected}`, but {value} has type `{ty}`"));
+            }
+        }
+    }
+
+    fn type_of(&mut self, value: Value) -> Option<&'m Type> {
+        let function: &'m Function = self.function;
+        let ty = function.values.get(value.0);
+        if ty.is_none() {
+            self.errors.push(format!("{value} has no type"));
+        }
+
+        ty
+    }
+}
+
+/// The values an instruction uses.
+fn operands(kind: &InstructionKind) -> Vec<Value> {
+    match kind {
+        InstructionKind::Binary(_, left, right) => vec![*left, *right],
+        InstructionKind::Unary(_, operand) | InstructionKind::GlobalSet(_, operand) => vec![*operand],
+        InstructionKind::Call(_, arguments) => arguments.clone(),
+        InstructionKind::CallIndirect(callee, arguments) => {
+            let mut operands = vec![*callee];
+            operands.extend(arguments);
+            operands
+        }
+        InstructionKind::Const(_) | InstructionKind::Function(_) | InstructionKind::GlobalGet(_) => vec![],
+    }
+}
+
+/// For each block, which blocks dominate it, or `None` if it's unreachable
+/// from the entry block.
+fn dominators(predecessors: &[Vec<Block>]) -> Vec<Option<Vec<bool>>> {
+    let count = predecessors.len();
+    let mut dominators = vec![None; count];
+    let mut entry = vec![false; count];
+    entry[0] = true;
+    dominators[0] = Some(entry);
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for block in 1..count {
+            let mut reached: Option<Vec<bool>> = None;
+            for predecessor in &predecessors[block] {
+                let Some(predecessor) = &dominators[predecessor.0] else {
+                    continue;
+                };
+
+                reached = Some(match reached {
+                    Some(reached) => reached.iter().zip(predecessor).map(|(a, b)| *a && *b).collect(),
+                    None => predecessor.clone(),
+                });
+            }
+
+            let Some(mut reached) = reached else {
+                continue;
+            };
+            reached[block] = true;
+            if dominators[block].as_ref() != Some(&reached) {
+                dominators[block] = Some(reached);
+                changed = true;
+            }
+        }
+    }
+
+    dominators
+}
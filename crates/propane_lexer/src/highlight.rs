@@ -0,0 +1,288 @@
+//! Highlighting for editors: tokens classified as LSP semantic tokens, and a
+//! TextMate grammar and tree-sitter highlights query generated from
+//! [KEYWORDS](crate::KEYWORDS), so editors agree with the lexer on what the
+//! keywords are.
+
+use std::fmt::Write;
+
+use codespan::Span;
+use serde_json::{json, Map, Value};
+
+use crate::{LexerToken, TokenKind, BOOLEANS, KEYWORDS};
+
+/// The kinds of token editors color differently, named after the LSP
+/// semantic token types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenType {
+    Keyword,
+    Comment,
+    String,
+    Number,
+    Operator,
+    Variable,
+    Function,
+    Parameter,
+    Type,
+}
+
+impl TokenType {
+    /// Every token type, in the order of their indices in an LSP legend.
+    pub const ALL: [TokenType; 9] = [
+        TokenType::Keyword,
+        TokenType::Comment,
+        TokenType::String,
+        TokenType::Number,
+        TokenType::Operator,
+        TokenType::Variable,
+        TokenType::Function,
+        TokenType::Parameter,
+        TokenType::Type,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenType::Keyword => "keyword",
+            TokenType::Comment => "comment",
+            TokenType::String => "string",
+            TokenType::Number => "number",
+            TokenType::Operator => "operator",
+            TokenType::Variable => "variable",
+            TokenType::Function => "function",
+            TokenType::Parameter => "parameter",
+            TokenType::Type => "type",
+        }
+    }
+
+    /// The index of the type in [TokenType::ALL].
+    pub fn index(self) -> u32 {
+        self as u32
+    }
+}
+
+/// Extra information about a token, named after the LSP semantic token
+/// modifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenModifier {
+    /// The name in a binding's declaration.
+    Declaration,
+    /// A built in type, like `int`.
+    DefaultLibrary,
+}
+
+impl TokenModifier {
+    /// Every modifier, in the order of their bits in an LSP legend.
+    pub const ALL: [TokenModifier; 2] = [TokenModifier::Declaration, TokenModifier::DefaultLibrary];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenModifier::Declaration => "declaration",
+            TokenModifier::DefaultLibrary => "defaultLibrary",
+        }
+    }
+
+    /// The bit for the modifier in [SemanticToken::modifiers].
+    pub fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SemanticToken {
+    pub span: Span,
+    pub token_type: TokenType,
+    /// The [TokenModifier::bit]s of the token's modifiers.
+    pub modifiers: u32,
+}
+
+/// The type of a token from its kind alone, or `None` for whitespace,
+/// punctuation and invalid tokens. Every identifier is a
+/// [TokenType::Variable], as only name resolution can tell the others apart.
+pub fn token_type(kind: TokenKind) -> Option<TokenType> {
+    use TokenKind::*;
+
+    let token_type = match kind {
+        LineComment | BlockComment { .. } => TokenType::Comment,
+        Let | If | Else | For | In | Fun | Return | While => TokenType::Keyword,
+        Literal { kind: crate::Literal::Bool, .. } => TokenType::Keyword,
+        Literal { kind: crate::Literal::Int { .. } | crate::Literal::Float { .. }, .. } => TokenType::Number,
+        Literal { kind: crate::Literal::Str { .. } | crate::Literal::Char { .. }, .. } => TokenType::String,
+        Ident => TokenType::Variable,
+        DotDot | BangEq | EqEq | GtEq | LtEq | Eq | Bang | Lt | Gt | Minus | Plus | Star | Slash => TokenType::Operator,
+        _ => return None,
+    };
+
+    Some(token_type)
+}
+
+/// Classifies the tokens that have a [token_type], without modifiers.
+pub fn semantic_tokens(tokens: &[LexerToken]) -> Vec<SemanticToken> {
+    tokens
+        .iter()
+        .filter_map(|token| Some(SemanticToken { span: token.span, token_type: token_type(token.kind)?, modifiers: 0 }))
+        .collect()
+}
+
+/// The groups keywords are highlighted in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeywordGroup {
+    Declaration,
+    Function,
+    Conditional,
+    Repeat,
+    Return,
+}
+
+impl KeywordGroup {
+    const ALL: [KeywordGroup; 5] =
+        [KeywordGroup::Declaration, KeywordGroup::Function, KeywordGroup::Conditional, KeywordGroup::Repeat, KeywordGroup::Return];
+
+    fn of(kind: TokenKind) -> KeywordGroup {
+        match kind {
+            TokenKind::Let => KeywordGroup::Declaration,
+            TokenKind::Fun => KeywordGroup::Function,
+            TokenKind::If | TokenKind::Else => KeywordGroup::Conditional,
+            TokenKind::For | TokenKind::In | TokenKind::While => KeywordGroup::Repeat,
+            TokenKind::Return => KeywordGroup::Return,
+            _ => unreachable!("{kind:?} is not a keyword"),
+        }
+    }
+
+    fn keywords(self) -> impl Iterator<Item = &'static str> {
+        KEYWORDS.iter().filter(move |&&(_, kind)| KeywordGroup::of(kind) == self).map(|&(keyword, _)| keyword)
+    }
+
+    fn textmate_scope(self) -> &'static str {
+        match self {
+            KeywordGroup::Declaration => "storage.type.propane",
+            KeywordGroup::Function => "storage.type.function.propane",
+            KeywordGroup::Conditional => "keyword.control.conditional.propane",
+            KeywordGroup::Repeat => "keyword.control.loop.propane",
+            KeywordGroup::Return => "keyword.control.return.propane",
+        }
+    }
+
+    fn tree_sitter_capture(self) -> &'static str {
+        match self {
+            KeywordGroup::Declaration => "@keyword",
+            KeywordGroup::Function => "@keyword.function",
+            KeywordGroup::Conditional => "@keyword.conditional",
+            KeywordGroup::Repeat => "@keyword.repeat",
+            KeywordGroup::Return => "@keyword.return",
+        }
+    }
+}
+
+/// The operators, longest first so that a regex alternation of them matches
+/// the way the lexer does.
+const OPERATORS: [&str; 13] = ["..", "==", "!=", "<=", ">=", "=", "!", "<", ">", "+", "-", "*", "/"];
+
+const IDENTIFIER: &str = r"[_\p{L}][_\p{L}\p{N}]*";
+
+/// A TextMate grammar for Propane, in JSON, for editors like VS Code.
+pub fn textmate_grammar() -> String {
+    let keyword_of = |kind: TokenKind| KEYWORDS.iter().find(|&&(_, keyword)| keyword == kind).map(|&(text, _)| text).unwrap();
+    let words = |words: &mut dyn Iterator<Item = &str>| format!(r"\b({})\b", words.collect::<Vec<_>>().join("|"));
+
+    let mut patterns = vec![
+        json!({ "include": "#comments" }),
+        pattern(r#""(?:[^"\\]|\\.)*("|$)"#, "string.quoted.double.propane"),
+        pattern(r"'(?:\\u\{[0-9A-Fa-f_]*\}|\\.|[^\\])'", "string.quoted.single.propane"),
+        pattern(
+            &format!(r"\b(?:0x[0-9A-Fa-f_]*|0[ob][0-9_]*|[0-9][0-9_]*(?:\.[0-9][0-9_]*)?(?:[eE][+-]?[0-9_]*)?)(?:{IDENTIFIER})?"),
+            "constant.numeric.propane",
+        ),
+        pattern(&words(&mut BOOLEANS.into_iter()), "constant.language.boolean.propane"),
+        captures(
+            &format!(r"\b({})\s+({IDENTIFIER})", keyword_of(TokenKind::Fun)),
+            &[KeywordGroup::Function.textmate_scope(), "entity.name.function.propane"],
+        ),
+    ];
+    for group in KeywordGroup::ALL {
+        patterns.push(pattern(&words(&mut group.keywords()), group.textmate_scope()));
+    }
+    patterns.extend([
+        captures(&format!(r"(:)\s*({IDENTIFIER})"), &["punctuation.separator.colon.propane", "entity.name.type.propane"]),
+        pattern(&format!(r"{IDENTIFIER}(?=\s*\()"), "entity.name.function.call.propane"),
+        pattern(IDENTIFIER, "variable.other.propane"),
+        pattern(&OPERATORS.map(escape_regex).join("|"), "keyword.operator.propane"),
+        pattern("[;,.]", "punctuation.separator.propane"),
+    ]);
+
+    let grammar = json!({
+        "$schema": "https://raw.githubusercontent.com/martinring/tmlanguage/master/tmlanguage.json",
+        "name": "Propane",
+        "scopeName": "source.propane",
+        "fileTypes": ["pp"],
+        "patterns": patterns,
+        "repository": {
+            "comments": {
+                "patterns": [pattern("//.*$", "comment.line.double-slash.propane"), { "include": "#block-comment" }],
+            },
+            // Block comments nest, so they include themselves, but not line
+            // comments, which can't hide their end.
+            "block-comment": {
+                "begin": r"/\*",
+                "end": r"\*/",
+                "name": "comment.block.propane",
+                "patterns": [{ "include": "#block-comment" }],
+            },
+        },
+    });
+
+    let mut grammar = serde_json::to_string_pretty(&grammar).expect("JSON values can always be written");
+    grammar.push('\n');
+
+    grammar
+}
+
+fn pattern(regex: &str, scope: &str) -> Value {
+    json!({ "match": regex, "name": scope })
+}
+
+/// A pattern naming each of the regex's groups.
+fn captures(regex: &str, scopes: &[&str]) -> Value {
+    let captures = scopes
+        .iter()
+        .enumerate()
+        .map(|(i, scope)| ((i + 1).to_string(), json!({ "name": scope })))
+        .collect::<Map<_, _>>();
+
+    json!({ "match": regex, "captures": captures })
+}
+
+fn escape_regex(text: &str) -> String {
+    text.chars().fold(String::new(), |mut escaped, c| {
+        if r"\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        escaped
+    })
+}
+
+/// A tree-sitter `highlights.scm` query for Propane, for editors like
+/// Neovim and Helix.
+///
+/// There is no tree-sitter grammar for Propane, so the query only matches
+/// tokens by their text, as anonymous nodes: the keywords, operators and
+/// punctuation. Earlier patterns take precedence, as in `tree-sitter
+/// highlight`.
+pub fn tree_sitter_highlights() -> String {
+    let quoted = |words: &mut dyn Iterator<Item = &str>| words.map(|word| format!("{word:?}")).collect::<Vec<_>>().join(" ");
+
+    let mut query = String::new();
+    writeln!(query, "; Keywords").unwrap();
+    writeln!(query).unwrap();
+    for group in KeywordGroup::ALL {
+        writeln!(query, "[{}] {}", quoted(&mut group.keywords()), group.tree_sitter_capture()).unwrap();
+    }
+    writeln!(query).unwrap();
+
+    writeln!(query, "; Punctuation").unwrap();
+    writeln!(query).unwrap();
+    writeln!(query, "[{}] @operator", quoted(&mut OPERATORS.into_iter())).unwrap();
+    writeln!(query, r#"["(" ")" "{{" "}}"] @punctuation.bracket"#).unwrap();
+    writeln!(query, r#"[";" "," ":" "."] @punctuation.delimiter"#).unwrap();
+
+    query
+}
@@ -4,7 +4,7 @@ use std::str::Chars;
 use codespan::Span;
 
 use crate::TokenKind::*;
-use crate::{LexerToken, Token, TokenKind};
+use crate::{keyword, Base, LexerToken, TextEdit, Token, TokenKind, BOOLEANS};
 
 struct Scanner<'src> {
     text: &'src str,
@@ -13,7 +13,18 @@ struct Scanner<'src> {
     char_index: u32,
 }
 
-impl Scanner<'_> {
+impl<'src> Scanner<'src> {
+    /// A scanner for the tokens of `src` from byte `offset`, which has to be
+    /// the start of a token.
+    fn new(src: &'src str, offset: usize) -> Scanner<'src> {
+        Scanner {
+            text: src,
+            source: src[offset..].chars(),
+            char_index: offset as u32,
+            length: src.len() as u32,
+        }
+    }
+
     fn next_token(&mut self) -> Option<LexerToken> {
         let char = self.source.next()?;
 
@@ -26,7 +37,7 @@ impl Scanner<'_> {
                 Whitespace
             }
             ',' => Comma,
-            '.' => Dot,
+            '.' => self.match_advance_or('.', DotDot, Dot),
             '(' => OpenParen,
             ')' => CloseParen,
             '{' => OpenBrace,
@@ -57,52 +68,52 @@ impl Scanner<'_> {
 
                     LineComment
                 }
-                // '*' => self.block_comment(),
+                '*' => self.block_comment(),
                 _ => Slash,
             },
             // Numeric literal.
-            '0'..='9' => TokenKind::Literal {
-                kind: crate::Literal::Float,
-            },
-            '"' => {
-                self.eat_while(|ch| ch != '"');
+            c @ '0'..='9' => {
+                let kind = self.number(c);
+                let suffix_start = self.position() - start;
 
-                let terminated = self.peek() == '"';
+                self.eat_literal_suffix();
 
-                let lit = Literal {
-                    kind: crate::Literal::Str { terminated },
-                };
+                TokenKind::Literal { kind, suffix_start }
+            }
+            '"' => {
+                let terminated = self.double_quoted_string();
 
-                if terminated {
-                    self.discard();
+                Literal {
+                    kind: crate::Literal::Str { terminated },
+                    suffix_start: self.position() - start,
                 }
+            }
+            '\'' => {
+                let terminated = self.single_quoted_string();
 
-                lit
+                Literal {
+                    kind: crate::Literal::Char { terminated },
+                    suffix_start: self.position() - start,
+                }
             }
             c if is_identifier(c) => {
                 self.eat_while(|c| is_identifier(c) || c.is_numeric() );
 
-                // todo common func
-                let end = self.length - self.source.as_str().len() as u32;
-
-                let ident_text = &self.text[start as usize..end as usize];
-
-                match ident_text {
-                    "let" => TokenKind::Let,
-                    // "if" => TokenKind::If,
-                    // "else" => TokenKind::Else,
-                    // "for" => TokenKind::For,
-                    "fun" => Fun,
-                    "return" => Return,
-                    "true" | "false" => TokenKind::Literal { kind: crate::Literal::Bool },
-                    // "while" => TokenKind::While,
-                    _ => TokenKind::Ident
+                let ident_text = &self.text[start as usize..self.position() as usize];
+
+                if BOOLEANS.contains(&ident_text) {
+                    TokenKind::Literal {
+                        kind: crate::Literal::Bool,
+                        suffix_start: self.position() - start,
+                    }
+                } else {
+                    keyword(ident_text).unwrap_or(TokenKind::Ident)
                 }
             }
             _ => Unknown,
         };
 
-        let end = self.length - self.source.as_str().len() as u32;
+        let end = self.position();
 
         let span = Span::new(start, end);
 
@@ -111,6 +122,182 @@ impl Scanner<'_> {
         Some(Token { kind, span })
     }
 
+    /// Eats the rest of a string after the opening `"`, skipping over escaped
+    /// quotes. Returns whether the closing `"` was found.
+    fn double_quoted_string(&mut self) -> bool {
+        while let Some(ch) = self.source.next() {
+            match ch {
+                '"' => return true,
+                '\\' if matches!(self.peek(), '\\' | '"') => self.discard(),
+                _ => (),
+            }
+        }
+
+        false
+    }
+
+    /// Eats the rest of a char literal after the opening `'`. Returns whether
+    /// the closing `'` was found; a newline or `/` ends the literal early so an
+    /// unmatched quote doesn't swallow the rest of the line.
+    fn single_quoted_string(&mut self) -> bool {
+        // Parse `'''` as a single char.
+        if self.peek_second() == '\'' && self.peek() != '\\' {
+            self.discard();
+            self.discard();
+
+            return true;
+        }
+
+        loop {
+            match self.peek() {
+                '\'' => {
+                    self.discard();
+
+                    return true;
+                }
+                '/' => break,
+                '\n' if self.peek_second() != '\'' => break,
+                '\0' if self.source.as_str().is_empty() => break,
+                '\\' => {
+                    self.discard();
+                    self.discard();
+                }
+                _ => self.discard(),
+            }
+        }
+
+        false
+    }
+
+    fn block_comment(&mut self) -> TokenKind {
+        self.discard();
+
+        let mut depth = 1usize;
+
+        while let Some(ch) = self.source.next() {
+            match ch {
+                '/' if self.peek() == '*' => {
+                    self.discard();
+                    depth += 1;
+                }
+                '*' if self.peek() == '/' => {
+                    self.discard();
+                    depth -= 1;
+
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        BlockComment { terminated: depth == 0 }
+    }
+
+    fn number(&mut self, first_digit: char) -> crate::Literal {
+        let mut base = Base::Decimal;
+
+        if first_digit == '0' {
+            let has_digits = match self.peek() {
+                'b' => {
+                    base = Base::Binary;
+                    self.discard();
+                    self.eat_decimal_digits()
+                }
+                'o' => {
+                    base = Base::Octal;
+                    self.discard();
+                    self.eat_decimal_digits()
+                }
+                'x' => {
+                    base = Base::Hexadecimal;
+                    self.discard();
+                    self.eat_hexadecimal_digits()
+                }
+                '0'..='9' | '_' | '.' | 'e' | 'E' => {
+                    self.eat_decimal_digits();
+                    true
+                }
+                _ => return crate::Literal::Int { base, empty_int: false },
+            };
+
+            if !has_digits {
+                return crate::Literal::Int { base, empty_int: true };
+            }
+        } else {
+            self.eat_decimal_digits();
+        }
+
+        match self.peek() {
+            // `1..2` is not a float and `1.foo` is a field or method access.
+            '.' if self.peek_second() != '.' && !is_identifier(self.peek_second()) => {
+                self.discard();
+
+                let mut empty_exponent = false;
+
+                if self.peek().is_ascii_digit() {
+                    self.eat_decimal_digits();
+
+                    if matches!(self.peek(), 'e' | 'E') {
+                        self.discard();
+                        empty_exponent = !self.eat_float_exponent();
+                    }
+                }
+
+                crate::Literal::Float { base, empty_exponent }
+            }
+            'e' | 'E' => {
+                self.discard();
+
+                let empty_exponent = !self.eat_float_exponent();
+
+                crate::Literal::Float { base, empty_exponent }
+            }
+            _ => crate::Literal::Int { base, empty_int: false },
+        }
+    }
+
+    /// Eats decimal digits and `_` separators, returning whether any digit was seen.
+    fn eat_decimal_digits(&mut self) -> bool {
+        let mut has_digits = false;
+
+        self.eat_while(|ch| {
+            has_digits |= ch.is_ascii_digit();
+
+            ch == '_' || ch.is_ascii_digit()
+        });
+
+        has_digits
+    }
+
+    fn eat_hexadecimal_digits(&mut self) -> bool {
+        let mut has_digits = false;
+
+        self.eat_while(|ch| {
+            has_digits |= ch.is_ascii_hexdigit();
+
+            ch == '_' || ch.is_ascii_hexdigit()
+        });
+
+        has_digits
+    }
+
+    /// Eats the exponent after the `e`, returning whether it contained any digits.
+    fn eat_float_exponent(&mut self) -> bool {
+        if matches!(self.peek(), '-' | '+') {
+            self.discard();
+        }
+
+        self.eat_decimal_digits()
+    }
+
+    fn eat_literal_suffix(&mut self) {
+        if is_identifier(self.peek()) {
+            self.eat_while(|c| is_identifier(c) || c.is_numeric());
+        }
+    }
+
     fn match_advance_or(&mut self, ch: char, is: TokenKind, or: TokenKind) -> TokenKind {
         if self.peek() == ch {
             self.discard();
@@ -124,6 +311,17 @@ impl Scanner<'_> {
         self.source.clone().next().unwrap_or('\0')
     }
 
+    fn peek_second(&self) -> char {
+        let mut chars = self.source.clone();
+        chars.next();
+        chars.next().unwrap_or('\0')
+    }
+
+    /// Byte offset of the next unconsumed character.
+    fn position(&self) -> u32 {
+        self.length - self.source.as_str().len() as u32
+    }
+
     fn discard(&mut self) {
         self.source.next();
     }
@@ -136,17 +334,68 @@ impl Scanner<'_> {
 }
 
 pub fn scan(src: &str) -> impl Iterator<Item = LexerToken> + '_ {
-    let mut scanner = Scanner {
-        text: src,
-        source: src.chars(),
-        char_index: 0,
-        length: src.len() as u32,
-    };
-
-    iter::from_fn(move || scanner.next_token()).chain(iter::once(Token {
+    let mut scanner = Scanner::new(src, 0);
+
+    iter::from_fn(move || scanner.next_token()).chain(iter::once(eof(src)))
+}
+
+/// Relexes the tokens from the first one the edit could have changed, and
+/// copies the ones after it over once the new tokens line up with them.
+///
+/// Lexing a token only looks at its text and the two characters after it, so
+/// the tokens before that can't have changed. And as the scanner keeps no
+/// state between tokens, once a new token starts where an old one did in the
+/// text after the edit, the rest of the tokens are the old ones, moved by
+/// the change in length. An edit that opens a string or a comment relexes
+/// up to its end, which may be the end of the file.
+pub fn relex(tokens: &[LexerToken], src: &str, edit: &TextEdit) -> Vec<LexerToken> {
+    let edit_start = edit.range.start;
+
+    // Each char is at most 4 bytes, so tokens ending 8 bytes before the edit
+    // have two whole characters after them that are unchanged.
+    let mut first = tokens.partition_point(|token| token.span.end().to_usize() + 8 <= edit_start);
+    while tokens.get(first).is_some_and(|token| {
+        let end = token.span.end().to_usize();
+
+        end <= edit_start && src[end..edit_start].chars().nth(1).is_some()
+    }) {
+        first += 1;
+    }
+
+    let mut relexed = tokens[..first].to_vec();
+    let start = tokens.get(first).map_or(0, |token| token.span.start().to_usize());
+    let mut scanner = Scanner::new(src, start);
+    let mut old = first;
+
+    while let Some(token) = scanner.next_token() {
+        let start = token.span.start().to_usize();
+
+        if start >= edit.new_range().end {
+            let old_start = edit.old_offset(start);
+            while tokens.get(old).is_some_and(|token| token.span.start().to_usize() < old_start) {
+                old += 1;
+            }
+
+            if tokens.get(old).is_some_and(|token| token.span.start().to_usize() == old_start) {
+                relexed.extend(tokens[old..].iter().map(|token| Token { kind: token.kind, span: edit.shift(token.span) }));
+
+                return relexed;
+            }
+        }
+
+        relexed.push(token);
+    }
+
+    relexed.push(eof(src));
+
+    relexed
+}
+
+fn eof(src: &str) -> LexerToken {
+    Token {
         kind: TokenKind::Eof,
         span: Span::new(src.len() as u32, src.len() as u32),
-    }))
+    }
 }
 
 fn is_whitespace(c: char) -> bool {
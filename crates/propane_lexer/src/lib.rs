@@ -1,12 +1,80 @@
+use std::ops::Range;
+
 use codespan::Span;
 
+pub mod highlight;
 mod lexer;
 
 pub fn tokenize(src: &str) -> Vec<LexerToken> {
-    lexer::scan(&src).collect()
+    lexer::scan(src).collect()
+}
+
+/// Updates the tokens of a file for an edit to it, relexing only the ones
+/// around the edit. `tokens` are the tokens of the text before the edit and
+/// `src` is the text after it. The result is the same as tokenizing `src`.
+pub fn relex(tokens: &[LexerToken], src: &str, edit: &TextEdit) -> Vec<LexerToken> {
+    lexer::relex(tokens, src, edit)
+}
+
+/// A change to the text of a file: the bytes in `range` replaced with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl TextEdit {
+    pub fn new(range: Range<usize>, text: impl Into<String>) -> TextEdit {
+        TextEdit { range, text: text.into() }
+    }
+
+    pub fn apply(&self, src: &mut String) {
+        src.replace_range(self.range.clone(), &self.text);
+    }
+
+    /// The range of the inserted text in the edited text.
+    pub fn new_range(&self) -> Range<usize> {
+        self.range.start..self.range.start + self.text.len()
+    }
+
+    /// Where an offset after the edit in the old text is in the edited text.
+    pub fn new_offset(&self, offset: usize) -> usize {
+        offset - self.range.end + self.new_range().end
+    }
+
+    /// Where an offset after the edit in the edited text was in the old text.
+    pub fn old_offset(&self, offset: usize) -> usize {
+        offset - self.new_range().end + self.range.end
+    }
+
+    /// Moves a span after the edit in the old text to where it is in the
+    /// edited text.
+    pub fn shift(&self, span: Span) -> Span {
+        Span::new(self.new_offset(span.start().to_usize()) as u32, self.new_offset(span.end().to_usize()) as u32)
+    }
+}
+
+/// The keywords and the tokens they are lexed as.
+pub const KEYWORDS: &[(&str, TokenKind)] = &[
+    ("let", TokenKind::Let),
+    ("if", TokenKind::If),
+    ("else", TokenKind::Else),
+    ("for", TokenKind::For),
+    ("in", TokenKind::In),
+    ("fun", TokenKind::Fun),
+    ("return", TokenKind::Return),
+    ("while", TokenKind::While),
+];
+
+/// The words lexed as [Literal::Bool].
+pub const BOOLEANS: [&str; 2] = ["true", "false"];
+
+/// The keyword token `text` is lexed as, if it is a keyword.
+pub fn keyword(text: &str) -> Option<TokenKind> {
+    KEYWORDS.iter().find(|(keyword, _)| *keyword == text).map(|&(_, kind)| kind)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Token<TK> {
     pub kind: TK,
     pub span: Span,
@@ -24,7 +92,7 @@ pub enum TokenKind {
     ///
     /// Block comments can be recursive, so a sequence like `/* /* */`
     /// will not be considered terminated and will result in a parsing error.
-    BlockComment,
+    BlockComment { terminated: bool },
 
     /// Any whitespace character sequence.
     Whitespace,
@@ -40,6 +108,7 @@ pub enum TokenKind {
     If,
     Else,
     For,
+    In,
     Fun,
     Return,
     While,
@@ -52,9 +121,14 @@ pub enum TokenKind {
     /// See [Literal] for more details.
     Literal {
         kind: Literal,
+        /// Offset from the start of the token to the start of the suffix,
+        /// equal to the token length when there is no suffix.
+        suffix_start: u32,
     },
 
     // Two-char tokens:
+    /// ".."
+    DotDot,
     BangEq,
     EqEq,
     GtEq,
@@ -132,11 +206,9 @@ pub enum TokenKind {
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Literal {
     /// "12_u8", "0o100", "0b120i99", "1f32".
-    // Int { base: Base, empty_int: bool },
-    Int,
+    Int { base: Base, empty_int: bool },
     /// "12.34f32", "1e3", but not "1f32".
-    // Float { base: Base, empty_exponent: bool },
-    Float,
+    Float { base: Base, empty_exponent: bool },
     Bool,
     /// "'a'", "'\\'", "'''", "';"
     Char { terminated: bool },
@@ -146,6 +218,19 @@ pub enum Literal {
     Str { terminated: bool },
 }
 
+/// Base of numeric literal encoding according to its prefix.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Base {
+    /// Literal starts with "0b".
+    Binary = 2,
+    /// Literal starts with "0o".
+    Octal = 8,
+    /// Literal doesn't contain a prefix.
+    Decimal = 10,
+    /// Literal starts with "0x".
+    Hexadecimal = 16,
+}
+
 #[cfg(test)]
 mod tests {
     use codespan::Files;
@@ -173,4 +258,305 @@ let x = 3;
             let _ = dbg!(&token.kind, files.source_slice(main, token.span).unwrap());
         }
     }
+
+    /// Tokenizes `src` and returns each non-whitespace token with its text.
+    fn lex(src: &str) -> Vec<(TokenKind, &str)> {
+        tokenize(src)
+            .into_iter()
+            .filter(|token| !matches!(token.kind, TokenKind::Whitespace | TokenKind::Eof))
+            .map(|token| (token.kind, &src[token.span.start().0 as usize..token.span.end().0 as usize]))
+            .collect()
+    }
+
+    fn int(base: Base, suffix_start: u32) -> TokenKind {
+        TokenKind::Literal { kind: Literal::Int { base, empty_int: false }, suffix_start }
+    }
+
+    fn float(empty_exponent: bool, suffix_start: u32) -> TokenKind {
+        TokenKind::Literal { kind: Literal::Float { base: Base::Decimal, empty_exponent }, suffix_start }
+    }
+
+    #[test]
+    fn integer_literals() {
+        assert_eq!(
+            lex("123 1_000 0 0x1F_ff 0o17 0b1010 12u8 0b120i99"),
+            vec![
+                (int(Base::Decimal, 3), "123"),
+                (int(Base::Decimal, 5), "1_000"),
+                (int(Base::Decimal, 1), "0"),
+                (int(Base::Hexadecimal, 7), "0x1F_ff"),
+                (int(Base::Octal, 4), "0o17"),
+                (int(Base::Binary, 6), "0b1010"),
+                (int(Base::Decimal, 2), "12u8"),
+                (int(Base::Binary, 5), "0b120i99"),
+            ],
+        );
+    }
+
+    #[test]
+    fn empty_int() {
+        assert_eq!(
+            lex("0x 0b_"),
+            vec![
+                (TokenKind::Literal { kind: Literal::Int { base: Base::Hexadecimal, empty_int: true }, suffix_start: 2 }, "0x"),
+                (TokenKind::Literal { kind: Literal::Int { base: Base::Binary, empty_int: true }, suffix_start: 3 }, "0b_"),
+            ],
+        );
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(
+            lex("1.5 12.34f32 1e3 1.0e-40 2E+1_0 1f32"),
+            vec![
+                (float(false, 3), "1.5"),
+                (float(false, 5), "12.34f32"),
+                (float(false, 3), "1e3"),
+                (float(false, 7), "1.0e-40"),
+                (float(false, 6), "2E+1_0"),
+                (int(Base::Decimal, 1), "1f32"),
+            ],
+        );
+    }
+
+    #[test]
+    fn empty_exponent() {
+        assert_eq!(lex("1e+"), vec![(float(true, 3), "1e+")]);
+    }
+
+    #[test]
+    fn block_comments() {
+        assert_eq!(
+            lex("/* a */ 1 /* outer /* inner */ still comment */ /**/"),
+            vec![
+                (TokenKind::BlockComment { terminated: true }, "/* a */"),
+                (int(Base::Decimal, 1), "1"),
+                (TokenKind::BlockComment { terminated: true }, "/* outer /* inner */ still comment */"),
+                (TokenKind::BlockComment { terminated: true }, "/**/"),
+            ],
+        );
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        assert_eq!(
+            lex("/* /* */ 1"),
+            vec![(TokenKind::BlockComment { terminated: false }, "/* /* */ 1")],
+        );
+    }
+
+    fn string(terminated: bool, len: u32) -> TokenKind {
+        TokenKind::Literal { kind: Literal::Str { terminated }, suffix_start: len }
+    }
+
+    fn char(terminated: bool, len: u32) -> TokenKind {
+        TokenKind::Literal { kind: Literal::Char { terminated }, suffix_start: len }
+    }
+
+    #[test]
+    fn string_literals() {
+        assert_eq!(
+            lex(r#""a\"b" "\\" "" "unterminated"#),
+            vec![
+                (string(true, 6), r#""a\"b""#),
+                (string(true, 4), r#""\\""#),
+                (string(true, 2), r#""""#),
+                (string(false, 13), r#""unterminated"#),
+            ],
+        );
+    }
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(
+            lex(r"'a' '\'' '\u{1F600}' ''' '/'"),
+            vec![
+                (char(true, 3), "'a'"),
+                (char(true, 4), r"'\''"),
+                (char(true, 11), r"'\u{1F600}'"),
+                (char(true, 3), "'''"),
+                (char(true, 3), "'/'"),
+            ],
+        );
+    }
+
+    #[test]
+    fn unterminated_char_literal() {
+        assert_eq!(
+            lex("'a\nlet"),
+            vec![
+                (char(false, 2), "'a"),
+                (TokenKind::Let, "let"),
+            ],
+        );
+    }
+
+    #[test]
+    fn keywords() {
+        assert_eq!(
+            lex("let if else for in fun return while iffy"),
+            vec![
+                (TokenKind::Let, "let"),
+                (TokenKind::If, "if"),
+                (TokenKind::Else, "else"),
+                (TokenKind::For, "for"),
+                (TokenKind::In, "in"),
+                (TokenKind::Fun, "fun"),
+                (TokenKind::Return, "return"),
+                (TokenKind::While, "while"),
+                (TokenKind::Ident, "iffy"),
+            ],
+        );
+    }
+
+    #[test]
+    fn keyword_table() {
+        for &(keyword, kind) in KEYWORDS {
+            assert_eq!(lex(keyword), vec![(kind, keyword)]);
+        }
+        for boolean in BOOLEANS {
+            assert!(matches!(lex(boolean)[0].0, TokenKind::Literal { kind: Literal::Bool, .. }));
+        }
+    }
+
+    #[test]
+    fn semantic_tokens() {
+        use highlight::TokenType;
+
+        let src = "let a = 1.5; // done\nfun f(s) { s + \"b\" == 'c' } true";
+        let tokens = highlight::semantic_tokens(&tokenize(src));
+        let types: Vec<_> = tokens.iter().map(|token| (token.token_type, &src[token.span.start().to_usize()..token.span.end().to_usize()])).collect();
+
+        assert_eq!(types, vec![
+            (TokenType::Keyword, "let"),
+            (TokenType::Variable, "a"),
+            (TokenType::Operator, "="),
+            (TokenType::Number, "1.5"),
+            (TokenType::Comment, "// done"),
+            (TokenType::Keyword, "fun"),
+            (TokenType::Variable, "f"),
+            (TokenType::Variable, "s"),
+            (TokenType::Variable, "s"),
+            (TokenType::Operator, "+"),
+            (TokenType::String, "\"b\""),
+            (TokenType::Operator, "=="),
+            (TokenType::String, "'c'"),
+            (TokenType::Keyword, "true"),
+        ]);
+        assert!(tokens.iter().all(|token| token.modifiers == 0));
+
+        for (i, token_type) in TokenType::ALL.into_iter().enumerate() {
+            assert_eq!(token_type.index() as usize, i);
+        }
+    }
+
+    #[test]
+    fn textmate_grammar() {
+        let grammar: serde_json::Value = serde_json::from_str(&highlight::textmate_grammar()).expect("the grammar is valid JSON");
+
+        assert_eq!(grammar["scopeName"], "source.propane");
+
+        let patterns = grammar["patterns"].as_array().unwrap();
+        let matching = |scope: &str| patterns.iter().find(|pattern| pattern["name"] == scope).map(|pattern| pattern["match"].as_str().unwrap().to_string());
+
+        assert_eq!(matching("keyword.control.conditional.propane").as_deref(), Some(r"\b(if|else)\b"));
+        assert_eq!(matching("keyword.control.loop.propane").as_deref(), Some(r"\b(for|in|while)\b"));
+        assert_eq!(matching("constant.language.boolean.propane").as_deref(), Some(r"\b(true|false)\b"));
+        assert_eq!(matching("keyword.operator.propane").as_deref(), Some(r"\.\.|==|!=|<=|>=|=|!|<|>|\+|-|\*|/"));
+        assert_eq!(grammar["repository"]["block-comment"]["patterns"][0]["include"], "#block-comment");
+    }
+
+    #[test]
+    fn tree_sitter_highlights() {
+        let query = highlight::tree_sitter_highlights();
+
+        assert!(query.contains("[\"if\" \"else\"] @keyword.conditional\n"), "{query}");
+        assert!(query.contains("[\"return\"] @keyword.return\n"), "{query}");
+        for &(keyword, _) in KEYWORDS {
+            assert_eq!(query.matches(&format!("\"{keyword}\"")).count(), 1, "{keyword}");
+        }
+
+        // Without a grammar, the query only matches anonymous nodes, whose
+        // text is quoted.
+        let unquoted: String = query.split('"').step_by(2).collect();
+        assert!(!unquoted.contains('('), "{query}");
+        assert_eq!(unquoted.matches('[').count(), unquoted.matches(']').count());
+        assert!(query.contains("\"==\""), "{query}");
+    }
+
+    /// Relexes `src` after replacing `range` with `text`, checking the tokens
+    /// against tokenizing the edited text from scratch.
+    fn check_relex(src: &str, range: Range<usize>, text: &str) {
+        let edit = TextEdit::new(range, text);
+        let mut edited = src.to_string();
+        edit.apply(&mut edited);
+
+        let spans = |tokens: Vec<LexerToken>| tokens.into_iter().map(|token| (token.kind, token.span)).collect::<Vec<_>>();
+
+        assert_eq!(spans(relex(&tokenize(src), &edited, &edit)), spans(tokenize(&edited)), "{src:?} edited to {edited:?}");
+    }
+
+    #[test]
+    fn relex_edits() {
+        // Edits within and between tokens.
+        check_relex("let a = 1;", 4..5, "abc");
+        check_relex("let a = 1;", 8..8, "2");
+        check_relex("let a = 1;", 0..10, "");
+        check_relex("", 0..0, "let a = 1;");
+        // Edits that change the tokens before them, through the two
+        // characters of lookahead after a number.
+        check_relex("1.x", 2..3, "5");
+        check_relex("1.5", 2..3, "x");
+        check_relex("1.", 2..2, ".");
+        check_relex("a !", 3..3, "=");
+        // Opening and closing strings and comments, which changes every
+        // token after them.
+        check_relex("let a = 1; let b = 2;", 8..8, "\"");
+        check_relex("let a = \"1; let b = 2;", 8..9, "");
+        check_relex("a /* b */ c d", 7..9, "");
+        check_relex("a /* b c d", 10..10, " */");
+        check_relex("a /* b */ c /* d */ e", 2..2, "/*");
+        check_relex("a // b\nc", 6..7, "");
+        check_relex("'a\nb'", 2..3, "");
+    }
+
+    #[test]
+    fn relex_every_edit() {
+        let sources = [
+            "let a = 1.5 + b; // done\nfun f(x) { x.y /* c /* d */ */ }",
+            "\"s\\\"\" 'c' '\\'' 0x1F_ff 1e-3 1..2 == != <= >=",
+            "let ü = \"ünïcödé\"; // ☃\n",
+        ];
+        let insertions = ["\"", "'", "/*", "*/", "//", "\n", ".", "5", "e", "x", "=", " ", "\\"];
+
+        for src in sources {
+            let boundaries: Vec<usize> = (0..=src.len()).filter(|&i| src.is_char_boundary(i)).collect();
+
+            for (i, &start) in boundaries.iter().enumerate() {
+                for text in insertions {
+                    check_relex(src, start..start, text);
+                }
+
+                for &end in boundaries.iter().skip(i + 1).take(3) {
+                    check_relex(src, start..end, "");
+                    check_relex(src, start..end, "a\"");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dot_after_integer() {
+        assert_eq!(
+            lex("1.foo 1..2"),
+            vec![
+                (int(Base::Decimal, 1), "1"),
+                (TokenKind::Dot, "."),
+                (TokenKind::Ident, "foo"),
+                (int(Base::Decimal, 1), "1"),
+                (TokenKind::DotDot, ".."),
+                (int(Base::Decimal, 1), "2"),
+            ],
+        );
+    }
 }
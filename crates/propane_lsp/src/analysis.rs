@@ -0,0 +1,252 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_lexer::highlight::{SemanticToken, TokenModifier, TokenType};
+use propane_parser::expression::{Expression, ExpressionKind, Ident, Statement, StatementKind};
+use propane_db::Database;
+use propane_lexer::LexerToken;
+use propane_resolve::BindingKind;
+use propane_typeck::{Scheme, Type};
+
+/// What the compiler knows about one document: its errors and warnings, and
+/// the bindings in it and where they are used.
+#[derive(Debug)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic<FileId>>,
+    /// Every binding declared in the document.
+    pub bindings: Vec<Binding>,
+    /// The `let`s and `fun`s in the document, nested by the ones they are
+    /// declared in.
+    pub symbols: Vec<Symbol>,
+    /// The binding each variable refers to, as an index into `bindings`,
+    /// keyed by the span of the variable.
+    uses: HashMap<Span, usize>,
+    /// The names of types in annotations.
+    annotations: Vec<Ident>,
+    tokens: Arc<Vec<LexerToken>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub name: Ident,
+    pub kind: BindingKind,
+    /// The type inferred for the binding, if type checking got that far.
+    pub ty: Option<Type>,
+}
+
+/// A `let` or `fun` declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    /// The index of the binding it declares in [Analysis::bindings].
+    pub binding: usize,
+    /// The span of the whole declaration.
+    pub span: Span,
+    pub children: Vec<Symbol>,
+}
+
+impl Analysis {
+    /// Lexes, parses, resolves and type checks a file in `db`, continuing
+    /// after errors like `propanec check` does.
+    pub fn new(db: &Database, file_id: FileId) -> Analysis {
+        let tokens = db.tokens(file_id);
+        let parse = db.parse(file_id);
+        let resolved = db.resolve(file_id);
+        let checked = db.check(file_id);
+        let mut diagnostics = db.diagnostics(file_id);
+
+        let mut collector = Collector { bindings: vec![], annotations: vec![], types: &checked.inference.types };
+        let symbols = collector.expression(&parse.program);
+        let Collector { bindings, annotations, .. } = collector;
+
+        // Uses refer to bindings by their ids in the resolver, which only
+        // this analysis has, so refer to them by their names' spans instead.
+        let indices: HashMap<Span, usize> = bindings.iter().enumerate().map(|(i, binding)| (binding.name.span, i)).collect();
+        let uses = resolved
+            .resolution
+            .uses
+            .iter()
+            .filter_map(|(&span, &id)| Some((span, *indices.get(&resolved.resolver.binding(id).name.span)?)))
+            .collect();
+
+        diagnostics.extend(db.unused_globals(file_id));
+
+        Analysis { diagnostics, bindings, symbols, uses, annotations, tokens }
+    }
+
+    /// The binding declared or used at `offset`, and the span of the name
+    /// there.
+    pub fn binding_at(&self, offset: usize) -> Option<(&Binding, Span)> {
+        if let Some(binding) = self.bindings.iter().find(|binding| contains(binding.name.span, offset)) {
+            return Some((binding, binding.name.span));
+        }
+
+        self.uses
+            .iter()
+            .find(|(&span, _)| contains(span, offset))
+            .map(|(&span, &index)| (&self.bindings[index], span))
+    }
+
+    /// The markdown shown when hovering over a binding.
+    pub fn hover(&self, offset: usize) -> Option<(String, Span)> {
+        let (binding, span) = self.binding_at(offset)?;
+
+        Some((format!("```propane\n{}\n```", binding.signature()), span))
+    }
+
+    /// The tokens to highlight, with identifiers told apart by what they
+    /// refer to.
+    pub fn semantic_tokens(&self) -> Vec<SemanticToken> {
+        let declarations: HashMap<Span, usize> = self.bindings.iter().enumerate().map(|(i, binding)| (binding.name.span, i)).collect();
+
+        let mut tokens = propane_lexer::highlight::semantic_tokens(&self.tokens);
+        for token in &mut tokens {
+            if token.token_type != TokenType::Variable {
+                continue;
+            }
+
+            if let Some(&index) = declarations.get(&token.span) {
+                token.token_type = self.bindings[index].token_type();
+                token.modifiers |= TokenModifier::Declaration.bit();
+            } else if let Some(&index) = self.uses.get(&token.span) {
+                token.token_type = self.bindings[index].token_type();
+            } else if let Some(annotation) = self.annotations.iter().find(|annotation| annotation.span == token.span) {
+                token.token_type = TokenType::Type;
+                if Type::from_name(&annotation.name).is_some() {
+                    token.modifiers |= TokenModifier::DefaultLibrary.bit();
+                }
+            }
+        }
+
+        tokens
+    }
+}
+
+impl Binding {
+    /// How the binding could be declared with its type written out, e.g.
+    /// `let a: int` or `fun id: fun('a) -> 'a`.
+    pub fn signature(&self) -> String {
+        let keyword = match self.kind {
+            BindingKind::Let => "let",
+            BindingKind::Function => "fun",
+            BindingKind::Parameter => "parameter",
+            BindingKind::Loop => "for",
+        };
+
+        match self.scheme() {
+            Some(scheme) => format!("{keyword} {}: {scheme}", self.name.name),
+            None => format!("{keyword} {}", self.name.name),
+        }
+    }
+
+    fn token_type(&self) -> TokenType {
+        match self.kind {
+            BindingKind::Function => TokenType::Function,
+            BindingKind::Parameter => TokenType::Parameter,
+            BindingKind::Let | BindingKind::Loop => TokenType::Variable,
+        }
+    }
+
+    /// The binding's type, generic in any type variables left in it.
+    pub fn scheme(&self) -> Option<Scheme> {
+        self.ty.clone().map(Scheme::quantify_all)
+    }
+}
+
+/// Gathers the bindings and symbols in a program.
+struct Collector<'a> {
+    bindings: Vec<Binding>,
+    annotations: Vec<Ident>,
+    types: &'a HashMap<Span, Type>,
+}
+
+impl Collector<'_> {
+    /// Collects the bindings in `expression`, returning the symbols for the
+    /// outermost `let`s and `fun`s in it.
+    fn expression(&mut self, expression: &Expression) -> Vec<Symbol> {
+        match &expression.kind {
+            ExpressionKind::Binary { left, right, .. } => {
+                let mut symbols = self.expression(left);
+                symbols.extend(self.expression(right));
+                symbols
+            }
+            ExpressionKind::Grouping(inner) | ExpressionKind::Unary(_, inner) => self.expression(inner),
+            ExpressionKind::Call { callee, args } => {
+                let mut symbols = self.expression(callee);
+                for arg in args {
+                    symbols.extend(self.expression(arg));
+                }
+                symbols
+            }
+            ExpressionKind::Block { statements, value } => {
+                let mut symbols = self.statements(statements);
+                if let Some(value) = value {
+                    symbols.extend(self.expression(value));
+                }
+                symbols
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                let mut symbols = self.expression(condition);
+                symbols.extend(self.expression(then_branch));
+                if let Some(else_branch) = else_branch {
+                    symbols.extend(self.expression(else_branch));
+                }
+                symbols
+            }
+            ExpressionKind::StmtExpr(statements) => self.statements(statements),
+            ExpressionKind::Literal(_) | ExpressionKind::Variable(_) | ExpressionKind::Error => vec![],
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Vec<Symbol> {
+        let mut symbols = vec![];
+
+        for statement in statements {
+            match &statement.kind {
+                StatementKind::Let { name, value } => {
+                    let children = self.expression(value);
+                    let binding = self.declare(name, BindingKind::Let);
+
+                    symbols.push(Symbol { binding, span: statement.span, children });
+                }
+                StatementKind::Function { name, parameters, return_type, body } => {
+                    let binding = self.declare(name, BindingKind::Function);
+                    for parameter in parameters {
+                        self.declare(&parameter.name, BindingKind::Parameter);
+                    }
+                    let types = parameters.iter().filter_map(|parameter| parameter.ty.as_ref());
+                    self.annotations.extend(types.chain(return_type).cloned());
+
+                    let children = self.expression(body);
+                    symbols.push(Symbol { binding, span: statement.span, children });
+                }
+                StatementKind::For { variable, start, end, body } => {
+                    symbols.extend(self.expression(start));
+                    symbols.extend(self.expression(end));
+                    self.declare(variable, BindingKind::Loop);
+                    symbols.extend(self.expression(body));
+                }
+                StatementKind::While { condition, body } => {
+                    symbols.extend(self.expression(condition));
+                    symbols.extend(self.expression(body));
+                }
+                StatementKind::Return { value } | StatementKind::Expression(value) => symbols.extend(self.expression(value)),
+            }
+        }
+
+        symbols
+    }
+
+    /// Adds a binding, returning its index.
+    fn declare(&mut self, name: &Ident, kind: BindingKind) -> usize {
+        self.bindings.push(Binding { name: name.clone(), kind, ty: self.types.get(&name.span).cloned() });
+
+        self.bindings.len() - 1
+    }
+}
+
+/// Whether the cursor at `offset` is on `span`, including just after it.
+fn contains(span: Span, offset: usize) -> bool {
+    span.start().to_usize() <= offset && offset <= span.end().to_usize()
+}
@@ -0,0 +1,135 @@
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle, Severity};
+use lsp_types::{DiagnosticRelatedInformation, DiagnosticSeverity, Location, Position, Range, Uri};
+use propane_lexer::highlight::SemanticToken;
+
+/// Converts between byte offsets into a document and LSP positions, whose
+/// characters are counted in UTF-16 code units.
+#[derive(Debug)]
+pub struct LineIndex {
+    /// The byte offset each line starts at.
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(src: &str) -> LineIndex {
+        let starts = std::iter::once(0).chain(src.match_indices('\n').map(|(i, _)| i + 1)).collect();
+
+        LineIndex { starts }
+    }
+
+    pub fn position(&self, src: &str, offset: usize) -> Position {
+        let offset = offset.min(src.len());
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.starts[line];
+        let character = src[start..offset].encode_utf16().count();
+
+        Position::new(line as u32, character as u32)
+    }
+
+    /// The byte offset of `position`, clamped to the end of its line and of
+    /// the document like the protocol asks for.
+    pub fn offset(&self, src: &str, position: Position) -> usize {
+        let Some(&start) = self.starts.get(position.line as usize) else {
+            return src.len();
+        };
+        let end = self.starts.get(position.line as usize + 1).map_or(src.len(), |&next| next - 1);
+
+        let mut units = 0;
+        for (i, c) in src[start..end].char_indices() {
+            if units >= position.character as usize {
+                return start + i;
+            }
+            units += c.len_utf16();
+        }
+
+        end
+    }
+
+    pub fn range(&self, src: &str, span: Span) -> Range {
+        Range::new(self.position(src, span.start().to_usize()), self.position(src, span.end().to_usize()))
+    }
+}
+
+/// Converts a diagnostic about the document at `uri`. The first primary
+/// label gives its range, and the other labels become related information.
+pub fn diagnostic(diagnostic: &Diagnostic<FileId>, uri: &Uri, src: &str, index: &LineIndex) -> lsp_types::Diagnostic {
+    let range_of = |range: &std::ops::Range<usize>| index.range(src, Span::new(range.start as u32, range.end as u32));
+
+    let primary = diagnostic.labels.iter().position(|label| label.style == LabelStyle::Primary);
+    let range = primary.map_or_else(Range::default, |i| range_of(&diagnostic.labels[i].range));
+
+    let related = diagnostic
+        .labels
+        .iter()
+        .enumerate()
+        .filter(|&(i, label)| Some(i) != primary && !label.message.is_empty())
+        .map(|(_, label)| DiagnosticRelatedInformation {
+            location: Location::new(uri.clone(), range_of(&label.range)),
+            message: label.message.clone(),
+        })
+        .collect::<Vec<_>>();
+
+    let mut message = diagnostic.message.clone();
+    if let Some(label) = primary.map(|i| &diagnostic.labels[i]).filter(|label| !label.message.is_empty()) {
+        message.push_str(&format!("\n{}", label.message));
+    }
+    for note in &diagnostic.notes {
+        message.push_str(&format!("\n{note}"));
+    }
+
+    lsp_types::Diagnostic {
+        range,
+        severity: Some(severity(diagnostic.severity)),
+        source: Some("propane".to_string()),
+        message,
+        related_information: (!related.is_empty()).then_some(related),
+        ..lsp_types::Diagnostic::default()
+    }
+}
+
+/// Encodes tokens, in order, relative to the ones before them like the
+/// protocol asks for. Tokens spanning several lines, like block comments,
+/// are split into one per line, as clients needn't support them.
+pub fn semantic_tokens(tokens: &[SemanticToken], src: &str, index: &LineIndex) -> Vec<lsp_types::SemanticToken> {
+    let mut encoded = vec![];
+    let mut previous = Position::new(0, 0);
+
+    for token in tokens {
+        let end = token.span.end().to_usize();
+        let mut start = token.span.start().to_usize();
+
+        while start < end {
+            let line_end = src[start..end].find('\n').map_or(end, |i| start + i);
+            let position = index.position(src, start);
+            let length = src[start..line_end].encode_utf16().count() as u32;
+
+            if length > 0 {
+                let delta_line = position.line - previous.line;
+                let delta_start = if delta_line == 0 { position.character - previous.character } else { position.character };
+
+                encoded.push(lsp_types::SemanticToken {
+                    delta_line,
+                    delta_start,
+                    length,
+                    token_type: token.token_type.index(),
+                    token_modifiers_bitset: token.modifiers,
+                });
+                previous = position;
+            }
+
+            start = line_end + 1;
+        }
+    }
+
+    encoded
+}
+
+fn severity(severity: Severity) -> DiagnosticSeverity {
+    match severity {
+        Severity::Bug | Severity::Error => DiagnosticSeverity::ERROR,
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Note => DiagnosticSeverity::INFORMATION,
+        Severity::Help => DiagnosticSeverity::HINT,
+    }
+}
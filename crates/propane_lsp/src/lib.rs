@@ -0,0 +1,420 @@
+//! A language server for Propane, giving editors its diagnostics,
+//! go-to-definition, hover, document symbols and semantic tokens.
+//!
+//! Each open document is analyzed on its own, from scratch, every time it
+//! changes.
+
+use lsp_server::Connection;
+use lsp_types::{
+    HoverProviderCapability, InitializeResult, OneOf, SemanticTokenModifier, SemanticTokenType, SemanticTokensFullOptions,
+    SemanticTokensLegend, SemanticTokensOptions, SemanticTokensServerCapabilities, ServerCapabilities, ServerInfo,
+    TextDocumentSyncCapability, TextDocumentSyncKind,
+};
+use propane_lexer::highlight::{TokenModifier, TokenType};
+
+pub use crate::analysis::{Analysis, Binding, Symbol};
+pub use crate::server::Result;
+
+mod analysis;
+mod convert;
+mod server;
+
+pub fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        definition_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        semantic_tokens_provider: Some(SemanticTokensServerCapabilities::SemanticTokensOptions(SemanticTokensOptions {
+            legend: legend(),
+            full: Some(SemanticTokensFullOptions::Bool(true)),
+            ..SemanticTokensOptions::default()
+        })),
+        ..ServerCapabilities::default()
+    }
+}
+
+/// The names of the semantic token types and modifiers, by their indices.
+pub fn legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TokenType::ALL.iter().map(|token_type| SemanticTokenType::new(token_type.name())).collect(),
+        token_modifiers: TokenModifier::ALL.iter().map(|modifier| SemanticTokenModifier::new(modifier.name())).collect(),
+    }
+}
+
+/// Serves a client over `connection`, from the `initialize` request until
+/// it shuts the server down.
+pub fn run(connection: &Connection) -> Result<()> {
+    let (id, _) = connection.initialize_start()?;
+    let result = InitializeResult {
+        capabilities: capabilities(),
+        server_info: Some(ServerInfo { name: "propane_lsp".to_string(), version: Some(env!("CARGO_PKG_VERSION").to_string()) }),
+    };
+    connection.initialize_finish(id, serde_json::to_value(result)?)?;
+
+    server::Server::new(connection).run()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    use lsp_server::{ErrorCode, Message, Notification, Request, RequestId, Response};
+    use lsp_types::notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Exit, Initialized, Notification as _, PublishDiagnostics,
+    };
+    use lsp_types::request::{
+        DocumentSymbolRequest, GotoDefinition, HoverRequest, Initialize, Request as _, SemanticTokensFullRequest, Shutdown,
+    };
+    use lsp_types::{
+        DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams, DocumentSymbolParams,
+        DocumentSymbolResponse, GotoDefinitionParams, GotoDefinitionResponse, HoverContents, HoverParams, InitializeParams,
+        InitializedParams, Location, Position, PublishDiagnosticsParams, Range, SemanticTokensParams, SemanticTokensResult, SymbolKind,
+        TextDocumentContentChangeEvent,
+        TextDocumentIdentifier, TextDocumentItem, TextDocumentPositionParams, Uri, VersionedTextDocumentIdentifier,
+    };
+
+    use super::*;
+    use crate::convert::LineIndex;
+
+    /// Talks to a server running on another thread, like an editor would.
+    struct Client {
+        connection: Connection,
+        server: JoinHandle<Result<()>>,
+        next_id: i32,
+        /// Notifications that arrived while waiting for a response.
+        notifications: VecDeque<Notification>,
+    }
+
+    impl Client {
+        fn start() -> Client {
+            let (server, connection) = Connection::memory();
+            let server = std::thread::spawn(move || run(&server));
+            let mut client = Client { connection, server, next_id: 0, notifications: VecDeque::new() };
+
+            let result = client.request::<Initialize>(InitializeParams::default());
+            assert_eq!(result.capabilities, capabilities());
+            client.notify::<Initialized>(InitializedParams {});
+
+            client
+        }
+
+        fn send(&self, message: Message) {
+            self.connection.sender.send(message).expect("the server stopped");
+        }
+
+        fn receive(&self) -> Message {
+            self.connection.receiver.recv_timeout(Duration::from_secs(10)).expect("the server didn't respond")
+        }
+
+        fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+            self.send(Message::Notification(Notification::new(N::METHOD.to_string(), params)));
+        }
+
+        /// Sends a request, returning the response to it.
+        fn send_request(&mut self, method: &str, params: impl serde::Serialize) -> Response {
+            self.next_id += 1;
+            let id = RequestId::from(self.next_id);
+            self.send(Message::Request(Request::new(id.clone(), method.to_string(), params)));
+
+            loop {
+                match self.receive() {
+                    Message::Response(response) if response.id == id => return response,
+                    Message::Notification(notification) => self.notifications.push_back(notification),
+                    message => panic!("unexpected message {message:?}"),
+                }
+            }
+        }
+
+        fn request<R: lsp_types::request::Request>(&mut self, params: R::Params) -> R::Result {
+            let response = self.send_request(R::METHOD, params);
+            let result = response.result.unwrap_or_else(|| panic!("`{}` failed: {:?}", R::METHOD, response.error));
+
+            serde_json::from_value(result).unwrap()
+        }
+
+        /// The next diagnostics the server publishes.
+        fn diagnostics(&mut self) -> PublishDiagnosticsParams {
+            let notification = match self.notifications.pop_front() {
+                Some(notification) => notification,
+                None => match self.receive() {
+                    Message::Notification(notification) => notification,
+                    message => panic!("unexpected message {message:?}"),
+                },
+            };
+
+            notification.extract(PublishDiagnostics::METHOD).unwrap()
+        }
+
+        fn open(&mut self, name: &str, text: &str) -> PublishDiagnosticsParams {
+            let document = TextDocumentItem::new(uri(name), "propane".to_string(), 1, text.to_string());
+            self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams { text_document: document });
+
+            self.diagnostics()
+        }
+
+        fn position(name: &str, position: Position) -> TextDocumentPositionParams {
+            TextDocumentPositionParams::new(TextDocumentIdentifier::new(uri(name)), position)
+        }
+
+        fn definition(&mut self, name: &str, position: Position) -> Option<GotoDefinitionResponse> {
+            self.request::<GotoDefinition>(GotoDefinitionParams {
+                text_document_position_params: Client::position(name, position),
+                work_done_progress_params: Default::default(),
+                partial_result_params: Default::default(),
+            })
+        }
+
+        /// The markdown and range of the hover at `position`.
+        fn hover(&mut self, name: &str, position: Position) -> Option<(String, Range)> {
+            let hover = self.request::<HoverRequest>(HoverParams {
+                text_document_position_params: Client::position(name, position),
+                work_done_progress_params: Default::default(),
+            })?;
+            let HoverContents::Markup(contents) = hover.contents else {
+                panic!("expected markdown, found {:?}", hover.contents);
+            };
+
+            Some((contents.value, hover.range.unwrap()))
+        }
+
+        fn shutdown(mut self) {
+            self.request::<Shutdown>(());
+            self.notify::<Exit>(());
+
+            self.server.join().unwrap().unwrap();
+        }
+    }
+
+    fn uri(name: &str) -> Uri {
+        format!("file:///{name}").parse().unwrap()
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[test]
+    fn publishes_diagnostics() {
+        let mut client = Client::start();
+
+        let published = client.open("a.pp", "let a = \"a\" + true;\nreturn a;");
+        assert_eq!(published.uri, uri("a.pp"));
+        assert_eq!(published.version, Some(1));
+        assert_eq!(published.diagnostics.len(), 1);
+
+        let diagnostic = &published.diagnostics[0];
+        assert_eq!(diagnostic.range, range((0, 14), (0, 18)));
+        assert_eq!(diagnostic.severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostic.source.as_deref(), Some("propane"));
+        assert!(diagnostic.message.starts_with("mismatched types\nexpected `string`, found `bool`"), "{}", diagnostic.message);
+
+        client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri("a.pp"), 2),
+            content_changes: vec![TextDocumentContentChangeEvent { range: None, range_length: None, text: "let a = 1;\nlet a = 2; (".to_string() }],
+        });
+        let published = client.diagnostics();
+        let summary: Vec<_> = published.diagnostics.iter().map(|diagnostic| (diagnostic.severity.unwrap(), diagnostic.range)).collect();
+        assert_eq!(published.version, Some(2));
+        assert_eq!(summary, vec![
+            (DiagnosticSeverity::ERROR, range((1, 12), (1, 12))),
+            (DiagnosticSeverity::WARNING, range((1, 4), (1, 5))),
+            (DiagnosticSeverity::WARNING, range((0, 4), (0, 5))),
+            (DiagnosticSeverity::WARNING, range((1, 4), (1, 5))),
+        ]);
+
+        // The earlier binding is related to the shadowing one.
+        let related = published.diagnostics[1].related_information.as_ref().unwrap();
+        assert_eq!(related[0].location, Location::new(uri("a.pp"), range((0, 4), (0, 5))));
+
+        client.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams { text_document: TextDocumentIdentifier::new(uri("a.pp")) });
+        assert_eq!(client.diagnostics().diagnostics, vec![]);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn reopens_documents() {
+        let mut client = Client::start();
+
+        assert_eq!(client.open("a.pp", "let a = 1;").diagnostics.len(), 1);
+        assert_eq!(client.open("a.pp", "let a = 1; a;").diagnostics.len(), 0);
+
+        client.notify::<DidCloseTextDocument>(DidCloseTextDocumentParams { text_document: TextDocumentIdentifier::new(uri("a.pp")) });
+        assert_eq!(client.diagnostics().diagnostics, vec![]);
+        assert_eq!(client.open("a.pp", "a;").diagnostics.len(), 1);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn goes_to_definition() {
+        let mut client = Client::start();
+        let src = "fun square(x) { x * x }\nlet nine = {\n    let three = 3;\n    square(three)\n};\nreturn nine;";
+        assert_eq!(client.open("a.pp", src).diagnostics, vec![]);
+
+        let definition = |client: &mut Client, line, character| match client.definition("a.pp", Position::new(line, character)) {
+            Some(GotoDefinitionResponse::Scalar(location)) => Some(location.range),
+            None => None,
+            response => panic!("expected a single location, found {response:?}"),
+        };
+
+        assert_eq!(definition(&mut client, 3, 6), Some(range((0, 4), (0, 10))));
+        assert_eq!(definition(&mut client, 3, 12), Some(range((2, 8), (2, 13))));
+        assert_eq!(definition(&mut client, 5, 11), Some(range((1, 4), (1, 8))));
+        assert_eq!(definition(&mut client, 0, 20), Some(range((0, 11), (0, 12))));
+        // A declaration is its own definition.
+        assert_eq!(definition(&mut client, 1, 5), Some(range((1, 4), (1, 8))));
+
+        assert_eq!(definition(&mut client, 2, 16), None);
+        assert_eq!(client.definition("b.pp", Position::new(0, 0)), None);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn hovers_over_bindings() {
+        let mut client = Client::start();
+        let src = "fun id(x) { x }\nlet a = id(1);\nfor i in 0..a { id(i); }";
+        client.open("a.pp", src);
+
+        assert_eq!(client.hover("a.pp", Position::new(1, 9)), Some(("```propane\nfun id: fun('a) -> 'a\n```".to_string(), range((1, 8), (1, 10)))));
+        assert_eq!(client.hover("a.pp", Position::new(1, 4)), Some(("```propane\nlet a: int\n```".to_string(), range((1, 4), (1, 5)))));
+        assert_eq!(client.hover("a.pp", Position::new(2, 19)).unwrap().0, "```propane\nfor i: int\n```");
+        assert_eq!(client.hover("a.pp", Position::new(0, 12)).unwrap().0, "```propane\nparameter x: 'a\n```");
+        assert_eq!(client.hover("a.pp", Position::new(1, 12)), None);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn lists_document_symbols() {
+        let mut client = Client::start();
+        let src = "fun f(a) {\n    let b = a + 1;\n    fun g() { b }\n    g()\n}\nlet c = { let d = f(1); d };\nf(c);";
+        client.open("a.pp", src);
+
+        let Some(DocumentSymbolResponse::Nested(symbols)) = client.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+            text_document: TextDocumentIdentifier::new(uri("a.pp")),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        }) else {
+            panic!("expected nested symbols");
+        };
+
+        let names = |symbols: &[lsp_types::DocumentSymbol]| symbols.iter().map(|symbol| symbol.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&symbols), vec!["f", "c"]);
+        assert_eq!(names(symbols[0].children.as_ref().unwrap()), vec!["b", "g"]);
+        assert_eq!(names(symbols[1].children.as_ref().unwrap()), vec!["d"]);
+
+        assert_eq!(symbols[0].kind, SymbolKind::FUNCTION);
+        assert_eq!(symbols[0].detail.as_deref(), Some("fun(int) -> int"));
+        assert_eq!(symbols[0].range, range((0, 0), (4, 1)));
+        assert_eq!(symbols[0].selection_range, range((0, 4), (0, 5)));
+        assert_eq!(symbols[1].kind, SymbolKind::VARIABLE);
+        assert_eq!(symbols[1].detail.as_deref(), Some("int"));
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn highlights_semantic_tokens() {
+        let mut client = Client::start();
+        let src = "fun f(a: int): Nat {\n    /* é\n */ a\n}\nlet b = f(1);";
+        client.open("a.pp", src);
+
+        let Some(SemanticTokensResult::Tokens(tokens)) = client.request::<SemanticTokensFullRequest>(SemanticTokensParams {
+            text_document: TextDocumentIdentifier::new(uri("a.pp")),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        }) else {
+            panic!("expected tokens");
+        };
+
+        // Undo the relative encoding, naming types and modifiers by the
+        // legend.
+        let legend = legend();
+        let mut position = (0, 0);
+        let decoded: Vec<_> = tokens
+            .data
+            .iter()
+            .map(|token| {
+                position = match token.delta_line {
+                    0 => (position.0, position.1 + token.delta_start),
+                    delta_line => (position.0 + delta_line, token.delta_start),
+                };
+                let modifiers: Vec<_> = (0..legend.token_modifiers.len())
+                    .filter(|i| token.token_modifiers_bitset & (1 << i) != 0)
+                    .map(|i| legend.token_modifiers[i].as_str())
+                    .collect();
+
+                (position, token.length, legend.token_types[token.token_type as usize].as_str(), modifiers.join(" "))
+            })
+            .collect();
+
+        assert_eq!(decoded, vec![
+            ((0, 0), 3, "keyword", String::new()),
+            ((0, 4), 1, "function", "declaration".to_string()),
+            ((0, 6), 1, "parameter", "declaration".to_string()),
+            ((0, 9), 3, "type", "defaultLibrary".to_string()),
+            ((0, 15), 3, "type", String::new()),
+            ((1, 4), 4, "comment", String::new()),
+            ((2, 0), 3, "comment", String::new()),
+            ((2, 4), 1, "parameter", String::new()),
+            ((4, 0), 3, "keyword", String::new()),
+            ((4, 4), 1, "variable", "declaration".to_string()),
+            ((4, 6), 1, "operator", String::new()),
+            ((4, 8), 1, "function", String::new()),
+            ((4, 10), 1, "number", String::new()),
+        ]);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn counts_utf16_code_units() {
+        let src = "let s = \"😀é\"; let t = s;\nt;";
+        let index = LineIndex::new(src);
+        let offset = src.rfind("s;").unwrap();
+
+        assert_eq!(index.position(src, offset), Position::new(0, 23));
+        assert_eq!(index.offset(src, Position::new(0, 23)), offset);
+        assert_eq!(index.offset(src, Position::new(0, 100)), src.find('\n').unwrap());
+        assert_eq!(index.offset(src, Position::new(1, 0)), src.len() - 2);
+        assert_eq!(index.offset(src, Position::new(5, 0)), src.len());
+
+        let mut client = Client::start();
+        client.open("a.pp", src);
+        assert_eq!(client.hover("a.pp", Position::new(0, 23)).unwrap().1, range((0, 23), (0, 24)));
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn rejects_unknown_requests() {
+        let mut client = Client::start();
+
+        let response = client.send_request("textDocument/rename", serde_json::json!({}));
+        assert_eq!(response.error.unwrap().code, ErrorCode::MethodNotFound as i32);
+
+        let response = client.send_request(HoverRequest::METHOD, serde_json::json!({ "position": 1 }));
+        assert_eq!(response.error.unwrap().code, ErrorCode::InvalidParams as i32);
+
+        client.shutdown();
+    }
+
+    #[test]
+    fn ignores_malformed_notifications() {
+        let mut client = Client::start();
+
+        for method in [DidOpenTextDocument::METHOD, DidChangeTextDocument::METHOD, DidCloseTextDocument::METHOD] {
+            client.send(Message::Notification(Notification::new(method.to_string(), serde_json::json!({ "textDocument": 1 }))));
+        }
+
+        // The server is still running.
+        assert_eq!(client.open("a.pp", "let a = 1;").diagnostics.len(), 1);
+
+        client.shutdown();
+    }
+}
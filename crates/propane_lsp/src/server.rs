@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use codespan::FileId;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics};
+use lsp_types::request::{DocumentSymbolRequest, GotoDefinition, HoverRequest, Request as _, SemanticTokensFullRequest};
+use lsp_types::{
+    DocumentSymbol, DocumentSymbolResponse, GotoDefinitionResponse, Hover, HoverContents, Location, MarkupContent, MarkupKind,
+    PublishDiagnosticsParams, SemanticTokens, SemanticTokensResult, SymbolKind, TextDocumentPositionParams, Uri,
+};
+use propane_db::Database;
+use propane_resolve::BindingKind;
+
+use crate::analysis::{Analysis, Symbol};
+use crate::convert::{self, LineIndex};
+
+pub type Result<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
+
+/// An open document and what is known about its latest version.
+struct Document {
+    file_id: FileId,
+    index: LineIndex,
+    analysis: Analysis,
+}
+
+pub struct Server<'a> {
+    connection: &'a Connection,
+    db: Database,
+    documents: HashMap<Uri, Document>,
+}
+
+impl<'a> Server<'a> {
+    pub fn new(connection: &'a Connection) -> Server<'a> {
+        Server { connection, db: Database::new(), documents: HashMap::new() }
+    }
+
+    /// Handles messages until the client shuts the server down.
+    pub fn run(&mut self) -> Result<()> {
+        for message in &self.connection.receiver {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+
+                    let response = self.request(request);
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => self.notification(notification)?,
+                Message::Response(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn request(&self, request: Request) -> Response {
+        match request.method.as_str() {
+            GotoDefinition::METHOD => self.respond::<GotoDefinition>(request, |server, params| {
+                server.definition(params.text_document_position_params)
+            }),
+            HoverRequest::METHOD => self.respond::<HoverRequest>(request, |server, params| server.hover(params.text_document_position_params)),
+            DocumentSymbolRequest::METHOD => self.respond::<DocumentSymbolRequest>(request, |server, params| {
+                server.symbols(&params.text_document.uri)
+            }),
+            SemanticTokensFullRequest::METHOD => self.respond::<SemanticTokensFullRequest>(request, |server, params| {
+                server.semantic_tokens(&params.text_document.uri)
+            }),
+            _ => Response::new_err(request.id, ErrorCode::MethodNotFound as i32, format!("unsupported request `{}`", request.method)),
+        }
+    }
+
+    fn respond<R: lsp_types::request::Request>(&self, request: Request, handler: impl FnOnce(&Self, R::Params) -> R::Result) -> Response {
+        let id = request.id.clone();
+
+        match request.extract::<R::Params>(R::METHOD) {
+            Ok((id, params)) => Response::new_ok(id, handler(self, params)),
+            Err(error) => Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string()),
+        }
+    }
+
+    fn notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Some(params) = params::<DidOpenTextDocument>(notification) else {
+                    return Ok(());
+                };
+                let document = params.text_document;
+                let file_id = match self.documents.get(&document.uri) {
+                    Some(open) => open.file_id,
+                    None => self.db.add_file(document.uri.as_str(), String::new()),
+                };
+
+                self.update(document.uri, file_id, document.version, document.text)
+            }
+            DidChangeTextDocument::METHOD => {
+                let Some(params) = params::<DidChangeTextDocument>(notification) else {
+                    return Ok(());
+                };
+                let Some(document) = self.documents.get(&params.text_document.uri) else {
+                    return Ok(());
+                };
+                // Only whole documents are synchronized, so the last change
+                // has the new text.
+                let Some(change) = params.content_changes.into_iter().last() else {
+                    return Ok(());
+                };
+
+                self.update(params.text_document.uri, document.file_id, params.text_document.version, change.text)
+            }
+            DidCloseTextDocument::METHOD => {
+                let Some(params) = params::<DidCloseTextDocument>(notification) else {
+                    return Ok(());
+                };
+                if let Some(document) = self.documents.remove(&params.text_document.uri) {
+                    self.db.remove_file(document.file_id);
+                }
+
+                self.publish(PublishDiagnosticsParams::new(params.text_document.uri, vec![], None))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Analyzes a new version of a document and publishes its diagnostics.
+    fn update(&mut self, uri: Uri, file_id: FileId, version: i32, text: String) -> Result<()> {
+        let index = LineIndex::new(&text);
+        self.db.set_source_text(file_id, text);
+
+        let analysis = Analysis::new(&self.db, file_id);
+        let src = self.db.source_text(file_id);
+        let diagnostics = analysis.diagnostics.iter().map(|diagnostic| convert::diagnostic(diagnostic, &uri, src, &index)).collect();
+        let params = PublishDiagnosticsParams::new(uri.clone(), diagnostics, Some(version));
+
+        self.documents.insert(uri, Document { file_id, index, analysis });
+
+        self.publish(params)
+    }
+
+    fn publish(&self, params: PublishDiagnosticsParams) -> Result<()> {
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+        self.connection.sender.send(Message::Notification(notification))?;
+
+        Ok(())
+    }
+
+    /// The document at a position, and the byte offset of the position.
+    fn locate(&self, position: &TextDocumentPositionParams) -> Option<(&Document, &str, usize)> {
+        let document = self.documents.get(&position.text_document.uri)?;
+        let src = self.db.source_text(document.file_id);
+
+        Some((document, src, document.index.offset(src, position.position)))
+    }
+
+    fn definition(&self, position: TextDocumentPositionParams) -> Option<GotoDefinitionResponse> {
+        let (document, src, offset) = self.locate(&position)?;
+        let (binding, _) = document.analysis.binding_at(offset)?;
+        let range = document.index.range(src, binding.name.span);
+
+        Some(GotoDefinitionResponse::Scalar(Location::new(position.text_document.uri, range)))
+    }
+
+    fn hover(&self, position: TextDocumentPositionParams) -> Option<Hover> {
+        let (document, src, offset) = self.locate(&position)?;
+        let (value, span) = document.analysis.hover(offset)?;
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value }),
+            range: Some(document.index.range(src, span)),
+        })
+    }
+
+    fn symbols(&self, uri: &Uri) -> Option<DocumentSymbolResponse> {
+        let document = self.documents.get(uri)?;
+        let src = self.db.source_text(document.file_id);
+        let symbols = document.analysis.symbols.iter().map(|symbol| self.symbol(document, src, symbol)).collect();
+
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+
+    fn semantic_tokens(&self, uri: &Uri) -> Option<SemanticTokensResult> {
+        let document = self.documents.get(uri)?;
+        let src = self.db.source_text(document.file_id);
+        let data = convert::semantic_tokens(&document.analysis.semantic_tokens(), src, &document.index);
+
+        Some(SemanticTokensResult::Tokens(SemanticTokens { result_id: None, data }))
+    }
+
+    fn symbol(&self, document: &Document, src: &str, symbol: &Symbol) -> DocumentSymbol {
+        let binding = &document.analysis.bindings[symbol.binding];
+        let kind = match binding.kind {
+            BindingKind::Function => SymbolKind::FUNCTION,
+            _ => SymbolKind::VARIABLE,
+        };
+        let children = symbol.children.iter().map(|child| self.symbol(document, src, child)).collect::<Vec<_>>();
+
+        #[allow(deprecated)]
+        DocumentSymbol {
+            name: binding.name.name.clone(),
+            detail: binding.scheme().map(|scheme| scheme.to_string()),
+            kind,
+            tags: None,
+            deprecated: None,
+            range: document.index.range(src, symbol.span),
+            selection_range: document.index.range(src, binding.name.span),
+            children: (!children.is_empty()).then_some(children),
+        }
+    }
+}
+
+/// The parameters of a notification. Malformed ones are logged and
+/// dropped, as there is no response to report the error in.
+fn params<N: lsp_types::notification::Notification>(notification: Notification) -> Option<N::Params> {
+    notification
+        .extract(N::METHOD)
+        .map_err(|error| eprintln!("error: ignoring malformed `{}` notification: {error}", N::METHOD))
+        .ok()
+}
@@ -0,0 +1,40 @@
+use propane_parser::expression::{Expression, ExpressionKind, StatementKind};
+
+use crate::visit;
+
+/// Removes the statements after a `return`, which never run, along with the
+/// value of a block they are in, returning how many were removed. Functions
+/// declared after a `return` are kept, as they can be called before their
+/// declaration.
+pub fn eliminate_dead_code(program: &mut Expression) -> usize {
+    let mut eliminated = 0;
+
+    visit::walk(program, &mut |expression| {
+        let (statements, value) = match &mut expression.kind {
+            ExpressionKind::Block { statements, value } => (statements, Some(value)),
+            ExpressionKind::StmtExpr(statements) => (statements, None),
+            _ => return,
+        };
+        let Some(end) = statements.iter().position(|statement| matches!(statement.kind, StatementKind::Return { .. })) else {
+            return;
+        };
+
+        let count = statements.len();
+        let mut i = 0;
+        statements.retain(|statement| {
+            let live = i <= end || matches!(statement.kind, StatementKind::Function { .. });
+            i += 1;
+            live
+        });
+        eliminated += count - statements.len();
+
+        if let Some(value) = value {
+            // The value may use `let`s that were just removed.
+            if value.take().is_some() {
+                eliminated += 1;
+            }
+        }
+    });
+
+    eliminated
+}
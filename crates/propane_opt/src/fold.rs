@@ -0,0 +1,96 @@
+use propane_parser::expression::{Expression, ExpressionKind, Literal, Operator};
+
+use crate::visit;
+
+/// Replaces operators applied to literals with their results, returning how
+/// many were replaced. Operations that raise an error are left to raise it
+/// at runtime, and those producing a NaN are left alone, as it can't be
+/// written as a literal.
+pub fn fold_constants(program: &mut Expression) -> usize {
+    let mut folded = 0;
+
+    visit::walk(program, &mut |expression| {
+        if let Some(value) = evaluate(expression) {
+            expression.kind = ExpressionKind::Literal(value);
+            folded += 1;
+        }
+    });
+
+    folded
+}
+
+/// The literal `expression` is, looking through parentheses.
+pub fn literal(expression: &Expression) -> Option<&Literal> {
+    match &expression.kind {
+        ExpressionKind::Literal(literal) => Some(literal),
+        ExpressionKind::Grouping(inner) => literal(inner),
+        _ => None,
+    }
+}
+
+fn evaluate(expression: &Expression) -> Option<Literal> {
+    match &expression.kind {
+        ExpressionKind::Unary(operator, operand) => unary(*operator, literal(operand)?),
+        ExpressionKind::Binary { left, operator, right } => binary(*operator, literal(left)?, literal(right)?),
+        _ => None,
+    }
+}
+
+/// Applies an operator like the interpreter does.
+fn unary(operator: Operator, operand: &Literal) -> Option<Literal> {
+    match (operator, operand) {
+        (Operator::Minus, Literal::Int(value)) => value.checked_neg().map(Literal::Int),
+        (Operator::Minus, Literal::Float(value)) => Some(Literal::Float(-value)),
+        (Operator::Not, Literal::Bool(value)) => Some(Literal::Bool(!value)),
+        _ => None,
+    }
+}
+
+/// Applies an operator like the interpreter does.
+fn binary(operator: Operator, left: &Literal, right: &Literal) -> Option<Literal> {
+    use Operator::*;
+
+    let value = match (operator, left, right) {
+        (EqEq, _, _) if same_type(left, right) => Literal::Bool(left == right),
+        (NotEq, _, _) if same_type(left, right) => Literal::Bool(left != right),
+
+        (Plus, Literal::Int(a), Literal::Int(b)) => Literal::Int(a.checked_add(*b)?),
+        (Minus, Literal::Int(a), Literal::Int(b)) => Literal::Int(a.checked_sub(*b)?),
+        (Star, Literal::Int(a), Literal::Int(b)) => Literal::Int(a.checked_mul(*b)?),
+        (Slash, Literal::Int(a), Literal::Int(b)) => Literal::Int(a.checked_div(*b)?),
+
+        (Plus, Literal::Float(a), Literal::Float(b)) => float(a + b)?,
+        (Minus, Literal::Float(a), Literal::Float(b)) => float(a - b)?,
+        (Star, Literal::Float(a), Literal::Float(b)) => float(a * b)?,
+        (Slash, Literal::Float(a), Literal::Float(b)) => float(a / b)?,
+
+        (Plus, Literal::Str(a), Literal::Str(b)) => Literal::Str(format!("{a}{b}")),
+
+        (Gt | GtEq | Lt | LtEq, Literal::Int(a), Literal::Int(b)) => Literal::Bool(compare(operator, a, b)),
+        (Gt | GtEq | Lt | LtEq, Literal::Float(a), Literal::Float(b)) => Literal::Bool(compare(operator, a, b)),
+        (Gt | GtEq | Lt | LtEq, Literal::Char(a), Literal::Char(b)) => Literal::Bool(compare(operator, a, b)),
+        (Gt | GtEq | Lt | LtEq, Literal::Str(a), Literal::Str(b)) => Literal::Bool(compare(operator, a, b)),
+
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn float(value: f64) -> Option<Literal> {
+    (!value.is_nan()).then_some(Literal::Float(value))
+}
+
+fn compare<T: PartialOrd>(operator: Operator, a: T, b: T) -> bool {
+    match operator {
+        Operator::Gt => a > b,
+        Operator::GtEq => a >= b,
+        Operator::Lt => a < b,
+        Operator::LtEq => a <= b,
+        _ => unreachable!("`{}` is not a comparison", operator.as_str()),
+    }
+}
+
+fn same_type(left: &Literal, right: &Literal) -> bool {
+    std::mem::discriminant(left) == std::mem::discriminant(right)
+}
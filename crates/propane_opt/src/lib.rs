@@ -0,0 +1,315 @@
+//! Optimizations of type checked syntax trees, run before they are
+//! interpreted or compiled.
+//!
+//! The passes only ever replace an expression with one of the same type that
+//! has the span of the expression it replaces, or of one inside it, so the
+//! types the type checker inferred for the program still apply.
+
+use std::fmt;
+
+use propane_parser::expression::Expression;
+
+pub use crate::dce::eliminate_dead_code;
+pub use crate::fold::fold_constants;
+pub use crate::propagate::propagate_constants;
+pub use crate::prune::prune_branches;
+
+mod dce;
+mod fold;
+mod propagate;
+mod prune;
+mod visit;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// No optimization.
+    #[default]
+    O0,
+    /// Each pass but constant propagation, once.
+    O1,
+    /// Every pass, repeatedly until none of them changes anything.
+    O2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    ConstantPropagation,
+    ConstantFolding,
+    BranchPruning,
+    DeadCodeElimination,
+}
+
+impl Pass {
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::ConstantPropagation => "constant propagation",
+            Pass::ConstantFolding => "constant folding",
+            Pass::BranchPruning => "branch pruning",
+            Pass::DeadCodeElimination => "dead code elimination",
+        }
+    }
+
+    /// Runs the pass, returning how many changes it made.
+    pub fn run(self, program: &mut Expression) -> usize {
+        match self {
+            Pass::ConstantPropagation => propagate_constants(program),
+            Pass::ConstantFolding => fold_constants(program),
+            Pass::BranchPruning => prune_branches(program),
+            Pass::DeadCodeElimination => eliminate_dead_code(program),
+        }
+    }
+
+    /// What the pass changes, for `count` changes.
+    fn describe(self, count: usize) -> String {
+        let (verb, noun) = match self {
+            Pass::ConstantPropagation => ("replaced", "variable"),
+            Pass::ConstantFolding => ("folded", "operation"),
+            Pass::BranchPruning => ("pruned", "branch"),
+            Pass::DeadCodeElimination => ("removed", "unreachable statement"),
+        };
+        let plural = match (count, noun) {
+            (1, _) => "",
+            (_, "branch") => "es",
+            _ => "s",
+        };
+
+        format!("{verb} {count} {noun}{plural}")
+    }
+}
+
+/// How many changes each pass that ran made.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub passes: Vec<(Pass, usize)>,
+}
+
+impl Report {
+    /// The number of changes `pass` made, if it ran.
+    pub fn changes(&self, pass: Pass) -> Option<usize> {
+        self.passes.iter().find(|(ran, _)| *ran == pass).map(|&(_, changes)| changes)
+    }
+
+    /// Adds the changes in `other`, such as for another program.
+    pub fn merge(&mut self, other: &Report) {
+        for &(pass, changes) in &other.passes {
+            self.record(pass, changes);
+        }
+    }
+
+    fn record(&mut self, pass: Pass, changes: usize) {
+        match self.passes.iter_mut().find(|(ran, _)| *ran == pass) {
+            Some((_, total)) => *total += changes,
+            None => self.passes.push((pass, changes)),
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &(pass, changes) in &self.passes {
+            writeln!(f, "{}: {}", pass.name(), pass.describe(changes))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Optimizes a program that has been type checked without errors.
+pub fn optimize(program: &mut Expression, level: OptLevel) -> Report {
+    let mut report = Report::default();
+
+    let passes: &[Pass] = match level {
+        OptLevel::O0 => return report,
+        OptLevel::O1 => &[Pass::ConstantFolding, Pass::BranchPruning, Pass::DeadCodeElimination],
+        OptLevel::O2 => &[Pass::ConstantPropagation, Pass::ConstantFolding, Pass::BranchPruning, Pass::DeadCodeElimination],
+    };
+
+    loop {
+        let mut changed = false;
+
+        for &pass in passes {
+            let changes = pass.run(program);
+            report.record(pass, changes);
+            changed |= changes > 0;
+        }
+
+        // Every change makes the program smaller or replaces a variable, so
+        // this ends.
+        if level == OptLevel::O1 || !changed {
+            return report;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::FileId;
+    use codespan_reporting::diagnostic::Diagnostic;
+    use propane_parser::expression::{ExpressionKind, Literal, StatementKind};
+
+    use super::*;
+
+    fn check(program: &Expression) {
+        let inference = propane_typeck::TypeChecker::new().check(program);
+        assert!(inference.diagnostics.is_empty(), "{:?}", inference.diagnostics);
+    }
+
+    fn optimized(src: &str, level: OptLevel) -> (Expression, Report) {
+        let mut program = propane_parser::parse_str(src);
+        check(&program);
+        let report = optimize(&mut program, level);
+
+        (program, report)
+    }
+
+    /// The top-level statements of a program.
+    fn statements(program: &Expression) -> &[propane_parser::expression::Statement] {
+        match &program.kind {
+            ExpressionKind::StmtExpr(statements) => statements,
+            _ => panic!("expected a statement list"),
+        }
+    }
+
+    fn let_value(program: &Expression, index: usize) -> &ExpressionKind {
+        match &statements(program)[index].kind {
+            StatementKind::Let { value, .. } => &value.kind,
+            kind => panic!("expected a `let`, found {kind:?}"),
+        }
+    }
+
+    fn interpret(program: Expression) -> Result<String, Diagnostic<FileId>> {
+        std::thread::Builder::new()
+            .stack_size(propane_interp::STACK_SIZE)
+            .spawn(move || propane_interp::Interpreter::new().run(&program).map(|value| value.to_string()))
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    #[test]
+    fn folds_constants() {
+        let (program, report) = optimized("let the_end = 14 * 2 / (8 / 2) - 14; let s = \"a\" + \"b\";", OptLevel::O1);
+
+        assert_eq!(let_value(&program, 0), &ExpressionKind::Literal(Literal::Int(-7)));
+        assert_eq!(let_value(&program, 1), &ExpressionKind::Literal(Literal::Str("ab".to_string())));
+        assert_eq!(report.changes(Pass::ConstantFolding), Some(5));
+        assert_eq!(report.changes(Pass::ConstantPropagation), None);
+    }
+
+    #[test]
+    fn leaves_runtime_errors_to_runtime() {
+        for src in ["let a = 1 / 0;", "let a = 9223372036854775807 + 1;", "let a = -(-9223372036854775807 - 1);", "let a = 0.0 / 0.0;"] {
+            let (program, _) = optimized(src, OptLevel::O2);
+
+            assert!(!matches!(let_value(&program, 0), ExpressionKind::Literal(_)), "{src}");
+        }
+
+        let (program, _) = optimized("let a = 1.0 / 0.0;", OptLevel::O1);
+        assert_eq!(let_value(&program, 0), &ExpressionKind::Literal(Literal::Float(f64::INFINITY)));
+    }
+
+    #[test]
+    fn propagates_constants_through_lets() {
+        let (program, report) = optimized("let main = 3 + 3; let the_end = 14 * 2 / (8 / 2) - 14; let another = main * the_end;", OptLevel::O2);
+
+        assert_eq!(let_value(&program, 2), &ExpressionKind::Literal(Literal::Int(-42)));
+        assert_eq!(report.changes(Pass::ConstantPropagation), Some(2));
+
+        // Only at -O2.
+        let (program, _) = optimized("let a = 1; let b = a + 1;", OptLevel::O1);
+        assert!(matches!(let_value(&program, 1), ExpressionKind::Binary { .. }));
+    }
+
+    #[test]
+    fn respects_scopes() {
+        // `a` in the block is the function declared there, and `f` doesn't
+        // see the `a` it is declared after.
+        let src = "let a = 1; let b = { fun a() { 2 } a() }; for a in 0..2 { a; } let c = a; fun f(): int { a }";
+        let (program, report) = optimized(src, OptLevel::O2);
+
+        assert_eq!(let_value(&program, 3), &ExpressionKind::Literal(Literal::Int(1)));
+        assert_eq!(report.changes(Pass::ConstantPropagation), Some(1));
+    }
+
+    #[test]
+    fn prunes_branches() {
+        let (program, report) = optimized("let a = if 1 < 2 { 10 } else { 20 }; while false { } if false { 1; }", OptLevel::O1);
+
+        let ExpressionKind::Block { value: Some(value), .. } = let_value(&program, 0) else {
+            panic!("expected the `then` branch");
+        };
+        assert_eq!(value.kind, ExpressionKind::Literal(Literal::Int(10)));
+        assert_eq!(statements(&program).len(), 2);
+        assert_eq!(report.changes(Pass::BranchPruning), Some(3));
+    }
+
+    #[test]
+    fn eliminates_code_after_return() {
+        let (program, report) = optimized("let a = 1; return a; let b = 2; b; fun f() { }", OptLevel::O1);
+
+        assert_eq!(statements(&program).len(), 3);
+        assert!(matches!(statements(&program)[2].kind, StatementKind::Function { .. }));
+        assert_eq!(report.changes(Pass::DeadCodeElimination), Some(2));
+
+        let (_, report) = optimized("fun f(): int { { return 1; let a = 2; a } }", OptLevel::O1);
+        assert_eq!(report.changes(Pass::DeadCodeElimination), Some(2));
+    }
+
+    #[test]
+    fn reports_each_pass() {
+        let (_, report) = optimized("let a = 2; if a > 1 { return a * 3; a; } 0;", OptLevel::O2);
+
+        assert_eq!(
+            report.to_string(),
+            "\
+constant propagation: replaced 3 variables
+constant folding: folded 2 operations
+branch pruning: pruned 1 branch
+dead code elimination: removed 1 unreachable statement
+"
+        );
+        assert_eq!(optimized("1;", OptLevel::O0).1, Report::default());
+    }
+
+    #[test]
+    fn preserves_behavior() {
+        let programs = [
+            "return 7 / 2 - -1;",
+            "return 14 * 2 - (8 / 2) - 14;",
+            "return 1.0 != 1.0;",
+            "return 0.1 + 0.2;",
+            "return -2.0 / 3.0;",
+            "return 1e308 * -10.0;",
+            "return 'a' < 'b';",
+            "return \"ab\" < \"b\";",
+            "return \"a\" + \"b\" == \"ab\";",
+            "return !(1 >= 2);",
+            "let a = 1; let a = a + 1; return a;",
+            "let i = 1; for i in 0..5 { } return i;",
+            "let total = { let sum = 0; for i in 0..10 { let sum = sum + i; } sum }; return total;",
+            "fun fib(n) { if n < 2 { return n; } fib(n - 1) + fib(n - 2) } return fib(15);",
+            "for i in 0..5 { if i == 3 { return i * 10; } } return 0;",
+            "let n = 3; fun sign(n) { if n < 0 { -1 } else if n == 0 { 0 } else { 1 } } return sign(-5) + sign(0) * 10 + n;",
+            "let limit = 2; fun f(x: int): int { if x > limit { x } else { limit } } return f(1);",
+            "return g(); fun g() { 5 }",
+            "let a = if true { 1 } else { 2 }; if a == 1 { 10 } else { 20 }",
+            "let nothing = if false { 1; }; nothing;",
+            "let a = 1; fun f() { a } let a = 2; return f();",
+            "fun f() { let a = 1; fun g() { a } let a = 2; g() } return f();",
+            "return { return 1; 2 };",
+            "let zero = 0; return 1 / zero;",
+            "return 9223372036854775807 + 1;",
+            "let min = -9223372036854775807 - 1; return -min;",
+            "let min = -9223372036854775807 - 1; return min / -1;",
+        ];
+
+        for src in programs {
+            for level in [OptLevel::O1, OptLevel::O2] {
+                let (program, _) = optimized(src, level);
+                check(&program);
+
+                assert_eq!(interpret(program), interpret(propane_parser::parse_str(src)), "{src} at {level:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use propane_parser::expression::{Expression, ExpressionKind, Literal, Statement, StatementKind};
+
+use crate::fold::literal;
+
+/// Replaces variables bound by `let` to a literal with the literal, returning
+/// how many were replaced.
+pub fn propagate_constants(program: &mut Expression) -> usize {
+    let mut propagator = Propagator { bindings: vec![], propagated: 0 };
+    propagator.expression(program);
+
+    propagator.propagated
+}
+
+enum Binding {
+    /// A name in scope, with its value if it is a literal.
+    Name(String, Option<Literal>),
+    /// The start of a function body. The interpreter looks up the variables
+    /// a function uses when it is called, not where it is declared, so
+    /// constants aren't propagated into functions.
+    Function,
+}
+
+struct Propagator {
+    /// The bindings in scope, innermost last.
+    bindings: Vec<Binding>,
+    propagated: usize,
+}
+
+impl Propagator {
+    fn expression(&mut self, expression: &mut Expression) {
+        match &mut expression.kind {
+            ExpressionKind::Variable(ident) => {
+                if let Some(value) = self.lookup(&ident.name) {
+                    expression.kind = ExpressionKind::Literal(value.clone());
+                    self.propagated += 1;
+                }
+            }
+            ExpressionKind::Binary { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            ExpressionKind::Grouping(inner) | ExpressionKind::Unary(_, inner) => self.expression(inner),
+            ExpressionKind::Call { callee, args } => {
+                self.expression(callee);
+                args.iter_mut().for_each(|arg| self.expression(arg));
+            }
+            ExpressionKind::Block { statements, value } => {
+                let depth = self.bindings.len();
+
+                self.statements(statements);
+                if let Some(value) = value {
+                    self.expression(value);
+                }
+
+                self.bindings.truncate(depth);
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                self.expression(condition);
+                self.expression(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.expression(else_branch);
+                }
+            }
+            ExpressionKind::StmtExpr(statements) => {
+                let depth = self.bindings.len();
+                self.statements(statements);
+                self.bindings.truncate(depth);
+            }
+            ExpressionKind::Literal(_) | ExpressionKind::Error => {}
+        }
+    }
+
+    fn statements(&mut self, statements: &mut [Statement]) {
+        // Functions are visible throughout the block they are declared in.
+        for statement in statements.iter() {
+            if let StatementKind::Function { name, .. } = &statement.kind {
+                self.bindings.push(Binding::Name(name.name.clone(), None));
+            }
+        }
+
+        for statement in statements {
+            match &mut statement.kind {
+                StatementKind::Let { name, value } => {
+                    self.expression(value);
+
+                    self.bindings.push(Binding::Name(name.name.clone(), literal(value).cloned()));
+                }
+                StatementKind::Return { value } | StatementKind::Expression(value) => self.expression(value),
+                StatementKind::While { condition, body } => {
+                    self.expression(condition);
+                    self.expression(body);
+                }
+                StatementKind::For { variable, start, end, body } => {
+                    self.expression(start);
+                    self.expression(end);
+
+                    self.bindings.push(Binding::Name(variable.name.clone(), None));
+                    self.expression(body);
+                    self.bindings.pop();
+                }
+                StatementKind::Function { body, .. } => {
+                    self.bindings.push(Binding::Function);
+                    self.expression(body);
+                    self.bindings.pop();
+                }
+            }
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Literal> {
+        for binding in self.bindings.iter().rev() {
+            match binding {
+                Binding::Name(bound, value) if bound == name => return value.as_ref(),
+                Binding::Name(..) => {}
+                Binding::Function => return None,
+            }
+        }
+
+        None
+    }
+}
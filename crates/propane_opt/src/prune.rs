@@ -0,0 +1,42 @@
+use propane_parser::expression::{Expression, ExpressionKind, Literal, StatementKind};
+
+use crate::fold::literal;
+use crate::visit;
+
+/// Replaces `if`s whose condition is a literal with the branch they take,
+/// and removes `while` loops whose condition is `false`, returning how many
+/// were pruned.
+pub fn prune_branches(program: &mut Expression) -> usize {
+    let mut pruned = 0;
+
+    visit::walk(program, &mut |expression| {
+        if let ExpressionKind::If { condition, then_branch, else_branch } = &mut expression.kind {
+            if let Some(&Literal::Bool(taken)) = literal(condition) {
+                let branch = if taken { Some(then_branch) } else { else_branch.as_mut() };
+                // Without an `else`, nothing happens.
+                let empty = Expression {
+                    kind: ExpressionKind::Block { statements: vec![], value: None },
+                    span: expression.span,
+                    file_id: expression.file_id,
+                };
+
+                *expression = match branch {
+                    Some(branch) => std::mem::replace(&mut **branch, empty),
+                    None => empty,
+                };
+                pruned += 1;
+            }
+        }
+
+        if let Some(statements) = visit::statements(expression) {
+            let count = statements.len();
+            statements.retain(|statement| {
+                !matches!(&statement.kind, StatementKind::While { condition, .. } if literal(condition) == Some(&Literal::Bool(false)))
+            });
+
+            pruned += count - statements.len();
+        }
+    });
+
+    pruned
+}
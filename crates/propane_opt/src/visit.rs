@@ -0,0 +1,59 @@
+use propane_parser::expression::{Expression, ExpressionKind, Statement, StatementKind};
+
+/// Calls `f` on `expression` and every expression in it, including those in
+/// statements and function bodies, children before their parents. `f` can
+/// replace the expression it is given.
+pub fn walk(expression: &mut Expression, f: &mut impl FnMut(&mut Expression)) {
+    match &mut expression.kind {
+        ExpressionKind::Binary { left, right, .. } => {
+            walk(left, f);
+            walk(right, f);
+        }
+        ExpressionKind::Grouping(inner) | ExpressionKind::Unary(_, inner) => walk(inner, f),
+        ExpressionKind::Call { callee, args } => {
+            walk(callee, f);
+            args.iter_mut().for_each(|arg| walk(arg, f));
+        }
+        ExpressionKind::Block { statements, value } => {
+            statements.iter_mut().for_each(|statement| walk_statement(statement, f));
+            if let Some(value) = value {
+                walk(value, f);
+            }
+        }
+        ExpressionKind::If { condition, then_branch, else_branch } => {
+            walk(condition, f);
+            walk(then_branch, f);
+            if let Some(else_branch) = else_branch {
+                walk(else_branch, f);
+            }
+        }
+        ExpressionKind::StmtExpr(statements) => statements.iter_mut().for_each(|statement| walk_statement(statement, f)),
+        ExpressionKind::Literal(_) | ExpressionKind::Variable(_) | ExpressionKind::Error => {}
+    }
+
+    f(expression);
+}
+
+fn walk_statement(statement: &mut Statement, f: &mut impl FnMut(&mut Expression)) {
+    match &mut statement.kind {
+        StatementKind::Let { value, .. } | StatementKind::Return { value } | StatementKind::Expression(value) => walk(value, f),
+        StatementKind::While { condition, body } => {
+            walk(condition, f);
+            walk(body, f);
+        }
+        StatementKind::For { start, end, body, .. } => {
+            walk(start, f);
+            walk(end, f);
+            walk(body, f);
+        }
+        StatementKind::Function { body, .. } => walk(body, f),
+    }
+}
+
+/// The statements of a block or statement list.
+pub fn statements(expression: &mut Expression) -> Option<&mut Vec<Statement>> {
+    match &mut expression.kind {
+        ExpressionKind::Block { statements, .. } | ExpressionKind::StmtExpr(statements) => Some(statements),
+        _ => None,
+    }
+}
@@ -0,0 +1,386 @@
+//! Typed views of the nodes in the lossless syntax tree.
+//!
+//! Each view wraps a [SyntaxNode] of one kind and finds its parts among the
+//! node's children, skipping trivia. Parts are optional, as a view can be
+//! over a tree built from code with errors in it.
+
+use codespan::Span;
+
+use crate::expression::{Literal, LiteralError, Operator};
+use crate::syntax::{self, SyntaxKind, SyntaxNode, SyntaxToken};
+
+/// A typed view of a node in the syntax tree.
+pub trait AstNode: Sized {
+    /// Views `node` as `Self`, if it is of the right kind.
+    fn cast(node: SyntaxNode) -> Option<Self>;
+
+    fn syntax(&self) -> &SyntaxNode;
+
+    /// The span of the node, without the trivia around it.
+    fn span(&self) -> Span {
+        syntax::span(self.syntax().text_range())
+    }
+}
+
+macro_rules! ast_node {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        pub struct $name(SyntaxNode);
+
+        impl AstNode for $name {
+            fn cast(node: SyntaxNode) -> Option<$name> {
+                (node.kind() == SyntaxKind::$name).then_some($name(node))
+            }
+
+            fn syntax(&self) -> &SyntaxNode {
+                &self.0
+            }
+        }
+    };
+}
+
+ast_node!(Program);
+ast_node!(LetStatement);
+ast_node!(ReturnStatement);
+ast_node!(WhileStatement);
+ast_node!(ForStatement);
+ast_node!(FunctionStatement);
+ast_node!(ExpressionStatement);
+ast_node!(ParameterList);
+ast_node!(Parameter);
+ast_node!(TypeAnnotation);
+ast_node!(LiteralExpression);
+ast_node!(VariableExpression);
+ast_node!(GroupingExpression);
+ast_node!(UnaryExpression);
+ast_node!(BinaryExpression);
+ast_node!(CallExpression);
+ast_node!(ArgumentList);
+ast_node!(BlockExpression);
+ast_node!(IfExpression);
+ast_node!(
+    /// A statement that failed to parse.
+    Error
+);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Statement {
+    Let(LetStatement),
+    Return(ReturnStatement),
+    While(WhileStatement),
+    For(ForStatement),
+    Function(FunctionStatement),
+    Expression(ExpressionStatement),
+    Error(Error),
+}
+
+impl AstNode for Statement {
+    fn cast(node: SyntaxNode) -> Option<Statement> {
+        let statement = match node.kind() {
+            SyntaxKind::LetStatement => Statement::Let(LetStatement(node)),
+            SyntaxKind::ReturnStatement => Statement::Return(ReturnStatement(node)),
+            SyntaxKind::WhileStatement => Statement::While(WhileStatement(node)),
+            SyntaxKind::ForStatement => Statement::For(ForStatement(node)),
+            SyntaxKind::FunctionStatement => Statement::Function(FunctionStatement(node)),
+            SyntaxKind::ExpressionStatement => Statement::Expression(ExpressionStatement(node)),
+            SyntaxKind::Error => Statement::Error(Error(node)),
+            _ => return None,
+        };
+
+        Some(statement)
+    }
+
+    fn syntax(&self) -> &SyntaxNode {
+        match self {
+            Statement::Let(statement) => statement.syntax(),
+            Statement::Return(statement) => statement.syntax(),
+            Statement::While(statement) => statement.syntax(),
+            Statement::For(statement) => statement.syntax(),
+            Statement::Function(statement) => statement.syntax(),
+            Statement::Expression(statement) => statement.syntax(),
+            Statement::Error(error) => error.syntax(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Expression {
+    Literal(LiteralExpression),
+    Variable(VariableExpression),
+    Grouping(GroupingExpression),
+    Unary(UnaryExpression),
+    Binary(BinaryExpression),
+    Call(CallExpression),
+    Block(BlockExpression),
+    If(IfExpression),
+}
+
+impl AstNode for Expression {
+    fn cast(node: SyntaxNode) -> Option<Expression> {
+        let expression = match node.kind() {
+            SyntaxKind::LiteralExpression => Expression::Literal(LiteralExpression(node)),
+            SyntaxKind::VariableExpression => Expression::Variable(VariableExpression(node)),
+            SyntaxKind::GroupingExpression => Expression::Grouping(GroupingExpression(node)),
+            SyntaxKind::UnaryExpression => Expression::Unary(UnaryExpression(node)),
+            SyntaxKind::BinaryExpression => Expression::Binary(BinaryExpression(node)),
+            SyntaxKind::CallExpression => Expression::Call(CallExpression(node)),
+            SyntaxKind::BlockExpression => Expression::Block(BlockExpression(node)),
+            SyntaxKind::IfExpression => Expression::If(IfExpression(node)),
+            _ => return None,
+        };
+
+        Some(expression)
+    }
+
+    fn syntax(&self) -> &SyntaxNode {
+        match self {
+            Expression::Literal(expression) => expression.syntax(),
+            Expression::Variable(expression) => expression.syntax(),
+            Expression::Grouping(expression) => expression.syntax(),
+            Expression::Unary(expression) => expression.syntax(),
+            Expression::Binary(expression) => expression.syntax(),
+            Expression::Call(expression) => expression.syntax(),
+            Expression::Block(expression) => expression.syntax(),
+            Expression::If(expression) => expression.syntax(),
+        }
+    }
+}
+
+impl Program {
+    pub fn statements(&self) -> impl Iterator<Item = Statement> {
+        children(&self.0)
+    }
+}
+
+impl LetStatement {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token(&self.0, SyntaxKind::Ident)
+    }
+
+    pub fn value(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+}
+
+impl ReturnStatement {
+    pub fn value(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+}
+
+impl WhileStatement {
+    pub fn condition(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+
+    pub fn body(&self) -> Option<BlockExpression> {
+        block(children(&self.0).nth(1))
+    }
+}
+
+impl ForStatement {
+    pub fn variable(&self) -> Option<SyntaxToken> {
+        token(&self.0, SyntaxKind::Ident)
+    }
+
+    pub fn start(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+
+    /// The exclusive end of the range.
+    pub fn end(&self) -> Option<Expression> {
+        children(&self.0).nth(1)
+    }
+
+    pub fn body(&self) -> Option<BlockExpression> {
+        block(children(&self.0).nth(2))
+    }
+}
+
+impl FunctionStatement {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token(&self.0, SyntaxKind::Ident)
+    }
+
+    pub fn parameters(&self) -> Option<ParameterList> {
+        children(&self.0).next()
+    }
+
+    pub fn return_type(&self) -> Option<TypeAnnotation> {
+        children(&self.0).next()
+    }
+
+    pub fn body(&self) -> Option<BlockExpression> {
+        children(&self.0).next()
+    }
+}
+
+impl ParameterList {
+    pub fn parameters(&self) -> impl Iterator<Item = Parameter> {
+        children(&self.0)
+    }
+}
+
+impl Parameter {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token(&self.0, SyntaxKind::Ident)
+    }
+
+    pub fn ty(&self) -> Option<TypeAnnotation> {
+        children(&self.0).next()
+    }
+}
+
+impl TypeAnnotation {
+    /// The name of the type.
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token(&self.0, SyntaxKind::Ident)
+    }
+}
+
+impl ExpressionStatement {
+    pub fn expression(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+
+    pub fn semicolon(&self) -> Option<SyntaxToken> {
+        token(&self.0, SyntaxKind::Semi)
+    }
+}
+
+impl LiteralExpression {
+    pub fn token(&self) -> Option<SyntaxToken> {
+        token(&self.0, SyntaxKind::Literal)
+    }
+
+    /// The value of the literal, which is an error if it isn't a valid one.
+    pub fn value(&self) -> Option<Result<Literal, LiteralError>> {
+        let token = self.token()?;
+        let lexed = propane_lexer::tokenize(token.text()).into_iter().next()?;
+
+        match lexed.kind {
+            propane_lexer::TokenKind::Literal { kind, suffix_start } => Some(Literal::from_token_literal(kind, token.text(), suffix_start)),
+            _ => None,
+        }
+    }
+}
+
+impl VariableExpression {
+    pub fn name(&self) -> Option<SyntaxToken> {
+        token(&self.0, SyntaxKind::Ident)
+    }
+}
+
+impl GroupingExpression {
+    pub fn inner(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+}
+
+impl UnaryExpression {
+    pub fn operator(&self) -> Option<Operator> {
+        operator(&self.0)
+    }
+
+    pub fn operand(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+}
+
+impl BinaryExpression {
+    pub fn left(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+
+    pub fn operator(&self) -> Option<Operator> {
+        operator(&self.0)
+    }
+
+    pub fn right(&self) -> Option<Expression> {
+        children(&self.0).nth(1)
+    }
+}
+
+impl CallExpression {
+    pub fn callee(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+
+    pub fn arguments(&self) -> Option<ArgumentList> {
+        children(&self.0).next()
+    }
+}
+
+impl ArgumentList {
+    pub fn arguments(&self) -> impl Iterator<Item = Expression> {
+        children(&self.0)
+    }
+}
+
+impl BlockExpression {
+    pub fn statements(&self) -> impl Iterator<Item = Statement> {
+        children(&self.0)
+    }
+
+    /// The expression the block ends with, and evaluates to.
+    pub fn value(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+}
+
+impl IfExpression {
+    pub fn condition(&self) -> Option<Expression> {
+        children(&self.0).next()
+    }
+
+    pub fn then_branch(&self) -> Option<BlockExpression> {
+        block(children(&self.0).nth(1))
+    }
+
+    /// The `else` block, or the `if` of an `else if`.
+    pub fn else_branch(&self) -> Option<Expression> {
+        let else_token = token(&self.0, SyntaxKind::Else)?;
+
+        else_token.siblings_with_tokens(rowan::Direction::Next).filter_map(|element| element.into_node()).find_map(Expression::cast)
+    }
+}
+
+/// The children of `node` that are `N`s.
+fn children<N: AstNode>(node: &SyntaxNode) -> impl Iterator<Item = N> {
+    node.children().filter_map(N::cast)
+}
+
+/// The first token of `kind` directly in `node`.
+fn token(node: &SyntaxNode, kind: SyntaxKind) -> Option<SyntaxToken> {
+    node.children_with_tokens().filter_map(|element| element.into_token()).find(|token| token.kind() == kind)
+}
+
+fn block(expression: Option<Expression>) -> Option<BlockExpression> {
+    match expression? {
+        Expression::Block(block) => Some(block),
+        _ => None,
+    }
+}
+
+/// The operator token directly in `node`.
+fn operator(node: &SyntaxNode) -> Option<Operator> {
+    node.children_with_tokens().filter_map(|element| element.into_token()).find_map(|token| {
+        let operator = match token.kind() {
+            SyntaxKind::Bang => Operator::Not,
+            SyntaxKind::BangEq => Operator::NotEq,
+            SyntaxKind::EqEq => Operator::EqEq,
+            SyntaxKind::Gt => Operator::Gt,
+            SyntaxKind::GtEq => Operator::GtEq,
+            SyntaxKind::Lt => Operator::Lt,
+            SyntaxKind::LtEq => Operator::LtEq,
+            SyntaxKind::Minus => Operator::Minus,
+            SyntaxKind::Plus => Operator::Plus,
+            SyntaxKind::Slash => Operator::Slash,
+            SyntaxKind::Star => Operator::Star,
+            _ => return None,
+        };
+
+        Some(operator)
+    })
+}
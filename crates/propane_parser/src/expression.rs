@@ -1,7 +1,19 @@
+use std::ops::Range;
+
+use codespan::{FileId, Span};
+use propane_lexer::Base;
+
 use crate::TokenKind;
 
-#[derive(Debug)]
-pub enum Expression {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+    pub file_id: FileId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExpressionKind {
     Binary {
         left: Box<Expression>,
         operator: Operator,
@@ -10,18 +22,78 @@ pub enum Expression {
     Grouping(Box<Expression>),
     Literal(Literal),
     Unary(Operator, Box<Expression>),
-    StmtExpr(Vec<Statement>)
+    Variable(Ident),
+    Call {
+        callee: Box<Expression>,
+        args: Vec<Expression>,
+    },
+    /// `{ let a = 1; a + 1 }`, evaluating to the trailing expression if there
+    /// is one.
+    Block {
+        statements: Vec<Statement>,
+        value: Option<Box<Expression>>,
+    },
+    /// `if a { b } else { c }`. Both branches are [ExpressionKind::Block]s,
+    /// except for `else if` chains where the else branch is another `If`.
+    If {
+        condition: Box<Expression>,
+        then_branch: Box<Expression>,
+        else_branch: Option<Box<Expression>>,
+    },
+    StmtExpr(Vec<Statement>),
+    /// Placeholder for code that failed to parse, see [crate::Parse].
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+    pub file_id: FileId,
 }
 
-#[derive(Debug)]
-pub enum Statement {
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
     Let {
-        name: String,
+        name: Ident,
         value: Expression,
     },
     Return {
         value: Expression
-    }
+    },
+    While {
+        condition: Expression,
+        body: Expression,
+    },
+    /// `for i in start..end { body }`, with `end` being exclusive.
+    For {
+        variable: Ident,
+        start: Expression,
+        end: Expression,
+        body: Expression,
+    },
+    /// `fun name(a: int, b) : int { body }`
+    Function {
+        name: Ident,
+        parameters: Vec<Parameter>,
+        return_type: Option<Ident>,
+        body: Expression,
+    },
+    Expression(Expression),
+}
+
+/// A name in the source, e.g. of a variable, function or type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ident {
+    pub name: String,
+    pub span: Span,
+    pub file_id: FileId,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Parameter {
+    pub name: Ident,
+    pub ty: Option<Ident>,
 }
 
 pub enum Node {
@@ -30,8 +102,9 @@ pub enum Node {
     Program(Vec<Statement>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Operator {
+    Not,
     NotEq,
     EqEq,
     Gt,
@@ -44,14 +117,12 @@ pub enum Operator {
     Star,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Literal {
-    /// "12_u8", "0o100", "0b120i99", "1f32".
-    // Int { base: Base, empty_int: bool },
-    Int(i32),
-    /// "12.34f32", "1e3", but not "1f32".
-    // Float { base: Base, empty_exponent: bool },
-    Float(f32),
+    /// "12_u8", "0o100", "0b1001i64".
+    Int(i64),
+    /// "12.34f32", "1e3", "1f32".
+    Float(f64),
     Bool(bool),
     /// "'a'", "'\\'", "'''", "';"
     Char(char),
@@ -61,23 +132,251 @@ pub enum Literal {
     Str(String),
 }
 
+/// A literal that was lexed but does not denote a valid value, e.g. `0b`,
+/// `1e+` or `256u8`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LiteralError {
+    pub message: String,
+    /// Byte range of the offending part, relative to the start of the token.
+    pub range: Range<usize>,
+}
+
+impl LiteralError {
+    fn new(message: impl Into<String>, range: Range<usize>) -> LiteralError {
+        LiteralError { message: message.into(), range }
+    }
+}
+
+const INT_SUFFIXES: &[(&str, i128)] = &[
+    ("u8", u8::MAX as i128),
+    ("u16", u16::MAX as i128),
+    ("u32", u32::MAX as i128),
+    ("u64", u64::MAX as i128),
+    ("i8", i8::MAX as i128),
+    ("i16", i16::MAX as i128),
+    ("i32", i32::MAX as i128),
+    ("i64", i64::MAX as i128),
+];
+
+const FLOAT_SUFFIXES: &[&str] = &["f32", "f64"];
+
 impl Literal {
-    pub fn from_token_literal(other: propane_lexer::Literal, text: &str) -> Literal {
+    /// Converts the text of a literal token to its value. `suffix_start` is
+    /// the offset of the suffix within `text`, as reported by the lexer.
+    pub fn from_token_literal(other: propane_lexer::Literal, text: &str, suffix_start: u32) -> Result<Literal, LiteralError> {
+        let (body, suffix) = text.split_at(suffix_start as usize);
+        let suffix_range = body.len()..text.len();
+
         match other {
-            propane_lexer::Literal::Int => Literal::Int(text.parse().unwrap()),
-            propane_lexer::Literal::Float => Literal::Float(text.parse().unwrap()),
-            propane_lexer::Literal::Bool => Literal::Bool(text.parse().unwrap()),
-            propane_lexer::Literal::Char { terminated: true } => Literal::Char(text.chars().nth(1).unwrap()),
-            propane_lexer::Literal::Str { terminated: true } => Literal::Str(text[1..text.len() - 1].to_string()),
-            _ => panic!("Unexpected literal type"),
+            propane_lexer::Literal::Int { empty_int: true, .. } => {
+                Err(LiteralError::new("no valid digits found for number", 0..text.len()))
+            }
+            propane_lexer::Literal::Int { base, .. } => {
+                if FLOAT_SUFFIXES.contains(&suffix) {
+                    if base != Base::Decimal {
+                        return Err(unsupported_float_base(base, text));
+                    }
+
+                    return parse_float(body);
+                }
+
+                let (suffix_name, max) = match INT_SUFFIXES.iter().find(|(name, _)| *name == suffix) {
+                    Some(&(name, max)) => (name, max),
+                    None if suffix.is_empty() => ("i64", i64::MAX as i128),
+                    None => return Err(invalid_suffix("integer", suffix, suffix_range)),
+                };
+
+                let digits_start = if base == Base::Decimal { 0 } else { 2 };
+
+                let value = parse_int(body, digits_start, base)?;
+
+                // Values are stored as `i64`, so `u64` literals are limited to its range too.
+                if value > max.min(i64::MAX as i128) {
+                    return Err(LiteralError::new(format!("literal out of range for `{suffix_name}`"), 0..text.len()));
+                }
+
+                Ok(Literal::Int(value as i64))
+            }
+            propane_lexer::Literal::Float { base, empty_exponent } => {
+                if base != Base::Decimal {
+                    return Err(unsupported_float_base(base, text));
+                }
+
+                if empty_exponent {
+                    return Err(LiteralError::new("expected at least one digit in exponent", 0..body.len()));
+                }
+
+                if !suffix.is_empty() && !FLOAT_SUFFIXES.contains(&suffix) {
+                    return Err(invalid_suffix("float", suffix, suffix_range));
+                }
+
+                parse_float(body)
+            }
+            propane_lexer::Literal::Bool => Ok(Literal::Bool(text == "true")),
+            propane_lexer::Literal::Char { terminated: false } => {
+                Err(LiteralError::new("unterminated character literal", 0..1))
+            }
+            propane_lexer::Literal::Char { terminated: true } => {
+                let value = unescape(&text[1..text.len() - 1], 1, '\'')?;
+
+                let mut chars = value.chars();
+
+                match (chars.next(), chars.next()) {
+                    (Some(ch), None) => Ok(Literal::Char(ch)),
+                    (None, _) => Err(LiteralError::new("empty character literal", 0..text.len())),
+                    (Some(_), Some(_)) => Err(LiteralError::new("character literal may only contain one codepoint", 0..text.len())),
+                }
+            }
+            propane_lexer::Literal::Str { terminated: false } => {
+                Err(LiteralError::new("unterminated double quote string", 0..1))
+            }
+            propane_lexer::Literal::Str { terminated: true } => {
+                unescape(&text[1..text.len() - 1], 1, '"').map(Literal::Str)
+            }
+        }
+    }
+}
+
+/// Resolves the escape sequences in the contents of a string or char literal.
+/// `offset` is where `contents` starts within the token, so error ranges stay
+/// relative to the token. `quote` is the delimiter, which may not appear
+/// unescaped.
+fn unescape(contents: &str, offset: usize, quote: char) -> Result<String, LiteralError> {
+    let mut value = String::with_capacity(contents.len());
+    let mut chars = contents.char_indices().peekable();
+
+    while let Some((start, ch)) = chars.next() {
+        if ch == quote {
+            let start = offset + start;
+
+            return Err(LiteralError::new(format!("character `{quote}` must be escaped"), start..start + 1));
         }
+
+        if ch != '\\' {
+            value.push(ch);
+            continue;
+        }
+
+        let escape_start = offset + start;
+
+        let Some((_, escape)) = chars.next() else {
+            return Err(LiteralError::new("expected an escape sequence after `\\`", escape_start..escape_start + 1));
+        };
+
+        let unescaped = match escape {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            '\'' => '\'',
+            'u' => {
+                let mut digits = String::new();
+                let mut end = escape_start + 2;
+
+                if chars.next_if(|&(_, ch)| ch == '{').is_none() {
+                    return Err(LiteralError::new("expected `{` after `\\u`", escape_start..end));
+                }
+
+                end += 1;
+
+                let mut closed = false;
+
+                for (index, ch) in chars.by_ref() {
+                    end = offset + index + ch.len_utf8();
+
+                    if ch == '}' {
+                        closed = true;
+                        break;
+                    }
+
+                    digits.push(ch);
+                }
+
+                let range = escape_start..end;
+
+                if !closed {
+                    return Err(LiteralError::new("unterminated unicode escape", range));
+                }
+
+                if digits.is_empty() || digits.len() > 6 {
+                    return Err(LiteralError::new("unicode escape must have between 1 and 6 hex digits", range));
+                }
+
+                let Ok(codepoint) = u32::from_str_radix(&digits, 16) else {
+                    return Err(LiteralError::new("invalid character in unicode escape", range));
+                };
+
+                match char::from_u32(codepoint) {
+                    Some(ch) => ch,
+                    None => return Err(LiteralError::new(format!("invalid unicode character escape `{codepoint:x}`"), range)),
+                }
+            }
+            other => {
+                let end = escape_start + 1 + other.len_utf8();
+
+                return Err(LiteralError::new(format!("unknown character escape `{}`", other.escape_default()), escape_start..end));
+            }
+        };
+
+        value.push(unescaped);
+    }
+
+    Ok(value)
+}
+
+fn parse_int(body: &str, digits_start: usize, base: Base) -> Result<i128, LiteralError> {
+    let mut value: i128 = 0;
+
+    for (offset, ch) in body[digits_start..].char_indices() {
+        if ch == '_' {
+            continue;
+        }
+
+        let offset = digits_start + offset;
+
+        let Some(digit) = ch.to_digit(base as u32) else {
+            return Err(LiteralError::new(
+                format!("invalid digit `{ch}` for a base {} literal", base as u32),
+                offset..offset + ch.len_utf8(),
+            ));
+        };
+
+        // Saturating keeps huge literals reported as out of range rather than overflowing.
+        value = value.saturating_mul(base as i128).saturating_add(digit as i128);
     }
+
+    Ok(value)
+}
+
+fn parse_float(body: &str) -> Result<Literal, LiteralError> {
+    body.replace('_', "")
+        .parse()
+        .map(Literal::Float)
+        .map_err(|_| LiteralError::new("invalid float literal", 0..body.len()))
+}
+
+fn invalid_suffix(kind: &str, suffix: &str, range: Range<usize>) -> LiteralError {
+    LiteralError::new(format!("invalid suffix `{suffix}` for {kind} literal"), range)
+}
+
+fn unsupported_float_base(base: Base, text: &str) -> LiteralError {
+    let name = match base {
+        Base::Binary => "binary",
+        Base::Octal => "octal",
+        Base::Decimal => "decimal",
+        Base::Hexadecimal => "hexadecimal",
+    };
+
+    LiteralError::new(format!("{name} float literal is not supported"), 0..text.len())
 }
 
 
 impl Operator {
     pub fn from_token(token: TokenKind) -> Option<Operator> {
         match token {
+            TokenKind::Bang => Some(Operator::Not),
             TokenKind::BangEq => Some(Operator::NotEq),
             TokenKind::EqEq => Some(Operator::EqEq),
             TokenKind::Gt => Some(Operator::Gt),
@@ -91,4 +390,39 @@ impl Operator {
             _ => None,
         }
     }
-}
\ No newline at end of file
+
+    /// Binding power of the operator when used infix, higher binds tighter.
+    /// All binary operators are left associative.
+    ///
+    /// Returns `None` for operators that can only be used as a prefix.
+    pub fn binary_precedence(self) -> Option<u8> {
+        match self {
+            Operator::EqEq | Operator::NotEq => Some(1),
+            Operator::Gt | Operator::GtEq | Operator::Lt | Operator::LtEq => Some(2),
+            Operator::Minus | Operator::Plus => Some(3),
+            Operator::Slash | Operator::Star => Some(4),
+            Operator::Not => None,
+        }
+    }
+
+    /// The operator as written in source.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Operator::Not => "!",
+            Operator::NotEq => "!=",
+            Operator::EqEq => "==",
+            Operator::Gt => ">",
+            Operator::GtEq => ">=",
+            Operator::Lt => "<",
+            Operator::LtEq => "<=",
+            Operator::Minus => "-",
+            Operator::Plus => "+",
+            Operator::Slash => "/",
+            Operator::Star => "*",
+        }
+    }
+
+    pub fn is_unary(self) -> bool {
+        matches!(self, Operator::Not | Operator::Minus)
+    }
+}
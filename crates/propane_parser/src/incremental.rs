@@ -0,0 +1,215 @@
+//! Reparsing a file after an edit, reusing the top-level statements the edit
+//! can't have changed.
+//!
+//! The parser only looks at a top-level statement's own tokens and the one
+//! after it, e.g. to see whether an `if` is followed by an `else`. So the
+//! statements before the edit are kept if the token after them is unchanged
+//! too, and the statements after it are kept from the first one the new
+//! statements line up with, with their spans moved. Green nodes don't know
+//! where they are, so the new syntax tree shares theirs with the old one.
+
+use std::ops::Range;
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_lexer::{LexerToken, TextEdit};
+use rowan::GreenNode;
+
+use crate::expression::{Expression, ExpressionKind, Ident, Statement, StatementKind};
+use crate::parser::{self, Parse, Statements};
+use crate::{syntax, ParserToken, TokenKind};
+
+pub fn reparse(old: &Parse, old_tokens: &[LexerToken], src: &str, tokens: &[ParserToken], lexer_tokens: &[LexerToken], edit: &TextEdit) -> Parse {
+    let file_id = old.program.file_id;
+    let ExpressionKind::StmtExpr(old_statements) = &old.program.kind else {
+        unreachable!("a parse is of a program")
+    };
+    let old_len = old.program.span.end().to_usize();
+
+    // The tokens the edit didn't change, and are the same before and after it.
+    let before = old_tokens
+        .iter()
+        .zip(lexer_tokens)
+        .take_while(|(old, new)| old.kind == new.kind && old.span == new.span && old.span.end().to_usize() <= edit.range.start && old.kind != propane_lexer::TokenKind::Eof)
+        .count();
+    let after = old_tokens
+        .iter()
+        .rev()
+        .zip(lexer_tokens.iter().rev())
+        .take_while(|(old, new)| old.kind == new.kind && old.span.start().to_usize() >= edit.range.end && edit.shift(old.span) == new.span)
+        .count();
+
+    let unchanged_before = before.checked_sub(1).map_or(0, |last| old_tokens[last].span.end().to_usize());
+    let unchanged_after = old_tokens.get(old_tokens.len() - after).map_or(usize::MAX, |first| first.span.start().to_usize());
+    // Whether the tokens in between are all skipped by the parser, so that it
+    // sees the same token after the edit as before it.
+    let only_trivia = lexer_tokens[before..lexer_tokens.len() - after].iter().all(|token| TokenKind::from_lexer(token.kind).is_none());
+
+    let kept_before = (0..old_statements.len())
+        .take_while(|&i| {
+            let next = old_statements.get(i + 1).map_or(old_len, |next| next.span.start().to_usize());
+
+            old_statements[i].span.end().to_usize() <= unchanged_before
+                && (next < unchanged_before || (next >= unchanged_after && only_trivia))
+        })
+        .count();
+
+    let start = kept_before.checked_sub(1).map_or(0, |last| old_statements[last].span.end().to_usize());
+    let mut resumed = None;
+    let Statements { statements, parsed, errors, events } = parser::parse_statements(
+        file_id,
+        src,
+        tokens,
+        tokens.partition_point(|token| token.span.start().to_usize() < start),
+        |token| {
+            let offset = token.span.start().to_usize();
+            if offset < edit.new_range().end || edit.old_offset(offset) < unchanged_after {
+                return false;
+            }
+
+            resumed = old_statements.binary_search_by_key(&edit.old_offset(offset), |statement| statement.span.start().to_usize()).ok();
+            resumed.is_some()
+        },
+    );
+    let kept_after = resumed.unwrap_or(old_statements.len());
+    let old_end = old_statements.get(kept_after).map_or(old_len, |statement| statement.span.start().to_usize());
+    let end = if kept_after < old_statements.len() { edit.new_offset(old_end) } else { src.len() };
+
+    let green = splice(
+        &old.green,
+        start..old_end,
+        &syntax::build(
+            &events,
+            src,
+            &lexer_tokens[lexer_tokens.partition_point(|token| token.span.start().to_usize() < start)..lexer_tokens.partition_point(|token| token.span.start().to_usize() < end)],
+        ),
+    );
+
+    // The old parser errors come after the lexer errors, in the order of the
+    // statements that made them.
+    let old_errors = &old.errors[old.errors.len() - old.statements.iter().map(|statement| statement.errors).sum::<usize>()..];
+    let errors_before = old.statements[..kept_before].iter().map(|statement| statement.errors).sum::<usize>();
+    let errors_after = old.statements[kept_after..].iter().map(|statement| statement.errors).sum::<usize>();
+
+    // Errors of the statements before the edit can point at the token after
+    // them, which can be after the edit.
+    let mut all_errors: Vec<_> = old_errors[..errors_before].iter().map(|error| shift_diagnostic(error.clone(), edit)).collect();
+    all_errors.extend(errors);
+    all_errors.extend(old_errors[old_errors.len() - errors_after..].iter().map(|error| shift_diagnostic(error.clone(), edit)));
+
+    let mut all_parsed = old.statements[..kept_before].to_vec();
+    all_parsed.extend(parsed);
+    all_parsed.extend_from_slice(&old.statements[kept_after..]);
+
+    let mut all_statements = old_statements[..kept_before].to_vec();
+    all_statements.extend(statements);
+    all_statements.extend(old_statements[kept_after..].iter().map(|statement| {
+        let mut statement = statement.clone();
+        shift_statement(&mut statement, edit);
+
+        statement
+    }));
+
+    Parse {
+        program: parser::program(file_id, src, all_statements),
+        errors: all_errors,
+        incomplete: all_parsed.iter().any(|statement| statement.unexpected_eof),
+        green,
+        statements: all_parsed,
+    }
+}
+
+/// Replaces the children of the root `old` in the text `range` with the
+/// children of `middle`.
+fn splice(old: &GreenNode, range: Range<usize>, middle: &GreenNode) -> GreenNode {
+    let offsets: Vec<usize> = old
+        .children()
+        .scan(0, |offset, child| {
+            let start = *offset;
+            *offset += usize::from(child.text_len());
+
+            Some(start)
+        })
+        .collect();
+
+    let first = offsets.partition_point(|&offset| offset < range.start);
+    let last = offsets.partition_point(|&offset| offset < range.end);
+
+    old.splice_children(first..last, middle.children().map(|child| child.to_owned()))
+}
+
+/// Moves the labels of a diagnostic that are after the edit.
+fn shift_diagnostic(mut diagnostic: Diagnostic<FileId>, edit: &TextEdit) -> Diagnostic<FileId> {
+    for label in diagnostic.labels.iter_mut().filter(|label| label.range.start >= edit.range.end) {
+        label.range = edit.new_offset(label.range.start)..edit.new_offset(label.range.end);
+    }
+
+    diagnostic
+}
+
+/// Moves the spans in a statement after the edit to where they are in the
+/// edited text.
+fn shift_statement(statement: &mut Statement, edit: &TextEdit) {
+    statement.span = edit.shift(statement.span);
+
+    match &mut statement.kind {
+        StatementKind::Let { name, value } => {
+            shift_ident(name, edit);
+            shift_expression(value, edit);
+        }
+        StatementKind::Return { value } | StatementKind::Expression(value) => shift_expression(value, edit),
+        StatementKind::While { condition, body } => {
+            shift_expression(condition, edit);
+            shift_expression(body, edit);
+        }
+        StatementKind::For { variable, start, end, body } => {
+            shift_ident(variable, edit);
+            shift_expression(start, edit);
+            shift_expression(end, edit);
+            shift_expression(body, edit);
+        }
+        StatementKind::Function { name, parameters, return_type, body } => {
+            shift_ident(name, edit);
+
+            for parameter in parameters {
+                shift_ident(&mut parameter.name, edit);
+                parameter.ty.iter_mut().for_each(|ty| shift_ident(ty, edit));
+            }
+
+            return_type.iter_mut().for_each(|ty| shift_ident(ty, edit));
+            shift_expression(body, edit);
+        }
+    }
+}
+
+fn shift_expression(expression: &mut Expression, edit: &TextEdit) {
+    expression.span = edit.shift(expression.span);
+
+    match &mut expression.kind {
+        ExpressionKind::Binary { left, right, .. } => {
+            shift_expression(left, edit);
+            shift_expression(right, edit);
+        }
+        ExpressionKind::Grouping(inner) | ExpressionKind::Unary(_, inner) => shift_expression(inner, edit),
+        ExpressionKind::Variable(ident) => shift_ident(ident, edit),
+        ExpressionKind::Call { callee, args } => {
+            shift_expression(callee, edit);
+            args.iter_mut().for_each(|arg| shift_expression(arg, edit));
+        }
+        ExpressionKind::Block { statements, value } => {
+            statements.iter_mut().for_each(|statement| shift_statement(statement, edit));
+            value.iter_mut().for_each(|value| shift_expression(value, edit));
+        }
+        ExpressionKind::If { condition, then_branch, else_branch } => {
+            shift_expression(condition, edit);
+            shift_expression(then_branch, edit);
+            else_branch.iter_mut().for_each(|branch| shift_expression(branch, edit));
+        }
+        ExpressionKind::StmtExpr(statements) => statements.iter_mut().for_each(|statement| shift_statement(statement, edit)),
+        ExpressionKind::Literal(_) | ExpressionKind::Error => {}
+    }
+}
+
+fn shift_ident(ident: &mut Ident, edit: &TextEdit) {
+    ident.span = edit.shift(ident.span);
+}
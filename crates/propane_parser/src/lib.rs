@@ -1,24 +1,96 @@
-use codespan::FileId;
-use propane_lexer::{tokenize, Literal, Token};
-use crate::expression::Expression;
-use crate::parser::ParseResult;
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use propane_lexer::{Literal, TextEdit, Token};
 
-mod expression;
+pub use crate::parser::{Parse, ParseResult};
+
+pub mod ast;
+pub mod expression;
+mod incremental;
 mod parser;
+pub mod syntax;
 
 type ParserToken = Token<TokenKind>;
 
-pub fn parse(file_id: FileId, src: &str, tokens: &[Token<propane_lexer::TokenKind>]) -> ParseResult {
-    let tokens = tokens.iter().filter_map(|token|
+pub fn parse(file_id: FileId, src: &str, lexer_tokens: &[Token<propane_lexer::TokenKind>]) -> Parse {
+    let (tokens, lexer_errors) = parser_tokens(file_id, lexer_tokens);
+
+    with_lexer_errors(parser::parse(file_id, src, &tokens, lexer_tokens), lexer_errors)
+}
+
+/// Tokenizes and parses `src` as a file of its own, for tests whose programs
+/// are known to be valid.
+///
+/// # Panics
+///
+/// Panics if `src` has syntax errors.
+#[cfg(any(test, feature = "test-support"))]
+pub fn parse_str(src: &str) -> expression::Expression {
+    let mut files = codespan::Files::new();
+    let file_id = files.add("test", src);
+    let tokens = propane_lexer::tokenize(src);
+
+    parse(file_id, src, &tokens)
+        .into_result()
+        .unwrap_or_else(|errors| panic!("failed to parse {src:?}: {errors:?}"))
+}
+
+/// Parses a file again after an edit to it, only parsing the top-level
+/// statements around the edit and reusing the rest from its previous parse.
+/// `old_tokens` are the tokens `old` was parsed from, and `lexer_tokens` the
+/// tokens of the edited text `src`, e.g. from [propane_lexer::relex]. The
+/// result is the same as parsing `src` from scratch.
+pub fn reparse(
+    old: &Parse,
+    old_tokens: &[Token<propane_lexer::TokenKind>],
+    src: &str,
+    lexer_tokens: &[Token<propane_lexer::TokenKind>],
+    edit: &TextEdit,
+) -> Parse {
+    let (tokens, lexer_errors) = parser_tokens(old.program.file_id, lexer_tokens);
+
+    with_lexer_errors(incremental::reparse(old, old_tokens, src, &tokens, lexer_tokens, edit), lexer_errors)
+}
+
+/// The tokens the parser sees, and the errors for the tokens it can't.
+fn parser_tokens(file_id: FileId, lexer_tokens: &[Token<propane_lexer::TokenKind>]) -> (Vec<ParserToken>, Vec<Diagnostic<FileId>>) {
+    let mut lexer_errors = vec![];
+
+    let tokens = lexer_tokens.iter().filter_map(|token| {
+        if let propane_lexer::TokenKind::BlockComment { terminated: false } = token.kind {
+            lexer_errors.push(unterminated_block_comment_error(file_id, token.span));
+        }
+
         TokenKind::from_lexer(token.kind).map(|kind|
             Token {
                 kind,
                 span: token.span,
             }
         )
-    ).collect::<Vec<_>>();
+    }).collect::<Vec<_>>();
+
+    (tokens, lexer_errors)
+}
 
-    parser::parse(file_id, src, &tokens)
+fn with_lexer_errors(mut parse: Parse, mut lexer_errors: Vec<Diagnostic<FileId>>) -> Parse {
+    // An unterminated comment runs to the end of the input, so more input
+    // could still close it.
+    parse.incomplete |= !lexer_errors.is_empty();
+    lexer_errors.append(&mut parse.errors);
+    parse.errors = lexer_errors;
+
+    parse
+}
+
+fn unterminated_block_comment_error(file_id: FileId, span: Span) -> Diagnostic<FileId> {
+    let start = span.start().0 as usize;
+
+    Diagnostic::error()
+        .with_message("Unterminated block comment")
+        .with_labels(vec![
+            Label::primary(file_id, start..start + 2).with_message("comment starts here"),
+        ])
+        .with_notes(vec!["block comments nest, so every `/*` needs a matching `*/`".to_string()])
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -28,8 +100,13 @@ pub enum TokenKind {
 
     // Keywords:
     Let,
+    If,
+    Else,
+    For,
+    In,
     Fun,
     Return,
+    While,
 
     /// Examples: `12u8`, `1.0e-40`, `b"123"`. Note that `_` is an invalid
     /// suffix, but may be present here on string and float literals. Users of
@@ -38,6 +115,7 @@ pub enum TokenKind {
     /// See [Literal] for more details.
     Literal {
         kind: Literal,
+        suffix_start: u32,
     },
 
     // One-char tokens:
@@ -51,6 +129,14 @@ pub enum TokenKind {
     OpenParen,
     /// ")"
     CloseParen,
+    /// "{"
+    OpenBrace,
+    /// "}"
+    CloseBrace,
+    /// ".."
+    DotDot,
+    /// ":"
+    Colon,
 
     Eq,
     Bang,
@@ -76,16 +162,35 @@ impl TokenKind {
         match kind {
             propane_lexer::TokenKind::Ident => Some(TokenKind::Ident),
             propane_lexer::TokenKind::Let => Some(TokenKind::Let),
+            propane_lexer::TokenKind::If => Some(TokenKind::If),
+            propane_lexer::TokenKind::Else => Some(TokenKind::Else),
+            propane_lexer::TokenKind::For => Some(TokenKind::For),
+            propane_lexer::TokenKind::In => Some(TokenKind::In),
             propane_lexer::TokenKind::Fun => Some(TokenKind::Fun),
             propane_lexer::TokenKind::Return => Some(TokenKind::Return),
-            propane_lexer::TokenKind::Literal { kind } => Some(TokenKind::Literal { kind }),
+            propane_lexer::TokenKind::While => Some(TokenKind::While),
+            propane_lexer::TokenKind::Literal { kind, suffix_start } => Some(TokenKind::Literal { kind, suffix_start }),
             propane_lexer::TokenKind::Semi => Some(TokenKind::Semi),
             propane_lexer::TokenKind::Comma => Some(TokenKind::Comma),
             propane_lexer::TokenKind::Dot => Some(TokenKind::Dot),
             propane_lexer::TokenKind::OpenParen => Some(TokenKind::OpenParen),
             propane_lexer::TokenKind::CloseParen => Some(TokenKind::CloseParen),
+            propane_lexer::TokenKind::OpenBrace => Some(TokenKind::OpenBrace),
+            propane_lexer::TokenKind::CloseBrace => Some(TokenKind::CloseBrace),
+            propane_lexer::TokenKind::DotDot => Some(TokenKind::DotDot),
+            propane_lexer::TokenKind::Colon => Some(TokenKind::Colon),
             propane_lexer::TokenKind::Eq => Some(TokenKind::Eq),
+            propane_lexer::TokenKind::Bang => Some(TokenKind::Bang),
+            propane_lexer::TokenKind::BangEq => Some(TokenKind::BangEq),
+            propane_lexer::TokenKind::EqEq => Some(TokenKind::EqEq),
+            propane_lexer::TokenKind::Gt => Some(TokenKind::Gt),
+            propane_lexer::TokenKind::GtEq => Some(TokenKind::GtEq),
+            propane_lexer::TokenKind::Lt => Some(TokenKind::Lt),
+            propane_lexer::TokenKind::LtEq => Some(TokenKind::LtEq),
+            propane_lexer::TokenKind::Minus => Some(TokenKind::Minus),
             propane_lexer::TokenKind::Plus => Some(TokenKind::Plus),
+            propane_lexer::TokenKind::Slash => Some(TokenKind::Slash),
+            propane_lexer::TokenKind::Star => Some(TokenKind::Star),
             propane_lexer::TokenKind::Eof => Some(TokenKind::Eof),
             _ => None,
         }
@@ -94,54 +199,220 @@ impl TokenKind {
 
 #[cfg(test)]
 mod tests {
-    use std::fmt;
-    use codespan::Files;
-    use propane_lexer::LexerToken;
+    use std::collections::HashSet;
+    use std::ops::Range;
+
+    use codespan::{Files, Span};
+    use propane_lexer::tokenize;
+
+    use crate::expression::{Expression, ExpressionKind, Ident, Literal, Operator, Parameter, Statement, StatementKind};
+    use crate::syntax::{self, SyntaxKind};
     use super::*;
 
-    #[test]
-    fn it_works() {
-//         let mut files = Files::new();
-//
-//         let main = files.add(
-//             "main",
-//             r#"
-// // this is a comment
-// (( )){} // grouping stuff
-// let x = 3;
-//         "#,
-//         );
-//
-//         let tokens = tokenize(files.source(main));
-//
-//         for token in &tokens {
-//             let _ = dbg!(&token.kind, files.source_slice(main, token.span).unwrap());
-//         }
-//
-//         let expression = parse(files.source(main), &tokens);
+    fn parse_src(src: &str) -> ParseResult {
+        parse_partial(src).into_result()
+    }
+
+    /// Parses `src`, erasing the spans of the resulting program so it can be
+    /// compared against trees built with the helpers below.
+    fn parse_partial(src: &str) -> Parse {
+        let mut parse = parse_with_spans(src);
+
+        erase_expression(&mut parse.program);
+
+        parse
+    }
+
+    fn parse_with_spans(src: &str) -> Parse {
+        let mut files = Files::new();
+
+        let main = files.add("main", src);
+
+        let tokens = tokenize(files.source(main));
+
+        parse(main, src, &tokens)
+    }
+
+    fn erase_expression(expression: &mut Expression) {
+        expression.span = Span::initial();
+
+        match &mut expression.kind {
+            ExpressionKind::Binary { left, right, .. } => {
+                erase_expression(left);
+                erase_expression(right);
+            }
+            ExpressionKind::Grouping(inner) | ExpressionKind::Unary(_, inner) => erase_expression(inner),
+            ExpressionKind::Variable(ident) => ident.span = Span::initial(),
+            ExpressionKind::Call { callee, args } => {
+                erase_expression(callee);
+                args.iter_mut().for_each(erase_expression);
+            }
+            ExpressionKind::Block { statements, value } => {
+                statements.iter_mut().for_each(erase_statement);
+                value.iter_mut().for_each(|value| erase_expression(value));
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                erase_expression(condition);
+                erase_expression(then_branch);
+                else_branch.iter_mut().for_each(|branch| erase_expression(branch));
+            }
+            ExpressionKind::StmtExpr(statements) => statements.iter_mut().for_each(erase_statement),
+            ExpressionKind::Literal(_) | ExpressionKind::Error => {}
+        }
+    }
+
+    fn erase_statement(statement: &mut Statement) {
+        statement.span = Span::initial();
+
+        match &mut statement.kind {
+            StatementKind::Let { name, value } => {
+                name.span = Span::initial();
+                erase_expression(value);
+            }
+            StatementKind::Return { value } | StatementKind::Expression(value) => erase_expression(value),
+            StatementKind::While { condition, body } => {
+                erase_expression(condition);
+                erase_expression(body);
+            }
+            StatementKind::For { variable, start, end, body } => {
+                variable.span = Span::initial();
+                erase_expression(start);
+                erase_expression(end);
+                erase_expression(body);
+            }
+            StatementKind::Function { name, parameters, return_type, body } => {
+                name.span = Span::initial();
+
+                for parameter in parameters {
+                    parameter.name.span = Span::initial();
+                    parameter.ty.iter_mut().for_each(|ty| ty.span = Span::initial());
+                }
+
+                return_type.iter_mut().for_each(|ty| ty.span = Span::initial());
+                erase_expression(body);
+            }
+        }
+    }
+
+    fn file_id() -> FileId {
+        Files::new().add("main", "")
+    }
+
+    fn expr(kind: ExpressionKind) -> Expression {
+        Expression { kind, span: Span::initial(), file_id: file_id() }
+    }
+
+    fn stmt(kind: StatementKind) -> Statement {
+        Statement { kind, span: Span::initial(), file_id: file_id() }
+    }
+
+    fn ident(name: &str) -> Ident {
+        Ident { name: name.to_string(), span: Span::initial(), file_id: file_id() }
+    }
+
+    fn parse_statements(src: &str) -> Vec<Statement> {
+        match parse_src(src) {
+            Ok(Expression { kind: ExpressionKind::StmtExpr(statements), .. }) => statements,
+            other => panic!("Expected `{src}` to parse, found {other:?}"),
+        }
+    }
+
+    fn parse_expr(src: &str) -> Expression {
+        match parse_statements(src).pop() {
+            Some(Statement { kind: StatementKind::Expression(expression), .. }) => expression,
+            other => panic!("Expected an expression statement, found {other:?}"),
+        }
+    }
+
+    fn program_statements(parse: Parse) -> Vec<Statement> {
+        let ExpressionKind::StmtExpr(statements) = parse.program.kind else {
+            panic!("Expected a program")
+        };
+
+        statements
+    }
+
+    fn int(value: i64) -> Expression {
+        literal(Literal::Int(value))
+    }
+
+    fn literal(literal: Literal) -> Expression {
+        expr(ExpressionKind::Literal(literal))
+    }
+
+    fn variable(name: &str) -> Expression {
+        expr(ExpressionKind::Variable(ident(name)))
+    }
+
+    fn binary(left: Expression, operator: Operator, right: Expression) -> Expression {
+        expr(ExpressionKind::Binary {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        })
+    }
+
+    fn unary(operator: Operator, right: Expression) -> Expression {
+        expr(ExpressionKind::Unary(operator, Box::new(right)))
+    }
+
+    fn call(callee: Expression, args: Vec<Expression>) -> Expression {
+        expr(ExpressionKind::Call { callee: Box::new(callee), args })
+    }
+
+    fn block(statements: Vec<Statement>, value: Option<Expression>) -> Expression {
+        expr(ExpressionKind::Block { statements, value: value.map(Box::new) })
+    }
+
+    fn if_expr(condition: Expression, then_branch: Expression, else_branch: Option<Expression>) -> Expression {
+        expr(ExpressionKind::If {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch: else_branch.map(Box::new),
+        })
+    }
+
+    fn let_stmt(name: &str, value: Expression) -> Statement {
+        stmt(StatementKind::Let { name: ident(name), value })
+    }
+
+    fn expr_stmt(expression: Expression) -> Statement {
+        stmt(StatementKind::Expression(expression))
+    }
+
+    fn error_statement() -> Statement {
+        expr_stmt(expr(ExpressionKind::Error))
     }
 
     #[test]
     fn parse_let() {
         let src = r#"
         let main = 3 + 3;
-        let the_end = 14 * 2 \ (8 / 2) - 14;
+        let the_end = 14 * 2 / (8 / 2) - 14;
 
         let another = main * the_end;
         "#;
 
-        let mut files = Files::new();
+        let statements = parse_statements(src);
 
-        let main = files.add(
-            "main",
-            src,
-        );
+        assert_eq!(statements.len(), 3);
 
-        let tokens = tokenize(files.source(main));
+        assert_eq!(statements[0], let_stmt("main", binary(int(3), Operator::Plus, int(3))));
 
-        let expression = parse(main, src, &tokens);
+        assert_eq!(statements[1], let_stmt(
+            "the_end",
+            binary(
+                binary(
+                    binary(int(14), Operator::Star, int(2)),
+                    Operator::Slash,
+                    expr(ExpressionKind::Grouping(Box::new(binary(int(8), Operator::Slash, int(2))))),
+                ),
+                Operator::Minus,
+                int(14),
+            ),
+        ));
 
-        test_print(&expression)
+        assert_eq!(statements[2], let_stmt("another", binary(variable("main"), Operator::Star, variable("the_end"))));
     }
 
     #[test]
@@ -150,23 +421,723 @@ mod tests {
         let main 3 + 3;
         "#;
 
-        let mut files = Files::new();
+        let Err(errors) = parse_src(src) else {
+            panic!("Expected err when parsing invalid let statement")
+        };
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].labels[0].range, 18..19);
+    }
+
+    #[test]
+    fn parse_return() {
+        assert_eq!(
+            parse_statements("return 1 + 2;"),
+            vec![stmt(StatementKind::Return { value: binary(int(1), Operator::Plus, int(2)) })],
+        );
+    }
 
-        let main = files.add(
-            "main",
-            src,
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse_expr("1 + 2 * 3 == 7;"),
+            binary(binary(int(1), Operator::Plus, binary(int(2), Operator::Star, int(3))), Operator::EqEq, int(7)),
         );
 
-        let tokens = tokenize(files.source(main));
+        assert_eq!(
+            parse_expr("1 < 2 != 3 >= 4;"),
+            binary(binary(int(1), Operator::Lt, int(2)), Operator::NotEq, binary(int(3), Operator::GtEq, int(4))),
+        );
+    }
 
-        let Err(expression) = parse(main, src, &tokens) else {
-            panic!("Expected err when parsing invalid let statement")
+    #[test]
+    fn left_associativity() {
+        assert_eq!(
+            parse_expr("8 - 4 - 2;"),
+            binary(binary(int(8), Operator::Minus, int(4)), Operator::Minus, int(2)),
+        );
+
+        assert_eq!(
+            parse_expr("8 / 4 * 2;"),
+            binary(binary(int(8), Operator::Slash, int(4)), Operator::Star, int(2)),
+        );
+    }
+
+    #[test]
+    fn unary_operators() {
+        assert_eq!(
+            parse_expr("-1 * !-2;"),
+            binary(
+                unary(Operator::Minus, int(1)),
+                Operator::Star,
+                unary(Operator::Not, unary(Operator::Minus, int(2))),
+            ),
+        );
+    }
+
+    #[test]
+    fn missing_operand() {
+        let Err(errors) = parse_src("1 + ;") else {
+            panic!("Expected err when parsing a dangling operator")
+        };
+
+        assert_eq!(errors[0].message, "Expected expression");
+        assert_eq!(errors[0].labels[0].range, 4..5);
+    }
+
+    #[test]
+    fn unclosed_grouping() {
+        let Err(errors) = parse_src("(1 + 2;") else {
+            panic!("Expected err when parsing an unclosed grouping")
+        };
+
+        assert_eq!(errors[0].labels[0].range, 6..7);
+    }
+
+    #[test]
+    fn numeric_literals() {
+        assert_eq!(parse_expr("1_000;"), int(1000));
+        assert_eq!(parse_expr("0xff;"), int(255));
+        assert_eq!(parse_expr("0o17;"), int(15));
+        assert_eq!(parse_expr("0b1010_1010;"), int(170));
+        assert_eq!(parse_expr("255u8;"), int(255));
+        assert_eq!(parse_expr("9_223_372_036_854_775_807i64;"), int(i64::MAX));
+        assert_eq!(parse_expr("1.5;"), literal(Literal::Float(1.5)));
+        assert_eq!(parse_expr("1e3;"), literal(Literal::Float(1000.0)));
+        assert_eq!(parse_expr("2.5e-1f64;"), literal(Literal::Float(0.25)));
+        assert_eq!(parse_expr("1f32;"), literal(Literal::Float(1.0)));
+    }
+
+    fn literal_error(src: &str) -> (String, std::ops::Range<usize>) {
+        let Err(errors) = parse_src(src) else {
+            panic!("Expected err when parsing `{src}`")
+        };
+
+        let label = &errors[0].labels[0];
+
+        (label.message.clone(), label.range.clone())
+    }
+
+    #[test]
+    fn invalid_numeric_literals() {
+        assert_eq!(literal_error("0x;"), ("no valid digits found for number".to_string(), 0..2));
+        assert_eq!(literal_error("0b102;"), ("invalid digit `2` for a base 2 literal".to_string(), 4..5));
+        assert_eq!(literal_error("1e;"), ("expected at least one digit in exponent".to_string(), 0..2));
+        assert_eq!(literal_error("256u8;"), ("literal out of range for `u8`".to_string(), 0..5));
+        assert_eq!(literal_error("12foo;"), ("invalid suffix `foo` for integer literal".to_string(), 2..5));
+        assert_eq!(literal_error("1.0u8;"), ("invalid suffix `u8` for float literal".to_string(), 3..5));
+        assert_eq!(literal_error("0b1f32;"), ("binary float literal is not supported".to_string(), 0..6));
+    }
+
+    #[test]
+    fn comments_are_skipped() {
+        assert_eq!(parse_expr("1 /* a /* nested */ comment */ + // trailing\n 2;"), binary(int(1), Operator::Plus, int(2)));
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let Err(errors) = parse_src("let a = 1; /* /* */ let b = 2;") else {
+            panic!("Expected err when parsing an unterminated block comment")
         };
 
-        test_print(&expression)
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Unterminated block comment");
+        assert_eq!(errors[0].labels[0].range, 11..13);
+    }
+
+    #[test]
+    fn string_escapes() {
+        assert_eq!(
+            parse_expr(r#""a\"b\n\t\\\0\u{48}\u{1F600}";"#),
+            literal(Literal::Str("a\"b\n\t\\\0H\u{1F600}".to_string())),
+        );
+    }
+
+    #[test]
+    fn char_literals() {
+        assert_eq!(parse_expr("'a';"), literal(Literal::Char('a')));
+        assert_eq!(parse_expr(r"'\'';"), literal(Literal::Char('\'')));
+        assert_eq!(parse_expr(r"'\u{e9}';"), literal(Literal::Char('é')));
+    }
+
+    #[test]
+    fn invalid_escapes() {
+        assert_eq!(literal_error(r#""a\qb";"#), ("unknown character escape `q`".to_string(), 2..4));
+        assert_eq!(literal_error(r#""\u{110000}";"#), ("invalid unicode character escape `110000`".to_string(), 1..11));
+        assert_eq!(literal_error(r#""\u{}";"#), ("unicode escape must have between 1 and 6 hex digits".to_string(), 1..5));
+        assert_eq!(literal_error(r#""\u{zz}";"#), ("invalid character in unicode escape".to_string(), 1..7));
+        assert_eq!(literal_error(r#""\u41";"#), ("expected `{` after `\\u`".to_string(), 1..3));
+        assert_eq!(literal_error(r#""\u{41";"#), ("unterminated unicode escape".to_string(), 1..6));
+        assert_eq!(literal_error(r#""abc;"#), ("unterminated double quote string".to_string(), 0..1));
+    }
+
+    #[test]
+    fn invalid_char_literals() {
+        assert_eq!(literal_error("'';"), ("empty character literal".to_string(), 0..2));
+        assert_eq!(literal_error("'ab';"), ("character literal may only contain one codepoint".to_string(), 0..4));
+        assert_eq!(literal_error("''';"), ("character `'` must be escaped".to_string(), 1..2));
+        assert_eq!(literal_error("'a\n;"), ("unterminated character literal".to_string(), 0..1));
+    }
+
+    #[test]
+    fn if_expression() {
+        let statements = parse_statements("let a = if x < 1 { 1 } else if x < 2 { 2 } else { let y = 3; y };");
+
+        assert_eq!(statements, vec![let_stmt(
+            "a",
+            if_expr(
+                binary(variable("x"), Operator::Lt, int(1)),
+                block(vec![], Some(int(1))),
+                Some(if_expr(
+                    binary(variable("x"), Operator::Lt, int(2)),
+                    block(vec![], Some(int(2))),
+                    Some(block(vec![let_stmt("y", int(3))], Some(variable("y")))),
+                )),
+            ),
+        )]);
     }
 
-    fn test_print(str: &impl fmt::Debug) {
-        assert!(false, "{:?}", str)
+    #[test]
+    fn if_statement_needs_no_semi() {
+        let statements = parse_statements("if a { b; } -1;");
+
+        assert_eq!(statements, vec![
+            expr_stmt(if_expr(variable("a"), block(vec![expr_stmt(variable("b"))], None), None)),
+            expr_stmt(unary(Operator::Minus, int(1))),
+        ]);
+    }
+
+    #[test]
+    fn while_loop() {
+        let statements = parse_statements("while i < 10 { i; if i == 5 { return i; } }");
+
+        assert_eq!(statements, vec![stmt(StatementKind::While {
+            condition: binary(variable("i"), Operator::Lt, int(10)),
+            body: block(
+                vec![expr_stmt(variable("i"))],
+                Some(if_expr(
+                    binary(variable("i"), Operator::EqEq, int(5)),
+                    block(vec![stmt(StatementKind::Return { value: variable("i") })], None),
+                    None,
+                )),
+            ),
+        })]);
+    }
+
+    #[test]
+    fn for_loop() {
+        let statements = parse_statements("for i in 0..n + 1 { total; }");
+
+        assert_eq!(statements, vec![stmt(StatementKind::For {
+            variable: ident("i"),
+            start: int(0),
+            end: binary(variable("n"), Operator::Plus, int(1)),
+            body: block(vec![expr_stmt(variable("total"))], None),
+        })]);
+    }
+
+    #[test]
+    fn for_loop_missing_range() {
+        let Err(errors) = parse_src("for i in 10 { }") else {
+            panic!("Expected err when parsing a for loop without a range")
+        };
+
+        assert_eq!(errors[0].labels[0].message, "expected `DotDot`, found `OpenBrace`");
+    }
+
+    #[test]
+    fn unclosed_block() {
+        let Err(errors) = parse_src("while a { b;") else {
+            panic!("Expected err when parsing an unclosed block")
+        };
+
+        assert_eq!(errors[0].labels[0].message, "expected `CloseBrace`, found `Eof`");
+    }
+
+    #[test]
+    fn function_declaration() {
+        let statements = parse_statements("fun add(a: int, b): int { a + b } fun nothing() {}");
+
+        assert_eq!(statements, vec![
+            stmt(StatementKind::Function {
+                name: ident("add"),
+                parameters: vec![
+                    Parameter { name: ident("a"), ty: Some(ident("int")) },
+                    Parameter { name: ident("b"), ty: None },
+                ],
+                return_type: Some(ident("int")),
+                body: block(vec![], Some(binary(variable("a"), Operator::Plus, variable("b")))),
+            }),
+            stmt(StatementKind::Function {
+                name: ident("nothing"),
+                parameters: vec![],
+                return_type: None,
+                body: block(vec![], None),
+            }),
+        ]);
+    }
+
+    #[test]
+    fn call_expression() {
+        assert_eq!(
+            parse_expr("-f(1, g(2) * 3)(x);"),
+            unary(Operator::Minus, call(
+                call(variable("f"), vec![
+                    int(1),
+                    binary(call(variable("g"), vec![int(2)]), Operator::Star, int(3)),
+                ]),
+                vec![variable("x")],
+            )),
+        );
+    }
+
+    #[test]
+    fn trailing_comma() {
+        assert_eq!(parse_expr("f(1,);"), call(variable("f"), vec![int(1)]));
+    }
+
+    #[test]
+    fn missing_parameter_type() {
+        let Err(errors) = parse_src("fun f(a: ) {}") else {
+            panic!("Expected err when parsing a parameter without a type")
+        };
+
+        assert_eq!(errors[0].labels[0].message, "expected `Ident`, found `CloseParen`");
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(parse_partial("let a = 1").incomplete);
+        assert!(parse_partial("let a =").incomplete);
+        assert!(parse_partial("fun f() { 1;").incomplete);
+        assert!(parse_partial("let a = 1; /* comment").incomplete);
+
+        assert!(!parse_partial("let a = 1;").incomplete);
+        assert!(!parse_partial("let a = ;").incomplete);
+        assert!(!parse_partial("let a = 1 let b = 2;").incomplete);
+    }
+
+    #[test]
+    fn recovers_after_errors() {
+        let parse = parse_partial("let a = ; let b = 2; return + ; fun f( {} let c = 3;");
+
+        assert_eq!(parse.errors.len(), 3);
+
+        assert_eq!(program_statements(parse), vec![
+            error_statement(),
+            let_stmt("b", int(2)),
+            error_statement(),
+            error_statement(),
+            let_stmt("c", int(3)),
+        ]);
+    }
+
+    #[test]
+    fn recovers_inside_blocks() {
+        let parse = parse_partial("fun f() { let a = 1 +; a b; a } let c = 3;");
+
+        assert_eq!(parse.errors.len(), 2);
+        assert_eq!(parse.errors[0].labels[0].message, "expected expression, found `Semi`");
+        assert_eq!(parse.errors[1].labels[0].message, "expected `Semi`, found `Ident`");
+
+        assert_eq!(program_statements(parse), vec![
+            stmt(StatementKind::Function {
+                name: ident("f"),
+                parameters: vec![],
+                return_type: None,
+                body: block(vec![error_statement(), error_statement()], Some(variable("a"))),
+            }),
+            let_stmt("c", int(3)),
+        ]);
+    }
+
+    #[test]
+    fn stray_close_brace() {
+        let parse = parse_partial("let a = 1 + }; let b = 2; } let c = 3;");
+
+        assert_eq!(parse.errors.len(), 2);
+
+        assert_eq!(program_statements(parse), vec![
+            error_statement(),
+            let_stmt("b", int(2)),
+            error_statement(),
+            let_stmt("c", int(3)),
+        ]);
+    }
+
+    fn span_text(src: &str, span: Span) -> &str {
+        &src[span.start().0 as usize..span.end().0 as usize]
+    }
+
+    #[test]
+    fn statement_and_expression_spans() {
+        let src = "let total = (a + b) * f(1, 2);\nif a { 1 } else { 2 }\nfun g(x: int): int { -x }";
+
+        let statements = program_statements(parse_with_spans(src));
+
+        assert_eq!(span_text(src, statements[0].span), "let total = (a + b) * f(1, 2);");
+
+        let StatementKind::Let { name, value } = &statements[0].kind else {
+            panic!("Expected a let statement")
+        };
+
+        assert_eq!(span_text(src, name.span), "total");
+        assert_eq!(span_text(src, value.span), "(a + b) * f(1, 2)");
+
+        let ExpressionKind::Binary { left, right, .. } = &value.kind else {
+            panic!("Expected a binary expression")
+        };
+
+        assert_eq!(span_text(src, left.span), "(a + b)");
+        assert_eq!(span_text(src, right.span), "f(1, 2)");
+
+        assert_eq!(span_text(src, statements[1].span), "if a { 1 } else { 2 }");
+
+        let StatementKind::Function { name, parameters, return_type, body } = &statements[2].kind else {
+            panic!("Expected a function")
+        };
+
+        assert_eq!(span_text(src, statements[2].span), "fun g(x: int): int { -x }");
+        assert_eq!(span_text(src, name.span), "g");
+        assert_eq!(span_text(src, parameters[0].name.span), "x");
+        assert_eq!(span_text(src, parameters[0].ty.as_ref().unwrap().span), "int");
+        assert_eq!(span_text(src, return_type.as_ref().unwrap().span), "int");
+        assert_eq!(span_text(src, body.span), "{ -x }");
+    }
+
+    #[test]
+    fn error_node_spans() {
+        let src = "let a = 1 +; let b = 2;";
+
+        let statements = program_statements(parse_with_spans(src));
+
+        assert_eq!(span_text(src, statements[0].span), "let a = 1 +;");
+        assert_eq!(span_text(src, statements[1].span), "let b = 2;");
+    }
+
+    #[test]
+    fn syntax_tree_is_lossless() {
+        let sources = [
+            "",
+            "  // only a comment\n",
+            "let a = 1 + f(2)(3) * -x; /* trailing */\n",
+            "fun g(a: int, b): int {\n    if {a} { b } else if c { 1 } else { a; }\n}\n",
+            "let = 2; let b = 3 & 4 № $;",
+            "let s = \"unterminated",
+            "/* unterminated /* nested */",
+            "{ let a = 1; a",
+            "}}) let ;",
+        ];
+
+        for src in sources {
+            let parse = parse_with_spans(src);
+
+            assert_eq!(parse.syntax().to_string(), src);
+            assert_eq!(parse.syntax().kind(), SyntaxKind::Program);
+        }
+    }
+
+    #[test]
+    fn syntax_tree_nodes() {
+        let parse = parse_with_spans("let a = -f(1); // done\n{ a }");
+
+        let expected = r#"Program@0..28
+  LetStatement@0..14
+    Let@0..3 "let"
+    Whitespace@3..4 " "
+    Ident@4..5 "a"
+    Whitespace@5..6 " "
+    Eq@6..7 "="
+    Whitespace@7..8 " "
+    UnaryExpression@8..13
+      Minus@8..9 "-"
+      CallExpression@9..13
+        VariableExpression@9..10
+          Ident@9..10 "f"
+        ArgumentList@10..13
+          OpenParen@10..11 "("
+          LiteralExpression@11..12
+            Literal@11..12 "1"
+          CloseParen@12..13 ")"
+    Semi@13..14 ";"
+  Whitespace@14..15 " "
+  LineComment@15..22 "// done"
+  Whitespace@22..23 "\n"
+  ExpressionStatement@23..28
+    BlockExpression@23..28
+      OpenBrace@23..24 "{"
+      Whitespace@24..25 " "
+      VariableExpression@25..26
+        Ident@25..26 "a"
+      Whitespace@26..27 " "
+      CloseBrace@27..28 "}"
+"#;
+        assert_eq!(format!("{:#?}", parse.syntax()), expected);
+    }
+
+    #[test]
+    fn syntax_tree_error_nodes() {
+        let parse = parse_with_spans("let a = 1 +; let b = 2;");
+        let kinds: Vec<_> = parse.syntax().children().map(|node| (node.kind(), node.to_string())).collect();
+
+        assert_eq!(kinds, [(SyntaxKind::Error, "let a = 1 +;".to_string()), (SyntaxKind::LetStatement, "let b = 2;".to_string())]);
+    }
+
+    /// Checks that every node in `expression` has a node of the matching
+    /// kind with the same span in the lossless tree, whose nodes are in
+    /// `nodes`.
+    fn check_expression_spans(nodes: &HashSet<(SyntaxKind, Span)>, expression: &Expression) {
+        let kind = match &expression.kind {
+            ExpressionKind::Binary { left, right, .. } => {
+                check_expression_spans(nodes, left);
+                check_expression_spans(nodes, right);
+                SyntaxKind::BinaryExpression
+            }
+            ExpressionKind::Grouping(inner) => {
+                check_expression_spans(nodes, inner);
+                SyntaxKind::GroupingExpression
+            }
+            ExpressionKind::Unary(_, operand) => {
+                check_expression_spans(nodes, operand);
+                SyntaxKind::UnaryExpression
+            }
+            ExpressionKind::Literal(_) => SyntaxKind::LiteralExpression,
+            ExpressionKind::Variable(_) => SyntaxKind::VariableExpression,
+            ExpressionKind::Call { callee, args } => {
+                check_expression_spans(nodes, callee);
+                args.iter().for_each(|arg| check_expression_spans(nodes, arg));
+                SyntaxKind::CallExpression
+            }
+            ExpressionKind::Block { statements, value } => {
+                statements.iter().for_each(|statement| check_statement_spans(nodes, statement));
+                value.iter().for_each(|value| check_expression_spans(nodes, value));
+                SyntaxKind::BlockExpression
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                check_expression_spans(nodes, condition);
+                check_expression_spans(nodes, then_branch);
+                else_branch.iter().for_each(|branch| check_expression_spans(nodes, branch));
+                SyntaxKind::IfExpression
+            }
+            ExpressionKind::StmtExpr(statements) => {
+                statements.iter().for_each(|statement| check_statement_spans(nodes, statement));
+                SyntaxKind::Program
+            }
+            ExpressionKind::Error => SyntaxKind::Error,
+        };
+
+        assert!(nodes.contains(&(kind, expression.span)), "no {kind:?} at {:?}", expression.span);
+    }
+
+    fn check_statement_spans(nodes: &HashSet<(SyntaxKind, Span)>, statement: &Statement) {
+        let kind = match &statement.kind {
+            StatementKind::Let { value, .. } => {
+                check_expression_spans(nodes, value);
+                SyntaxKind::LetStatement
+            }
+            StatementKind::Return { value } => {
+                check_expression_spans(nodes, value);
+                SyntaxKind::ReturnStatement
+            }
+            StatementKind::While { condition, body } => {
+                check_expression_spans(nodes, condition);
+                check_expression_spans(nodes, body);
+                SyntaxKind::WhileStatement
+            }
+            StatementKind::For { start, end, body, .. } => {
+                check_expression_spans(nodes, start);
+                check_expression_spans(nodes, end);
+                check_expression_spans(nodes, body);
+                SyntaxKind::ForStatement
+            }
+            StatementKind::Function { body, .. } => {
+                check_expression_spans(nodes, body);
+                SyntaxKind::FunctionStatement
+            }
+            StatementKind::Expression(Expression { kind: ExpressionKind::Error, .. }) => SyntaxKind::Error,
+            StatementKind::Expression(value) => {
+                check_expression_spans(nodes, value);
+                SyntaxKind::ExpressionStatement
+            }
+        };
+
+        assert!(nodes.contains(&(kind, statement.span)), "no {kind:?} at {:?}", statement.span);
+    }
+
+    #[test]
+    fn syntax_tree_matches_spans() {
+        let src = "\
+fun fib(n: int): int {
+    // base case
+    if n < 2 { return n; }
+    fib(n - 1) + fib(n - 2)
+}
+let a = (1 + 2) * -fib(10)(3); /* odd */
+for i in 0..a { while !(i == 3) { print(i, a); } }
+{ let b = { 1 }; b };
+let c = ;
+";
+        let parse = parse_with_spans(src);
+        let nodes: HashSet<_> = parse.syntax().descendants().map(|node| (node.kind(), syntax::span(node.text_range()))).collect();
+
+        check_expression_spans(&nodes, &parse.program);
+    }
+
+    #[test]
+    fn typed_views() {
+        use crate::ast::{self, AstNode};
+
+        let src = "fun add(a: int, b) /* sum */ : int { let c = a + b; c }\nif {true} { add(1, 2) } else if x { 0x1F } else { -1 };";
+        let parse = parse_with_spans(src);
+        let program = ast::Program::cast(parse.syntax()).unwrap();
+        let statements: Vec<_> = program.statements().collect();
+
+        let ast::Statement::Function(function) = &statements[0] else {
+            panic!("Expected a function, found {:?}", statements[0])
+        };
+        assert_eq!(function.name().unwrap().text(), "add");
+        let parameters: Vec<_> = function.parameters().unwrap().parameters().collect();
+        assert_eq!(parameters.len(), 2);
+        assert_eq!(parameters[0].name().unwrap().text(), "a");
+        assert_eq!(parameters[0].ty().unwrap().name().unwrap().text(), "int");
+        assert!(parameters[1].ty().is_none());
+        assert_eq!(function.return_type().unwrap().name().unwrap().text(), "int");
+
+        let body = function.body().unwrap();
+        let Some(ast::Statement::Let(let_statement)) = body.statements().next() else {
+            panic!("Expected a let statement")
+        };
+        assert_eq!(let_statement.name().unwrap().text(), "c");
+        let Some(ast::Expression::Binary(sum)) = let_statement.value() else {
+            panic!("Expected a binary expression")
+        };
+        assert_eq!(sum.operator(), Some(Operator::Plus));
+        assert_eq!(sum.left().unwrap().syntax().to_string(), "a");
+        assert_eq!(sum.right().unwrap().syntax().to_string(), "b");
+        assert_eq!(body.value().unwrap().syntax().to_string(), "c");
+        assert_eq!(span_text(src, body.span()), "{ let c = a + b; c }");
+
+        let ast::Statement::Expression(statement) = &statements[1] else {
+            panic!("Expected an expression statement, found {:?}", statements[1])
+        };
+        assert!(statement.semicolon().is_some());
+        let Some(ast::Expression::If(if_expression)) = statement.expression() else {
+            panic!("Expected an if")
+        };
+        assert!(matches!(if_expression.condition(), Some(ast::Expression::Block(_))));
+        assert_eq!(if_expression.then_branch().unwrap().syntax().to_string(), "{ add(1, 2) }");
+
+        let Some(ast::Expression::If(else_if)) = if_expression.else_branch() else {
+            panic!("Expected an else if")
+        };
+        let Some(ast::Expression::Literal(literal)) = else_if.then_branch().unwrap().value() else {
+            panic!("Expected a literal")
+        };
+        assert_eq!(literal.value(), Some(Ok(Literal::Int(31))));
+
+        let Some(ast::Expression::Block(else_block)) = else_if.else_branch() else {
+            panic!("Expected an else block")
+        };
+        let Some(ast::Expression::Unary(negation)) = else_block.value() else {
+            panic!("Expected a unary expression")
+        };
+        assert_eq!(negation.operator(), Some(Operator::Minus));
+    }
+
+    /// Reparses `src` after replacing `range` with `text`, checking the result
+    /// against parsing the edited text from scratch. Returns the reparse and
+    /// the number of its statements that share their node with the old tree.
+    fn check_reparse(old: &Parse, src: &str, range: Range<usize>, text: &str) -> (Parse, String, usize) {
+        let edit = TextEdit::new(range, text);
+        let mut edited = src.to_string();
+        edit.apply(&mut edited);
+
+        let old_tokens = tokenize(src);
+        let tokens = propane_lexer::relex(&old_tokens, &edited, &edit);
+        let reparsed = reparse(old, &old_tokens, &edited, &tokens, &edit);
+        let expected = parse(old.program.file_id, &edited, &tokenize(&edited));
+
+        assert_eq!(reparsed.program, expected.program, "{src:?} edited to {edited:?}");
+        assert_eq!(reparsed.errors, expected.errors, "{src:?} edited to {edited:?}");
+        assert_eq!(reparsed.incomplete, expected.incomplete, "{src:?} edited to {edited:?}");
+        assert_eq!(reparsed.green, expected.green, "{src:?} edited to {edited:?}");
+
+        let shared = reparsed
+            .green
+            .children()
+            .filter_map(|child| child.into_node())
+            .filter(|node| old.green.children().filter_map(|child| child.into_node()).any(|old| std::ptr::eq(old, *node)))
+            .count();
+
+        (reparsed, edited, shared)
+    }
+
+    #[test]
+    fn reparse_reuses_statements() {
+        let src = "let a = 1;\nfun f(x) { x + a }\n\nif a { f(a) }\nlet b = f(2);\n";
+        let old = parse_with_spans(src);
+        let at = |text: &str| src.find(text).unwrap();
+        let after = |text: &str| at(text) + text.len();
+
+        // Only the statement with the edit in it is parsed again.
+        assert_eq!(check_reparse(&old, src, at("x +")..at("x +") + 1, "x * 2").2, 3);
+        assert_eq!(check_reparse(&old, src, at("1;")..at("1;") + 1, "100").2, 3);
+        // Edits between statements reuse them all.
+        assert_eq!(check_reparse(&old, src, at("if")..at("if"), "// f\n").2, 4);
+        assert_eq!(check_reparse(&old, src, 0..0, "\n").2, 4);
+        // A new statement is parsed along with the one before it, as it
+        // could have gone on into it, e.g. an `if` into an `else`.
+        assert_eq!(check_reparse(&old, src, at("if")..at("if"), "let c = 3;\n").2, 3);
+        assert_eq!(check_reparse(&old, src, after("{ f(a) }")..after("{ f(a) }"), " else { 0 }").2, 3);
+        // Unclosing a block changes the statements it runs into, up to where
+        // the parser recovers.
+        assert_eq!(check_reparse(&old, src, after("+ a ")..after("+ a }"), "").2, 2);
+    }
+
+    #[test]
+    fn reparse_every_edit() {
+        let sources = [
+            "let a = 1;\nfun f(x: int): int { if x < 2 { x } else { f(x - 1) } }\nwhile a { print(a); }\nlet b = f(a);",
+            "let a = ;\nlet b = 2 /* c */;\n{ 1 } - 2;\nfor i in 0..3 { }\nreturn b",
+            "let s = \"a\"; if s { 1 } else { 2 }; } let t = 0b; f(",
+        ];
+        let insertions = [";", "{", "}", "(", "let ", "else { 0 }", "\"", "/*", "*/", "// ", "\n", " ", "-"];
+
+        for src in sources {
+            let old = parse_with_spans(src);
+            let boundaries: Vec<usize> = (0..=src.len()).filter(|&i| src.is_char_boundary(i)).collect();
+
+            for (i, &start) in boundaries.iter().enumerate() {
+                for text in insertions {
+                    check_reparse(&old, src, start..start, text);
+                }
+
+                for &end in boundaries.iter().skip(i + 1).take(4) {
+                    check_reparse(&old, src, start..end, "");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reparse_after_reparse() {
+        let edits = [
+            (0..0, "let a = 1;\n"),
+            (8..9, "2 +"),
+            (11..11, " 3"),
+            (0..0, "fun f() {\n"),
+            (25..25, "}"),
+            (9..10, ""),
+            (0..9, ""),
+        ];
+
+        let mut src = String::new();
+        let mut parsed = parse_with_spans(&src);
+        for (range, text) in edits {
+            let (reparsed, edited, _) = check_reparse(&parsed, &src, range, text);
+
+            parsed = reparsed;
+            src = edited;
+        }
     }
 }
@@ -1,115 +1,605 @@
 use codespan::{FileId, Span};
 use codespan_reporting::diagnostic::{Diagnostic, Label};
-use crate::expression::{Expression, Literal, Statement};
+use propane_lexer::LexerToken;
+use rowan::GreenNode;
+use crate::expression::{Expression, ExpressionKind, Ident, Literal, LiteralError, Operator, Parameter, Statement, StatementKind};
+use crate::syntax::{self, Event, SyntaxKind, SyntaxNode};
 use crate::{ParserToken, TokenKind};
-use crate::expression::Expression::StmtExpr;
 
 struct Parser<'src> {
     tokens: &'src [ParserToken],
     src: &'src str,
     file_id: FileId,
     current: usize,
-    errors: Vec<Diagnostic<FileId>>
+    /// Number of blocks the current token is nested in.
+    block_depth: usize,
+    /// Whether an error was caused by reaching the end of the input.
+    unexpected_eof: bool,
+    errors: Vec<Diagnostic<FileId>>,
+    /// The nodes started and finished and the tokens consumed so far, for
+    /// building the syntax tree.
+    events: Vec<Event>,
 }
 
 pub type ParseResult = Result<Expression, Vec<Diagnostic<FileId>>>;
 
+/// The outcome of parsing a file: the program, with [ExpressionKind::Error]
+/// nodes in place of statements that failed to parse, and every error
+/// encountered.
+#[derive(Debug, PartialEq)]
+pub struct Parse {
+    pub program: Expression,
+    pub errors: Vec<Diagnostic<FileId>>,
+    /// Whether the input ended in the middle of a statement, e.g. before its
+    /// `;` or a block's closing `}`, so that more input could complete it.
+    pub incomplete: bool,
+    /// The lossless syntax tree of the file, see [Parse::syntax].
+    pub green: GreenNode,
+    /// What parsing each top-level statement added, for [crate::reparse] to
+    /// reuse along with the statement.
+    pub(crate) statements: Vec<ParsedStatement>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ParsedStatement {
+    /// The number of errors the statement added to [Parse::errors].
+    pub errors: usize,
+    /// Whether the statement ran into the end of the input.
+    pub unexpected_eof: bool,
+}
+
+/// The top-level statements parsed from a run of tokens, and the tree
+/// events for them inside a [SyntaxKind::Program] node.
+pub(crate) struct Statements {
+    pub statements: Vec<Statement>,
+    pub parsed: Vec<ParsedStatement>,
+    pub errors: Vec<Diagnostic<FileId>>,
+    pub events: Vec<Event>,
+}
+
+impl Parse {
+    /// The root of the lossless syntax tree, whose text is the whole file.
+    pub fn syntax(&self) -> SyntaxNode {
+        SyntaxNode::new_root(self.green.clone())
+    }
+
+    /// Discards the partial program if there were any errors.
+    pub fn into_result(self) -> ParseResult {
+        if self.errors.is_empty() {
+            Ok(self.program)
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
 impl Parser<'_> {
-    fn parse(mut self) -> ParseResult {
+    /// Parses top-level statements until the end of the input, or until
+    /// `stop` returns true for the token the next one would start at.
+    fn statements(mut self, mut stop: impl FnMut(ParserToken) -> bool) -> Statements {
         let mut statements = vec![];
+        let mut parsed = vec![];
 
-        loop {
-            if self.tokens.get(self.current).unwrap().kind == TokenKind::Eof {
-                break;
-            }
+        self.start_node(SyntaxKind::Program);
+        while self.current_token().kind != TokenKind::Eof && !stop(self.current_token()) {
+            let errors = self.errors.len();
+            statements.push(self.parse_statement_or_recover());
 
-            if let Some(statement) = self.parse_statement() {
-                statements.push(statement);
-            } else {
-                break;
+            parsed.push(ParsedStatement {
+                errors: self.errors.len() - errors,
+                unexpected_eof: std::mem::take(&mut self.unexpected_eof),
+            });
+        }
+        self.finish_node();
+
+        Statements { statements, parsed, errors: self.errors, events: self.events }
+    }
+
+    /// Parses a statement, or on failure skips ahead to the next likely start
+    /// of a statement and returns an error node in its place.
+    fn parse_statement_or_recover(&mut self) -> Statement {
+        let start = self.current;
+        let checkpoint = self.checkpoint();
+
+        match self.parse_statement() {
+            Some(statement) => statement,
+            None => self.recover(start, checkpoint),
+        }
+    }
+
+    /// Skips past a statement that failed to parse from token index `start`,
+    /// returning the error node to use in its place. The nodes it started
+    /// after `checkpoint` are replaced by an error node with its tokens.
+    fn recover(&mut self, start: usize, checkpoint: usize) -> Statement {
+        self.synchronize();
+
+        // Always make progress, e.g. when the statement failed on its first token.
+        if self.current == start {
+            self.advance();
+        }
+
+        self.events.truncate(checkpoint);
+        self.start_node(SyntaxKind::Error);
+        for index in start..self.current {
+            if self.token_at(index).kind != TokenKind::Eof {
+                self.events.push(Event::Token);
             }
         }
+        self.finish_node();
 
-        if self.errors.is_empty() {
-            Ok(StmtExpr(statements))
-        } else {
-            Err(self.errors)
+        let span = self.span_from(self.token_at(start).span);
+
+        Statement {
+            kind: StatementKind::Expression(self.expression(ExpressionKind::Error, span)),
+            span,
+            file_id: self.file_id,
+        }
+    }
+
+    /// Panic mode recovery: skips tokens until just past a `;`, or until a
+    /// `}` or a keyword that starts a statement. Outside of a block a `}` is
+    /// stray, so it is skipped over too.
+    fn synchronize(&mut self) {
+        loop {
+            match self.current_token().kind {
+                TokenKind::Semi => {
+                    self.advance();
+
+                    return;
+                }
+                TokenKind::CloseBrace if self.block_depth == 0 => self.advance(),
+                TokenKind::Eof
+                | TokenKind::CloseBrace
+                | TokenKind::Let
+                | TokenKind::Fun
+                | TokenKind::Return
+                | TokenKind::While
+                | TokenKind::For => return,
+                _ => self.advance(),
+            }
         }
     }
 
     fn parse_statement(&mut self) -> Option<Statement> {
-        match self.current_token().kind {
+        let start = self.current_token().span;
+        let checkpoint = self.checkpoint();
+
+        let kind = match self.current_token().kind {
             TokenKind::Let => {
                 self.parse_let_statement()
             }
             TokenKind::Return => {
                 self.parse_return_statement()
             }
+            TokenKind::While => {
+                self.parse_while_statement()
+            }
+            TokenKind::For => {
+                self.parse_for_statement()
+            }
+            TokenKind::Fun => {
+                self.parse_function_statement()
+            }
+            TokenKind::If | TokenKind::OpenBrace => {
+                let value = self.parse_block_like()?;
+
+                self.eat(TokenKind::Semi);
+
+                Some(StatementKind::Expression(value))
+            }
             _ => {
                 self.parse_expression_statement()
             }
-        }
-    }
+        }?;
 
-    fn parse_let_statement(&mut self) -> Option<Statement> {
-        let Some(ident_token) = self.peek_expect_and_advance(TokenKind::Ident) else {
-            return None;
+        let node = match kind {
+            StatementKind::Let { .. } => SyntaxKind::LetStatement,
+            StatementKind::Return { .. } => SyntaxKind::ReturnStatement,
+            StatementKind::While { .. } => SyntaxKind::WhileStatement,
+            StatementKind::For { .. } => SyntaxKind::ForStatement,
+            StatementKind::Function { .. } => SyntaxKind::FunctionStatement,
+            StatementKind::Expression(_) => SyntaxKind::ExpressionStatement,
         };
+        self.start_node_at(checkpoint, node);
+        self.finish_node();
 
-        let name = self.src[ident_token.span.start().0 as usize..ident_token.span.end().0 as usize].to_string();
+        Some(self.statement(kind, start))
+    }
 
-        if self.peek_expect_and_advance(TokenKind::Eq).is_none() {
-            return None;
-        }
+    fn parse_while_statement(&mut self) -> Option<StatementKind> {
+        self.advance();
+
+        let condition = self.parse_expression()?;
+        let body = self.parse_block()?;
+
+        Some(StatementKind::While { condition, body })
+    }
+
+    fn parse_for_statement(&mut self) -> Option<StatementKind> {
+        let variable_token = self.peek_expect_and_advance(TokenKind::Ident)?;
+
+        let variable = self.ident(variable_token);
 
-        if !self.skip_expression_to_semi_temp() {
-            return None;
+        self.peek_expect_and_advance(TokenKind::In)?;
+        self.advance();
+
+        let start = self.parse_expression()?;
+
+        self.expect_and_advance(TokenKind::DotDot)?;
+
+        let end = self.parse_expression()?;
+        let body = self.parse_block()?;
+
+        Some(StatementKind::For { variable, start, end, body })
+    }
+
+    fn parse_function_statement(&mut self) -> Option<StatementKind> {
+        let name_token = self.peek_expect_and_advance(TokenKind::Ident)?;
+
+        let name = self.ident(name_token);
+
+        self.peek_expect_and_advance(TokenKind::OpenParen)?;
+        self.start_node(SyntaxKind::ParameterList);
+        self.advance();
+
+        let mut parameters = vec![];
+
+        while self.current_token().kind != TokenKind::CloseParen {
+            self.start_node(SyntaxKind::Parameter);
+            let parameter_token = self.expect_and_advance(TokenKind::Ident)?;
+
+            parameters.push(Parameter {
+                name: self.ident(parameter_token),
+                ty: self.parse_type_annotation()?,
+            });
+            self.finish_node();
+
+            if !self.eat(TokenKind::Comma) {
+                break;
+            }
         }
 
-        Some(Statement::Let { name, value: Expression::Literal(Literal::Int(1)) } )
+        self.expect_and_advance(TokenKind::CloseParen)?;
+        self.finish_node();
+
+        let return_type = self.parse_type_annotation()?;
+        let body = self.parse_block()?;
+
+        Some(StatementKind::Function { name, parameters, return_type, body })
     }
 
-    fn parse_return_statement(&mut self) -> Option<Statement> {
-        if !self.skip_expression_to_semi_temp() {
-            return None;
+    /// Parses an optional `: type` annotation. The outer `Option` is `None` on
+    /// a parse error.
+    fn parse_type_annotation(&mut self) -> Option<Option<Ident>> {
+        if self.current_token().kind != TokenKind::Colon {
+            return Some(None);
         }
 
-        Some(Statement::Return { value: Expression::Literal(Literal::Int(1)) } )
+        self.start_node(SyntaxKind::TypeAnnotation);
+        self.advance();
+        let type_token = self.expect_and_advance(TokenKind::Ident)?;
+        self.finish_node();
+
+        Some(Some(self.ident(type_token)))
     }
 
-    fn parse_expression_statement(&mut self) -> Option<Statement> {
-        if !self.skip_expression_to_semi_temp() {
-            return None;
+    /// Parses an expression that starts with a block or an `if` on its own,
+    /// so that `if a { b } - 1` isn't mistaken for a subtraction when used as
+    /// a statement.
+    fn parse_block_like(&mut self) -> Option<Expression> {
+        if self.current_token().kind == TokenKind::If {
+            self.parse_if()
+        } else {
+            self.parse_block()
         }
+    }
 
-        Some(Statement::Return { value: Expression::Literal(Literal::Int(1)) } )
+    fn parse_block(&mut self) -> Option<Expression> {
+        self.start_node(SyntaxKind::BlockExpression);
+        let open = self.expect_and_advance(TokenKind::OpenBrace)?;
+
+        self.block_depth += 1;
+        let block = self.parse_block_contents();
+        self.block_depth -= 1;
+
+        let kind = block?;
+        self.finish_node();
+
+        Some(self.expression(kind, self.span_from(open.span)))
     }
 
-    fn skip_expression_to_semi_temp(&mut self) -> bool {
+    fn parse_block_contents(&mut self) -> Option<ExpressionKind> {
+        let mut statements = vec![];
+        let mut value = None;
+
         loop {
+            let start = self.current;
+            let start_span = self.current_token().span;
+            let checkpoint = self.checkpoint();
+
             match self.current_token().kind {
-                TokenKind::Semi => {
-                    self.advance();
+                TokenKind::CloseBrace | TokenKind::Eof => break,
+                TokenKind::Let | TokenKind::Return | TokenKind::While | TokenKind::For | TokenKind::Fun => {
+                    statements.push(self.parse_statement_or_recover());
+                }
+                kind => {
+                    let block_like = matches!(kind, TokenKind::If | TokenKind::OpenBrace);
+
+                    let expression = if block_like {
+                        self.parse_block_like()
+                    } else {
+                        self.parse_expression()
+                    };
+
+                    let Some(expression) = expression else {
+                        statements.push(self.recover(start, checkpoint));
+                        continue;
+                    };
+
+                    if self.current_token().kind == TokenKind::CloseBrace {
+                        value = Some(Box::new(expression));
+                        break;
+                    }
+
+                    if block_like {
+                        self.eat(TokenKind::Semi);
+                    } else if self.expect_and_advance(TokenKind::Semi).is_none() {
+                        statements.push(self.recover(start, checkpoint));
+                        continue;
+                    }
+
+                    self.start_node_at(checkpoint, SyntaxKind::ExpressionStatement);
+                    self.finish_node();
+                    statements.push(self.statement(StatementKind::Expression(expression), start_span));
+                }
+            }
+        }
+
+        self.expect_and_advance(TokenKind::CloseBrace)?;
+
+        Some(ExpressionKind::Block { statements, value })
+    }
+
+    fn parse_if(&mut self) -> Option<Expression> {
+        self.start_node(SyntaxKind::IfExpression);
+        let start = self.expect_and_advance(TokenKind::If)?.span;
+
+        let condition = self.parse_expression()?;
+        let then_branch = self.parse_block()?;
+
+        let else_branch = if self.eat(TokenKind::Else) {
+            let branch = if self.current_token().kind == TokenKind::If {
+                self.parse_if()?
+            } else {
+                self.parse_block()?
+            };
+
+            Some(Box::new(branch))
+        } else {
+            None
+        };
+
+        let kind = ExpressionKind::If {
+            condition: Box::new(condition),
+            then_branch: Box::new(then_branch),
+            else_branch,
+        };
+        self.finish_node();
+
+        Some(self.expression(kind, self.span_from(start)))
+    }
+
+    fn parse_let_statement(&mut self) -> Option<StatementKind> {
+        let ident_token = self.peek_expect_and_advance(TokenKind::Ident)?;
+
+        let name = self.ident(ident_token);
+
+        self.peek_expect_and_advance(TokenKind::Eq)?;
+        self.advance();
+
+        let value = self.parse_expression()?;
+
+        self.expect_and_advance(TokenKind::Semi)?;
+
+        Some(StatementKind::Let { name, value })
+    }
+
+    fn parse_return_statement(&mut self) -> Option<StatementKind> {
+        self.advance();
+
+        let value = self.parse_expression()?;
+
+        self.expect_and_advance(TokenKind::Semi)?;
+
+        Some(StatementKind::Return { value })
+    }
+
+    fn parse_expression_statement(&mut self) -> Option<StatementKind> {
+        let value = self.parse_expression()?;
+
+        self.expect_and_advance(TokenKind::Semi)?;
+
+        Some(StatementKind::Expression(value))
+    }
+
+    fn parse_expression(&mut self) -> Option<Expression> {
+        self.parse_binary(0)
+    }
+
+    /// Precedence climbing: parses operands and any binary operators binding at
+    /// least as tightly as `min_precedence`, folding them to the left.
+    fn parse_binary(&mut self, min_precedence: u8) -> Option<Expression> {
+        let checkpoint = self.checkpoint();
+        let mut left = self.parse_unary()?;
+
+        while let Some(operator) = Operator::from_token(self.current_token().kind) {
+            let Some(precedence) = operator.binary_precedence() else {
+                break;
+            };
+
+            if precedence < min_precedence {
+                break;
+            }
+
+            self.advance();
+
+            let right = self.parse_binary(precedence + 1)?;
+            self.start_node_at(checkpoint, SyntaxKind::BinaryExpression);
+            self.finish_node();
+
+            let span = left.span.merge(right.span);
+
+            left = self.expression(ExpressionKind::Binary {
+                left: Box::new(left),
+                operator,
+                right: Box::new(right),
+            }, span);
+        }
+
+        Some(left)
+    }
+
+    fn parse_unary(&mut self) -> Option<Expression> {
+        let token = self.current_token();
+
+        match Operator::from_token(token.kind) {
+            Some(operator) if operator.is_unary() => {
+                self.start_node(SyntaxKind::UnaryExpression);
+                self.advance();
+
+                let right = self.parse_unary()?;
+                self.finish_node();
+
+                let span = token.span.merge(right.span);
+
+                Some(self.expression(ExpressionKind::Unary(operator, Box::new(right)), span))
+            }
+            _ => self.parse_call(),
+        }
+    }
+
+    fn parse_call(&mut self) -> Option<Expression> {
+        let checkpoint = self.checkpoint();
+        let mut expression = self.parse_primary()?;
+
+        while self.current_token().kind == TokenKind::OpenParen {
+            self.start_node_at(checkpoint, SyntaxKind::CallExpression);
+            self.start_node(SyntaxKind::ArgumentList);
+            self.advance();
+
+            let mut args = vec![];
+
+            while self.current_token().kind != TokenKind::CloseParen {
+                args.push(self.parse_expression()?);
+
+                if !self.eat(TokenKind::Comma) {
                     break;
-                },
-                TokenKind::Eof => {
-                    let diagnostic = self.expected_token_error(TokenKind::Eof, TokenKind::Semi, self.current_token().span);
+                }
+            }
+
+            self.expect_and_advance(TokenKind::CloseParen)?;
+            self.finish_node();
+            self.finish_node();
+
+            let span = self.span_from(expression.span);
+
+            expression = self.expression(ExpressionKind::Call {
+                callee: Box::new(expression),
+                args,
+            }, span);
+        }
+
+        Some(expression)
+    }
+
+    fn parse_primary(&mut self) -> Option<Expression> {
+        let token = self.current_token();
 
-                    self.errors.push(diagnostic);
+        match token.kind {
+            TokenKind::Literal { kind, suffix_start } => {
+                self.start_node(SyntaxKind::LiteralExpression);
+                self.advance();
+                self.finish_node();
+
+                match Literal::from_token_literal(kind, self.token_text(token), suffix_start) {
+                    Ok(literal) => Some(self.expression(ExpressionKind::Literal(literal), token.span)),
+                    Err(error) => {
+                        let diagnostic = self.literal_error(error, token.span);
 
-                    return false;
+                        self.errors.push(diagnostic);
+
+                        None
+                    }
                 }
-                _ => {
-                    self.advance();
-                },
+            }
+            TokenKind::Ident => {
+                self.start_node(SyntaxKind::VariableExpression);
+                self.advance();
+                self.finish_node();
+
+                Some(self.expression(ExpressionKind::Variable(self.ident(token)), token.span))
+            }
+            TokenKind::OpenParen => {
+                self.start_node(SyntaxKind::GroupingExpression);
+                self.advance();
+
+                let expression = self.parse_expression()?;
+
+                self.expect_and_advance(TokenKind::CloseParen)?;
+                self.finish_node();
+
+                Some(self.expression(ExpressionKind::Grouping(Box::new(expression)), self.span_from(token.span)))
+            }
+            TokenKind::If => self.parse_if(),
+            TokenKind::OpenBrace => self.parse_block(),
+            _ => {
+                let diagnostic = self.expected_expression_error(token);
+
+                self.unexpected_eof |= token.kind == TokenKind::Eof;
+                self.errors.push(diagnostic);
+
+                None
             }
         }
+    }
 
-        true
+    fn expression(&self, kind: ExpressionKind, span: Span) -> Expression {
+        Expression { kind, span, file_id: self.file_id }
+    }
+
+    /// Creates a statement spanning from `start` to the last consumed token.
+    fn statement(&self, kind: StatementKind, start: Span) -> Statement {
+        Statement { kind, span: self.span_from(start), file_id: self.file_id }
+    }
+
+    fn ident(&self, token: ParserToken) -> Ident {
+        Ident {
+            name: self.token_text(token).to_string(),
+            span: token.span,
+            file_id: self.file_id,
+        }
+    }
+
+    /// Span from the start of `start` to the end of the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        let end = match self.current.checked_sub(1) {
+            Some(previous) => self.token_at(previous).span.end(),
+            None => start.end(),
+        };
+
+        Span::new(start.start(), end.max(start.end()))
     }
 
     fn current_token(&self) -> ParserToken {
-        self.tokens.get(self.current).cloned().unwrap_or(ParserToken { kind: TokenKind::Eof, span: Span::new(self.src.len() as u32, self.src.len() as u32) })
+        self.token_at(self.current)
+    }
+
+    fn token_at(&self, index: usize) -> ParserToken {
+        self.tokens.get(index).cloned().unwrap_or(ParserToken { kind: TokenKind::Eof, span: Span::new(self.src.len() as u32, self.src.len() as u32) })
+    }
+
+    fn token_text(&self, token: ParserToken) -> &str {
+        &self.src[token.span.start().0 as usize..token.span.end().0 as usize]
     }
 
     fn peek_token(&self) -> Option<ParserToken> {
@@ -117,9 +607,32 @@ impl Parser<'_> {
     }
 
     fn advance(&mut self) {
+        if self.current_token().kind != TokenKind::Eof {
+            self.events.push(Event::Token);
+        }
+
         self.current += 1;
     }
 
+    fn start_node(&mut self, kind: SyntaxKind) {
+        self.events.push(Event::Start(kind));
+    }
+
+    fn finish_node(&mut self) {
+        self.events.push(Event::Finish);
+    }
+
+    /// A point to start a node at later with [Parser::start_node_at], once
+    /// it is known what the node is, e.g. a binary expression around its
+    /// left operand.
+    fn checkpoint(&self) -> usize {
+        self.events.len()
+    }
+
+    fn start_node_at(&mut self, checkpoint: usize, kind: SyntaxKind) {
+        self.events.insert(checkpoint, Event::Start(kind));
+    }
+
     fn peek_expect_and_advance(&mut self, kind: TokenKind) -> Option<ParserToken> {
         if let Some(token) = self.peek_token() {
             if token.kind == kind {
@@ -129,6 +642,7 @@ impl Parser<'_> {
             } else {
                 let diagnostic = self.expected_token_error(token.kind, kind, token.span);
 
+                self.unexpected_eof |= token.kind == TokenKind::Eof;
                 self.errors.push(diagnostic);
 
                 None
@@ -138,6 +652,34 @@ impl Parser<'_> {
         }
     }
 
+    fn expect_and_advance(&mut self, kind: TokenKind) -> Option<ParserToken> {
+        let token = self.current_token();
+
+        if token.kind == kind {
+            self.advance();
+
+            Some(token)
+        } else {
+            let diagnostic = self.expected_token_error(token.kind, kind, token.span);
+
+            self.unexpected_eof |= token.kind == TokenKind::Eof;
+            self.errors.push(diagnostic);
+
+            None
+        }
+    }
+
+    /// Advances past the current token if it is of the given kind.
+    fn eat(&mut self, kind: TokenKind) -> bool {
+        if self.current_token().kind == kind {
+            self.advance();
+
+            true
+        } else {
+            false
+        }
+    }
+
     fn expected_token_error(&self, found: TokenKind, expected: TokenKind, span: Span) -> Diagnostic<FileId> {
         Diagnostic::error()
             .with_message("Unexpected token found")
@@ -153,154 +695,60 @@ impl Parser<'_> {
         // ",
         //     )]
     }
-    //     fn parse(&mut self) -> Expression {
-    //         self.expression()
-    //     }
-    //
-    //     fn is_at_end(&self) -> bool {
-    //         self.current >= self.tokens.len()
-    //     }
-    //
-    //     fn peek(&self) -> &ParserToken{
-    //         &self.tokens[self.current]
-    //     }
-    //
-    //     fn previous(&self) -> &ParserToken {
-    //         &self.tokens[self.current - 1]
-    //     }
-    //
-    //     fn advance(&mut self) -> &ParserToken{
-    //         if !self.is_at_end() {
-    //             self.current += 1;
-    //         }
-    //
-    //         self.previous()
-    //     }
-    //
-    //     fn match_token(&mut self, kinds: &[TokenKind]) -> bool {
-    //         if self.is_at_end() {
-    //             return false;
-    //         }
-    //
-    //         if kinds.contains(&self.peek().kind) {
-    //             self.current += 1;
-    //
-    //             true
-    //         } else {
-    //             false
-    //         }
-    //     }
-    //
-    //     fn expression(&mut self) -> Expression {
-    //         self.equality()
-    //     }
-    //
-    //     fn equality(&mut self) -> Expression {
-    //         let mut expr = self.comparison();
-    //
-    //         while self.match_token(&[TokenKind::BangEq, TokenKind::EqEq]) {
-    //             let operator = Operator::from_token(self.previous().kind).unwrap();
-    //
-    //             let right = self.comparison();
-    //             expr = Expression::Binary {
-    //                 left: Box::new(expr),
-    //                 operator,
-    //                 right: Box::new(right),
-    //             };
-    //         }
-    //
-    //         expr
-    //     }
-    //
-    //     fn comparison(&mut self) -> Expression {
-    //         let mut expr = self.term();
-    //
-    //         while self.match_token(&[TokenKind::Gt, TokenKind::GtEq, TokenKind::Lt, TokenKind::LtEq]) {
-    //             let operator = Operator::from_token(self.previous().kind).unwrap();
-    //
-    //             let right = self.term();
-    //             expr = Expression::Binary {
-    //                 left: Box::new(expr),
-    //                 operator,
-    //                 right: Box::new(right),
-    //             };
-    //         }
-    //
-    //         expr
-    //     }
-    //
-    //     fn term(&mut self) -> Expression {
-    //         let mut expr = self.factor();
-    //
-    //         while self.match_token(&[TokenKind::Minus, TokenKind::Plus]) {
-    //             let operator = Operator::from_token(self.previous().kind).unwrap();
-    //
-    //             let right = self.factor();
-    //             expr = Expression::Binary {
-    //                 left: Box::new(expr),
-    //                 operator,
-    //                 right: Box::new(right),
-    //             };
-    //         }
-    //
-    //         expr
-    //     }
-    //
-    //     fn factor(&mut self) -> Expression {
-    //         let mut expr = self.unary();
-    //
-    //         while self.match_token(&[TokenKind::Slash, TokenKind::Star]) {
-    //             let operator = Operator::from_token(self.previous().kind).unwrap();
-    //
-    //             let right = self.unary();
-    //             expr = Expression::Binary {
-    //                 left: Box::new(expr),
-    //                 operator,
-    //                 right: Box::new(right),
-    //             };
-    //         }
-    //
-    //         expr
-    //     }
-    //
-    //     fn unary(&mut self) -> Expression {
-    //         if self.match_token(&[TokenKind::Bang, TokenKind::Minus]) {
-    //             let operator = Operator::from_token(self.previous().kind).unwrap();
-    //
-    //             let right = self.unary();
-    //             Expression::Unary(operator, Box::new(right))
-    //         } else {
-    //             self.primary()
-    //         }
-    //     }
-    //
-    //     fn primary(&mut self) -> Expression {
-    //         if let Token { kind: TokenKind::Literal { kind }, span } = *self.peek() {
-    //             self.advance();
-    //
-    //             Expression::Literal(Literal::from_token_literal(kind, &self.src[span.start().0 as usize..span.end().0 as usize]))
-    //         } else if self.match_token(&[TokenKind::OpenParen]) {
-    //             let expr = self.expression();
-    //             if !self.match_token(&[TokenKind::CloseParen]) {
-    //                 panic!("Expected ')' after expression.");
-    //             }
-    //
-    //             Expression::Grouping(Box::new(expr))
-    //         } else {
-    //             dbg!(self.peek());
-    //             panic!("Expected expression.");
-    //         }
-    //     }
+
+    fn literal_error(&self, error: LiteralError, span: Span) -> Diagnostic<FileId> {
+        let start = span.start().0 as usize;
+
+        Diagnostic::error()
+            .with_message("Invalid literal")
+            .with_labels(vec![
+                Label::primary(self.file_id, start + error.range.start..start + error.range.end).with_message(error.message),
+            ])
+    }
+
+    fn expected_expression_error(&self, found: ParserToken) -> Diagnostic<FileId> {
+        Diagnostic::error()
+            .with_message("Expected expression")
+            .with_labels(vec![
+                Label::primary(self.file_id, found.span.start().0 as usize..found.span.end().0 as usize).with_message(format!("expected expression, found `{:?}`", found.kind)),
+            ])
+    }
 }
 
-pub fn parse(file_id: FileId, src: &str, tokens: &[ParserToken]) -> ParseResult {
-    let mut parser = Parser {
+pub fn parse(file_id: FileId, src: &str, tokens: &[ParserToken], lexer_tokens: &[LexerToken]) -> Parse {
+    let Statements { statements, parsed, errors, events } = parse_statements(file_id, src, tokens, 0, |_| false);
+
+    Parse {
+        program: program(file_id, src, statements),
+        errors,
+        incomplete: parsed.iter().any(|statement| statement.unexpected_eof),
+        green: syntax::build(&events, src, lexer_tokens),
+        statements: parsed,
+    }
+}
+
+/// Parses the top-level statements from the token at index `start`, see
+/// [Parser::statements].
+pub(crate) fn parse_statements(file_id: FileId, src: &str, tokens: &[ParserToken], start: usize, stop: impl FnMut(ParserToken) -> bool) -> Statements {
+    let parser = Parser {
         tokens,
         src,
         file_id,
-        current: 0,
+        current: start,
+        block_depth: 0,
+        unexpected_eof: false,
         errors: Vec::new(),
+        events: Vec::new(),
     };
 
-    parser.parse()
+    parser.statements(stop)
+}
+
+/// The program made of a file's top-level statements.
+pub(crate) fn program(file_id: FileId, src: &str, statements: Vec<Statement>) -> Expression {
+    Expression {
+        kind: ExpressionKind::StmtExpr(statements),
+        span: Span::new(0, src.len() as u32),
+        file_id,
+    }
 }
@@ -0,0 +1,275 @@
+//! The lossless syntax tree: every token of the source, including the
+//! whitespace, comments and unknown characters the parser skips, in nodes for
+//! the statements and expressions they make up.
+//!
+//! The tree is a [rowan] tree. Its immutable green nodes only know their kind
+//! and text, so they can be shared between versions of a file, and are
+//! walked through red [SyntaxNode]s, which know their parent and offset. The
+//! typed views in [crate::ast] wrap those nodes.
+
+use codespan::Span;
+use propane_lexer::LexerToken;
+use rowan::{GreenNode, GreenNodeBuilder, TextRange};
+
+use crate::TokenKind;
+
+/// The kinds of tokens and nodes in the syntax tree. Tokens mirror
+/// [propane_lexer::TokenKind], without their data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[repr(u16)]
+pub enum SyntaxKind {
+    // Tokens:
+    Whitespace,
+    LineComment,
+    BlockComment,
+    Ident,
+    InvalidIdent,
+    Let,
+    If,
+    Else,
+    For,
+    In,
+    Fun,
+    Return,
+    While,
+    Literal,
+    DotDot,
+    BangEq,
+    EqEq,
+    GtEq,
+    LtEq,
+    Semi,
+    Comma,
+    Dot,
+    OpenParen,
+    CloseParen,
+    OpenBrace,
+    CloseBrace,
+    OpenBracket,
+    CloseBracket,
+    At,
+    Pound,
+    Tilde,
+    Question,
+    Colon,
+    Dollar,
+    Eq,
+    Bang,
+    Lt,
+    Gt,
+    Minus,
+    And,
+    Or,
+    Plus,
+    Star,
+    Slash,
+    Caret,
+    Percent,
+    Unknown,
+
+    // Nodes:
+    /// The whole file, with the trivia before and after its statements.
+    Program,
+    LetStatement,
+    ReturnStatement,
+    WhileStatement,
+    ForStatement,
+    FunctionStatement,
+    /// An expression and its `;`, which is optional after blocks and `if`s.
+    ExpressionStatement,
+    /// `(a: int, b)`
+    ParameterList,
+    Parameter,
+    /// `: int`
+    TypeAnnotation,
+    LiteralExpression,
+    VariableExpression,
+    GroupingExpression,
+    UnaryExpression,
+    BinaryExpression,
+    CallExpression,
+    /// `(a, b)`
+    ArgumentList,
+    BlockExpression,
+    IfExpression,
+    /// The tokens of a statement that failed to parse.
+    Error,
+}
+
+impl SyntaxKind {
+    pub fn from_lexer(kind: propane_lexer::TokenKind) -> Option<SyntaxKind> {
+        use propane_lexer::TokenKind as Lexer;
+
+        let kind = match kind {
+            Lexer::Whitespace => SyntaxKind::Whitespace,
+            Lexer::LineComment => SyntaxKind::LineComment,
+            Lexer::BlockComment { .. } => SyntaxKind::BlockComment,
+            Lexer::Ident => SyntaxKind::Ident,
+            Lexer::InvalidIdent => SyntaxKind::InvalidIdent,
+            Lexer::Let => SyntaxKind::Let,
+            Lexer::If => SyntaxKind::If,
+            Lexer::Else => SyntaxKind::Else,
+            Lexer::For => SyntaxKind::For,
+            Lexer::In => SyntaxKind::In,
+            Lexer::Fun => SyntaxKind::Fun,
+            Lexer::Return => SyntaxKind::Return,
+            Lexer::While => SyntaxKind::While,
+            Lexer::Literal { .. } => SyntaxKind::Literal,
+            Lexer::DotDot => SyntaxKind::DotDot,
+            Lexer::BangEq => SyntaxKind::BangEq,
+            Lexer::EqEq => SyntaxKind::EqEq,
+            Lexer::GtEq => SyntaxKind::GtEq,
+            Lexer::LtEq => SyntaxKind::LtEq,
+            Lexer::Semi => SyntaxKind::Semi,
+            Lexer::Comma => SyntaxKind::Comma,
+            Lexer::Dot => SyntaxKind::Dot,
+            Lexer::OpenParen => SyntaxKind::OpenParen,
+            Lexer::CloseParen => SyntaxKind::CloseParen,
+            Lexer::OpenBrace => SyntaxKind::OpenBrace,
+            Lexer::CloseBrace => SyntaxKind::CloseBrace,
+            Lexer::OpenBracket => SyntaxKind::OpenBracket,
+            Lexer::CloseBracket => SyntaxKind::CloseBracket,
+            Lexer::At => SyntaxKind::At,
+            Lexer::Pound => SyntaxKind::Pound,
+            Lexer::Tilde => SyntaxKind::Tilde,
+            Lexer::Question => SyntaxKind::Question,
+            Lexer::Colon => SyntaxKind::Colon,
+            Lexer::Dollar => SyntaxKind::Dollar,
+            Lexer::Eq => SyntaxKind::Eq,
+            Lexer::Bang => SyntaxKind::Bang,
+            Lexer::Lt => SyntaxKind::Lt,
+            Lexer::Gt => SyntaxKind::Gt,
+            Lexer::Minus => SyntaxKind::Minus,
+            Lexer::And => SyntaxKind::And,
+            Lexer::Or => SyntaxKind::Or,
+            Lexer::Plus => SyntaxKind::Plus,
+            Lexer::Star => SyntaxKind::Star,
+            Lexer::Slash => SyntaxKind::Slash,
+            Lexer::Caret => SyntaxKind::Caret,
+            Lexer::Percent => SyntaxKind::Percent,
+            Lexer::Unknown => SyntaxKind::Unknown,
+            Lexer::Eof => return None,
+        };
+
+        Some(kind)
+    }
+
+    /// Whether the kind is a token the parser skips, which can appear
+    /// anywhere in the tree.
+    pub fn is_trivia(self) -> bool {
+        matches!(self, SyntaxKind::Whitespace | SyntaxKind::LineComment | SyntaxKind::BlockComment)
+    }
+}
+
+impl From<SyntaxKind> for rowan::SyntaxKind {
+    fn from(kind: SyntaxKind) -> rowan::SyntaxKind {
+        rowan::SyntaxKind(kind as u16)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Propane {}
+
+impl rowan::Language for Propane {
+    type Kind = SyntaxKind;
+
+    fn kind_from_raw(raw: rowan::SyntaxKind) -> SyntaxKind {
+        assert!(raw.0 <= SyntaxKind::Error as u16, "invalid syntax kind {}", raw.0);
+        // SAFETY: `SyntaxKind` is a fieldless `u16` enum, and `raw` is in the
+        // range of its discriminants.
+        unsafe { std::mem::transmute::<u16, SyntaxKind>(raw.0) }
+    }
+
+    fn kind_to_raw(kind: SyntaxKind) -> rowan::SyntaxKind {
+        kind.into()
+    }
+}
+
+pub type SyntaxNode = rowan::SyntaxNode<Propane>;
+pub type SyntaxToken = rowan::SyntaxToken<Propane>;
+pub type SyntaxElement = rowan::SyntaxElement<Propane>;
+
+/// The span of a node or token, as in the syntax tree built by the parser.
+pub fn span(range: TextRange) -> Span {
+    Span::new(u32::from(range.start()), u32::from(range.end()))
+}
+
+/// What the parser did, in order, for [build] to turn into a tree.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Event {
+    Start(SyntaxKind),
+    /// Consumed the next token the parser sees.
+    Token,
+    Finish,
+}
+
+/// Builds the tree for the parser's `events` from every token of `src`,
+/// putting the tokens the parser skipped between the ones it consumed.
+///
+/// Skipped tokens before a node go in its parent, so that nodes start and
+/// end with tokens the parser consumed and have the same spans as in the
+/// syntax tree.
+pub(crate) fn build(events: &[Event], src: &str, tokens: &[LexerToken]) -> GreenNode {
+    let mut builder = TreeBuilder { builder: GreenNodeBuilder::new(), src, tokens, next: 0, depth: 0 };
+
+    for event in events {
+        match *event {
+            Event::Start(kind) => {
+                if builder.depth > 0 {
+                    builder.skipped();
+                }
+
+                builder.builder.start_node(kind.into());
+                builder.depth += 1;
+            }
+            Event::Token => {
+                builder.skipped();
+                builder.token();
+            }
+            Event::Finish => {
+                builder.depth -= 1;
+                if builder.depth == 0 {
+                    // The root keeps the trailing trivia.
+                    while builder.next < builder.tokens.len() {
+                        builder.token();
+                    }
+                }
+
+                builder.builder.finish_node();
+            }
+        }
+    }
+
+    builder.builder.finish()
+}
+
+struct TreeBuilder<'a> {
+    builder: GreenNodeBuilder<'static>,
+    src: &'a str,
+    tokens: &'a [LexerToken],
+    /// The index in `tokens` of the next token to add.
+    next: usize,
+    /// The number of nodes started but not finished.
+    depth: usize,
+}
+
+impl TreeBuilder<'_> {
+    /// Adds the tokens before the next one the parser consumes.
+    fn skipped(&mut self) {
+        while self.tokens.get(self.next).is_some_and(|token| TokenKind::from_lexer(token.kind).is_none()) {
+            self.token();
+        }
+    }
+
+    fn token(&mut self) {
+        let Some(token) = self.tokens.get(self.next) else {
+            return;
+        };
+        self.next += 1;
+
+        if let Some(kind) = SyntaxKind::from_lexer(token.kind) {
+            let text = &self.src[token.span.start().to_usize()..token.span.end().to_usize()];
+            self.builder.token(kind.into(), text);
+        }
+    }
+}
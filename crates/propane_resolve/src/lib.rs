@@ -0,0 +1,133 @@
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_parser::expression::Expression;
+
+pub use crate::resolver::{Binding, BindingId, BindingKind, Resolution, Resolver};
+
+mod resolver;
+
+/// Resolves a single program, returning its errors and warnings.
+pub fn check(program: &Expression) -> Vec<Diagnostic<FileId>> {
+    let mut resolver = Resolver::new();
+    let mut diagnostics = resolver.resolve(program).diagnostics;
+
+    diagnostics.extend(resolver.finish());
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::{FileId, Span};
+    use codespan_reporting::diagnostic::{Diagnostic, Severity};
+
+    use super::*;
+
+    fn diagnostics(src: &str) -> Vec<Diagnostic<FileId>> {
+        check(&propane_parser::parse_str(src))
+    }
+
+    /// The severity, message and primary label range of each diagnostic.
+    fn summary(src: &str) -> Vec<(Severity, String, std::ops::Range<usize>)> {
+        diagnostics(src)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.severity, diagnostic.message, diagnostic.labels[0].range.clone()))
+            .collect()
+    }
+
+    fn span_of(src: &str, needle: &str, nth: usize) -> Span {
+        let start = src.match_indices(needle).nth(nth).expect("needle not found").0;
+
+        Span::new(start as u32, (start + needle.len()) as u32)
+    }
+
+    #[test]
+    fn clean_program() {
+        let src = "
+            fun add(a: int, b: int): int { a + b }
+            let main = add(3, 3);
+            return main;
+        ";
+        assert_eq!(summary(src), vec![]);
+    }
+
+    #[test]
+    fn undefined_names() {
+        assert_eq!(summary("let another = main * 2; return another;"), vec![
+            (Severity::Error, "cannot find value `main` in this scope".to_string(), 14..18),
+        ]);
+
+        // A binding isn't visible in its own initializer.
+        assert_eq!(summary("let a = a; return a;")[0].1, "cannot find value `a` in this scope");
+
+        // Nor outside of the block it was declared in.
+        assert_eq!(summary("{ let a = 1; a; } return a;")[0].1, "cannot find value `a` in this scope");
+        assert_eq!(summary("for i in 0..2 { i; } return i;")[0].1, "cannot find value `i` in this scope");
+    }
+
+    #[test]
+    fn binding_ids() {
+        let src = "let a = 1; let b = { let a = 2; a }; return a + b;";
+        let mut resolver = Resolver::new();
+        let resolution = resolver.resolve(&propane_parser::parse_str(src));
+
+        let outer = resolution.uses[&span_of(src, "a", 3)];
+        let inner = resolution.uses[&span_of(src, "a", 2)];
+
+        assert_ne!(outer, inner);
+        assert_eq!(resolver.binding(outer).name.span, span_of(src, "a", 0));
+        assert_eq!(resolver.binding(inner).name.span, span_of(src, "a", 1));
+        assert_eq!(resolver.binding(inner).kind, BindingKind::Let);
+    }
+
+    #[test]
+    fn functions_are_hoisted() {
+        let src = "
+            fun even(n) { if n == 0 { true } else { odd(n - 1) } }
+            fun odd(n) { if n == 0 { false } else { even(n - 1) } }
+            return even(4);
+        ";
+        assert_eq!(summary(src), vec![]);
+
+        assert_eq!(summary("return f(); fun f() { 1 }"), vec![]);
+    }
+
+    #[test]
+    fn unused_bindings() {
+        let src = "fun f(a, _b) { let c = 1; 2 } let d = 3; for i in 0..2 { } return f(1, 2);";
+
+        assert_eq!(summary(src), vec![
+            (Severity::Warning, "unused variable `c`".to_string(), 19..20),
+            (Severity::Warning, "unused variable `a`".to_string(), 6..7),
+            (Severity::Warning, "unused variable `i`".to_string(), 45..46),
+            (Severity::Warning, "unused variable `d`".to_string(), 34..35),
+        ]);
+
+        assert_eq!(summary("fun f() { }")[0].1, "unused function `f`");
+    }
+
+    #[test]
+    fn shadowed_bindings() {
+        let src = "let a = 1; let a = a + 1; return a;";
+        let diagnostics = diagnostics(src);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Warning);
+        assert_eq!(diagnostics[0].message, "`a` shadows an earlier binding");
+        assert_eq!(diagnostics[0].labels[0].range, 15..16);
+        assert_eq!(diagnostics[0].labels[1].range, 4..5);
+        assert_eq!(diagnostics[0].labels[1].message, "`a` first declared here");
+
+        // Parameters don't shadow bindings outside their function.
+        assert_eq!(summary("let a = 1; fun f(a) { a } return f(a);"), vec![]);
+        assert_eq!(summary("let a = 1; { let a = 2; a; } return a;")[0].1, "`a` shadows an earlier binding");
+    }
+
+    #[test]
+    fn globals_persist_between_programs() {
+        let mut resolver = Resolver::new();
+
+        assert!(resolver.resolve(&propane_parser::parse_str("let a = 1; fun f() { 2 }")).diagnostics.is_empty());
+        assert!(resolver.resolve(&propane_parser::parse_str("return a + f();")).diagnostics.is_empty());
+        assert!(resolver.finish().is_empty());
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use propane_parser::expression::{Expression, ExpressionKind, Ident, Statement, StatementKind};
+
+/// Identifies a binding within a [Resolver].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BindingId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BindingKind {
+    Let,
+    Function,
+    Parameter,
+    /// The variable of a `for` loop.
+    Loop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binding {
+    pub name: Ident,
+    pub kind: BindingKind,
+    pub used: bool,
+}
+
+/// The outcome of resolving one program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Resolution {
+    /// The binding each variable in the program refers to, keyed by the span
+    /// of the variable.
+    pub uses: HashMap<Span, BindingId>,
+    pub diagnostics: Vec<Diagnostic<FileId>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Scope {
+    bindings: HashMap<String, BindingId>,
+    /// Bindings in the order they were declared, for reporting unused ones.
+    declared: Vec<BindingId>,
+    /// Whether this is the outermost scope of a function body. Bindings
+    /// outside a function are not considered shadowed by ones inside it.
+    function: bool,
+}
+
+/// Resolves programs one after another in a shared global scope, the same
+/// way the interpreter runs them.
+///
+/// Function declarations are visible throughout the block they are declared
+/// in, so that they can be mutually recursive; all other bindings are visible
+/// from the statement after their declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resolver {
+    bindings: Vec<Binding>,
+    scopes: Vec<Scope>,
+    resolution: Resolution,
+}
+
+impl Default for Resolver {
+    fn default() -> Resolver {
+        Resolver::new()
+    }
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            bindings: vec![],
+            scopes: vec![Scope::default()],
+            resolution: Resolution::default(),
+        }
+    }
+
+    pub fn binding(&self, id: BindingId) -> &Binding {
+        &self.bindings[id.0 as usize]
+    }
+
+    /// Resolves a program as returned by [propane_parser::parse]. Its
+    /// top-level bindings stay visible to later programs.
+    pub fn resolve(&mut self, program: &Expression) -> Resolution {
+        match &program.kind {
+            ExpressionKind::StmtExpr(statements) => self.resolve_statements(statements),
+            _ => self.resolve_expression(program),
+        }
+
+        std::mem::take(&mut self.resolution)
+    }
+
+    /// Reports top-level bindings that none of the programs used.
+    pub fn finish(mut self) -> Vec<Diagnostic<FileId>> {
+        let globals = std::mem::take(&mut self.scopes[0].declared);
+        self.report_unused(&globals);
+
+        self.resolution.diagnostics
+    }
+
+    fn resolve_statements(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            if let StatementKind::Function { name, .. } = &statement.kind {
+                self.declare(name, BindingKind::Function);
+            }
+        }
+
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Let { name, value } => {
+                self.resolve_expression(value);
+                self.declare(name, BindingKind::Let);
+            }
+            StatementKind::Return { value } => self.resolve_expression(value),
+            StatementKind::While { condition, body } => {
+                self.resolve_expression(condition);
+                self.resolve_expression(body);
+            }
+            StatementKind::For { variable, start, end, body } => {
+                self.resolve_expression(start);
+                self.resolve_expression(end);
+
+                self.push_scope(false);
+                self.declare(variable, BindingKind::Loop);
+                self.resolve_expression(body);
+                self.pop_scope();
+            }
+            StatementKind::Function { parameters, body, .. } => {
+                self.push_scope(true);
+                for parameter in parameters {
+                    self.declare(&parameter.name, BindingKind::Parameter);
+                }
+                self.resolve_expression(body);
+                self.pop_scope();
+            }
+            StatementKind::Expression(expression) => self.resolve_expression(expression),
+        }
+    }
+
+    fn resolve_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Binary { left, right, .. } => {
+                self.resolve_expression(left);
+                self.resolve_expression(right);
+            }
+            ExpressionKind::Grouping(inner) | ExpressionKind::Unary(_, inner) => self.resolve_expression(inner),
+            ExpressionKind::Literal(_) | ExpressionKind::Error => {}
+            ExpressionKind::Variable(ident) => self.resolve_use(ident),
+            ExpressionKind::Call { callee, args } => {
+                self.resolve_expression(callee);
+                for arg in args {
+                    self.resolve_expression(arg);
+                }
+            }
+            ExpressionKind::Block { statements, value } => {
+                self.push_scope(false);
+                self.resolve_statements(statements);
+                if let Some(value) = value {
+                    self.resolve_expression(value);
+                }
+                self.pop_scope();
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                self.resolve_expression(condition);
+                self.resolve_expression(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_expression(else_branch);
+                }
+            }
+            ExpressionKind::StmtExpr(statements) => self.resolve_statements(statements),
+        }
+    }
+
+    fn resolve_use(&mut self, ident: &Ident) {
+        let Some(id) = self.lookup(&ident.name) else {
+            self.resolution.diagnostics.push(undefined_error(ident));
+
+            return;
+        };
+
+        self.bindings[id.0 as usize].used = true;
+        self.resolution.uses.insert(ident.span, id);
+    }
+
+    fn lookup(&self, name: &str) -> Option<BindingId> {
+        self.scopes.iter().rev().find_map(|scope| scope.bindings.get(name).copied())
+    }
+
+    /// Finds the binding a new binding of `name` would shadow, stopping at
+    /// the enclosing function.
+    fn lookup_shadowed(&self, name: &str) -> Option<BindingId> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(&id) = scope.bindings.get(name) {
+                return Some(id);
+            }
+
+            if scope.function {
+                break;
+            }
+        }
+
+        None
+    }
+
+    fn declare(&mut self, name: &Ident, kind: BindingKind) {
+        if !name.name.starts_with('_') {
+            if let Some(shadowed) = self.lookup_shadowed(&name.name) {
+                let diagnostic = shadowed_warning(name, &self.binding(shadowed).name);
+
+                self.resolution.diagnostics.push(diagnostic);
+            }
+        }
+
+        let id = BindingId(self.bindings.len() as u32);
+        self.bindings.push(Binding { name: name.clone(), kind, used: false });
+
+        let scope = self.scopes.last_mut().expect("the global scope is never popped");
+        scope.bindings.insert(name.name.clone(), id);
+        scope.declared.push(id);
+    }
+
+    fn push_scope(&mut self, function: bool) {
+        self.scopes.push(Scope { function, ..Scope::default() });
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = self.scopes.pop().expect("scopes are balanced");
+
+        self.report_unused(&scope.declared);
+    }
+
+    fn report_unused(&mut self, declared: &[BindingId]) {
+        for &id in declared {
+            let binding = &self.bindings[id.0 as usize];
+
+            if !binding.used && !binding.name.name.starts_with('_') {
+                self.resolution.diagnostics.push(unused_warning(binding));
+            }
+        }
+    }
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
+
+fn undefined_error(ident: &Ident) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message(format!("cannot find value `{}` in this scope", ident.name))
+        .with_labels(vec![
+            Label::primary(ident.file_id, range(ident.span)).with_message("not found in this scope"),
+        ])
+}
+
+fn unused_warning(binding: &Binding) -> Diagnostic<FileId> {
+    let what = match binding.kind {
+        BindingKind::Function => "function",
+        BindingKind::Let | BindingKind::Parameter | BindingKind::Loop => "variable",
+    };
+
+    Diagnostic::warning()
+        .with_message(format!("unused {} `{}`", what, binding.name.name))
+        .with_labels(vec![
+            Label::primary(binding.name.file_id, range(binding.name.span)),
+        ])
+        .with_notes(vec![format!("if this is intentional, prefix it with an underscore: `_{}`", binding.name.name)])
+}
+
+fn shadowed_warning(name: &Ident, shadowed: &Ident) -> Diagnostic<FileId> {
+    Diagnostic::warning()
+        .with_message(format!("`{}` shadows an earlier binding", name.name))
+        .with_labels(vec![
+            Label::primary(name.file_id, range(name.span)).with_message("this binding shadows the earlier one"),
+            Label::secondary(shadowed.file_id, range(shadowed.span)).with_message(format!("`{}` first declared here", shadowed.name)),
+        ])
+}
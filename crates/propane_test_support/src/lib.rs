@@ -0,0 +1,127 @@
+//! Programs and helpers shared by the tests of the backends, which check
+//! that each of them runs programs the same way as the interpreter.
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_parser::expression::Expression;
+use propane_typeck::Inference;
+
+/// Programs every backend can run, as far as their types go, and their
+/// runtime errors.
+pub const PROGRAMS: &[&str] = &[
+    "return 7 / 2 - -1;",
+    "return 14 * 2 - (8 / 2) - 14;",
+    "return 1.0 != 1.0;",
+    "return 1.5 * 2.0;",
+    "return -0.5 / 2.0;",
+    "return 'a' < 'b';",
+    "return 'λ';",
+    "return !(1 >= 2);",
+    "return true == !false;",
+    "let a = 1; let a = a + 1; return a;",
+    "let i = 1; for i in 0..5 { } return i;",
+    "let total = { let sum = 0; for i in 0..10 { let sum = sum + i; } sum }; return total;",
+    "while 1 > 2 { } return 1;",
+    "fun add(a: int, b: int): int { a + b } return add(3, 3);",
+    "fun fib(n) { if n < 2 { return n; } fib(n - 1) + fib(n - 2) } return fib(20);",
+    "fun count(n) { if n == 0 { 0 } else { 1 + count(n - 1) } } return count(500);",
+    "for i in 0..5 { if i == 3 { return i * 10; } } return 0;",
+    "fun sign(n) { if n < 0 { -1 } else if n == 0 { 0 } else { 1 } } return sign(-5) + sign(0) * 10;",
+    "fun even(n) { if n == 0 { true } else { odd(n - 1) } } fun odd(n) { if n == 0 { false } else { even(n - 1) } } return even(10);",
+    "fun outer(n) { fun twice(x) { x * 2 } twice(n) + 1 } return outer(20);",
+    "fun id(x) { x } return id(1) + id(2);",
+    "fun id(x) { x } if id(true) { id(1.5) } else { 0.0 }",
+    "fun nothing() { } return nothing() == nothing();",
+    "let a = if true { 1 } else { 2 }; if a == 1 { 10 } else { 20 }",
+    "fun first(n: int): int { if n > 0 { return n; } 0 } return first(3);",
+    "return { let a = 'a'; a };",
+    "let nothing = if false { 1; }; nothing;",
+    "let a = 1; fun f() { a } let a = 2; return f();",
+    // Runtime errors.
+    "let zero = 0; return 1 / zero;",
+    "fun f(n: int): int { 10 / n } return f(1) + f(0);",
+];
+
+/// Programs whose floats need 64 bits to print like the interpreter's.
+pub const FLOATS: &[&str] = &[
+    "return 0.1 + 0.2;",
+    "return -2.0 / 3.0;",
+    "return 1e16 * 10.0;",
+    "return 0.00001;",
+    "return 1.0 / 0.0;",
+];
+
+/// Programs that overflow 64-bit integers, which is a runtime error.
+pub const OVERFLOWS: &[&str] = &[
+    "return 9223372036854775807 + 1;",
+    "return -9223372036854775807 - 2;",
+    "return 4611686018427387904 * 2;",
+    "let min = -9223372036854775807 - 1; return -min;",
+    "let min = -9223372036854775807 - 1; return min / -1;",
+];
+
+/// Programs that use strings.
+pub const STRINGS: &[&str] = &[
+    r#"return "a" == "a";"#,
+    r#"return "abc" < "abd";"#,
+    r#"return "tab\tquote\"question?";"#,
+    r#"let s = "é" + "\u{1F600}"; s;"#,
+    r#"fun id(x) { x } fun pair(a, b) { id(a) + id(b) } return pair("a", "b");"#,
+];
+
+/// Two programs that run one after the other in a shared global scope, the
+/// second evaluating to `42` with the bindings of the first.
+pub const SHARED_BINDINGS: [&str; 2] = ["let a = 20; fun double(x: int): int { x * 2 }", "double(a) + 2;"];
+
+/// Type checks a program, which must not have any errors.
+pub fn check(program: &Expression) -> Inference {
+    let inference = propane_typeck::TypeChecker::new().check(program);
+    assert!(inference.diagnostics.is_empty(), "{:?}", inference.diagnostics);
+
+    inference
+}
+
+/// Parses and type checks [SHARED_BINDINGS] one after the other.
+pub fn shared_bindings() -> [(Expression, Inference); 2] {
+    let mut checker = propane_typeck::TypeChecker::new();
+
+    SHARED_BINDINGS.map(|src| {
+        let program = propane_parser::parse_str(src);
+        let inference = checker.check(&program);
+        assert!(inference.diagnostics.is_empty(), "{:?}", inference.diagnostics);
+
+        (program, inference)
+    })
+}
+
+/// Runs `src` on the interpreter, returning its value as it prints it.
+pub fn interpret(src: &str) -> Result<String, Diagnostic<FileId>> {
+    let program = propane_parser::parse_str(src);
+
+    std::thread::Builder::new()
+        .stack_size(propane_interp::STACK_SIZE)
+        .spawn(move || propane_interp::Interpreter::new().run(&program).map(|value| value.to_string()))
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// Runs each program with `run` and on the interpreter, checking they agree
+/// on the value, or on the error. Backends that can't tell where an error
+/// happened leave out its labels, and the ones they do give are checked
+/// against the interpreter's first labels.
+pub fn agrees_with_the_interpreter(programs: &[&str], run: impl Fn(&str) -> Result<String, Diagnostic<FileId>>) {
+    for src in programs {
+        match (run(src), interpret(src)) {
+            (Ok(value), Ok(expected)) => assert_eq!(value, expected, "{src}"),
+            (Err(error), Err(expected)) => {
+                assert_eq!(error.message, expected.message, "{src}");
+                assert!(error.labels.len() <= expected.labels.len(), "{src}: {error:?}");
+                for (label, expected) in error.labels.iter().zip(&expected.labels) {
+                    assert_eq!((&label.range, &label.message), (&expected.range, &expected.message), "{src}");
+                }
+            }
+            (outcome, expected) => panic!("{src}: the backend gave {outcome:?} but the interpreter gave {expected:?}"),
+        }
+    }
+}
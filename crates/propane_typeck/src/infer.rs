@@ -0,0 +1,642 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use propane_parser::expression::{Expression, ExpressionKind, Ident, Literal, Operator, Parameter, Statement, StatementKind};
+
+use crate::types::{Scheme, Type, TypeVar};
+
+/// The outcome of checking one program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Inference {
+    /// The type of each expression and binding in the program, keyed by its
+    /// span. Where spans coincide, the outermost expression wins.
+    pub types: HashMap<Span, Type>,
+    pub diagnostics: Vec<Diagnostic<FileId>>,
+}
+
+/// Which types an operator accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Class {
+    /// `-`, `*` and `/`.
+    Numeric,
+    /// `+`, which also concatenates strings.
+    Addable,
+    /// `<`, `<=`, `>` and `>=`.
+    Ordered,
+}
+
+impl Class {
+    fn contains(self, ty: &Type) -> bool {
+        match self {
+            Class::Numeric => matches!(ty, Type::Int | Type::Float),
+            Class::Addable => matches!(ty, Type::Int | Type::Float | Type::Str),
+            Class::Ordered => matches!(ty, Type::Int | Type::Float | Type::Char | Type::Str),
+        }
+    }
+}
+
+/// An operator applied to a type that may not be known yet.
+#[derive(Debug, Clone, PartialEq)]
+struct Constraint {
+    ty: Type,
+    class: Class,
+    operator: Operator,
+    span: Span,
+    file_id: FileId,
+}
+
+enum UnifyError {
+    Mismatch,
+    InfiniteType,
+}
+
+/// Infers types for programs one after another in a shared global scope,
+/// like the interpreter runs them.
+///
+/// This is Hindley-Milner inference: functions and `let`s are generalized,
+/// so `fun id(x) { x }` can be used at any type. Operators are overloaded,
+/// so the types they are applied to must be known by the end of the
+/// enclosing function to be generalized, and default to `int` if they are
+/// still unknown at the end of the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeChecker {
+    /// The type each type variable has been unified with, if any.
+    substitution: Vec<Option<Type>>,
+    scopes: Vec<HashMap<String, Scheme>>,
+    /// Signatures of functions that have been hoisted but not checked yet,
+    /// keyed by the span of their name.
+    signatures: HashMap<Span, Type>,
+    /// The return type of each enclosing function, innermost last.
+    return_types: Vec<Type>,
+    constraints: Vec<Constraint>,
+    inference: Inference,
+}
+
+impl Default for TypeChecker {
+    fn default() -> TypeChecker {
+        TypeChecker::new()
+    }
+}
+
+impl TypeChecker {
+    pub fn new() -> TypeChecker {
+        TypeChecker {
+            substitution: vec![],
+            scopes: vec![HashMap::new()],
+            signatures: HashMap::new(),
+            return_types: vec![],
+            constraints: vec![],
+            inference: Inference::default(),
+        }
+    }
+
+    /// The type of a top-level binding.
+    pub fn global(&self, name: &str) -> Option<Scheme> {
+        let scheme = self.scopes[0].get(name)?;
+
+        Some(Scheme { vars: scheme.vars.clone(), ty: self.resolve(&scheme.ty) })
+    }
+
+    /// Checks a program as returned by [propane_parser::parse]. Its top-level
+    /// bindings stay visible to later programs.
+    pub fn check(&mut self, program: &Expression) -> Inference {
+        let program_return = self.fresh();
+        self.return_types.push(program_return);
+
+        match &program.kind {
+            ExpressionKind::StmtExpr(statements) => {
+                self.check_statements(statements);
+            }
+            _ => {
+                self.infer(program);
+            }
+        }
+
+        self.return_types.pop();
+
+        for constraint in std::mem::take(&mut self.constraints) {
+            if let Type::Var(var) = self.shallow(&constraint.ty) {
+                self.bind(var, Type::Int);
+            }
+            self.check_constraint(&constraint);
+        }
+
+        let mut inference = std::mem::take(&mut self.inference);
+        for ty in inference.types.values_mut() {
+            *ty = self.resolve(ty);
+        }
+
+        inference
+    }
+
+    /// Checks `statements`, evaluating to the type of the last one if it is an
+    /// expression statement, like the interpreter.
+    fn check_statements(&mut self, statements: &[Statement]) -> Type {
+        for statement in statements {
+            if let StatementKind::Function { name, parameters, return_type, .. } = &statement.kind {
+                let signature = self.signature(parameters, return_type.as_ref());
+
+                self.define(&name.name, Scheme::monomorphic(signature.clone()));
+                self.signatures.insert(name.span, signature);
+            }
+        }
+
+        let mut ty = Type::Unit;
+        for statement in statements {
+            ty = self.check_statement(statement);
+        }
+
+        ty
+    }
+
+    /// Checks a statement, returning the type of an expression statement, a
+    /// fresh type for `return`s as they never complete, and `()` otherwise.
+    fn check_statement(&mut self, statement: &Statement) -> Type {
+        match &statement.kind {
+            StatementKind::Let { name, value } => {
+                let ty = self.infer(value);
+
+                self.record(name.span, &ty);
+
+                let scheme = self.generalize(&ty);
+                self.define(&name.name, scheme);
+            }
+            StatementKind::Return { value } => {
+                let ty = self.infer(value);
+                let expected = self.return_types.last().expect("programs have a return type").clone();
+
+                self.expect(value, &ty, &expected);
+
+                return self.fresh();
+            }
+            StatementKind::While { condition, body } => {
+                let ty = self.infer(condition);
+                self.expect(condition, &ty, &Type::Bool);
+
+                self.infer(body);
+            }
+            StatementKind::For { variable, start, end, body } => {
+                for bound in [start, end] {
+                    let ty = self.infer(bound);
+                    self.expect(bound, &ty, &Type::Int);
+                }
+
+                self.scopes.push(HashMap::new());
+                self.define(&variable.name, Scheme::monomorphic(Type::Int));
+                self.record(variable.span, &Type::Int);
+                self.infer(body);
+                self.scopes.pop();
+            }
+            StatementKind::Function { name, parameters, return_type, body } => {
+                let signature = match self.signatures.remove(&name.span) {
+                    Some(signature) => signature,
+                    None => self.signature(parameters, return_type.as_ref()),
+                };
+                let Type::Function { parameters: parameter_types, ret } = &signature else {
+                    unreachable!("signatures are function types");
+                };
+
+                self.scopes.push(HashMap::new());
+                for (parameter, ty) in parameters.iter().zip(parameter_types) {
+                    self.define(&parameter.name.name, Scheme::monomorphic(ty.clone()));
+                    self.record(parameter.name.span, ty);
+                }
+
+                self.return_types.push((**ret).clone());
+                let body_type = self.infer(body);
+                self.return_types.pop();
+
+                self.expect(tail(body), &body_type, ret);
+                self.scopes.pop();
+
+                self.check_resolved_constraints();
+
+                // The function's own, monomorphic, binding would otherwise keep
+                // its type variables from being generalized.
+                self.scopes.last_mut().expect("there is always a scope").remove(&name.name);
+                let scheme = self.generalize(&signature);
+
+                self.record(name.span, &signature);
+                self.define(&name.name, scheme);
+            }
+            StatementKind::Expression(expression) => return self.infer(expression),
+        }
+
+        Type::Unit
+    }
+
+    fn infer(&mut self, expression: &Expression) -> Type {
+        let ty = self.infer_kind(expression);
+
+        self.record(expression.span, &ty);
+
+        ty
+    }
+
+    fn infer_kind(&mut self, expression: &Expression) -> Type {
+        match &expression.kind {
+            ExpressionKind::Binary { left, operator, right } => {
+                let left_type = self.infer(left);
+                let right_type = self.infer(right);
+
+                self.expect(right, &right_type, &left_type);
+
+                match operator {
+                    Operator::Plus => self.constrain(expression, &left_type, Class::Addable, *operator),
+                    Operator::Minus | Operator::Star | Operator::Slash => self.constrain(expression, &left_type, Class::Numeric, *operator),
+                    Operator::Gt | Operator::GtEq | Operator::Lt | Operator::LtEq => {
+                        self.constrain(expression, &left_type, Class::Ordered, *operator);
+
+                        return Type::Bool;
+                    }
+                    Operator::EqEq | Operator::NotEq => return Type::Bool,
+                    Operator::Not => unreachable!("`!` is not a binary operator"),
+                }
+
+                left_type
+            }
+            ExpressionKind::Grouping(inner) => self.infer(inner),
+            ExpressionKind::Literal(literal) => match literal {
+                Literal::Int(_) => Type::Int,
+                Literal::Float(_) => Type::Float,
+                Literal::Bool(_) => Type::Bool,
+                Literal::Char(_) => Type::Char,
+                Literal::Str(_) => Type::Str,
+            },
+            ExpressionKind::Unary(operator, operand) => {
+                let ty = self.infer(operand);
+
+                match operator {
+                    Operator::Not => {
+                        self.expect(operand, &ty, &Type::Bool);
+                    }
+                    _ => self.constrain(expression, &ty, Class::Numeric, *operator),
+                }
+
+                ty
+            }
+            // Undefined names are reported by name resolution.
+            ExpressionKind::Variable(ident) => match self.lookup(&ident.name) {
+                Some(scheme) => self.instantiate(&scheme),
+                None => self.fresh(),
+            },
+            ExpressionKind::Call { callee, args } => {
+                let callee_type = self.infer(callee);
+                let arg_types: Vec<Type> = args.iter().map(|arg| self.infer(arg)).collect();
+
+                match self.shallow(&callee_type) {
+                    Type::Function { parameters, ret } => {
+                        if parameters.len() != args.len() {
+                            let error = arity_error(expression, parameters.len(), args.len());
+                            self.inference.diagnostics.push(error);
+                        } else {
+                            for ((arg, arg_type), parameter) in args.iter().zip(&arg_types).zip(&parameters) {
+                                self.expect(arg, arg_type, parameter);
+                            }
+                        }
+
+                        *ret
+                    }
+                    Type::Var(_) => {
+                        let ret = self.fresh();
+                        let function = Type::Function { parameters: arg_types, ret: Box::new(ret.clone()) };
+
+                        self.expect(callee, &function, &callee_type);
+
+                        ret
+                    }
+                    other => {
+                        let error = not_callable_error(callee, &other);
+                        self.inference.diagnostics.push(error);
+
+                        self.fresh()
+                    }
+                }
+            }
+            ExpressionKind::Block { statements, value } => {
+                self.scopes.push(HashMap::new());
+                self.check_statements(statements);
+
+                let ty = match value {
+                    Some(value) => self.infer(value),
+                    None if diverges(statements) => self.fresh(),
+                    None => Type::Unit,
+                };
+
+                self.scopes.pop();
+
+                ty
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                let condition_type = self.infer(condition);
+                self.expect(condition, &condition_type, &Type::Bool);
+
+                let then_type = self.infer(then_branch);
+
+                match else_branch {
+                    // After a mismatch the `if` could be meant to have either
+                    // type, so it's left unknown to not report it again.
+                    Some(else_branch) => {
+                        let else_type = self.infer(else_branch);
+
+                        if self.expect(tail(else_branch), &else_type, &then_type) {
+                            then_type
+                        } else {
+                            self.fresh()
+                        }
+                    }
+                    // Without an `else`, the `if` evaluates to `()` when the
+                    // condition is false, so the `then` block must too.
+                    None => {
+                        self.expect(tail(then_branch), &then_type, &Type::Unit);
+
+                        Type::Unit
+                    }
+                }
+            }
+            ExpressionKind::StmtExpr(statements) => self.check_statements(statements),
+            ExpressionKind::Error => self.fresh(),
+        }
+    }
+
+    /// The type of a function with the given, possibly missing, annotations.
+    fn signature(&mut self, parameters: &[Parameter], return_type: Option<&Ident>) -> Type {
+        let parameters = parameters.iter().map(|parameter| self.annotation(parameter.ty.as_ref())).collect();
+        let ret = self.annotation(return_type);
+
+        Type::Function { parameters, ret: Box::new(ret) }
+    }
+
+    fn annotation(&mut self, annotation: Option<&Ident>) -> Type {
+        let Some(ident) = annotation else {
+            return self.fresh();
+        };
+
+        match Type::from_name(&ident.name) {
+            Some(ty) => ty,
+            None => {
+                self.inference.diagnostics.push(unknown_type_error(ident));
+
+                self.fresh()
+            }
+        }
+    }
+
+    /// Unifies `found` with `expected`, reporting an error at `expression`
+    /// and returning false if they don't unify.
+    fn expect(&mut self, expression: &Expression, found: &Type, expected: &Type) -> bool {
+        let error = match self.unify(found, expected) {
+            Ok(()) => return true,
+            Err(UnifyError::Mismatch) => mismatch_error(expression, &self.resolve(found), &self.resolve(expected)),
+            Err(UnifyError::InfiniteType) => infinite_type_error(expression),
+        };
+
+        self.inference.diagnostics.push(error);
+
+        false
+    }
+
+    fn constrain(&mut self, expression: &Expression, ty: &Type, class: Class, operator: Operator) {
+        self.constraints.push(Constraint {
+            ty: ty.clone(),
+            class,
+            operator,
+            span: expression.span,
+            file_id: expression.file_id,
+        });
+    }
+
+    /// Checks, and forgets, the constraints on types that are known by now.
+    fn check_resolved_constraints(&mut self) {
+        let constraints = std::mem::take(&mut self.constraints);
+
+        for constraint in constraints {
+            if let Type::Var(_) = self.shallow(&constraint.ty) {
+                self.constraints.push(constraint);
+            } else {
+                self.check_constraint(&constraint);
+            }
+        }
+    }
+
+    fn check_constraint(&mut self, constraint: &Constraint) {
+        let ty = self.resolve(&constraint.ty);
+
+        if !constraint.class.contains(&ty) {
+            self.inference.diagnostics.push(operator_error(constraint, &ty));
+        }
+    }
+
+    fn unify(&mut self, left: &Type, right: &Type) -> Result<(), UnifyError> {
+        match (self.shallow(left), self.shallow(right)) {
+            (Type::Var(left), Type::Var(right)) if left == right => Ok(()),
+            (Type::Var(var), ty) | (ty, Type::Var(var)) => {
+                let mut vars = vec![];
+                self.resolve(&ty).free_vars(&mut vars);
+
+                if vars.contains(&var) {
+                    return Err(UnifyError::InfiniteType);
+                }
+
+                self.bind(var, ty);
+
+                Ok(())
+            }
+            (
+                Type::Function { parameters: left_parameters, ret: left_ret },
+                Type::Function { parameters: right_parameters, ret: right_ret },
+            ) => {
+                if left_parameters.len() != right_parameters.len() {
+                    return Err(UnifyError::Mismatch);
+                }
+
+                for (left, right) in left_parameters.iter().zip(&right_parameters) {
+                    self.unify(left, right)?;
+                }
+
+                self.unify(&left_ret, &right_ret)
+            }
+            (left, right) if left == right => Ok(()),
+            _ => Err(UnifyError::Mismatch),
+        }
+    }
+
+    fn fresh(&mut self) -> Type {
+        let var = TypeVar(self.substitution.len() as u32);
+        self.substitution.push(None);
+
+        Type::Var(var)
+    }
+
+    fn bind(&mut self, var: TypeVar, ty: Type) {
+        self.substitution[var.0 as usize] = Some(ty);
+    }
+
+    /// Follows type variables until reaching a type that isn't a variable
+    /// bound by the substitution.
+    fn shallow(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+
+        while let Type::Var(var) = ty {
+            match &self.substitution[var.0 as usize] {
+                Some(bound) => ty = bound.clone(),
+                None => break,
+            }
+        }
+
+        ty
+    }
+
+    /// Applies the substitution throughout `ty`.
+    fn resolve(&self, ty: &Type) -> Type {
+        match self.shallow(ty) {
+            Type::Function { parameters, ret } => Type::Function {
+                parameters: parameters.iter().map(|parameter| self.resolve(parameter)).collect(),
+                ret: Box::new(self.resolve(&ret)),
+            },
+            ty => ty,
+        }
+    }
+
+    /// Quantifies the type variables in `ty` that aren't used by the
+    /// bindings in scope or by unchecked operators.
+    fn generalize(&self, ty: &Type) -> Scheme {
+        let ty = self.resolve(ty);
+
+        let mut fixed = vec![];
+        for scope in &self.scopes {
+            for scheme in scope.values() {
+                let mut vars = vec![];
+                self.resolve(&scheme.ty).free_vars(&mut vars);
+                fixed.extend(vars.into_iter().filter(|var| !scheme.vars.contains(var)));
+            }
+        }
+        for ty in self.signatures.values().chain(&self.return_types) {
+            self.resolve(ty).free_vars(&mut fixed);
+        }
+        for constraint in &self.constraints {
+            self.resolve(&constraint.ty).free_vars(&mut fixed);
+        }
+
+        let mut vars = vec![];
+        ty.free_vars(&mut vars);
+        vars.retain(|var| !fixed.contains(var));
+
+        Scheme { vars, ty }
+    }
+
+    fn instantiate(&mut self, scheme: &Scheme) -> Type {
+        let mut substitution = HashMap::new();
+        for var in &scheme.vars {
+            substitution.insert(*var, self.fresh());
+        }
+
+        replace(&scheme.ty, &substitution)
+    }
+
+    fn lookup(&self, name: &str) -> Option<Scheme> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).cloned())
+    }
+
+    fn define(&mut self, name: &str, scheme: Scheme) {
+        self.scopes.last_mut().expect("there is always a scope").insert(name.to_string(), scheme);
+    }
+
+    fn record(&mut self, span: Span, ty: &Type) {
+        self.inference.types.insert(span, ty.clone());
+    }
+}
+
+fn replace(ty: &Type, substitution: &HashMap<TypeVar, Type>) -> Type {
+    match ty {
+        Type::Function { parameters, ret } => Type::Function {
+            parameters: parameters.iter().map(|parameter| replace(parameter, substitution)).collect(),
+            ret: Box::new(replace(ret, substitution)),
+        },
+        Type::Var(var) => substitution.get(var).cloned().unwrap_or(Type::Var(*var)),
+        ty => ty.clone(),
+    }
+}
+
+/// Whether a block of `statements` never reaches its end, because one of
+/// them returns.
+fn diverges(statements: &[Statement]) -> bool {
+    statements.iter().any(|statement| matches!(statement.kind, StatementKind::Return { .. }))
+}
+
+/// The expression that determines the value of a block, to point errors about
+/// the block's type at.
+fn tail(expression: &Expression) -> &Expression {
+    match &expression.kind {
+        ExpressionKind::Block { value: Some(value), .. } => tail(value),
+        _ => expression,
+    }
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
+
+fn mismatch_error(expression: &Expression, found: &Type, expected: &Type) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("mismatched types")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span))
+                .with_message(format!("expected `{expected}`, found `{found}`")),
+        ])
+        .with_notes(vec![format!("expected type `{expected}`\n   found type `{found}`")])
+}
+
+fn infinite_type_error(expression: &Expression) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("cannot construct an infinite type")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span))
+                .with_message("this would need to contain its own type"),
+        ])
+}
+
+fn operator_error(constraint: &Constraint, ty: &Type) -> Diagnostic<FileId> {
+    let expected = match constraint.class {
+        Class::Numeric => "`int` or `float`",
+        Class::Addable => "`int`, `float` or `string`",
+        Class::Ordered => "`int`, `float`, `char` or `string`",
+    };
+
+    Diagnostic::error()
+        .with_message(format!("cannot apply `{}` to type `{ty}`", constraint.operator.as_str()))
+        .with_labels(vec![
+            Label::primary(constraint.file_id, range(constraint.span))
+                .with_message(format!("`{}` expects {expected}", constraint.operator.as_str())),
+        ])
+}
+
+fn arity_error(call: &Expression, expected: usize, found: usize) -> Diagnostic<FileId> {
+    let plural = if expected == 1 { "" } else { "s" };
+    let supplied = if found == 1 { "argument was" } else { "arguments were" };
+
+    Diagnostic::error()
+        .with_message(format!("this function takes {expected} argument{plural} but {found} {supplied} supplied"))
+        .with_labels(vec![
+            Label::primary(call.file_id, range(call.span)),
+        ])
+}
+
+fn not_callable_error(callee: &Expression, ty: &Type) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message(format!("expected function, found `{ty}`"))
+        .with_labels(vec![
+            Label::primary(callee.file_id, range(callee.span)).with_message("call expression requires function"),
+        ])
+}
+
+fn unknown_type_error(ident: &Ident) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message(format!("cannot find type `{}` in this scope", ident.name))
+        .with_labels(vec![
+            Label::primary(ident.file_id, range(ident.span)).with_message("not found in this scope"),
+        ])
+        .with_notes(vec!["the types are `int`, `float`, `bool`, `char` and `string`".to_string()])
+}
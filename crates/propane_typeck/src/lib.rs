@@ -0,0 +1,154 @@
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_parser::expression::Expression;
+
+pub use crate::infer::{Inference, TypeChecker};
+pub use crate::types::{Scheme, Type, TypeVar};
+
+mod infer;
+mod types;
+
+/// Checks a single program, returning its type errors.
+pub fn check(program: &Expression) -> Vec<Diagnostic<FileId>> {
+    TypeChecker::new().check(program).diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Span;
+
+    use super::*;
+
+    /// The messages of the errors in `src`.
+    fn errors(src: &str) -> Vec<String> {
+        check(&propane_parser::parse_str(src)).into_iter().map(|diagnostic| diagnostic.message).collect()
+    }
+
+    /// The type of the top-level binding `name` after checking `src`.
+    fn type_of(src: &str, name: &str) -> String {
+        let mut checker = TypeChecker::new();
+        let inference = checker.check(&propane_parser::parse_str(src));
+
+        assert_eq!(inference.diagnostics.iter().map(|diagnostic| &diagnostic.message).collect::<Vec<_>>(), Vec::<&String>::new());
+
+        checker.global(name).expect("binding not found").to_string()
+    }
+
+    #[test]
+    fn literals_and_operators() {
+        assert_eq!(type_of("let a = 1 + 2 * 3;", "a"), "int");
+        assert_eq!(type_of("let a = 1.5 / 2.0;", "a"), "float");
+        assert_eq!(type_of(r#"let a = "a" + "b";"#, "a"), "string");
+        assert_eq!(type_of("let a = 'a' < 'b';", "a"), "bool");
+        assert_eq!(type_of("let a = !(1 == 2);", "a"), "bool");
+        assert_eq!(type_of("let a = { let b = 1; b };", "a"), "int");
+        assert_eq!(type_of("let a = if true { 1 } else { 2 };", "a"), "int");
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(type_of("fun add(a: int, b: int): int { a + b }", "add"), "fun(int, int) -> int");
+        assert_eq!(type_of("fun not(a) { !a }", "not"), "fun(bool) -> bool");
+        assert_eq!(type_of("fun fib(n) { if n < 2 { return n; } fib(n - 1) + fib(n - 2) }", "fib"), "fun(int) -> int");
+        assert_eq!(type_of("fun f(x) { return 1.0; }", "f"), "fun('a) -> float");
+        assert_eq!(type_of("fun f(c) { if c { return 1; 99; } else { 3 } }", "f"), "fun(bool) -> int");
+        assert_eq!(type_of("fun f(c) { if c { 1; } 2 }", "f"), "fun(bool) -> int");
+    }
+
+    #[test]
+    fn let_polymorphism() {
+        let src = "
+            fun id(x) { x }
+            fun apply(f, x) { f(x) }
+            let a = id(1);
+            let b = id(true);
+            let c = apply(id, 'c');
+        ";
+
+        assert_eq!(type_of(src, "id"), "fun('a) -> 'a");
+        assert_eq!(type_of(src, "apply"), "fun(fun('a) -> 'b, 'a) -> 'b");
+        assert_eq!(type_of(src, "b"), "bool");
+        assert_eq!(type_of(src, "c"), "char");
+
+        assert_eq!(type_of("let f = { fun k(a, b) { a } k }; let a = f(1, 'a');", "f"), "fun('a, 'b) -> 'a");
+    }
+
+    #[test]
+    fn operators_on_unknown_types() {
+        // Defaults to `int` if nothing else decides it.
+        assert_eq!(type_of("fun add(a, b) { a + b }", "add"), "fun(int, int) -> int");
+        assert_eq!(type_of("fun add(a, b) { a + b } let c = add(1.0, 2.0);", "add"), "fun(float, float) -> float");
+    }
+
+    #[test]
+    fn mutually_recursive_functions() {
+        let src = "
+            fun even(n) { if n == 0 { true } else { odd(n - 1) } }
+            fun odd(n) { if n == 0 { false } else { even(n - 1) } }
+        ";
+
+        assert_eq!(type_of(src, "even"), "fun(int) -> bool");
+        assert_eq!(type_of(src, "odd"), "fun(int) -> bool");
+    }
+
+    #[test]
+    fn mismatched_types() {
+        let src = r#"let a = "a" + true;"#;
+        let diagnostics = check(&propane_parser::parse_str(src));
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "mismatched types");
+        assert_eq!(diagnostics[0].labels[0].range, 14..18);
+        assert_eq!(diagnostics[0].labels[0].message, "expected `string`, found `bool`");
+        assert_eq!(diagnostics[0].notes, vec!["expected type `string`\n   found type `bool`"]);
+
+        assert_eq!(errors("if 1 { }"), vec!["mismatched types"]);
+        assert_eq!(errors("let a = if true { 1 } else { 'a' };"), vec!["mismatched types"]);
+        assert_eq!(errors("for i in 0..1.5 { }"), vec!["mismatched types"]);
+        assert_eq!(errors("fun f(a: int): string { a }"), vec!["mismatched types"]);
+        assert_eq!(errors("fun f(a: int) { a } f('a');"), vec!["mismatched types"]);
+        assert_eq!(errors("fun f() { return 1; return true; }"), vec!["mismatched types"]);
+        assert_eq!(errors("let x = if true { 1 }; x;"), vec!["mismatched types"]);
+        assert_eq!(errors("fun f(c) { let a = if c { 1 } else { 'a' }; a + 1 }"), vec!["mismatched types"]);
+    }
+
+    #[test]
+    fn operator_errors() {
+        assert_eq!(errors("let a = true + false;"), vec!["cannot apply `+` to type `bool`"]);
+        assert_eq!(errors(r#"let a = "a" * "b";"#), vec!["cannot apply `*` to type `string`"]);
+        assert_eq!(errors("let a = -'a';"), vec!["cannot apply `-` to type `char`"]);
+        assert_eq!(errors("let a = true < false;"), vec!["cannot apply `<` to type `bool`"]);
+        assert_eq!(errors("let a = !1;"), vec!["mismatched types"]);
+    }
+
+    #[test]
+    fn call_errors() {
+        assert_eq!(errors("let a = 1; a();"), vec!["expected function, found `int`"]);
+        assert_eq!(errors("fun f(a) { a } f(1, 2);"), vec!["this function takes 1 argument but 2 arguments were supplied"]);
+        assert_eq!(errors("fun f(x) { x(x) }"), vec!["cannot construct an infinite type"]);
+    }
+
+    #[test]
+    fn annotations() {
+        assert_eq!(type_of("fun f(a: float, b: char, c: bool, d: string) { a }", "f"), "fun(float, char, bool, string) -> float");
+        assert_eq!(errors("fun f(a: Nat) { a }"), vec!["cannot find type `Nat` in this scope"]);
+    }
+
+    #[test]
+    fn expression_types() {
+        let src = "let a = 1; let b = a == 2;";
+        let inference = TypeChecker::new().check(&propane_parser::parse_str(src));
+
+        assert_eq!(inference.types[&Span::new(4, 5)], Type::Int);
+        assert_eq!(inference.types[&Span::new(19, 25)], Type::Bool);
+    }
+
+    #[test]
+    fn globals_persist_between_programs() {
+        let mut checker = TypeChecker::new();
+
+        assert!(checker.check(&propane_parser::parse_str("fun id(x) { x }")).diagnostics.is_empty());
+        assert!(checker.check(&propane_parser::parse_str("let a = id(1) + 1;")).diagnostics.is_empty());
+        assert_eq!(checker.check(&propane_parser::parse_str("let b = a + true;")).diagnostics[0].message, "mismatched types");
+    }
+}
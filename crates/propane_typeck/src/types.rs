@@ -0,0 +1,113 @@
+use std::fmt;
+
+/// A type variable, standing for a type that isn't known yet or, in a
+/// [Scheme], for any type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TypeVar(pub(crate) u32);
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Char,
+    Str,
+    Unit,
+    Function {
+        parameters: Vec<Type>,
+        ret: Box<Type>,
+    },
+    Var(TypeVar),
+}
+
+impl Type {
+    /// The type an annotation like `a: int` refers to.
+    pub fn from_name(name: &str) -> Option<Type> {
+        match name {
+            "int" => Some(Type::Int),
+            "float" => Some(Type::Float),
+            "bool" => Some(Type::Bool),
+            "char" => Some(Type::Char),
+            "string" => Some(Type::Str),
+            _ => None,
+        }
+    }
+
+    /// Appends the type variables in this type to `vars`, without duplicates.
+    pub(crate) fn free_vars(&self, vars: &mut Vec<TypeVar>) {
+        match self {
+            Type::Function { parameters, ret } => {
+                for parameter in parameters {
+                    parameter.free_vars(vars);
+                }
+                ret.free_vars(vars);
+            }
+            Type::Var(var) if !vars.contains(var) => vars.push(*var),
+            _ => {}
+        }
+    }
+
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, quantified: &[TypeVar]) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Char => write!(f, "char"),
+            Type::Str => write!(f, "string"),
+            Type::Unit => write!(f, "()"),
+            Type::Function { parameters, ret } => {
+                write!(f, "fun(")?;
+                for (i, parameter) in parameters.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    parameter.fmt_with(f, quantified)?;
+                }
+                write!(f, ") -> ")?;
+                ret.fmt_with(f, quantified)
+            }
+            Type::Var(var) => match quantified.iter().position(|quantified| quantified == var) {
+                Some(i) if i < 26 => write!(f, "'{}", (b'a' + i as u8) as char),
+                Some(i) => write!(f, "'t{i}"),
+                None => write!(f, "_"),
+            },
+        }
+    }
+}
+
+/// Unknown types are shown as `_`.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &[])
+    }
+}
+
+/// A possibly polymorphic type, e.g. `fun('a) -> 'a`, that is instantiated
+/// with fresh type variables every time it is used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scheme {
+    pub vars: Vec<TypeVar>,
+    pub ty: Type,
+}
+
+impl Scheme {
+    pub fn monomorphic(ty: Type) -> Scheme {
+        Scheme { vars: vec![], ty }
+    }
+
+    /// A scheme for any type `ty` could be, quantifying over every type
+    /// variable in it, such as for showing the type recorded for a generic
+    /// function.
+    pub fn quantify_all(ty: Type) -> Scheme {
+        let mut vars = vec![];
+        ty.free_vars(&mut vars);
+
+        Scheme { vars, ty }
+    }
+}
+
+impl fmt::Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.ty.fmt_with(f, &self.vars)
+    }
+}
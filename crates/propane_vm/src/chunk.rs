@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+use codespan::{FileId, Span};
+
+/// A single VM instruction. Operands index into the chunk's constants, the
+/// current frame's locals or closure's upvalues, or the chunk's code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Constant(u32),
+    Unit,
+    True,
+    False,
+    Pop,
+    GetLocal(u32),
+    SetLocal(u32),
+    GetUpvalue(u32),
+    /// Operand is the global's slot.
+    GetGlobal(u32),
+    /// Pops the value on top of the stack into the global's slot.
+    DefineGlobal(u32),
+    /// Reports that the variable named by the given constant can't be found.
+    Undefined(u32),
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Negate,
+    Not,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    /// Reports an error unless the value on top of the stack is an `int`.
+    CheckInt,
+    Jump(u32),
+    /// Pops a `bool` and jumps if it is false.
+    JumpIfFalse(u32),
+    /// Calls the function below the given number of arguments.
+    Call(u32),
+    /// Creates a closure over the function prototype in the given constant.
+    Closure(u32),
+    Return,
+    /// Pops the given number of locals from below the value on top of the
+    /// stack, at the end of a block.
+    EndBlock(u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Char(char),
+    Str(Rc<str>),
+    Function(Rc<Prototype>),
+}
+
+/// Where a closure gets an upvalue from when it is created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// A local of the enclosing function.
+    Local(u32),
+    /// An upvalue of the enclosing function.
+    Upvalue(u32),
+}
+
+/// Compiled code, with a line table mapping each instruction back to the
+/// source it was compiled from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    pub code: Vec<Instruction>,
+    pub constants: Vec<Constant>,
+    pub file_id: FileId,
+    /// The span of the instructions from each offset on, until the next
+    /// entry.
+    spans: Vec<(u32, Span)>,
+    /// The span of the callee of each `Call`, for reporting calls to values
+    /// that are not functions.
+    callees: Vec<(u32, Span)>,
+    /// The span of the divisor of each `Divide`, for reporting division by
+    /// zero.
+    divisors: Vec<(u32, Span)>,
+    /// The name of each global the chunk uses, by slot, for the disassembler.
+    globals: BTreeMap<u32, Rc<str>>,
+}
+
+impl Chunk {
+    pub fn new(file_id: FileId) -> Chunk {
+        Chunk {
+            code: vec![],
+            constants: vec![],
+            file_id,
+            spans: vec![],
+            callees: vec![],
+            divisors: vec![],
+            globals: BTreeMap::new(),
+        }
+    }
+
+    pub fn write(&mut self, instruction: Instruction, span: Span) -> usize {
+        if self.spans.last().map(|&(_, last)| last) != Some(span) {
+            self.spans.push((self.code.len() as u32, span));
+        }
+
+        self.code.push(instruction);
+        self.code.len() - 1
+    }
+
+    /// Writes a `Call` instruction, recording the span of its callee.
+    pub fn write_call(&mut self, arguments: u32, span: Span, callee: Span) -> usize {
+        let offset = self.write(Instruction::Call(arguments), span);
+        self.callees.push((offset as u32, callee));
+
+        offset
+    }
+
+    /// Writes a `Divide` instruction, recording the span of its divisor.
+    pub fn write_divide(&mut self, span: Span, divisor: Span) -> usize {
+        let offset = self.write(Instruction::Divide, span);
+        self.divisors.push((offset as u32, divisor));
+
+        offset
+    }
+
+    /// Writes a `GetGlobal` or `DefineGlobal` instruction, recording the name
+    /// of the global in its slot.
+    pub fn write_global(&mut self, instruction: Instruction, name: &str, span: Span) -> usize {
+        if let Instruction::GetGlobal(slot) | Instruction::DefineGlobal(slot) = instruction {
+            self.globals.entry(slot).or_insert_with(|| name.into());
+        }
+
+        self.write(instruction, span)
+    }
+
+    pub fn add_constant(&mut self, constant: Constant) -> u32 {
+        let index = match self.constants.iter().position(|existing| existing == &constant) {
+            Some(index) => index,
+            None => {
+                self.constants.push(constant);
+                self.constants.len() - 1
+            }
+        };
+
+        index as u32
+    }
+
+    /// The span of the source the instruction at `offset` was compiled from.
+    pub fn span_at(&self, offset: usize) -> Span {
+        let entry = self.spans.partition_point(|&(start, _)| start as usize <= offset);
+
+        self.spans[entry.saturating_sub(1)].1
+    }
+
+    /// The span of the callee of the `Call` instruction at `offset`.
+    pub fn callee_span(&self, offset: usize) -> Span {
+        let entry = self.callees.binary_search_by_key(&(offset as u32), |&(offset, _)| offset);
+
+        entry.map_or_else(|_| self.span_at(offset), |entry| self.callees[entry].1)
+    }
+
+    /// The span of the divisor of the `Divide` instruction at `offset`.
+    pub fn divisor_span(&self, offset: usize) -> Span {
+        let entry = self.divisors.binary_search_by_key(&(offset as u32), |&(offset, _)| offset);
+
+        entry.map_or_else(|_| self.span_at(offset), |entry| self.divisors[entry].1)
+    }
+}
+
+/// A compiled function, or the top level of a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prototype {
+    pub name: String,
+    pub arity: u32,
+    pub chunk: Chunk,
+    pub captures: Vec<Capture>,
+}
+
+/// Disassembles the prototype and then the functions it contains.
+impl fmt::Display for Prototype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "== {} ({} parameters, {} upvalues) ==", self.name, self.arity, self.captures.len())?;
+
+        let mut last_span = None;
+        for (offset, instruction) in self.chunk.code.iter().enumerate() {
+            let span = self.chunk.span_at(offset);
+            let location = if last_span == Some(span) {
+                "|".to_string()
+            } else {
+                format!("{}..{}", span.start(), span.end())
+            };
+            last_span = Some(span);
+
+            write!(f, "{offset:04} {location:>9} {instruction:?}")?;
+
+            match instruction {
+                Instruction::Constant(index)
+                | Instruction::Undefined(index)
+                | Instruction::Closure(index) => writeln!(f, "\t; {}", self.chunk.constants[*index as usize])?,
+                Instruction::GetGlobal(slot) | Instruction::DefineGlobal(slot) => {
+                    writeln!(f, "\t; {}", self.chunk.globals[slot])?
+                }
+                _ => writeln!(f)?,
+            }
+        }
+
+        for constant in &self.chunk.constants {
+            if let Constant::Function(function) = constant {
+                writeln!(f)?;
+                write!(f, "{function}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Int(value) => write!(f, "{value}"),
+            Constant::Float(value) => write!(f, "{value:?}"),
+            Constant::Char(value) => write!(f, "{value:?}"),
+            Constant::Str(value) => write!(f, "{value:?}"),
+            Constant::Function(function) => write!(f, "<fun {}>", function.name),
+        }
+    }
+}
@@ -0,0 +1,557 @@
+use std::ops::Range;
+use std::rc::Rc;
+
+use codespan::{FileId, Span};
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+use propane_parser::expression::{Expression, ExpressionKind, Ident, Literal, Operator, Parameter, Statement, StatementKind};
+
+use crate::chunk::{Capture, Chunk, Constant, Instruction, Prototype};
+
+struct Local {
+    name: String,
+    depth: usize,
+    /// Whether the local can be referred to yet. Slots for a block's bindings
+    /// are reserved on entering it, but each only comes into scope after its
+    /// declaration.
+    declared: bool,
+}
+
+/// A top-level binding, which lives in the VM's globals.
+struct Global {
+    name: String,
+    /// Whether the global can be referred to yet, like [Local::declared].
+    declared: bool,
+}
+
+/// The state of compiling one function.
+struct FunctionCompiler {
+    prototype: Prototype,
+    /// Locals by slot. Slot 0 holds the function being called.
+    locals: Vec<Local>,
+    scope_depth: usize,
+}
+
+impl FunctionCompiler {
+    fn new(name: &str, arity: u32, file_id: FileId) -> FunctionCompiler {
+        FunctionCompiler {
+            prototype: Prototype {
+                name: name.to_string(),
+                arity,
+                chunk: Chunk::new(file_id),
+                captures: vec![],
+            },
+            locals: vec![Local { name: String::new(), depth: 0, declared: true }],
+            scope_depth: 0,
+        }
+    }
+
+    fn resolve_local(&self, name: &str) -> Option<u32> {
+        self.locals.iter().rposition(|local| local.declared && local.name == name).map(|slot| slot as u32)
+    }
+
+    fn add_capture(&mut self, capture: Capture) -> u32 {
+        let captures = &mut self.prototype.captures;
+
+        let index = match captures.iter().position(|existing| *existing == capture) {
+            Some(index) => index,
+            None => {
+                captures.push(capture);
+                captures.len() - 1
+            }
+        };
+
+        index as u32
+    }
+}
+
+/// Compiles programs one after another into prototypes of functions that
+/// take no arguments, for a [crate::Vm] to run in the same order.
+///
+/// Top-level bindings become globals, which are resolved to slots like
+/// locals, so that each use refers to the binding in scope where it is, and
+/// later programs can use the bindings of earlier ones.
+#[derive(Default)]
+pub struct Compiler {
+    /// The globals declared so far by slot.
+    globals: Vec<Global>,
+    /// The functions being compiled, innermost last. The first is the
+    /// program itself.
+    functions: Vec<FunctionCompiler>,
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler::default()
+    }
+
+    /// Compiles a program as returned by [propane_parser::parse]. If it
+    /// fails, the program's globals are forgotten, as it never runs.
+    pub fn compile(&mut self, program: &Expression) -> Result<Rc<Prototype>, Diagnostic<FileId>> {
+        let globals = self.globals.len();
+        self.functions = vec![FunctionCompiler::new("<program>", 0, program.file_id)];
+
+        let result = match &program.kind {
+            ExpressionKind::StmtExpr(statements) => self.program(statements, program.span),
+            _ => self.expression(program),
+        };
+        if let Err(error) = result {
+            self.globals.truncate(globals);
+
+            return Err(error);
+        }
+        self.emit(Instruction::Return, program.span);
+
+        let function = self.functions.pop().expect("the program is always being compiled");
+
+        Ok(Rc::new(function.prototype))
+    }
+
+    /// Compiles top-level statements, leaving the value of the last one if it
+    /// is an expression statement, like the interpreter.
+    fn program(&mut self, statements: &[Statement], span: Span) -> Result<(), Diagnostic<FileId>> {
+        self.declare_functions(statements)?;
+
+        for (i, statement) in statements.iter().enumerate() {
+            match &statement.kind {
+                StatementKind::Expression(expression) if i == statements.len() - 1 => {
+                    return self.expression(expression);
+                }
+                _ => self.statement(statement)?,
+            }
+        }
+
+        self.emit(Instruction::Unit, span);
+
+        Ok(())
+    }
+
+    fn statement(&mut self, statement: &Statement) -> Result<(), Diagnostic<FileId>> {
+        match &statement.kind {
+            StatementKind::Let { name, value } => {
+                self.expression(value)?;
+                self.define(name);
+            }
+            StatementKind::Return { value } => {
+                self.expression(value)?;
+                self.emit(Instruction::Return, statement.span);
+            }
+            StatementKind::While { condition, body } => {
+                let start = self.current().prototype.chunk.code.len();
+
+                self.expression(condition)?;
+                let exit = self.emit(Instruction::JumpIfFalse(u32::MAX), condition.span);
+
+                self.expression(body)?;
+                self.emit(Instruction::Pop, body.span);
+                self.emit(Instruction::Jump(start as u32), statement.span);
+
+                self.patch_jump(exit);
+            }
+            StatementKind::For { variable, start, end, body } => self.for_statement(statement, variable, start, end, body)?,
+            // Created on entering the enclosing block, see `declare_functions`.
+            StatementKind::Function { .. } => {}
+            StatementKind::Expression(expression) => {
+                self.expression(expression)?;
+                self.emit(Instruction::Pop, expression.span);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles `for variable in start..end { body }` to a loop over two
+    /// hidden locals holding the counter and the end, with a fresh local for
+    /// the variable in each iteration.
+    fn for_statement(
+        &mut self,
+        statement: &Statement,
+        variable: &Ident,
+        start: &Expression,
+        end: &Expression,
+        body: &Expression,
+    ) -> Result<(), Diagnostic<FileId>> {
+        self.begin_scope();
+
+        self.expression(start)?;
+        self.emit(Instruction::CheckInt, start.span);
+        let counter = self.add_local(" counter");
+
+        self.expression(end)?;
+        self.emit(Instruction::CheckInt, end.span);
+        let end_slot = self.add_local(" end");
+
+        let loop_start = self.current().prototype.chunk.code.len();
+        self.emit(Instruction::GetLocal(counter), variable.span);
+        self.emit(Instruction::GetLocal(end_slot), variable.span);
+        self.emit(Instruction::Less, variable.span);
+        let exit = self.emit(Instruction::JumpIfFalse(u32::MAX), variable.span);
+
+        self.begin_scope();
+        self.emit(Instruction::GetLocal(counter), variable.span);
+        self.add_local(&variable.name);
+        self.expression(body)?;
+        self.end_scope(body.span);
+        self.emit(Instruction::Pop, body.span);
+
+        let one = self.add_constant(Constant::Int(1));
+        self.emit(Instruction::GetLocal(counter), variable.span);
+        self.emit(Instruction::Constant(one), variable.span);
+        self.emit(Instruction::Add, variable.span);
+        self.emit(Instruction::SetLocal(counter), variable.span);
+        self.emit(Instruction::Pop, variable.span);
+        self.emit(Instruction::Jump(loop_start as u32), statement.span);
+
+        self.patch_jump(exit);
+
+        self.emit(Instruction::Unit, statement.span);
+        self.end_scope(statement.span);
+        self.emit(Instruction::Pop, statement.span);
+
+        Ok(())
+    }
+
+    /// Creates closures for the functions declared in `statements` up front,
+    /// so that they can be called before their declaration and be mutually
+    /// recursive.
+    ///
+    /// This also reserves a slot, or a global at the top level, for each
+    /// `let`, so that a function can refer to the bindings declared before
+    /// it, but not to ones declared after it with the same name.
+    fn declare_functions(&mut self, statements: &[Statement]) -> Result<(), Diagnostic<FileId>> {
+        let global = self.is_global_scope();
+
+        // The slot reserved for each statement that declares something.
+        let mut slots = vec![];
+        for statement in statements {
+            let slot = match &statement.kind {
+                StatementKind::Let { name, .. } | StatementKind::Function { name, .. } => {
+                    let declared = matches!(statement.kind, StatementKind::Function { .. });
+
+                    if global {
+                        self.globals.push(Global { name: name.name.clone(), declared });
+
+                        Some((self.globals.len() - 1) as u32)
+                    } else {
+                        self.emit(Instruction::Unit, name.span);
+
+                        let slot = self.add_local(&name.name);
+                        self.current_mut().locals[slot as usize].declared = declared;
+
+                        Some(slot)
+                    }
+                }
+                _ => None,
+            };
+
+            slots.push(slot);
+        }
+
+        for (i, statement) in statements.iter().enumerate() {
+            let StatementKind::Function { name, parameters, body, .. } = &statement.kind else {
+                continue;
+            };
+
+            let earlier_lets: Vec<u32> = statements[..i].iter().zip(&slots)
+                .filter(|(statement, _)| matches!(statement.kind, StatementKind::Let { .. }))
+                .filter_map(|(_, slot)| *slot)
+                .collect();
+
+            self.set_declared(&earlier_lets, true);
+            let result = self.function(name, parameters, body);
+            self.set_declared(&earlier_lets, false);
+            result?;
+
+            let slot = slots[i].expect("functions have a slot");
+            if global {
+                self.emit_global(Instruction::DefineGlobal(slot), &name.name, name.span);
+            } else {
+                self.emit(Instruction::SetLocal(slot), name.span);
+                self.emit(Instruction::Pop, name.span);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sets whether the locals, or the globals at the top level, in `slots`
+    /// are declared.
+    fn set_declared(&mut self, slots: &[u32], declared: bool) {
+        for &slot in slots {
+            if self.is_global_scope() {
+                self.globals[slot as usize].declared = declared;
+            } else {
+                self.current_mut().locals[slot as usize].declared = declared;
+            }
+        }
+    }
+
+    /// Compiles a function and emits the instruction creating its closure.
+    fn function(&mut self, name: &Ident, parameters: &[Parameter], body: &Expression) -> Result<(), Diagnostic<FileId>> {
+        let file_id = self.current().prototype.chunk.file_id;
+        let mut function = FunctionCompiler::new(&name.name, parameters.len() as u32, file_id);
+        function.scope_depth = 1;
+        for parameter in parameters {
+            function.locals.push(Local { name: parameter.name.name.clone(), depth: 1, declared: true });
+        }
+
+        self.functions.push(function);
+        let body_result = self.expression(body);
+        self.emit(Instruction::Return, body.span);
+        let function = self.functions.pop().expect("the function was just pushed");
+        body_result?;
+
+        let constant = self.add_constant(Constant::Function(Rc::new(function.prototype)));
+        self.emit(Instruction::Closure(constant), name.span);
+
+        Ok(())
+    }
+
+    fn expression(&mut self, expression: &Expression) -> Result<(), Diagnostic<FileId>> {
+        let span = expression.span;
+
+        match &expression.kind {
+            ExpressionKind::Binary { left, operator, right } => {
+                self.expression(left)?;
+                self.expression(right)?;
+
+                let instruction = match operator {
+                    Operator::Plus => Instruction::Add,
+                    Operator::Minus => Instruction::Subtract,
+                    Operator::Star => Instruction::Multiply,
+                    Operator::Slash => Instruction::Divide,
+                    Operator::EqEq => Instruction::Equal,
+                    Operator::NotEq => Instruction::NotEqual,
+                    Operator::Gt => Instruction::Greater,
+                    Operator::GtEq => Instruction::GreaterEqual,
+                    Operator::Lt => Instruction::Less,
+                    Operator::LtEq => Instruction::LessEqual,
+                    Operator::Not => unreachable!("`!` is not a binary operator"),
+                };
+
+                if instruction == Instruction::Divide {
+                    self.current_mut().prototype.chunk.write_divide(span, right.span);
+                } else {
+                    self.emit(instruction, span);
+                }
+            }
+            ExpressionKind::Grouping(inner) => self.expression(inner)?,
+            ExpressionKind::Literal(literal) => {
+                let constant = match literal {
+                    Literal::Bool(true) => {
+                        self.emit(Instruction::True, span);
+                        return Ok(());
+                    }
+                    Literal::Bool(false) => {
+                        self.emit(Instruction::False, span);
+                        return Ok(());
+                    }
+                    Literal::Int(value) => Constant::Int(*value),
+                    Literal::Float(value) => Constant::Float(*value),
+                    Literal::Char(value) => Constant::Char(*value),
+                    Literal::Str(value) => Constant::Str(value.as_str().into()),
+                };
+
+                let index = self.add_constant(constant);
+                self.emit(Instruction::Constant(index), span);
+            }
+            ExpressionKind::Unary(operator, operand) => {
+                self.expression(operand)?;
+
+                let instruction = match operator {
+                    Operator::Not => Instruction::Not,
+                    _ => Instruction::Negate,
+                };
+                self.emit(instruction, span);
+            }
+            ExpressionKind::Variable(ident) => self.variable(ident),
+            ExpressionKind::Call { callee, args } => {
+                self.expression(callee)?;
+                for arg in args {
+                    self.expression(arg)?;
+                }
+
+                self.current_mut().prototype.chunk.write_call(args.len() as u32, span, callee.span);
+            }
+            ExpressionKind::Block { statements, value } => {
+                self.begin_scope();
+                self.declare_functions(statements)?;
+
+                for statement in statements {
+                    self.statement(statement)?;
+                }
+
+                match value {
+                    Some(value) => self.expression(value)?,
+                    None => {
+                        self.emit(Instruction::Unit, span);
+                    }
+                }
+
+                self.end_scope(span);
+            }
+            ExpressionKind::If { condition, then_branch, else_branch } => {
+                self.expression(condition)?;
+                let else_jump = self.emit(Instruction::JumpIfFalse(u32::MAX), condition.span);
+
+                self.expression(then_branch)?;
+
+                match else_branch {
+                    Some(else_branch) => {
+                        let end_jump = self.emit(Instruction::Jump(u32::MAX), span);
+
+                        self.patch_jump(else_jump);
+                        self.expression(else_branch)?;
+                        self.patch_jump(end_jump);
+                    }
+                    // Without an `else`, the `if` evaluates to `()` either
+                    // way.
+                    None => {
+                        self.emit(Instruction::Pop, span);
+                        self.patch_jump(else_jump);
+                        self.emit(Instruction::Unit, span);
+                    }
+                }
+            }
+            ExpressionKind::StmtExpr(statements) => self.program(statements, span)?,
+            ExpressionKind::Error => return Err(error_node_error(expression)),
+        }
+
+        Ok(())
+    }
+
+    fn variable(&mut self, ident: &Ident) {
+        let depth = self.functions.len() - 1;
+
+        let instruction = if let Some(slot) = self.current().resolve_local(&ident.name) {
+            Instruction::GetLocal(slot)
+        } else if let Some(index) = self.resolve_upvalue(depth, &ident.name) {
+            Instruction::GetUpvalue(index)
+        } else if let Some(slot) = self.globals.iter().rposition(|global| global.declared && global.name == ident.name) {
+            self.emit_global(Instruction::GetGlobal(slot as u32), &ident.name, ident.span);
+
+            return;
+        } else {
+            let name = self.add_constant(Constant::Str(ident.name.as_str().into()));
+
+            Instruction::Undefined(name)
+        };
+
+        self.emit(instruction, ident.span);
+    }
+
+    /// Finds `name` in the functions enclosing the one at `depth`, capturing
+    /// it in each function in between.
+    fn resolve_upvalue(&mut self, depth: usize, name: &str) -> Option<u32> {
+        if depth == 0 {
+            return None;
+        }
+
+        let enclosing = &self.functions[depth - 1];
+        let capture = if let Some(slot) = enclosing.resolve_local(name) {
+            Capture::Local(slot)
+        } else {
+            Capture::Upvalue(self.resolve_upvalue(depth - 1, name)?)
+        };
+
+        Some(self.functions[depth].add_capture(capture))
+    }
+
+    /// Binds the value on top of the stack to `name`, popping it.
+    fn define(&mut self, name: &Ident) {
+        if self.is_global_scope() {
+            let slot = self.globals.iter()
+                .position(|global| !global.declared && global.name == name.name)
+                .expect("a global is reserved for every top-level `let`");
+            self.globals[slot].declared = true;
+
+            self.emit_global(Instruction::DefineGlobal(slot as u32), &name.name, name.span);
+
+            return;
+        }
+
+        let function = self.current_mut();
+        let depth = function.scope_depth;
+        let slot = function.locals.iter()
+            .position(|local| local.depth == depth && !local.declared && local.name == name.name)
+            .expect("a slot is reserved for every `let` in a block");
+        function.locals[slot].declared = true;
+
+        self.emit(Instruction::SetLocal(slot as u32), name.span);
+        self.emit(Instruction::Pop, name.span);
+    }
+
+    fn is_global_scope(&self) -> bool {
+        self.functions.len() == 1 && self.current().scope_depth == 0
+    }
+
+    /// Makes the value on top of the stack a local called `name`.
+    fn add_local(&mut self, name: &str) -> u32 {
+        let function = self.current_mut();
+
+        function.locals.push(Local { name: name.to_string(), depth: function.scope_depth, declared: true });
+
+        (function.locals.len() - 1) as u32
+    }
+
+    fn begin_scope(&mut self) {
+        self.current_mut().scope_depth += 1;
+    }
+
+    /// Pops the scope's locals from below the value on top of the stack.
+    fn end_scope(&mut self, span: Span) {
+        let function = self.current_mut();
+        function.scope_depth -= 1;
+
+        let depth = function.scope_depth;
+        let count = function.locals.iter().rev().take_while(|local| local.depth > depth).count();
+        function.locals.truncate(function.locals.len() - count);
+
+        if count > 0 {
+            self.emit(Instruction::EndBlock(count as u32), span);
+        }
+    }
+
+    fn add_constant(&mut self, constant: Constant) -> u32 {
+        self.current_mut().prototype.chunk.add_constant(constant)
+    }
+
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.current_mut().prototype.chunk.write(instruction, span)
+    }
+
+    fn emit_global(&mut self, instruction: Instruction, name: &str, span: Span) -> usize {
+        self.current_mut().prototype.chunk.write_global(instruction, name, span)
+    }
+
+    /// Points the jump at `offset` to the next instruction.
+    fn patch_jump(&mut self, offset: usize) {
+        let chunk = &mut self.current_mut().prototype.chunk;
+        let target = chunk.code.len() as u32;
+
+        chunk.code[offset] = match chunk.code[offset] {
+            Instruction::Jump(_) => Instruction::Jump(target),
+            Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(target),
+            instruction => unreachable!("{instruction:?} is not a jump"),
+        };
+    }
+
+    fn current(&self) -> &FunctionCompiler {
+        self.functions.last().expect("the program is always being compiled")
+    }
+
+    fn current_mut(&mut self) -> &mut FunctionCompiler {
+        self.functions.last_mut().expect("the program is always being compiled")
+    }
+}
+
+fn range(span: Span) -> Range<usize> {
+    span.start().to_usize()..span.end().to_usize()
+}
+
+fn error_node_error(expression: &Expression) -> Diagnostic<FileId> {
+    Diagnostic::error()
+        .with_message("cannot run code that failed to parse")
+        .with_labels(vec![
+            Label::primary(expression.file_id, range(expression.span)),
+        ])
+}
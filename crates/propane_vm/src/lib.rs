@@ -0,0 +1,175 @@
+use std::rc::Rc;
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Diagnostic;
+use propane_parser::expression::Expression;
+
+pub use crate::chunk::{Capture, Chunk, Constant, Instruction, Prototype};
+pub use crate::compiler::Compiler;
+pub use crate::value::{Closure, Upvalue, Value};
+pub use crate::vm::Vm;
+
+mod chunk;
+mod compiler;
+mod value;
+mod vm;
+
+/// Compiles a single program as returned by [propane_parser::parse] to
+/// bytecode.
+pub fn compile(program: &Expression) -> Result<Rc<Prototype>, Diagnostic<FileId>> {
+    Compiler::new().compile(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use codespan::Span;
+
+    use super::*;
+
+    fn run(src: &str) -> Result<Value, Diagnostic<FileId>> {
+        Vm::new().run(compile(&propane_parser::parse_str(src))?)
+    }
+
+    #[test]
+    fn arithmetic_and_comparisons() {
+        assert_eq!(run("return 1 + 2 * 3;"), Ok(Value::Int(7)));
+        assert_eq!(run("return 1.5 * 2.0;"), Ok(Value::Float(3.0)));
+        assert_eq!(run(r#"return "foo" + "bar";"#), Ok(Value::Str("foobar".into())));
+        assert_eq!(run("return 'a' < 'b';"), Ok(Value::Bool(true)));
+        assert_eq!(run("return !(1 == 2);"), Ok(Value::Bool(true)));
+        assert_eq!(run("return -(2 - 5);"), Ok(Value::Int(3)));
+    }
+
+    #[test]
+    fn locals_and_blocks() {
+        assert_eq!(run("let a = 1; let b = { let a = 10; let c = 2; a + c }; return a + b;"), Ok(Value::Int(13)));
+        assert_eq!(run("let a = 2; a * 3;"), Ok(Value::Int(6)));
+        assert_eq!(run("let a = 2;"), Ok(Value::Unit));
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(run("return if 1 < 2 { 1 } else { 2 };"), Ok(Value::Int(1)));
+        assert_eq!(run("return if false { 1 };"), Ok(Value::Unit));
+        assert_eq!(run("return if true { 1 };"), Ok(Value::Unit));
+        assert_eq!(run("for i in 0..5 { if i == 3 { return i * 10; } } return 0;"), Ok(Value::Int(30)));
+        assert_eq!(run("while 1 > 2 { } return 1;"), Ok(Value::Int(1)));
+    }
+
+    #[test]
+    fn functions_and_closures() {
+        let src = "
+            fun fib(n) {
+                if n < 2 { return n; }
+                fib(n - 1) + fib(n - 2)
+            }
+            return fib(20);
+        ";
+        assert_eq!(run(src), Ok(Value::Int(6765)));
+
+        let src = "
+            let f = {
+                let x = 41;
+                fun inner() { x + 1 }
+                inner
+            };
+            return f();
+        ";
+        assert_eq!(run(src), Ok(Value::Int(42)));
+
+        let src = "
+            fun outer(n) {
+                fun even(n) { if n == 0 { true } else { odd(n - 1) } }
+                fun odd(n) { if n == 0 { false } else { even(n - 1) } }
+                even(n)
+            }
+            return outer(7);
+        ";
+        assert_eq!(run(src), Ok(Value::Bool(false)));
+    }
+
+    #[test]
+    fn globals_persist_between_runs() {
+        let mut compiler = Compiler::new();
+        let mut vm = Vm::new();
+
+        assert_eq!(vm.run(compiler.compile(&propane_parser::parse_str("let a = 20; fun double(x) { x * 2 }")).unwrap()), Ok(Value::Unit));
+        assert_eq!(vm.run(compiler.compile(&propane_parser::parse_str("double(a) + 2;")).unwrap()), Ok(Value::Int(42)));
+        assert_eq!(vm.run(compiler.compile(&propane_parser::parse_str("fun get() { a } let a = 1; get() + a;")).unwrap()), Ok(Value::Int(21)));
+    }
+
+    #[test]
+    fn runtime_errors_point_at_the_source() {
+        let error = run("let zero = 0;\nreturn 1 / zero;").unwrap_err();
+
+        assert_eq!(error.message, "attempt to divide by zero");
+        assert_eq!(error.labels[0].range, 21..29);
+        assert_eq!(error.labels[1].range, 25..29);
+        assert_eq!(error.labels[1].message, "this evaluates to zero");
+
+        let error = run("fun f(a) { a + 1 }\nf(true);").unwrap_err();
+        assert_eq!(error.labels[0].message, "cannot apply `+` to `bool` and `int`");
+        assert_eq!(error.labels[0].range, 11..16);
+    }
+
+    #[test]
+    fn line_table() {
+        let program = compile(&propane_parser::parse_str("let a = 1;\nlet b = a + 2;")).unwrap();
+        let chunk = &program.chunk;
+
+        let add = chunk.code.iter().position(|instruction| *instruction == Instruction::Add).unwrap();
+        assert_eq!(chunk.span_at(add), Span::new(19, 24));
+        assert_eq!(chunk.span_at(0), Span::new(8, 9));
+    }
+
+    #[test]
+    fn disassembler() {
+        let program = compile(&propane_parser::parse_str("fun f(x) { x + 1 } return f(2);")).unwrap();
+
+        assert_eq!(program.to_string(), "\
+== <program> (0 parameters, 0 upvalues) ==
+0000      4..5 Closure(0)\t; <fun f>
+0001         | DefineGlobal(0)\t; f
+0002    26..27 GetGlobal(0)\t; f
+0003    28..29 Constant(1)\t; 2
+0004    26..30 Call(1)
+0005    19..31 Return
+0006     0..31 Unit
+0007         | Return
+
+== f (1 parameters, 0 upvalues) ==
+0000    11..12 GetLocal(1)
+0001    15..16 Constant(0)\t; 1
+0002    11..16 Add
+0003     9..18 Return
+");
+    }
+
+    #[test]
+    fn agrees_with_the_interpreter() {
+        let programs = [
+            "fun f(x) { x } return f == f;",
+            "fun f() { { let a = 1; fun g() { a } g } } return f()();",
+            "fun make(n) { fun get() { n } get } let a = make(1); let b = make(2); return a() + b();",
+            "let a = 1; return { let a = a + 1; fun f() { a } f() };",
+            "fun f(n) { let g = { fun h() { n } h }; g() } return f(3);",
+            "fun f() { let a = 1; fun g() { a } let a = 2; g() } return f();",
+            "fun f() { g() } let a = 1; fun g() { a } return f();",
+            "return if true { 1 };",
+            // Runtime errors.
+            r#"return 1 + "a";"#,
+            "return -true;",
+            "if 1 { 2 }",
+            "return 1 == 1.0;",
+            "return a;",
+            "let a = 1; a();",
+            "fun f(a) { a } f();",
+            "for i in 0..'a' { }",
+            "fun f() { { if true { 1 + f() } else { 0 } } } f();",
+        ];
+
+        for programs in [propane_test_support::PROGRAMS, propane_test_support::FLOATS, propane_test_support::OVERFLOWS, propane_test_support::STRINGS, &programs] {
+            propane_test_support::agrees_with_the_interpreter(programs, |src| run(src).map(|value| value.to_string()));
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::{Diagnostic, Label};
+
+use crate::chunk::{Capture, Constant, Instruction, Prototype};
+use crate::value::{Closure, Upvalue, Value};
+
+/// Calls nested deeper than this are reported as a stack overflow, the same
+/// limit as the interpreter's.
+const MAX_CALL_DEPTH: usize = 1000;
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// The stack slot of the function being called, which locals are
+    /// relative to.
+    base: usize,
+}
+
+/// Runs compiled programs one after another, sharing globals between them.
+#[derive(Default)]
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    /// The globals by slot, which are `None` until they are defined.
+    globals: Vec<Option<Value>>,
+    /// Upvalues still referring to the stack, for closures to share.
+    open_upvalues: Vec<Rc<RefCell<Upvalue>>>,
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm::default()
+    }
+
+    /// Runs a program compiled by [crate::compile], or by a [crate::Compiler]
+    /// that compiled the programs run before it, returning its value.
+    pub fn run(&mut self, program: Rc<Prototype>) -> Result<Value, Diagnostic<FileId>> {
+        self.stack.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+
+        let closure = Rc::new(Closure { prototype: program, upvalues: vec![] });
+        self.stack.push(Value::Closure(closure.clone()));
+        self.frames.push(Frame { closure, ip: 0, base: 0 });
+
+        self.execute()
+    }
+
+    fn execute(&mut self) -> Result<Value, Diagnostic<FileId>> {
+        loop {
+            let frame = self.frames.last_mut().expect("there is a frame while executing");
+            let instruction = frame.closure.prototype.chunk.code[frame.ip];
+            frame.ip += 1;
+
+            match instruction {
+                Instruction::Constant(index) => {
+                    let value = Value::from_constant(self.constant(index));
+                    self.stack.push(value);
+                }
+                Instruction::Unit => self.stack.push(Value::Unit),
+                Instruction::True => self.stack.push(Value::Bool(true)),
+                Instruction::False => self.stack.push(Value::Bool(false)),
+                Instruction::Pop => {
+                    self.pop();
+                }
+                Instruction::GetLocal(slot) => {
+                    let value = self.stack[self.frame().base + slot as usize].clone();
+                    self.stack.push(value);
+                }
+                Instruction::SetLocal(slot) => {
+                    let index = self.frame().base + slot as usize;
+                    self.stack[index] = self.peek().clone();
+                }
+                Instruction::GetUpvalue(index) => {
+                    let value = match &*self.frame().closure.upvalues[index as usize].borrow() {
+                        Upvalue::Open(slot) => self.stack[*slot].clone(),
+                        Upvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                Instruction::GetGlobal(slot) => match self.globals.get(slot as usize) {
+                    Some(Some(value)) => self.stack.push(value.clone()),
+                    // Only if the program defining it didn't get that far.
+                    _ => return Err(self.error(
+                        "use of a global that was never defined".to_string(),
+                        "defined by a program that failed".to_string(),
+                    )),
+                },
+                Instruction::DefineGlobal(slot) => {
+                    let value = self.pop();
+
+                    if self.globals.len() <= slot as usize {
+                        self.globals.resize(slot as usize + 1, None);
+                    }
+                    self.globals[slot as usize] = Some(value);
+                }
+                Instruction::Undefined(index) => {
+                    let name = self.global_name(index);
+
+                    return Err(self.error(
+                        format!("cannot find value `{name}` in this scope"),
+                        "not found in this scope".to_string(),
+                    ));
+                }
+                Instruction::Add
+                | Instruction::Subtract
+                | Instruction::Multiply
+                | Instruction::Divide
+                | Instruction::Equal
+                | Instruction::NotEqual
+                | Instruction::Greater
+                | Instruction::GreaterEqual
+                | Instruction::Less
+                | Instruction::LessEqual => {
+                    let right = self.pop();
+                    let left = self.pop();
+
+                    let value = self.binary(instruction, left, right)?;
+                    self.stack.push(value);
+                }
+                Instruction::Negate | Instruction::Not => {
+                    let operand = self.pop();
+
+                    let value = match (instruction, operand) {
+                        (Instruction::Negate, Value::Int(value)) => match value.checked_neg() {
+                            Some(value) => Value::Int(value),
+                            None => return Err(self.overflow_error()),
+                        },
+                        (Instruction::Negate, Value::Float(value)) => Value::Float(-value),
+                        (Instruction::Not, Value::Bool(value)) => Value::Bool(!value),
+                        (_, operand) => {
+                            let operator = if instruction == Instruction::Not { "!" } else { "-" };
+
+                            return Err(self.error(
+                                "mismatched types".to_string(),
+                                format!("cannot apply unary operator `{operator}` to type `{}`", operand.type_name()),
+                            ));
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                Instruction::CheckInt => {
+                    if !matches!(self.peek(), Value::Int(_)) {
+                        return Err(self.expected_type_error("int", &self.peek().clone()));
+                    }
+                }
+                Instruction::Jump(target) => self.frame_mut().ip = target as usize,
+                Instruction::JumpIfFalse(target) => match self.pop() {
+                    Value::Bool(true) => {}
+                    Value::Bool(false) => self.frame_mut().ip = target as usize,
+                    other => return Err(self.expected_type_error("bool", &other)),
+                },
+                Instruction::Call(argc) => self.call(argc as usize)?,
+                Instruction::Closure(index) => {
+                    let Constant::Function(prototype) = self.constant(index).clone() else {
+                        unreachable!("closures are created from functions");
+                    };
+
+                    let upvalues = prototype.captures.iter().map(|capture| match *capture {
+                        Capture::Local(slot) => self.capture_upvalue(self.frame().base + slot as usize),
+                        Capture::Upvalue(index) => self.frame().closure.upvalues[index as usize].clone(),
+                    }).collect();
+
+                    self.stack.push(Value::Closure(Rc::new(Closure { prototype, upvalues })));
+                }
+                Instruction::Return => {
+                    let value = self.pop();
+                    let frame = self.frames.pop().expect("there is a frame while executing");
+
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+
+                    if self.frames.is_empty() {
+                        return Ok(value);
+                    }
+
+                    self.stack.push(value);
+                }
+                Instruction::EndBlock(count) => {
+                    let value = self.pop();
+                    let height = self.stack.len() - count as usize;
+
+                    self.close_upvalues(height);
+                    self.stack.truncate(height);
+                    self.stack.push(value);
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, argc: usize) -> Result<(), Diagnostic<FileId>> {
+        let base = self.stack.len() - argc - 1;
+
+        let closure = match &self.stack[base] {
+            Value::Closure(closure) => closure.clone(),
+            other => {
+                let frame = self.frame();
+                let chunk = &frame.closure.prototype.chunk;
+                let span = chunk.callee_span(frame.ip - 1);
+
+                return Err(Diagnostic::error()
+                    .with_message(format!("expected function, found `{}`", other.type_name()))
+                    .with_labels(vec![
+                        Label::primary(chunk.file_id, span.start().to_usize()..span.end().to_usize())
+                            .with_message("call expression requires function"),
+                    ]));
+            }
+        };
+
+        let arity = closure.prototype.arity as usize;
+        if argc != arity {
+            let plural = if arity == 1 { "" } else { "s" };
+            let supplied = if argc == 1 { "argument was" } else { "arguments were" };
+
+            return Err(self.error(
+                format!("this function takes {arity} argument{plural} but {argc} {supplied} supplied"),
+                String::new(),
+            ));
+        }
+
+        // The program itself has a frame too.
+        if self.frames.len() > MAX_CALL_DEPTH {
+            return Err(self.error("stack overflow".to_string(), format!("calls nested more than {MAX_CALL_DEPTH} deep")));
+        }
+
+        self.frames.push(Frame { closure, ip: 0, base });
+
+        Ok(())
+    }
+
+    fn binary(&self, instruction: Instruction, left: Value, right: Value) -> Result<Value, Diagnostic<FileId>> {
+        use Instruction::*;
+
+        let overflow = || self.overflow_error();
+
+        let value = match (instruction, &left, &right) {
+            (Equal, _, _) if same_type(&left, &right) => Value::Bool(left == right),
+            (NotEqual, _, _) if same_type(&left, &right) => Value::Bool(left != right),
+
+            (Add, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_add(*b).ok_or_else(overflow)?),
+            (Subtract, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_sub(*b).ok_or_else(overflow)?),
+            (Multiply, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_mul(*b).ok_or_else(overflow)?),
+            (Divide, Value::Int(_), Value::Int(0)) => return Err(self.division_by_zero_error()),
+            (Divide, Value::Int(a), Value::Int(b)) => Value::Int(a.checked_div(*b).ok_or_else(overflow)?),
+
+            (Add, Value::Float(a), Value::Float(b)) => Value::Float(a + b),
+            (Subtract, Value::Float(a), Value::Float(b)) => Value::Float(a - b),
+            (Multiply, Value::Float(a), Value::Float(b)) => Value::Float(a * b),
+            (Divide, Value::Float(a), Value::Float(b)) => Value::Float(a / b),
+
+            (Add, Value::Str(a), Value::Str(b)) => Value::Str(format!("{a}{b}").into()),
+
+            (Greater | GreaterEqual | Less | LessEqual, Value::Int(a), Value::Int(b)) => Value::Bool(compare(instruction, a, b)),
+            (Greater | GreaterEqual | Less | LessEqual, Value::Float(a), Value::Float(b)) => Value::Bool(compare(instruction, a, b)),
+            (Greater | GreaterEqual | Less | LessEqual, Value::Char(a), Value::Char(b)) => Value::Bool(compare(instruction, a, b)),
+            (Greater | GreaterEqual | Less | LessEqual, Value::Str(a), Value::Str(b)) => Value::Bool(compare(instruction, a, b)),
+
+            _ => return Err(self.error(
+                "mismatched types".to_string(),
+                format!("cannot apply `{}` to `{}` and `{}`", operator(instruction), left.type_name(), right.type_name()),
+            )),
+        };
+
+        Ok(value)
+    }
+
+    /// Finds or creates the upvalue referring to the stack slot `slot`.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<Upvalue>> {
+        let existing = self.open_upvalues.iter().find(|upvalue| matches!(*upvalue.borrow(), Upvalue::Open(open) if open == slot));
+        if let Some(upvalue) = existing {
+            return upvalue.clone();
+        }
+
+        let upvalue = Rc::new(RefCell::new(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue.clone());
+
+        upvalue
+    }
+
+    /// Moves the values of upvalues referring to slots from `height` up off
+    /// the stack, before those slots are popped.
+    fn close_upvalues(&mut self, height: usize) {
+        let stack = &self.stack;
+
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= height => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn frame(&self) -> &Frame {
+        self.frames.last().expect("there is a frame while executing")
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("there is a frame while executing")
+    }
+
+    fn constant(&self, index: u32) -> &Constant {
+        &self.frame().closure.prototype.chunk.constants[index as usize]
+    }
+
+    fn global_name(&self, index: u32) -> Rc<str> {
+        match self.constant(index) {
+            Constant::Str(name) => name.clone(),
+            constant => unreachable!("global names are strings, not {constant:?}"),
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the compiler keeps the stack balanced")
+    }
+
+    fn peek(&self) -> &Value {
+        self.stack.last().expect("the compiler keeps the stack balanced")
+    }
+
+    /// A runtime error at the instruction being executed.
+    fn error(&self, message: String, label: String) -> Diagnostic<FileId> {
+        let frame = self.frame();
+        let chunk = &frame.closure.prototype.chunk;
+        let span = chunk.span_at(frame.ip - 1);
+
+        Diagnostic::error()
+            .with_message(message)
+            .with_labels(vec![
+                Label::primary(chunk.file_id, span.start().to_usize()..span.end().to_usize()).with_message(label),
+            ])
+    }
+
+    fn division_by_zero_error(&self) -> Diagnostic<FileId> {
+        let frame = self.frame();
+        let chunk = &frame.closure.prototype.chunk;
+        let divisor = chunk.divisor_span(frame.ip - 1);
+
+        let mut diagnostic = self.error("attempt to divide by zero".to_string(), String::new());
+        diagnostic.labels.push(
+            Label::secondary(chunk.file_id, divisor.start().to_usize()..divisor.end().to_usize())
+                .with_message("this evaluates to zero"),
+        );
+
+        diagnostic
+    }
+
+    fn expected_type_error(&self, expected: &str, found: &Value) -> Diagnostic<FileId> {
+        self.error("mismatched types".to_string(), format!("expected `{expected}`, found `{}`", found.type_name()))
+    }
+
+    fn overflow_error(&self) -> Diagnostic<FileId> {
+        self.error("arithmetic overflow".to_string(), "result does not fit in an `int`".to_string())
+    }
+}
+
+fn compare<T: PartialOrd>(instruction: Instruction, a: T, b: T) -> bool {
+    match instruction {
+        Instruction::Greater => a > b,
+        Instruction::GreaterEqual => a >= b,
+        Instruction::Less => a < b,
+        Instruction::LessEqual => a <= b,
+        _ => unreachable!("{instruction:?} is not a comparison"),
+    }
+}
+
+fn operator(instruction: Instruction) -> &'static str {
+    match instruction {
+        Instruction::Add => "+",
+        Instruction::Subtract => "-",
+        Instruction::Multiply => "*",
+        Instruction::Divide => "/",
+        Instruction::Equal => "==",
+        Instruction::NotEqual => "!=",
+        Instruction::Greater => ">",
+        Instruction::GreaterEqual => ">=",
+        Instruction::Less => "<",
+        Instruction::LessEqual => "<=",
+        _ => unreachable!("{instruction:?} is not a binary operator"),
+    }
+}
+
+fn same_type(left: &Value, right: &Value) -> bool {
+    std::mem::discriminant(left) == std::mem::discriminant(right)
+}
@@ -1,30 +1,684 @@
-use codespan::Files;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use codespan::FileId;
+use codespan_reporting::diagnostic::{Diagnostic, Severity};
 use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+use propane_db::Database;
+use propane_parser::expression::Expression;
+
+mod repl;
+
+#[derive(Debug, Parser)]
+#[command(name = "propanec", about = "The Propane compiler")]
+struct Cli {
+    /// When to color diagnostics.
+    #[arg(long, value_enum, default_value_t = Color::Auto, global = true)]
+    color: Color,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print the tokens of each file.
+    Tokens {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Print the syntax tree of each file.
+    Parse {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Report errors in each file without running it.
+    Check {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Run the files in order, sharing top-level bindings, and print the
+    /// result of the last one.
+    Run {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Compile to bytecode and run it on the virtual machine instead of
+        /// interpreting the syntax tree.
+        #[arg(long)]
+        vm: bool,
+        /// Compile to machine code in memory and run it.
+        #[arg(long, conflicts_with = "vm")]
+        jit: bool,
+        #[command(flatten)]
+        optimization: Optimization,
+    },
+    /// Compile the files, in order, into a single program that prints the
+    /// result of the last one.
+    Build {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// The platform to compile for.
+        #[arg(long, value_enum, default_value_t = Target::Native)]
+        target: Target,
+        /// What to generate. Defaults to C for native builds and a binary
+        /// module for WebAssembly.
+        #[arg(long, value_enum)]
+        emit: Option<Emit>,
+        /// Where to write the output. Defaults to the name of the first file
+        /// with the extension of the output.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[command(flatten)]
+        optimization: Optimization,
+    },
+    /// Format the files in place.
+    Fmt {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Don't write the files, but fail if any of them isn't formatted.
+        #[arg(long)]
+        check: bool,
+        /// The number of columns to fit lines in where possible.
+        #[arg(long, default_value_t = propane_fmt::Config::default().width)]
+        width: usize,
+    },
+    /// Read, evaluate and print programs interactively.
+    Repl,
+    /// Print syntax highlighting rules for editors, generated from the
+    /// lexer's keywords.
+    Highlighting {
+        #[arg(value_enum)]
+        format: HighlightingFormat,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Args)]
+struct Optimization {
+    /// How much to optimize the programs before running or compiling them.
+    #[arg(short = 'O', value_enum, default_value_t = OptLevel::O0)]
+    opt_level: OptLevel,
+    /// Print how many changes each optimization pass made.
+    #[arg(long)]
+    opt_report: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OptLevel {
+    /// No optimization.
+    #[value(name = "0")]
+    O0,
+    /// Constant folding, branch pruning and dead code elimination.
+    #[value(name = "1")]
+    O1,
+    /// As `1`, with constant propagation, repeated until nothing changes.
+    #[value(name = "2")]
+    O2,
+}
+
+impl From<OptLevel> for propane_opt::OptLevel {
+    fn from(level: OptLevel) -> propane_opt::OptLevel {
+        match level {
+            OptLevel::O0 => propane_opt::OptLevel::O0,
+            OptLevel::O1 => propane_opt::OptLevel::O1,
+            OptLevel::O2 => propane_opt::OptLevel::O2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum HighlightingFormat {
+    /// A TextMate grammar in JSON, for VS Code and similar editors.
+    Textmate,
+    /// A tree-sitter `highlights.scm` query.
+    TreeSitter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Target {
+    /// A native executable, built from C source.
+    Native,
+    /// A WebAssembly module exporting `main` and the top-level functions.
+    Wasm,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Emit {
+    /// C99 source.
+    C,
+    /// An object file for the host, defining `main`. Link it with the
+    /// runtime to get an executable.
+    Object,
+    /// An executable for the host, linked with the system C compiler.
+    Exe,
+    /// WebAssembly text format.
+    Wat,
+    /// WebAssembly binary format.
+    Wasm,
+}
+
+impl Target {
+    fn default_emit(self) -> Emit {
+        match self {
+            Target::Native => Emit::C,
+            Target::Wasm => Emit::Wasm,
+        }
+    }
+
+    fn supports(self, emit: Emit) -> bool {
+        matches!((self, emit), (Target::Native, Emit::C | Emit::Object | Emit::Exe) | (Target::Wasm, Emit::Wat | Emit::Wasm))
+    }
+}
+
+impl Emit {
+    fn extension(self) -> &'static str {
+        match self {
+            Emit::C => "c",
+            Emit::Object => "o",
+            Emit::Exe => "",
+            Emit::Wat => "wat",
+            Emit::Wasm => "wasm",
+        }
+    }
+}
+
+impl Command {
+    fn files(&self) -> &[PathBuf] {
+        match self {
+            Command::Tokens { files } | Command::Parse { files } | Command::Check { files } => files,
+            Command::Run { files, .. } | Command::Build { files, .. } | Command::Fmt { files, .. } => files,
+            Command::Repl | Command::Highlighting { .. } => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Color {
+    /// Color if stderr is a terminal.
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    fn choice(self) -> ColorChoice {
+        match self {
+            Color::Auto if std::io::stderr().is_terminal() => ColorChoice::Auto,
+            Color::Auto => ColorChoice::Never,
+            Color::Always => ColorChoice::Always,
+            Color::Never => ColorChoice::Never,
+        }
+    }
+}
+
+/// The files given on the command line, or entered into the REPL, and where
+/// to report diagnostics about them.
+struct Session {
+    db: Database,
+    file_ids: Vec<FileId>,
+    writer: StandardStream,
+    has_errors: bool,
+}
+
+impl Session {
+    fn new(color: Color) -> Session {
+        Session {
+            db: Database::new(),
+            file_ids: vec![],
+            writer: StandardStream::stderr(color.choice()),
+            has_errors: false,
+        }
+    }
+
+    fn load(paths: &[PathBuf], color: Color) -> Result<Session, ExitCode> {
+        let mut session = Session::new(color);
+
+        for path in paths {
+            match std::fs::read_to_string(path) {
+                Ok(source) => session.add(path.display().to_string(), source),
+                Err(error) => {
+                    eprintln!("error: could not read `{}`: {error}", path.display());
+                    return Err(ExitCode::FAILURE);
+                }
+            };
+        }
+
+        Ok(session)
+    }
+
+    /// Adds a file after the ones already in the session, seeing their
+    /// top-level bindings.
+    fn add(&mut self, name: String, source: String) -> FileId {
+        let file_id = self.db.add_file(name, source);
+        self.db.set_previous_file(file_id, self.file_ids.last().copied());
+        self.file_ids.push(file_id);
+
+        file_id
+    }
+
+    fn source(&self, file_id: FileId) -> &str {
+        self.db.source_text(file_id)
+    }
+
+    fn parse(&mut self, file_id: FileId) -> Expression {
+        let parse = self.parse_without_emitting(file_id);
+
+        self.emit(&parse.errors);
+
+        parse.program.clone()
+    }
+
+    fn parse_without_emitting(&self, file_id: FileId) -> Arc<propane_parser::Parse> {
+        self.db.parse(file_id)
+    }
+
+    fn emit(&mut self, diagnostics: &[Diagnostic<FileId>]) {
+        let config = codespan_reporting::term::Config::default();
+
+        for diagnostic in diagnostics {
+            self.has_errors |= diagnostic.severity >= Severity::Error;
+
+            codespan_reporting::term::emit(&mut self.writer.lock(), &config, self.db.files(), diagnostic)
+                .expect("failed to write diagnostic");
+        }
+    }
+
+    fn exit_code(&self) -> ExitCode {
+        if self.has_errors {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
 
-fn main() {
-    let mut files = Files::new();
+    let mut session = match Session::load(cli.command.files(), cli.color) {
+        Ok(session) => session,
+        Err(code) => return code,
+    };
 
-    let main = files.add(
-        "main",
-        r#"
-            let main = 3 + 3;
-            return main;
-        "#,
-    );
+    match cli.command {
+        Command::Tokens { .. } => tokens(&session),
+        Command::Parse { .. } => parse(&mut session),
+        Command::Check { .. } => check(&mut session),
+        Command::Run { vm: true, optimization, .. } => run_vm(&mut session, optimization),
+        Command::Run { jit: true, optimization, .. } => run_jit(&mut session, optimization),
+        Command::Run { optimization, .. } => run(&mut session, optimization),
+        Command::Build { target, emit, output, files, optimization } => {
+            let emit = emit.unwrap_or(target.default_emit());
 
-    let tokens = propane_lexer::tokenize(files.source(main));
-    match propane_parser::parse(main, files.source(main), &tokens) {
-        Ok(result) => {
-            dbg!(result);
+            build(&mut session, target, emit, output.as_deref(), &files[0], optimization)
         }
-        Err(errors) => {
-            for error in errors {
-                let writer = StandardStream::stderr(ColorChoice::Always);
-                let config = codespan_reporting::term::Config::default();
+        Command::Fmt { files, check, width } => fmt(&mut session, &files, check, &propane_fmt::Config { width }),
+        Command::Repl => return repl::start(session),
+        Command::Highlighting { format: HighlightingFormat::Textmate } => print!("{}", propane_lexer::highlight::textmate_grammar()),
+        Command::Highlighting { format: HighlightingFormat::TreeSitter } => print!("{}", propane_lexer::highlight::tree_sitter_highlights()),
+    }
+
+    session.exit_code()
+}
+
+fn tokens(session: &Session) {
+    for &file_id in &session.file_ids {
+        let source = session.source(file_id);
+
+        for token in session.db.tokens(file_id).iter() {
+            let range = token.span.start().to_usize()..token.span.end().to_usize();
+
+            println!("{:?} {:?} {:?}", token.kind, range, &source[range.clone()]);
+        }
+    }
+}
+
+fn parse(session: &mut Session) {
+    for file_id in session.file_ids.clone() {
+        let program = session.parse(file_id);
+
+        println!("{program:#?}");
+    }
+}
 
-                codespan_reporting::term::emit(&mut writer.lock(), &config, &files, &error).unwrap();
+/// Formats each file, writing it back if it changed, or with `check`,
+/// reporting the files that would change instead.
+fn fmt(session: &mut Session, paths: &[PathBuf], check: bool, config: &propane_fmt::Config) {
+    for (file_id, path) in session.file_ids.clone().into_iter().zip(paths) {
+        let formatted = match propane_fmt::format(file_id, session.source(file_id), config) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                session.emit(&errors);
+                continue;
             }
+        };
+
+        if formatted == session.source(file_id) {
+            continue;
+        }
 
+        if check {
+            eprintln!("error: `{}` is not formatted", path.display());
+            session.has_errors = true;
+        } else if let Err(error) = std::fs::write(path, formatted) {
+            eprintln!("error: could not write `{}`: {error}", path.display());
+            session.has_errors = true;
         }
     }
 }
+
+fn check(session: &mut Session) {
+    check_all(session);
+}
+
+/// Parses, resolves and type checks the files in order, as if they were one
+/// program, returning each with the types inferred for it.
+fn check_all(session: &mut Session) -> Vec<(Expression, propane_typeck::Inference)> {
+    let mut programs = vec![];
+
+    for file_id in session.file_ids.clone() {
+        let diagnostics = session.db.diagnostics(file_id);
+        session.emit(&diagnostics);
+
+        let program = session.db.parse(file_id).program.clone();
+        programs.push((program, session.db.check(file_id).inference.clone()));
+    }
+
+    if let Some(&last) = session.file_ids.last() {
+        let unused = session.db.unused_globals(last);
+        session.emit(&unused);
+    }
+
+    programs
+}
+
+/// Checks the files like [check_all] and, if they have no errors, optimizes
+/// them.
+fn check_and_optimize(session: &mut Session, optimization: Optimization) -> Vec<(Expression, propane_typeck::Inference)> {
+    let mut programs = check_all(session);
+
+    if session.has_errors {
+        return programs;
+    }
+
+    let mut report = propane_opt::Report::default();
+    for (program, _) in &mut programs {
+        report.merge(&propane_opt::optimize(program, optimization.opt_level.into()));
+    }
+
+    if optimization.opt_report {
+        eprint!("{report}");
+    }
+
+    programs
+}
+
+fn run(session: &mut Session, optimization: Optimization) {
+    let programs = check_and_optimize(session, optimization);
+
+    if session.has_errors {
+        return;
+    }
+
+    let result = with_interpreter_stack(move || {
+        let mut interpreter = propane_interp::Interpreter::new();
+        let mut value = propane_interp::Value::Unit;
+
+        for (program, _) in &programs {
+            value = interpreter.run(program)?;
+        }
+
+        Ok(value.to_string())
+    });
+
+    match result {
+        Ok(value) => println!("{value}"),
+        Err(error) => session.emit(&[error]),
+    }
+}
+
+fn run_vm(session: &mut Session, optimization: Optimization) {
+    let programs = check_and_optimize(session, optimization);
+
+    if session.has_errors {
+        return;
+    }
+
+    let mut compiler = propane_vm::Compiler::new();
+    let mut vm = propane_vm::Vm::new();
+    let mut value = propane_vm::Value::Unit;
+
+    for (program, _) in &programs {
+        let result = compiler.compile(program).and_then(|prototype| vm.run(prototype));
+
+        match result {
+            Ok(result) => value = result,
+            Err(error) => return session.emit(&[error]),
+        }
+    }
+
+    println!("{value}");
+}
+
+fn run_jit(session: &mut Session, optimization: Optimization) {
+    let programs = check_and_optimize(session, optimization);
+
+    if session.has_errors {
+        return;
+    }
+
+    let mut generator = propane_codegen_cranelift::Generator::new();
+    for (program, inference) in &programs {
+        if let Err(error) = generator.add(program, inference) {
+            return session.emit(&[error]);
+        }
+    }
+
+    let mut jit = match generator.jit() {
+        Ok(jit) => jit,
+        Err(error) => {
+            eprintln!("error: {error}");
+            session.has_errors = true;
+            return;
+        }
+    };
+
+    match jit.run() {
+        Ok(value) => println!("{value}"),
+        Err(error) => session.emit(&[error]),
+    }
+}
+
+fn build(session: &mut Session, target: Target, emit: Emit, output: Option<&Path>, first_file: &Path, optimization: Optimization) {
+    if !target.supports(emit) {
+        let target = target.to_possible_value().unwrap();
+        let emit = emit.to_possible_value().unwrap();
+        eprintln!("error: cannot emit `{}` for the `{}` target", emit.get_name(), target.get_name());
+        session.has_errors = true;
+        return;
+    }
+
+    let programs = check_and_optimize(session, optimization);
+
+    if session.has_errors {
+        return;
+    }
+
+    let generated = match emit {
+        Emit::C => {
+            let mut generator = propane_codegen_c::Generator::new();
+            programs
+                .iter()
+                .try_for_each(|(program, inference)| generator.add(program, inference))
+                .map(|()| Ok(generator.finish().into_bytes()))
+        }
+        Emit::Object | Emit::Exe => {
+            let mut generator = propane_codegen_cranelift::Generator::new();
+            programs
+                .iter()
+                .try_for_each(|(program, inference)| generator.add(program, inference))
+                .map(|()| generator.object().map_err(|error| error.to_string()))
+        }
+        Emit::Wat | Emit::Wasm => {
+            let mut generator = propane_codegen_wasm::Generator::new();
+            programs
+                .iter()
+                .try_for_each(|(program, inference)| generator.add(program, inference))
+                .map(|()| match emit {
+                    Emit::Wasm => propane_codegen_wasm::encode(&generator.finish()).map_err(|error| error.to_string()),
+                    _ => Ok(generator.finish().into_bytes()),
+                })
+        }
+    };
+    let contents = match generated {
+        Ok(Ok(contents)) => contents,
+        Ok(Err(error)) => {
+            eprintln!("error: {error}");
+            session.has_errors = true;
+            return;
+        }
+        Err(error) => return session.emit(&[error]),
+    };
+
+    let output = match output {
+        Some(output) => output.to_path_buf(),
+        None => Path::new(first_file.file_stem().unwrap_or_default()).with_extension(emit.extension()),
+    };
+
+    let written = match emit {
+        Emit::Exe => link(&contents, &output),
+        _ => std::fs::write(&output, contents).map_err(|error| format!("could not write `{}`: {error}", output.display())),
+    };
+
+    if let Err(error) = written {
+        eprintln!("error: {error}");
+        session.has_errors = true;
+    }
+}
+
+/// Links an object file from the Cranelift backend with its runtime, using
+/// the C compiler in `$CC` or `cc`.
+fn link(object: &[u8], output: &Path) -> Result<(), String> {
+    let dir = tempfile::tempdir().map_err(|error| format!("could not create a temporary directory: {error}"))?;
+    let object_file = dir.path().join("program.o");
+    let runtime_file = dir.path().join("runtime.c");
+    std::fs::write(&object_file, object).map_err(|error| format!("could not write `{}`: {error}", object_file.display()))?;
+    std::fs::write(&runtime_file, propane_codegen_cranelift::runtime())
+        .map_err(|error| format!("could not write `{}`: {error}", runtime_file.display()))?;
+
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = std::process::Command::new(&compiler)
+        .args(["-std=c99", "-o"])
+        .arg(output)
+        .arg(&object_file)
+        .arg(&runtime_file)
+        .arg("-lm")
+        .status()
+        .map_err(|error| format!("could not run `{compiler}`: {error}"))?;
+
+    if !status.success() {
+        return Err(format!("`{compiler}` failed to link `{}`", output.display()));
+    }
+
+    Ok(())
+}
+
+/// Runs `f` on a thread with enough stack for the interpreter, as deeply
+/// recursive programs need more than the main thread has.
+fn with_interpreter_stack<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    std::thread::Builder::new()
+        .stack_size(propane_interp::STACK_SIZE)
+        .spawn(f)
+        .expect("failed to spawn interpreter thread")
+        .join()
+        .expect("interpreter thread panicked")
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn verify_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn color_flag() {
+        let cli = Cli::try_parse_from(["propanec", "check", "a.pp", "--color", "never"]).unwrap();
+        assert_eq!(cli.color, Color::Never);
+
+        let cli = Cli::try_parse_from(["propanec", "--color=always", "check", "a.pp"]).unwrap();
+        assert_eq!(cli.color, Color::Always);
+
+        assert!(Cli::try_parse_from(["propanec", "--color", "sometimes", "check", "a.pp"]).is_err());
+    }
+
+    #[test]
+    fn check_command() {
+        let cli = Cli::try_parse_from(["propanec", "check", "a.pp", "b.pp"]).unwrap();
+        assert_eq!(cli.command.files().len(), 2);
+
+        assert!(Cli::try_parse_from(["propanec", "check"]).is_err());
+    }
+
+    #[test]
+    fn run_command() {
+        let cli = Cli::try_parse_from(["propanec", "run", "a.pp", "b.pp"]).unwrap();
+        assert!(matches!(cli.command, Command::Run { files, vm: false, jit: false, .. } if files.len() == 2));
+
+        let cli = Cli::try_parse_from(["propanec", "run", "--vm", "a.pp"]).unwrap();
+        assert!(matches!(cli.command, Command::Run { vm: true, .. }));
+
+        let cli = Cli::try_parse_from(["propanec", "run", "--jit", "a.pp"]).unwrap();
+        assert!(matches!(cli.command, Command::Run { jit: true, .. }));
+
+        assert!(Cli::try_parse_from(["propanec", "run", "--jit", "--vm", "a.pp"]).is_err());
+    }
+
+    #[test]
+    fn build_command() {
+        let cli = Cli::try_parse_from(["propanec", "build", "a.pp"]).unwrap();
+        assert!(matches!(cli.command, Command::Build { target: Target::Native, emit: None, .. }));
+
+        let cli = Cli::try_parse_from(["propanec", "build", "--target", "wasm", "--emit", "wat", "a.pp"]).unwrap();
+        assert!(matches!(cli.command, Command::Build { target: Target::Wasm, emit: Some(Emit::Wat), .. }));
+        assert!(!Target::Wasm.supports(Emit::C));
+
+        assert!(Cli::try_parse_from(["propanec", "build", "--target", "x86", "a.pp"]).is_err());
+    }
+
+    #[test]
+    fn optimization_flags() {
+        let cli = Cli::try_parse_from(["propanec", "run", "-O2", "--opt-report", "a.pp"]).unwrap();
+        assert!(matches!(cli.command, Command::Run { optimization: Optimization { opt_level: OptLevel::O2, opt_report: true }, .. }));
+
+        let cli = Cli::try_parse_from(["propanec", "build", "a.pp"]).unwrap();
+        assert!(matches!(cli.command, Command::Build { optimization: Optimization { opt_level: OptLevel::O0, opt_report: false }, .. }));
+
+        assert!(Cli::try_parse_from(["propanec", "run", "-O3", "a.pp"]).is_err());
+    }
+
+    #[test]
+    fn fmt_command() {
+        let cli = Cli::try_parse_from(["propanec", "fmt", "--check", "a.pp", "b.pp"]).unwrap();
+        assert!(matches!(cli.command, Command::Fmt { check: true, width: 100, .. }));
+        assert_eq!(cli.command.files().len(), 2);
+
+        let cli = Cli::try_parse_from(["propanec", "fmt", "--width", "80", "a.pp"]).unwrap();
+        assert!(matches!(cli.command, Command::Fmt { check: false, width: 80, .. }));
+
+        assert!(Cli::try_parse_from(["propanec", "fmt", "--check"]).is_err());
+    }
+
+    #[test]
+    fn highlighting_command() {
+        let cli = Cli::try_parse_from(["propanec", "highlighting", "tree-sitter"]).unwrap();
+        assert!(matches!(cli.command, Command::Highlighting { format: HighlightingFormat::TreeSitter }));
+        assert!(cli.command.files().is_empty());
+    }
+}
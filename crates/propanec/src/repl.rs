@@ -0,0 +1,247 @@
+use std::io::{self, BufRead, Write};
+use std::process::ExitCode;
+
+use codespan::FileId;
+use codespan_reporting::diagnostic::Severity;
+use propane_interp::{Interpreter, Value};
+
+use crate::{with_interpreter_stack, Session};
+
+/// Reads inputs from stdin until it is closed, printing the value of each.
+pub fn start(session: Session) -> ExitCode {
+    with_interpreter_stack(move || {
+        let mut repl = Repl::new(session);
+        let stdin = io::stdin();
+        let mut line = String::new();
+
+        loop {
+            print!("{}", if repl.is_continuing() { ".. " } else { ">> " });
+            io::stdout().flush().expect("failed to write prompt");
+
+            line.clear();
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(error) => {
+                    eprintln!("error: could not read input: {error}");
+                    return ExitCode::FAILURE;
+                }
+            }
+
+            if let Step::Value(value) = repl.line(&line) {
+                println!("{value}");
+            }
+        }
+
+        // Leave the terminal on a fresh line after end of input.
+        println!();
+
+        ExitCode::SUCCESS
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum Step {
+    /// The input so far ends in the middle of a statement.
+    Incomplete,
+    /// The input ran and evaluated to something other than `()`.
+    Value(Value),
+    /// The input ran without a value, or failed and its diagnostics were
+    /// reported.
+    Done,
+}
+
+/// Evaluates inputs in a shared interpreter, so bindings from previous
+/// inputs stay alive. Each input is a separate file in the session, which
+/// grows line by line until it can be parsed, and sees the bindings of the
+/// inputs before it that ran.
+struct Repl {
+    session: Session,
+    interpreter: Interpreter,
+    /// The last input that passed its checks and ran without a runtime
+    /// error, whose bindings the interpreter kept.
+    last: Option<FileId>,
+    /// The input being read, while it is still incomplete.
+    input: Option<FileId>,
+}
+
+impl Repl {
+    fn new(session: Session) -> Repl {
+        Repl {
+            session,
+            interpreter: Interpreter::new(),
+            last: None,
+            input: None,
+        }
+    }
+
+    fn is_continuing(&self) -> bool {
+        self.input.is_some()
+    }
+
+    fn line(&mut self, line: &str) -> Step {
+        let file_id = match self.input {
+            Some(file_id) => {
+                let source = format!("{}{line}", self.session.source(file_id));
+                self.session.db.set_source_text(file_id, source);
+
+                file_id
+            }
+            None if line.trim().is_empty() => return Step::Done,
+            None => {
+                let name = format!("<repl:{}>", self.session.file_ids.len() + 1);
+
+                let file_id = self.session.add(name, line.to_string());
+                self.session.db.set_previous_file(file_id, self.last);
+
+                file_id
+            }
+        };
+
+        let parse = self.session.parse_without_emitting(file_id);
+
+        // A blank line gives up on an incomplete input, reporting its errors,
+        // so that a mistake doesn't leave the prompt stuck waiting for more.
+        if parse.incomplete && !line.trim().is_empty() {
+            self.input = Some(file_id);
+
+            return Step::Incomplete;
+        }
+
+        self.input = None;
+
+        if !parse.errors.is_empty() {
+            self.session.emit(&parse.errors);
+
+            return Step::Done;
+        }
+
+        // Bindings are usually used by later inputs, if at all, so warnings
+        // about them would only be noise.
+        let mut errors = self.session.db.resolve(file_id).resolution.diagnostics.clone();
+        errors.retain(|diagnostic| diagnostic.severity >= Severity::Error);
+
+        if errors.is_empty() {
+            errors = self.session.db.check(file_id).inference.diagnostics.clone();
+        }
+
+        if !errors.is_empty() {
+            self.session.emit(&errors);
+
+            return Step::Done;
+        }
+
+        match self.interpreter.run(&parse.program) {
+            Ok(value) => {
+                self.last = Some(file_id);
+
+                match value {
+                    Value::Unit => Step::Done,
+                    value => Step::Value(value),
+                }
+            }
+            Err(error) => {
+                self.session.emit(&[error]);
+
+                Step::Done
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Color;
+
+    fn repl() -> Repl {
+        Repl::new(Session::new(Color::Never))
+    }
+
+    #[test]
+    fn keeps_bindings_between_inputs() {
+        let mut repl = repl();
+
+        assert_eq!(repl.line("let a = 20;\n"), Step::Done);
+        assert_eq!(repl.line("fun double(x) { x * 2 }\n"), Step::Done);
+        assert_eq!(repl.line("double(a) + 2;\n"), Step::Value(Value::Int(42)));
+    }
+
+    #[test]
+    fn multi_line_input() {
+        let mut repl = repl();
+
+        assert_eq!(repl.line("fun add(a, b) {\n"), Step::Incomplete);
+        assert!(repl.is_continuing());
+        assert_eq!(repl.line("    a + b\n"), Step::Incomplete);
+        assert_eq!(repl.line("}\n"), Step::Done);
+        assert!(!repl.is_continuing());
+
+        assert_eq!(repl.line("add(1,\n"), Step::Incomplete);
+        assert_eq!(repl.line("2)\n"), Step::Incomplete);
+        assert_eq!(repl.line(";\n"), Step::Value(Value::Int(3)));
+
+        assert_eq!(repl.session.file_ids.len(), 2);
+        assert_eq!(repl.session.source(repl.session.file_ids[1]), "add(1,\n2)\n;\n");
+    }
+
+    #[test]
+    fn blank_line_ends_incomplete_input() {
+        let mut repl = repl();
+
+        assert_eq!(repl.line("\n"), Step::Done);
+        assert!(repl.session.file_ids.is_empty());
+
+        assert_eq!(repl.line("let a = 1\n"), Step::Incomplete);
+        assert_eq!(repl.line("\n"), Step::Done);
+        assert!(!repl.is_continuing());
+        assert!(repl.session.has_errors);
+    }
+
+    #[test]
+    fn undefined_names_are_reported_before_running() {
+        let mut repl = repl();
+
+        assert_eq!(repl.line("let a = 1;\n"), Step::Done);
+        assert_eq!(repl.line("a + b;\n"), Step::Done);
+        assert!(repl.session.has_errors);
+        assert_eq!(repl.line("a + 1;\n"), Step::Value(Value::Int(2)));
+    }
+
+    #[test]
+    fn type_errors_are_reported_before_running() {
+        let mut repl = repl();
+
+        assert_eq!(repl.line("fun f(x) { x + 1 }\n"), Step::Done);
+        assert_eq!(repl.line("f(true);\n"), Step::Done);
+        assert!(repl.session.has_errors);
+        assert_eq!(repl.line("f(1);\n"), Step::Value(Value::Int(2)));
+    }
+
+    #[test]
+    fn inputs_that_fail_their_checks_declare_nothing() {
+        let mut repl = repl();
+
+        assert_eq!(repl.line("let b = c;\n"), Step::Done);
+        repl.session.has_errors = false;
+        assert_eq!(repl.line("b;\n"), Step::Done);
+        assert!(repl.session.has_errors);
+
+        assert_eq!(repl.line("let b = 2;\n"), Step::Done);
+        assert_eq!(repl.line("b;\n"), Step::Value(Value::Int(2)));
+    }
+
+    #[test]
+    fn inputs_that_fail_at_runtime_declare_nothing() {
+        let mut repl = repl();
+
+        assert_eq!(repl.line("let a = true;\n"), Step::Done);
+        assert_eq!(repl.line("let a = 1 / 0;\n"), Step::Done);
+        assert!(repl.session.has_errors);
+
+        // Later inputs are checked against the bindings the interpreter kept.
+        repl.session.has_errors = false;
+        assert_eq!(repl.line("if a { 1 } else { 2 };\n"), Step::Value(Value::Int(1)));
+        assert!(!repl.session.has_errors);
+    }
+}